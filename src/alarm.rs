@@ -0,0 +1,310 @@
+//! Alarms: at the set time the bot joins the user's voice channel and
+//! plays their song, getting louder over a minute.
+use std::{
+    env,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Result};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use serenity::{
+    client::Context,
+    model::id::{ChannelId, GuildId, UserId},
+    prelude::TypeMapKey,
+};
+use tokio::sync::RwLock;
+use tracing::warn;
+
+use crate::{
+    check_msg, crossfade, hibernate, history, limiter,
+    playback::{self, GuildPlayer},
+    preflight,
+    queue::{self, Requester},
+    reconnect, resolve, settings, soft_mute, store, track_name,
+};
+
+const ALARMS: &str = "alarms";
+
+/// How often due alarms are looked for.
+pub(crate) const ALARM_TICK: Duration = Duration::from_secs(15);
+
+/// Alarms a user may have set in a guild.
+pub(crate) const ALARMS_MAX: usize = 5;
+
+const RAMP_FROM: f32 = 0.05;
+const RAMP_DURATION: Duration = Duration::from_secs(60);
+
+const DAY: i64 = 24 * 60 * 60;
+
+lazy_static! {
+    /// Hours from UTC that alarm times are given in, e.g. `8` for Beijing.
+    static ref ALARM_UTC_OFFSET: i64 = env::var("ALARM_UTC_OFFSET")
+        .ok()
+        .and_then(|x| x.trim_start_matches('+').parse::<f64>().ok())
+        .map(|x| (x * 3600.0) as i64)
+        .unwrap_or(0);
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Alarm {
+    pub id: u64,
+    pub guild_id: u64,
+    pub text_channel: u64,
+    pub requester: Requester,
+    /// Unix time it goes off.
+    pub time: i64,
+    pub url: String,
+}
+
+pub(crate) struct Alarms;
+
+impl TypeMapKey for Alarms {
+    type Value = Arc<RwLock<Vec<Alarm>>>;
+}
+
+pub(crate) async fn load() -> Result<Vec<Alarm>> {
+    store::load(ALARMS).await
+}
+
+async fn alarms_lock(ctx: &Context) -> Arc<RwLock<Vec<Alarm>>> {
+    let read = ctx.data.read().await;
+
+    read.get::<Alarms>()
+        .expect("Expected Alarms in TypeMap.")
+        .clone()
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// Parses `HH:MM`, given in the offset from UTC, into the next time it
+/// comes up after `now`.
+fn next_occurrence(now: i64, time: &str, offset: i64) -> Option<i64> {
+    let (hours, minutes) = time.split_once(':')?;
+    let hours = hours.parse::<i64>().ok().filter(|x| (0..24).contains(x))?;
+    let minutes = minutes
+        .parse::<i64>()
+        .ok()
+        .filter(|x| (0..60).contains(x))?;
+
+    let local = now + offset;
+    let midnight = local - local.rem_euclid(DAY);
+    let mut at = midnight + hours * 3600 + minutes * 60;
+    if at <= local {
+        at += DAY;
+    }
+
+    Some(at - offset)
+}
+
+/// Sets an alarm for `time` (`HH:MM`), returns when it goes off.
+pub(crate) async fn set(
+    ctx: &Context,
+    guild_id: u64,
+    text_channel: ChannelId,
+    requester: Requester,
+    time: &str,
+    url: String,
+) -> Result<Alarm> {
+    let at = next_occurrence(now(), time, *ALARM_UTC_OFFSET)
+        .ok_or_else(|| anyhow!("Time must be like 07:30"))?;
+
+    let lock = alarms_lock(ctx).await;
+    let mut alarms = lock.write().await;
+    let set = alarms
+        .iter()
+        .filter(|x| x.guild_id == guild_id && x.requester.id == requester.id)
+        .count();
+    if set >= ALARMS_MAX {
+        bail!("You can have at most {} alarms", ALARMS_MAX);
+    }
+
+    let alarm = Alarm {
+        id: alarms.iter().map(|x| x.id + 1).max().unwrap_or(1),
+        guild_id,
+        text_channel: text_channel.0,
+        requester,
+        time: at,
+        url,
+    };
+    alarms.push(alarm.clone());
+    store::save(ALARMS, &*alarms).await?;
+
+    Ok(alarm)
+}
+
+/// The user's alarms in the guild, soonest first.
+pub(crate) async fn list(ctx: &Context, guild_id: u64, user: u64) -> Vec<Alarm> {
+    let lock = alarms_lock(ctx).await;
+    let alarms = lock.read().await;
+    let mut alarms = alarms
+        .iter()
+        .filter(|x| x.guild_id == guild_id && x.requester.id == user)
+        .cloned()
+        .collect::<Vec<_>>();
+    alarms.sort_by_key(|x| x.time);
+
+    alarms
+}
+
+/// Cancels one of the user's alarms, `false` if they have no such alarm.
+pub(crate) async fn cancel(ctx: &Context, guild_id: u64, user: u64, id: u64) -> Result<bool> {
+    let lock = alarms_lock(ctx).await;
+    let mut alarms = lock.write().await;
+    let len = alarms.len();
+    alarms.retain(|x| !(x.id == id && x.guild_id == guild_id && x.requester.id == user));
+    if alarms.len() == len {
+        return Ok(false);
+    }
+    store::save(ALARMS, &*alarms).await?;
+
+    Ok(true)
+}
+
+/// Takes the alarms which are due out of the list.
+async fn take_due(ctx: &Context) -> Result<Vec<Alarm>> {
+    let lock = alarms_lock(ctx).await;
+    let mut alarms = lock.write().await;
+    let now = now();
+    let (due, later): (Vec<_>, Vec<_>) = alarms.drain(..).partition(|x| x.time <= now);
+    *alarms = later;
+    if !due.is_empty() {
+        store::save(ALARMS, &*alarms).await?;
+    }
+
+    Ok(due)
+}
+
+/// Fires due alarms, forever. Alarms missed while the bot was down go off
+/// once it's back.
+pub(crate) async fn run(ctx: Context) {
+    loop {
+        match take_due(&ctx).await {
+            Ok(due) => {
+                for alarm in due {
+                    let channel = ChannelId(alarm.text_channel);
+                    if let Err(e) = ring(&ctx, &alarm).await {
+                        warn!("Err ringing alarm {}: {:?}", alarm.id, e);
+                        check_msg(
+                            channel
+                                .say(
+                                    &ctx.http,
+                                    format!("<@{}> your alarm went off: {}", alarm.requester.id, e),
+                                )
+                                .await,
+                        );
+                    }
+                }
+            }
+            Err(e) => warn!("Err checking alarms: {:?}", e),
+        }
+        tokio::time::sleep(ALARM_TICK).await;
+    }
+}
+
+async fn ring(ctx: &Context, alarm: &Alarm) -> Result<()> {
+    hibernate::wake(ctx).await;
+    let guild_id = GuildId(alarm.guild_id);
+    let text_channel = ChannelId(alarm.text_channel);
+    let voice_channel = ctx
+        .cache
+        .guild_field(guild_id, |x| {
+            x.voice_states
+                .get(&UserId(alarm.requester.id))
+                .and_then(|x| x.channel_id)
+        })
+        .flatten()
+        .ok_or_else(|| anyhow!("you're not in a voice channel"))?;
+
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    // Don't pull the bot away from other listeners, queue the song there.
+    let handler_lock = match manager.get(guild_id) {
+        Some(handler_lock) if handler_lock.lock().await.current_channel().is_some() => handler_lock,
+        _ => {
+            preflight::check(&ctx.cache, guild_id, voice_channel)?;
+            let (handler_lock, success) = manager.join(guild_id, voice_channel).await;
+            success.map_err(|e| anyhow!("can not join the voice channel: {:?}", e))?;
+            playback::start_session(ctx, guild_id.0).await;
+            reconnect::watch(ctx, guild_id.0, &handler_lock).await;
+            playback::reset_announcements(ctx, guild_id.0, text_channel).await;
+
+            handler_lock
+        }
+    };
+
+    let player = GuildPlayer::new(ctx, guild_id.0, handler_lock.clone()).await;
+    let resolved = resolve::resolve(
+        alarm.url.clone(),
+        player.quality().await,
+        player.filter().await,
+    )
+    .await?;
+    let volume = settings::get(ctx, guild_id.0).await.default_volume();
+    let volume = limiter::cap(volume, playback::volume_ceiling(ctx, guild_id.0).await);
+    let (track, first) = {
+        let mut handler = handler_lock.lock().await;
+        let first = handler.queue().is_empty();
+        (handler.enqueue_source(resolved.input), first)
+    };
+    // Before `attach`, so a soft mute keeps this volume to give back.
+    track.set_volume(if first { RAMP_FROM.min(volume) } else { volume })?;
+    queue::set_requester(&track, alarm.requester.clone()).await;
+    player.attach(&track, first).await?;
+    let title = track_name(track.metadata());
+    history::record(
+        ctx,
+        guild_id.0,
+        &alarm.requester,
+        vec![(alarm.url.clone(), track.metadata().clone())],
+    )
+    .await;
+
+    let s = if first {
+        if soft_mute::volume_before(&player, &track).await.is_some() {
+            // Muted, it gets the full volume on unmute instead of the ramp.
+            soft_mute::set_volume(&player.playback, guild_id.0, &[track], volume).await?;
+        } else {
+            tokio::spawn(async move {
+                let ramp = crossfade::ramp_volume(&track, RAMP_FROM, volume, RAMP_DURATION);
+                if let Err(e) = ramp.await {
+                    warn!("Err ramping alarm volume: {:?}", e);
+                }
+            });
+        }
+        format!("<@{}> wake up! Playing {}", alarm.requester.id, title)
+    } else {
+        format!(
+            "<@{}> your alarm went off, queued {}",
+            alarm.requester.id, title
+        )
+    };
+    check_msg(text_channel.say(&ctx.http, s).await);
+
+    Ok(())
+}
+
+#[test]
+fn test_next_occurrence() {
+    // 2022-01-01 06:00 UTC
+    let now = 1_640_995_200 + 6 * 3600;
+
+    assert_eq!(next_occurrence(now, "07:30", 0), Some(now + 5400));
+    // Already past today, so tomorrow.
+    assert_eq!(next_occurrence(now, "05:00", 0), Some(now + DAY - 3600));
+    // 07:30 in UTC+8 is 23:30 UTC.
+    assert_eq!(
+        next_occurrence(now, "07:30", 8 * 3600),
+        Some(now + 17 * 3600 + 1800)
+    );
+    assert_eq!(next_occurrence(now, "24:00", 0), None);
+    assert_eq!(next_occurrence(now, "7", 0), None);
+}
@@ -0,0 +1,323 @@
+//! An HTTP and WebSocket API to drive the player from other programs, e.g.
+//! a now playing widget in OBS. Served on `API_ADDR`, e.g. `0.0.0.0:8081`,
+//! and off unless it is set. `~apitoken` gives a server's admins a token
+//! for their server, sent as `Authorization: Bearer TOKEN` or, where
+//! headers can't be set like in a browser's WebSocket, as `?token=TOKEN`.
+//!
+//! - `GET /guilds/{id}/queue`: the queue, `POST` with `{"from", "to"}` moves
+//!   an entry
+//! - `GET /guilds/{id}/player`: the current song, `POST` with
+//!   `{"action": "skip" | "pause" | "resume"}` or `{"volume": 0~200}`
+//! - `GET /guilds/{id}/events`: a WebSocket sending the player each time the
+//!   song, pause or volume changes
+use std::{collections::HashMap, convert::Infallible, env, net::SocketAddr, time::Duration};
+
+use anyhow::Result;
+use async_tungstenite::{
+    tokio::TokioAdapter,
+    tungstenite::{handshake::derive_accept_key, protocol::Role, Message},
+    WebSocketStream,
+};
+use futures_util::{SinkExt, StreamExt};
+use hyper::{
+    header,
+    service::{make_service_fn, service_fn},
+    upgrade::Upgraded,
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use reqwest::Url;
+use serde::Deserialize;
+use serenity::{client::Context, model::id::GuildId};
+use tracing::{info, warn};
+
+use crate::{
+    control::{self, Player},
+    dashboard::{done, error, json_response, read_json, respond, MoveBody},
+    playback, store,
+};
+
+const API_TOKENS: &str = "api_tokens";
+/// How often event sockets look for a change of the player.
+const EVENT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// SHA-256 of each guild's token, the tokens themselves are only shown once.
+type ApiTokens = HashMap<u64, String>;
+
+fn digest(token: &str) -> String {
+    hex::encode(openssl::sha::sha256(token.as_bytes()))
+}
+
+/// Makes a new token for the guild, the old one stops working.
+pub(crate) async fn new_token(guild_id: u64) -> Result<String> {
+    let token = hex::encode(rand::random::<[u8; 32]>());
+    let mut tokens = store::load::<ApiTokens>(API_TOKENS).await?;
+    tokens.insert(guild_id, digest(&token));
+    store::save(API_TOKENS, &tokens).await?;
+
+    Ok(token)
+}
+
+/// Takes the guild's token back, `false` if it had none.
+pub(crate) async fn revoke(guild_id: u64) -> Result<bool> {
+    let mut tokens = store::load::<ApiTokens>(API_TOKENS).await?;
+    let revoked = tokens.remove(&guild_id).is_some();
+    store::save(API_TOKENS, &tokens).await?;
+
+    Ok(revoked)
+}
+
+fn authorized(tokens: &ApiTokens, guild_id: u64, token: &str) -> bool {
+    tokens
+        .get(&guild_id)
+        .is_some_and(|x| openssl::memcmp::eq(x.as_bytes(), digest(token).as_bytes()))
+}
+
+/// The token sent with `req`, from the header or the query.
+fn token(req: &Request<Body>) -> Option<String> {
+    let bearer = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.strip_prefix("Bearer "));
+    if let Some(token) = bearer {
+        return Some(token.trim().to_string());
+    }
+    let url = Url::parse(&format!("http://localhost{}", req.uri())).ok()?;
+    let token = url.query_pairs().find(|(k, _)| k == "token")?.1;
+
+    Some(token.into_owned())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Action {
+    Skip,
+    Pause,
+    Resume,
+}
+
+#[derive(Deserialize)]
+struct PlayerBody {
+    action: Option<Action>,
+    volume: Option<f32>,
+}
+
+fn not_connected() -> Response<Body> {
+    error(StatusCode::NOT_FOUND, "Not in a voice channel")
+}
+
+async fn player(ctx: &Context, guild_id: GuildId, req: Request<Body>) -> Result<Response<Body>> {
+    if req.method() == Method::GET {
+        return Ok(match control::snapshot(ctx, guild_id).await {
+            Some(snapshot) => json_response(StatusCode::OK, &Player::from(snapshot)),
+            None => not_connected(),
+        });
+    }
+    let body = match read_json::<PlayerBody>(req).await {
+        Some(body) => body,
+        None => {
+            return Ok(error(
+                StatusCode::BAD_REQUEST,
+                "Expected {\"action\"} or {\"volume\"}",
+            ))
+        }
+    };
+    let response = match (body.action, body.volume) {
+        (Some(Action::Skip), None) => done(control::skip(ctx, guild_id).await),
+        (Some(Action::Pause), None) => done(control::pause(ctx, guild_id, true).await?),
+        (Some(Action::Resume), None) => done(control::pause(ctx, guild_id, false).await?),
+        (None, Some(volume)) if (0.0..=200.0).contains(&volume) => {
+            let channel = playback::text_channel(ctx, guild_id.0).await;
+            match control::set_volume(ctx, guild_id, channel, volume / 100.0).await? {
+                Some(volume) => json_response(
+                    StatusCode::OK,
+                    &serde_json::json!({ "volume": (volume * 100.0).round() }),
+                ),
+                None => not_connected(),
+            }
+        }
+        _ => error(
+            StatusCode::BAD_REQUEST,
+            "Expected {\"action\"} or {\"volume\"} from 0 to 200",
+        ),
+    };
+
+    Ok(response)
+}
+
+async fn queue(ctx: &Context, guild_id: GuildId, req: Request<Body>) -> Response<Body> {
+    if req.method() == Method::GET {
+        return match control::snapshot(ctx, guild_id).await {
+            Some(snapshot) => json_response(StatusCode::OK, &snapshot),
+            None => not_connected(),
+        };
+    }
+    match read_json::<MoveBody>(req).await {
+        Some(body) => {
+            let (from, to) = (body.from.saturating_sub(1), body.to.saturating_sub(1));
+            done(control::move_entry(ctx, guild_id, from, to).await)
+        }
+        None => error(StatusCode::BAD_REQUEST, "Expected {\"from\", \"to\"}"),
+    }
+}
+
+/// Sends the player of the guild over `ws` now and whenever it changes,
+/// until the other side closes the socket.
+async fn events(ctx: Context, guild_id: GuildId, mut ws: WebSocketStream<TokioAdapter<Upgraded>>) {
+    // `None` until the first message, which is always sent.
+    let mut last: Option<Option<Player>> = None;
+    let mut interval = tokio::time::interval(EVENT_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let player = control::snapshot(&ctx, guild_id).await.map(Player::from);
+                let changed = match (&last, &player) {
+                    (Some(Some(last)), Some(player)) => last.changed(player),
+                    (Some(None), None) => false,
+                    _ => true,
+                };
+                if changed {
+                    let text = serde_json::json!({ "event": "player", "player": player }).to_string();
+                    if ws.send(Message::Text(text)).await.is_err() {
+                        break;
+                    }
+                }
+                last = Some(player);
+            }
+            message = ws.next() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+/// Answers a WebSocket handshake and hands the socket to `events`.
+fn upgrade(ctx: &Context, guild_id: GuildId, req: Request<Body>) -> Response<Body> {
+    let is_websocket = req
+        .headers()
+        .get(header::UPGRADE)
+        .and_then(|x| x.to_str().ok())
+        .is_some_and(|x| x.eq_ignore_ascii_case("websocket"));
+    let key = match req.headers().get(header::SEC_WEBSOCKET_KEY) {
+        Some(key) if is_websocket => key.as_bytes().to_vec(),
+        _ => return error(StatusCode::BAD_REQUEST, "Expected a WebSocket"),
+    };
+
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        match hyper::upgrade::on(req).await {
+            Ok(upgraded) => {
+                let ws = WebSocketStream::from_raw_socket(
+                    TokioAdapter::new(upgraded),
+                    Role::Server,
+                    None,
+                )
+                .await;
+                events(ctx, guild_id, ws).await;
+            }
+            Err(e) => warn!("Err upgrading to a WebSocket: {:?}", e),
+        }
+    });
+
+    Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(header::UPGRADE, "websocket")
+        .header(header::CONNECTION, "Upgrade")
+        .header(header::SEC_WEBSOCKET_ACCEPT, derive_accept_key(&key))
+        .body(Body::empty())
+        .expect("valid response")
+}
+
+async fn route(ctx: &Context, req: Request<Body>) -> Result<Response<Body>> {
+    let path = req.uri().path().to_string();
+    let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+    let (guild_id, resource) = match segments.as_slice() {
+        ["guilds", id, resource] => match id.parse::<u64>() {
+            Ok(id) => (GuildId(id), *resource),
+            Err(_) => return Ok(error(StatusCode::NOT_FOUND, "Not found")),
+        },
+        _ => return Ok(error(StatusCode::NOT_FOUND, "Not found")),
+    };
+    let tokens = store::load::<ApiTokens>(API_TOKENS).await?;
+    if !token(&req).is_some_and(|x| authorized(&tokens, guild_id.0, &x)) {
+        return Ok(error(StatusCode::UNAUTHORIZED, "Invalid token"));
+    }
+
+    let response = match (req.method(), resource) {
+        (&Method::GET | &Method::POST, "queue") => queue(ctx, guild_id, req).await,
+        (&Method::GET | &Method::POST, "player") => player(ctx, guild_id, req).await?,
+        (&Method::GET, "events") => upgrade(ctx, guild_id, req),
+        _ => respond(StatusCode::NOT_FOUND, "text/plain", "not found"),
+    };
+
+    Ok(response)
+}
+
+async fn handle(ctx: Context, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    match route(&ctx, req).await {
+        Ok(response) => Ok(response),
+        Err(e) => {
+            warn!("Err serving the API: {:?}", e);
+            Ok(error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Something went wrong",
+            ))
+        }
+    }
+}
+
+/// Serves the API until the bot stops, if `API_ADDR` is set.
+pub(crate) async fn run(ctx: Context) {
+    let addr = match env::var("API_ADDR").map(|x| x.parse::<SocketAddr>()) {
+        Ok(Ok(addr)) => addr,
+        Ok(Err(e)) => {
+            warn!("Err parsing API_ADDR: {:?}", e);
+            return;
+        }
+        Err(_) => return,
+    };
+
+    let make_service = make_service_fn(move |_| {
+        let ctx = ctx.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| handle(ctx.clone(), req))) }
+    });
+    let server = match Server::try_bind(&addr) {
+        Ok(server) => server.serve(make_service),
+        Err(e) => {
+            warn!("Err serving the API on {}: {:?}", addr, e);
+            return;
+        }
+    };
+    info!("Serving the API on {}", addr);
+    if let Err(e) = server.await {
+        warn!("Err serving the API: {:?}", e);
+    }
+}
+
+#[test]
+fn test_authorized() {
+    let tokens = HashMap::from([(1, digest("secret"))]);
+
+    assert!(authorized(&tokens, 1, "secret"));
+    assert!(!authorized(&tokens, 1, "guess"));
+    assert!(!authorized(&tokens, 2, "secret"));
+}
+
+#[test]
+fn test_token() {
+    let header = Request::builder()
+        .header(header::AUTHORIZATION, "Bearer abc")
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(token(&header).as_deref(), Some("abc"));
+
+    let query = Request::builder()
+        .uri("/guilds/1/events?token=def")
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(token(&query).as_deref(), Some("def"));
+
+    let none = Request::builder().body(Body::empty()).unwrap();
+    assert_eq!(token(&none), None);
+}
@@ -0,0 +1,155 @@
+//! Typed command arguments. Commands take them with `?`, a missing or
+//! invalid one is answered with the command's usage by the `after` hook.
+use std::{fmt, ops::RangeInclusive, str::FromStr};
+
+use serenity::framework::standard::Args;
+
+/// A command called with bad arguments, with its expected syntax.
+#[derive(Debug)]
+pub(crate) struct Usage(pub String);
+
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Usage: {}", self.0)
+    }
+}
+
+impl std::error::Error for Usage {}
+
+pub(crate) trait ArgsExt {
+    /// The next argument as `T`.
+    fn required<T: FromStr>(&mut self, usage: &str) -> Result<T, Usage>;
+
+    /// The next argument as `T` if it is one, it is left for the next call
+    /// otherwise.
+    fn optional<T: FromStr>(&mut self) -> Option<T>;
+
+    /// The next argument as `T` within `range`.
+    fn within<T: FromStr + PartialOrd>(
+        &mut self,
+        range: RangeInclusive<T>,
+        usage: &str,
+    ) -> Result<T, Usage>;
+
+    /// Whether the next argument is `name`, taken if it is.
+    fn flag(&mut self, name: &str) -> bool;
+}
+
+impl ArgsExt for Args {
+    fn required<T: FromStr>(&mut self, usage: &str) -> Result<T, Usage> {
+        self.optional().ok_or_else(|| Usage(usage.to_string()))
+    }
+
+    fn optional<T: FromStr>(&mut self) -> Option<T> {
+        // `single` only moves on when the argument parsed.
+        self.single::<T>().ok()
+    }
+
+    fn within<T: FromStr + PartialOrd>(
+        &mut self,
+        range: RangeInclusive<T>,
+        usage: &str,
+    ) -> Result<T, Usage> {
+        self.required::<T>(usage)
+            .ok()
+            .filter(|x| range.contains(x))
+            .ok_or_else(|| Usage(usage.to_string()))
+    }
+
+    fn flag(&mut self, name: &str) -> bool {
+        let found = self.current().is_some_and(|x| x.eq_ignore_ascii_case(name));
+        if found {
+            self.advance();
+        }
+
+        found
+    }
+}
+
+/// `on` or `off`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Switch(pub bool);
+
+impl FromStr for Switch {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "on" => Ok(Self(true)),
+            "off" => Ok(Self(false)),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A volume in percent. Only plain numbers, `1e2` or `+5` are no volume.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Percent(pub f32);
+
+impl FromStr for Percent {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() || !s.chars().all(|x| x.is_ascii_digit() || x == '.') {
+            return Err(());
+        }
+
+        s.parse().map(Self).map_err(|_| ())
+    }
+}
+
+impl PartialOrd for Percent {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.0.partial_cmp(&other.0)
+    }
+}
+
+/// 1-based queue positions written `3-7`, or `3` for one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Span {
+    pub first: usize,
+    pub last: usize,
+}
+
+impl FromStr for Span {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (first, last) = s.split_once('-').unwrap_or((s, s));
+        let first = first.trim().parse().map_err(|_| ())?;
+        let last = last.trim().parse().map_err(|_| ())?;
+        if first < 1 || first > last {
+            return Err(());
+        }
+
+        Ok(Self { first, last })
+    }
+}
+
+#[test]
+fn test_args() {
+    use serenity::framework::standard::Delimiter;
+
+    let mut args = Args::new("sync 3-7 on 150", &[Delimiter::Single(' ')]);
+    assert!(!args.flag("live"));
+    assert!(args.flag("sync"));
+    assert_eq!(args.optional::<usize>(), None);
+    assert_eq!(
+        args.required::<Span>("~remove FROM-TO").unwrap(),
+        Span { first: 3, last: 7 }
+    );
+    assert_eq!(args.required::<Switch>("~x on|off").unwrap(), Switch(true));
+    assert_eq!(
+        args.within(Percent(0.0)..=Percent(100.0), "~vol [0~100]")
+            .unwrap_err()
+            .to_string(),
+        "Usage: ~vol [0~100]"
+    );
+
+    assert_eq!("7-3".parse::<Span>(), Err(()));
+    assert_eq!("0-3".parse::<Span>(), Err(()));
+    assert_eq!("1e2".parse::<Percent>(), Err(()));
+    assert_eq!("+5".parse::<Percent>(), Err(()));
+    assert_eq!("-5".parse::<Percent>(), Err(()));
+    assert_eq!("55.5".parse::<Percent>(), Ok(Percent(55.5)));
+}
@@ -0,0 +1,312 @@
+//! Keeps songs which are played again and again as files, so they play
+//! from disk instead of being fetched and transcoded every time. Off unless
+//! `AUDIO_CACHE_MAX_MB` is set. A song is saved when it starts for the
+//! second time, and the ones played longest ago go when the files take
+//! more than the limit.
+use std::{
+    collections::HashMap,
+    env,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use songbird::input::{
+    error::Result as InputResult, restartable::Restart, Codec, Container, Input, Metadata,
+    Restartable,
+};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::{
+    download,
+    ffmpeg::{self, FilterHandle, Pipeline},
+    local,
+    quality::Quality,
+    source, store,
+};
+
+const INDEX: &str = "audio_cache";
+/// Starts of a song before it is saved.
+const PLAYS_TO_SAVE: u32 = 2;
+/// Longer songs are more likely mixes or streams than favourites.
+const MAX_DURATION: Duration = Duration::from_secs(20 * 60);
+/// Songs remembered with their plays but not saved, so the index stays
+/// small.
+const UNSAVED_MAX: usize = 5000;
+const BITRATE: &str = "128k";
+
+lazy_static! {
+    static ref MAX_BYTES: Option<u64> = env::var("AUDIO_CACHE_MAX_MB")
+        .ok()
+        .and_then(|x| x.parse::<u64>().ok())
+        .filter(|x| *x > 0)
+        .map(|x| x * 1024 * 1024);
+    static ref SONGS: Mutex<Option<Songs>> = Mutex::new(None);
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct Song {
+    url: String,
+    title: Option<String>,
+    artist: Option<String>,
+    /// Seconds.
+    duration: Option<f64>,
+    thumbnail: Option<String>,
+    plays: u32,
+    /// Size of the file, 0 until it is saved.
+    bytes: u64,
+    /// Seconds since the epoch.
+    last_played: u64,
+    #[serde(skip)]
+    saving: bool,
+}
+
+impl Song {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            title: self.title.clone(),
+            artist: self.artist.clone(),
+            duration: self.duration.map(Duration::from_secs_f64),
+            thumbnail: self.thumbnail.clone(),
+            source_url: Some(self.url.clone()),
+            channels: Some(2),
+            sample_rate: Some(48000),
+            ..Default::default()
+        }
+    }
+}
+
+/// Songs by the hash of their URL.
+type Songs = HashMap<String, Song>;
+
+fn key(url: &str) -> String {
+    hex::encode(openssl::sha::sha256(url.as_bytes()))
+}
+
+fn file(key: &str) -> PathBuf {
+    store::dir(INDEX).join(format!("{}.ogg", key))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or_default()
+}
+
+/// Runs `f` on the index, loading it first, and saves it if `f` says so.
+async fn with_songs<T>(f: impl FnOnce(&mut Songs) -> (T, bool)) -> Result<T> {
+    let mut songs = SONGS.lock().await;
+    if songs.is_none() {
+        *songs = Some(store::load(INDEX).await?);
+    }
+    let songs = songs.as_mut().expect("loaded above");
+    let (value, changed) = f(songs);
+    if changed {
+        store::save(INDEX, songs).await?;
+    }
+
+    Ok(value)
+}
+
+/// Keys of the songs to forget so the saved ones fit in `max_bytes`, and
+/// no more than `UNSAVED_MAX` unsaved ones are remembered. The ones played
+/// longest ago go first.
+fn evict(songs: &Songs, max_bytes: u64) -> Vec<String> {
+    let mut by_age = songs.iter().collect::<Vec<_>>();
+    by_age.sort_by_key(|(_, x)| x.last_played);
+
+    let mut bytes = songs.values().map(|x| x.bytes).sum::<u64>();
+    let mut unsaved = songs.values().filter(|x| x.bytes == 0).count();
+    let mut evicted = vec![];
+    for (key, song) in by_age {
+        if song.bytes > 0 && bytes > max_bytes {
+            bytes -= song.bytes;
+            evicted.push(key.clone());
+        } else if song.bytes == 0 && unsaved > UNSAVED_MAX {
+            unsaved -= 1;
+            evicted.push(key.clone());
+        }
+    }
+
+    evicted
+}
+
+/// Plays a saved song from its file.
+struct Saved {
+    path: String,
+    metadata: Metadata,
+    filter: FilterHandle,
+}
+
+#[async_trait]
+impl Restart for Saved {
+    async fn call_restart(&mut self, time: Option<Duration>) -> InputResult<Input> {
+        let ffmpeg = Pipeline::new(&self.path, self.filter.get())
+            .seek(time)
+            .spawn()?;
+
+        Ok(ffmpeg::input(
+            vec![ffmpeg],
+            self.metadata.clone(),
+            self.filter.guild_id(),
+        )?)
+    }
+
+    async fn lazy_init(&mut self) -> InputResult<(Option<Metadata>, Codec, Container)> {
+        Ok((Some(self.metadata.clone()), Codec::FloatPcm, Container::Raw))
+    }
+}
+
+/// The song at `url` from its file, if it was saved.
+pub(crate) async fn open(url: &str, lazy: bool, filter: FilterHandle) -> Option<Restartable> {
+    MAX_BYTES.as_ref()?;
+    let key = key(url);
+    let path = file(&key);
+    let song = with_songs(|songs| {
+        let song = songs.get(&key).filter(|x| x.bytes > 0).cloned();
+        (song, false)
+    })
+    .await
+    .ok()??;
+    if !path.exists() {
+        return None;
+    }
+    let saved = Saved {
+        path: path.to_string_lossy().into_owned(),
+        metadata: song.metadata(),
+        filter,
+    };
+
+    Restartable::new(saved, lazy).await.ok()
+}
+
+async fn save(key: String, url: String) -> Result<()> {
+    let input: Input = source::open(&url, Quality::High, FilterHandle::default())
+        .await?
+        .into();
+    if !matches!(input.kind, Codec::FloatPcm) {
+        bail!("Can not save {:?} audio", input.kind);
+    }
+    let path = file(&key);
+    let tmp = path.with_extension("ogg.tmp");
+    tokio::fs::create_dir_all(store::dir(INDEX)).await?;
+    let tmp_arg = tmp.to_string_lossy().into_owned();
+    tokio::task::spawn_blocking(move || {
+        download::transcode(
+            input,
+            &[
+                "-c:a", "libopus", "-b:a", BITRATE, "-f", "ogg", "-y", &tmp_arg,
+            ],
+        )
+    })
+    .await??;
+    tokio::fs::rename(&tmp, &path).await?;
+    let bytes = tokio::fs::metadata(&path).await?.len();
+    info!("Saved {} to the audio cache", url);
+
+    let max_bytes = MAX_BYTES.unwrap_or_default();
+    let evicted = with_songs(|songs| {
+        let forgotten = match songs.get_mut(&key) {
+            Some(song) => {
+                song.bytes = bytes;
+                song.saving = false;
+                false
+            }
+            // Forgotten while it was saved.
+            None => true,
+        };
+        let mut evicted = evict(songs, max_bytes);
+        if forgotten {
+            evicted.push(key.clone());
+        }
+        for key in &evicted {
+            songs.remove(key);
+        }
+        (evicted, true)
+    })
+    .await?;
+    for key in evicted {
+        let _ = tokio::fs::remove_file(file(&key)).await;
+    }
+
+    Ok(())
+}
+
+/// Counts a start of the song, saving it in the background once it was
+/// played often enough.
+pub(crate) async fn played(metadata: &Metadata) {
+    if MAX_BYTES.is_none() {
+        return;
+    }
+    let url = match &metadata.source_url {
+        Some(url) => url.clone(),
+        None => return,
+    };
+    // Local files are on disk already, and streams have no length.
+    if local::is_local(&url) || metadata.duration.is_none_or(|x| x > MAX_DURATION) {
+        return;
+    }
+    let key = key(&url);
+    let due = with_songs(|songs| {
+        let song = songs.entry(key.clone()).or_insert_with(|| Song {
+            url: url.clone(),
+            title: metadata.title.clone(),
+            artist: metadata.artist.clone(),
+            duration: metadata.duration.map(|x| x.as_secs_f64()),
+            thumbnail: metadata.thumbnail.clone(),
+            ..Default::default()
+        });
+        song.plays += 1;
+        song.last_played = now();
+
+        let due = song.plays >= PLAYS_TO_SAVE && song.bytes == 0 && !song.saving;
+        song.saving |= due;
+
+        (due, true)
+    })
+    .await;
+
+    match due {
+        Ok(true) => {
+            tokio::spawn(async move {
+                if let Err(e) = save(key.clone(), url.clone()).await {
+                    warn!("Err saving {} to the audio cache: {:?}", url, e);
+                    // Tried again when it is played next.
+                    let _ = with_songs(|songs| {
+                        if let Some(song) = songs.get_mut(&key) {
+                            song.saving = false;
+                        }
+                        ((), false)
+                    })
+                    .await;
+                }
+            });
+        }
+        Ok(false) => {}
+        Err(e) => warn!("Err counting a play for the audio cache: {:?}", e),
+    }
+}
+
+#[test]
+fn test_evict() {
+    let song = |bytes, last_played| Song {
+        bytes,
+        last_played,
+        ..Default::default()
+    };
+    let songs = Songs::from([
+        ("old".to_string(), song(300, 1)),
+        ("older".to_string(), song(0, 0)),
+        ("recent".to_string(), song(300, 2)),
+        ("newest".to_string(), song(300, 3)),
+    ]);
+
+    assert!(evict(&songs, 1000).is_empty());
+    assert_eq!(evict(&songs, 700), ["old"]);
+    assert_eq!(evict(&songs, 300), ["old", "recent"]);
+}
@@ -0,0 +1,134 @@
+//! `~autoplay`: when the queue runs out, keeps the music going with songs
+//! like the last one, from the service it came from.
+use std::collections::HashSet;
+
+use anyhow::{anyhow, Result};
+use serenity::async_trait;
+use songbird::{
+    tracks::{PlayMode, TrackHandle},
+    Event, EventContext, EventHandler as VoiceEventHandler,
+};
+use tracing::warn;
+
+use crate::{
+    looping::LoopMode,
+    playback::GuildPlayer,
+    queue::{self, Requester},
+    source,
+};
+
+const AUTOPLAY_REQUESTER: &str = "Autoplay";
+/// Songs added each time the queue runs out.
+const AUTOPLAY_BATCH: usize = 3;
+/// Songs played this recently are not picked again.
+const RECENT_PLAYS: usize = 30;
+
+/// The first `n` of `related` which were not played lately.
+fn pick(related: Vec<String>, recent: &HashSet<String>, n: usize) -> Vec<String> {
+    related
+        .into_iter()
+        .filter(|x| !recent.contains(x))
+        .take(n)
+        .collect()
+}
+
+/// Queues songs like `last`, returns how many were added.
+async fn continue_from(player: &GuildPlayer, last: &TrackHandle, volume: f32) -> Result<usize> {
+    let url = last
+        .metadata()
+        .source_url
+        .clone()
+        .ok_or_else(|| anyhow!("Track has no source url"))?;
+    // Ask for more, some may have been played lately.
+    let related = source::provider(&url)
+        .related(&url, AUTOPLAY_BATCH + RECENT_PLAYS)
+        .await?;
+    let recent = {
+        let played = player.played.read().await;
+        played
+            .get(&player.guild_id)
+            .map(|x| {
+                x.iter()
+                    .rev()
+                    .take(RECENT_PLAYS)
+                    .map(|x| x.url.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    let quality = player.quality().await;
+    let filter = player.filter().await;
+    let mut added = 0;
+    for url in pick(related, &recent, AUTOPLAY_BATCH) {
+        let source = match source::restartable(&url, quality, filter.clone()).await {
+            Ok(source) => source,
+            Err(e) => {
+                warn!("Err starting autoplay song {}: {:?}", url, e);
+                continue;
+            }
+        };
+        let (track, first) = {
+            let mut handler = player.call.lock().await;
+            let first = handler.queue().is_empty();
+            (handler.enqueue_source(source.into()), first)
+        };
+        track.set_volume(volume)?;
+        queue::set_requester(&track, Requester::bot(AUTOPLAY_REQUESTER)).await;
+        player.attach(&track, first).await?;
+        added += 1;
+    }
+
+    Ok(added)
+}
+
+/// Continues the queue when its last track ends while autoplay is on.
+pub(crate) struct Continuer {
+    pub player: GuildPlayer,
+}
+
+#[async_trait]
+impl VoiceEventHandler for Continuer {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let (state, track) = match ctx {
+            // Skipped or cleared tracks are stopped, that's no drained queue.
+            EventContext::Track(&[(state, track)]) if state.playing == PlayMode::End => {
+                (state, track)
+            }
+            _ => return None,
+        };
+        // FM and looping keep the queue going on their own.
+        let on = self
+            .player
+            .state(|x| x.autoplay && !x.fm && x.loop_mode == LoopMode::Off)
+            .await;
+        let drained = self
+            .player
+            .call
+            .lock()
+            .await
+            .queue()
+            .current_queue()
+            .iter()
+            .all(|x| x.uuid() == track.uuid());
+        if !on || !drained {
+            return None;
+        }
+
+        match continue_from(&self.player, track, state.volume).await {
+            Ok(0) => warn!("Autoplay found nothing after {:?}", track.metadata().title),
+            Ok(_) => (),
+            Err(e) => warn!("Err continuing with autoplay: {:?}", e),
+        }
+
+        None
+    }
+}
+
+#[test]
+fn test_pick() {
+    let related = ["a", "b", "c", "d"].map(str::to_string).to_vec();
+    let recent = HashSet::from(["b".to_string()]);
+
+    assert_eq!(pick(related, &recent, 2), ["a", "c"]);
+}
@@ -0,0 +1,209 @@
+//! Traffic of every guild this month, for bots on metered hosts, shown by
+//! `~stats`. The audio goes through ffmpeg, so it is estimated from the
+//! time songs played: the stream fetched at its quality plus the voice sent
+//! to Discord. Guilds with a `bandwidthcap` in `~settings` get low quality
+//! streams once they are over it.
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serenity::{async_trait, client::Context, prelude::TypeMapKey};
+use songbird::{Event, EventContext, EventHandler as VoiceEventHandler};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use crate::{playback::GuildPlayer, profile, quality::Quality, source, store};
+
+const BANDWIDTH: &str = "bandwidth";
+
+/// Bits per second songbird encodes voice at.
+const VOICE_BITRATE: u64 = 128_000;
+
+/// Bytes in a GB, as hosts count them.
+pub(crate) const GB: u64 = 1_000_000_000;
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Usage {
+    /// Month counted, see `month`.
+    month: u32,
+    /// Bytes per source name.
+    pub sources: HashMap<String, u64>,
+}
+
+impl Usage {
+    fn add(&mut self, month: u32, source: &str, bytes: u64) {
+        if self.month != month {
+            *self = Usage {
+                month,
+                ..Default::default()
+            };
+        }
+        *self.sources.entry(source.to_string()).or_default() += bytes;
+    }
+
+    /// The usage as of `month`, empty once it is over.
+    pub(crate) fn in_month(self, month: u32) -> Self {
+        if self.month == month {
+            self
+        } else {
+            Usage {
+                month,
+                ..Default::default()
+            }
+        }
+    }
+
+    pub(crate) fn total(&self) -> u64 {
+        self.sources.values().sum()
+    }
+}
+
+/// Bytes fetched and sent for `played` of a song in `quality`.
+fn estimate(played: Duration, quality: Quality) -> u64 {
+    played.as_secs() * (quality.stream_bitrate() + VOICE_BITRATE) / 8
+}
+
+/// Months since the Unix epoch of `day`, counted like `profile::today`.
+pub(crate) fn month(day: u64) -> u32 {
+    let (year, month) = year_month(day);
+
+    (year - 1970) * 12 + month - 1
+}
+
+/// `2026-10` for `month`.
+pub(crate) fn month_name(month: u32) -> String {
+    format!("{}-{:02}", 1970 + month / 12, month % 12 + 1)
+}
+
+/// Year and month of a day since the Unix epoch, in the proleptic Gregorian
+/// calendar.
+fn year_month(day: u64) -> (u32, u32) {
+    // Counted in 400 year eras starting 0000-03-01, so leap days come last.
+    let z = day + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    (year as u32, month as u32)
+}
+
+/// `1.3 GB`, `450 MB`.
+pub(crate) fn format_bytes(bytes: u64) -> String {
+    if bytes >= GB {
+        format!("{:.1} GB", bytes as f64 / GB as f64)
+    } else {
+        format!("{} MB", bytes / 1_000_000)
+    }
+}
+
+type Bandwidth = HashMap<u64, Usage>;
+
+pub(crate) type BandwidthLock = Arc<RwLock<Bandwidth>>;
+
+pub(crate) struct GuildBandwidth;
+
+impl TypeMapKey for GuildBandwidth {
+    type Value = BandwidthLock;
+}
+
+pub(crate) async fn load() -> Result<Bandwidth> {
+    store::load(BANDWIDTH).await
+}
+
+pub(crate) async fn bandwidth_lock(ctx: &Context) -> BandwidthLock {
+    let read = ctx.data.read().await;
+
+    read.get::<GuildBandwidth>()
+        .expect("Expected GuildBandwidth in TypeMap.")
+        .clone()
+}
+
+/// The guild's usage this month.
+pub(crate) async fn usage(lock: &BandwidthLock, guild_id: u64) -> Usage {
+    let bandwidth = lock.read().await;
+
+    bandwidth
+        .get(&guild_id)
+        .cloned()
+        .unwrap_or_default()
+        .in_month(month(profile::today()))
+}
+
+/// Counts the traffic of a song when it ends.
+pub(crate) struct BandwidthCounter {
+    pub player: GuildPlayer,
+}
+
+#[async_trait]
+impl VoiceEventHandler for BandwidthCounter {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let (played, source) = match ctx {
+            EventContext::Track(&[(state, handle)]) if !state.play_time.is_zero() => {
+                let source = match &handle.metadata().source_url {
+                    Some(url) => source::provider(url).name(),
+                    None => "Other",
+                };
+                (state.play_time, source)
+            }
+            _ => return None,
+        };
+        let was_over = self.player.over_bandwidth_cap().await;
+        let bytes = estimate(played, self.player.quality().await);
+
+        {
+            let mut bandwidth = self.player.bandwidth.write().await;
+            bandwidth.entry(self.player.guild_id).or_default().add(
+                month(profile::today()),
+                source,
+                bytes,
+            );
+            if let Err(e) = store::save(BANDWIDTH, &*bandwidth).await {
+                warn!("Err saving bandwidth: {:?}", e);
+            }
+        }
+
+        if !was_over && self.player.over_bandwidth_cap().await {
+            info!(
+                "Guild {} is over its bandwidth cap, streams are low quality",
+                self.player.guild_id
+            );
+        }
+
+        None
+    }
+}
+
+#[test]
+fn test_month() {
+    assert_eq!(month_name(month(0)), "1970-01");
+    // 2000-02-29 and 2000-03-01
+    assert_eq!(month_name(month(11_016)), "2000-02");
+    assert_eq!(month_name(month(11_017)), "2000-03");
+    // 2026-10-17
+    assert_eq!(month_name(month(20_743)), "2026-10");
+    // 2026-12-31 and 2027-01-01
+    assert_eq!(month_name(month(20_818)), "2026-12");
+    assert_eq!(month_name(month(20_819)), "2027-01");
+}
+
+#[test]
+fn test_usage() {
+    let mut usage = Usage::default();
+    usage.add(5, "YouTube", 100);
+    usage.add(5, "Netease", 50);
+    usage.add(5, "YouTube", 1);
+    assert_eq!(usage.total(), 151);
+    assert_eq!(usage.clone().in_month(6).total(), 0);
+
+    usage.add(6, "Netease", 10);
+    assert_eq!(usage.total(), 10);
+    assert_eq!(
+        estimate(Duration::from_secs(60), Quality::Low),
+        60 * (Quality::Low.stream_bitrate() + VOICE_BITRATE) / 8
+    );
+}
@@ -0,0 +1,318 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use reqwest::{Client, Url};
+use serde::{de::DeserializeOwned, Deserialize};
+use songbird::input::{restartable::Restart, Codec, Container, Input, Metadata, Restartable};
+use tracing::{debug, info};
+
+use crate::{
+    ffmpeg::{self, FilterHandle, Pipeline},
+    seek_cache,
+};
+
+#[derive(Deserialize, Debug)]
+struct ApiResult<T> {
+    code: i64,
+    message: Option<String>,
+    data: Option<T>,
+}
+
+#[derive(Deserialize, Debug)]
+struct VideoView {
+    bvid: String,
+    title: String,
+    pic: Option<String>,
+    owner: Option<VideoOwner>,
+    #[serde(default)]
+    pages: Vec<VideoPage>,
+}
+
+#[derive(Deserialize, Debug)]
+struct VideoOwner {
+    name: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct VideoPage {
+    cid: u64,
+    page: u32,
+    part: Option<String>,
+    /// In seconds.
+    duration: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PlayUrl {
+    dash: Option<PlayUrlDash>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PlayUrlDash {
+    #[serde(default)]
+    audio: Vec<PlayUrlAudio>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PlayUrlAudio {
+    #[serde(rename(deserialize = "baseUrl"))]
+    base_url: String,
+    bandwidth: u64,
+}
+
+/// A video and which of its parts to play.
+#[derive(Debug, PartialEq, Eq)]
+struct VideoId {
+    /// `BV...`, or `av...` for old links.
+    id: String,
+    page: u32,
+}
+
+const USER_AGENT: &str = "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0 Safari/537.36";
+/// The audio CDN refuses requests coming from anywhere else.
+const REFERER: &str = "https://www.bilibili.com";
+const BASE_URL: &str = "https://api.bilibili.com/x";
+
+struct BilibiliClient {
+    client: Client,
+}
+
+impl BilibiliClient {
+    fn new() -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .user_agent(USER_AGENT)
+            .timeout(Duration::from_secs(10))
+            .build()?;
+
+        Ok(Self { client })
+    }
+
+    async fn get<T: DeserializeOwned>(&self, url: &str, query: &[(&str, &str)]) -> Result<T> {
+        let result = self
+            .client
+            .get(url)
+            .header("Referer", REFERER)
+            .query(query)
+            .send()
+            .await?
+            .json::<ApiResult<T>>()
+            .await?;
+        if result.code != 0 {
+            bail!(
+                "Bilibili api error {}: {}",
+                result.code,
+                result.message.unwrap_or_default()
+            );
+        }
+
+        result
+            .data
+            .ok_or_else(|| anyhow!("Bilibili api returned no data!"))
+    }
+
+    /// `b23.tv` short links redirect to the video page.
+    async fn resolve_short_link(&self, url: &str) -> Result<String> {
+        if !url.contains("b23.tv") {
+            return Ok(url.to_string());
+        }
+        let response = self.client.get(url).send().await?;
+
+        Ok(response.url().to_string())
+    }
+}
+
+fn get_video_id(url: &str) -> Result<VideoId> {
+    let url = Url::parse(url)?;
+    let id = url
+        .path_segments()
+        .and_then(|mut x| x.find(|x| x.starts_with("BV") || x.starts_with("av")))
+        .ok_or_else(|| anyhow!("Url is not right!"))?
+        .to_string();
+    let page = url
+        .query_pairs()
+        .find(|(k, _)| k == "p")
+        .and_then(|(_, v)| v.parse::<u32>().ok())
+        .unwrap_or(1);
+
+    Ok(VideoId { id, page })
+}
+
+fn video_url(bvid: &str, page: u32) -> String {
+    if page == 1 {
+        format!("https://www.bilibili.com/video/{}", bvid)
+    } else {
+        format!("https://www.bilibili.com/video/{}?p={}", bvid, page)
+    }
+}
+
+fn id_query(id: &str) -> (&'static str, &str) {
+    match id.strip_prefix("av") {
+        Some(aid) => ("aid", aid),
+        None => ("bvid", id),
+    }
+}
+
+async fn get_video_view(client: &BilibiliClient, id: &VideoId) -> Result<VideoView> {
+    let url = format!("{}/web-interface/view", BASE_URL);
+
+    client.get(&url, &[id_query(&id.id)]).await
+}
+
+fn metadata(view: &VideoView, page: &VideoPage) -> Metadata {
+    let title = match &page.part {
+        Some(part) if view.pages.len() > 1 => format!("{} - {}", view.title, part),
+        _ => view.title.to_owned(),
+    };
+
+    Metadata {
+        track: None,
+        artist: view.owner.as_ref().and_then(|x| x.name.clone()),
+        date: None,
+        channels: Some(2),
+        channel: None,
+        start_time: None,
+        duration: page.duration.map(Duration::from_secs),
+        sample_rate: Some(48000),
+        source_url: Some(video_url(&view.bvid, page.page)),
+        title: Some(title),
+        thumbnail: view.pic.to_owned(),
+    }
+}
+
+async fn get_video_metadata(
+    client: &BilibiliClient,
+    url: &str,
+) -> Result<(VideoView, u64, Metadata)> {
+    let url = client.resolve_short_link(url).await?;
+    let id = get_video_id(&url)?;
+    let view = get_video_view(client, &id).await?;
+    let page = view
+        .pages
+        .iter()
+        .find(|x| x.page == id.page)
+        .ok_or_else(|| anyhow!("Video has no part {}!", id.page))?;
+    let cid = page.cid;
+    let metadata = metadata(&view, page);
+    debug!("{:?}", metadata);
+
+    Ok((view, cid, metadata))
+}
+
+/// Picks the audio stream with the highest bandwidth.
+async fn get_audio_url(client: &BilibiliClient, bvid: &str, cid: u64) -> Result<String> {
+    let url = format!("{}/player/playurl", BASE_URL);
+    let cid = cid.to_string();
+    // fnval=16 asks for DASH, which has separate audio streams.
+    let play_url = client
+        .get::<PlayUrl>(&url, &[("bvid", bvid), ("cid", &cid), ("fnval", "16")])
+        .await?;
+
+    play_url
+        .dash
+        .and_then(|x| x.audio.into_iter().max_by_key(|x| x.bandwidth))
+        .map(|x| x.base_url)
+        .ok_or_else(|| anyhow!("Can not get audio url!"))
+}
+
+struct BilibiliRestarter {
+    url: String,
+    client: BilibiliClient,
+    filter: FilterHandle,
+}
+
+#[async_trait]
+impl Restart for BilibiliRestarter {
+    async fn call_restart(
+        &mut self,
+        time: Option<Duration>,
+    ) -> songbird::input::error::Result<Input> {
+        Ok(_bilibili(&self.url, time, &self.filter)
+            .await
+            .map_err(std::io::Error::other)?)
+    }
+
+    async fn lazy_init(
+        &mut self,
+    ) -> songbird::input::error::Result<(Option<Metadata>, Codec, Container)> {
+        let (_, _, metadata) = get_video_metadata(&self.client, &self.url)
+            .await
+            .map_err(std::io::Error::other)?;
+
+        Ok((Some(metadata), Codec::FloatPcm, Container::Raw))
+    }
+}
+
+pub(crate) async fn _bilibili_restartable(
+    url: &str,
+    lazy: bool,
+    filter: FilterHandle,
+) -> Result<Restartable> {
+    let client = BilibiliClient::new()?;
+    let restarter = BilibiliRestarter {
+        url: url.to_string(),
+        client,
+        filter: filter.clone(),
+    };
+
+    Ok(seek_cache::restartable(restarter, filter, lazy).await?)
+}
+
+async fn _bilibili(uri: &str, time: Option<Duration>, filter: &FilterHandle) -> Result<Input> {
+    let client = BilibiliClient::new()?;
+    let (view, cid, metadata) = get_video_metadata(&client, uri).await?;
+    let url = get_audio_url(&client, &view.bvid, cid).await?;
+    let headers = format!("Referer: {}\r\n", REFERER);
+    let ffmpeg_command = Pipeline::new(&url, filter.get())
+        .seek(time)
+        .input_args(&["-user_agent", USER_AGENT, "-headers", &headers])
+        .spawn()?;
+    info!("bilibili video metadata {:?}", metadata);
+
+    Ok(ffmpeg::input(
+        vec![ffmpeg_command],
+        metadata,
+        filter.guild_id(),
+    )?)
+}
+
+#[test]
+fn test_get_video_id() {
+    let id = get_video_id("https://www.bilibili.com/video/BV1GJ411x7h7?p=3&t=10").unwrap();
+    assert_eq!(
+        id,
+        VideoId {
+            id: "BV1GJ411x7h7".to_string(),
+            page: 3
+        }
+    );
+
+    let id = get_video_id("https://m.bilibili.com/video/av170001/").unwrap();
+    assert_eq!(id.id, "av170001");
+    assert_eq!(id.page, 1);
+    assert_eq!(id_query(&id.id), ("aid", "170001"));
+
+    assert!(get_video_id("https://www.bilibili.com/").is_err());
+}
+
+#[test]
+fn test_part_metadata() {
+    let view = serde_json::from_str::<ApiResult<VideoView>>(
+        r#"{"code":0,"message":"0","data":{"bvid":"BV1xx411c7mD","title":"Album",
+        "pic":"http://i0.hdslb.com/a.jpg","owner":{"name":"uploader"},
+        "pages":[{"cid":1,"page":1,"part":"Intro","duration":60},
+                 {"cid":2,"page":2,"part":"Song","duration":200}]}}"#,
+    )
+    .unwrap()
+    .data
+    .unwrap();
+    let metadata = metadata(&view, &view.pages[1]);
+
+    assert_eq!(metadata.title, Some("Album - Song".to_string()));
+    assert_eq!(metadata.artist, Some("uploader".to_string()));
+    assert_eq!(metadata.duration, Some(Duration::from_secs(200)));
+    assert_eq!(
+        metadata.source_url,
+        Some("https://www.bilibili.com/video/BV1xx411c7mD?p=2".to_string())
+    );
+}
@@ -0,0 +1,15 @@
+use songbird::input::Restartable;
+
+use self::bilibili::_bilibili_restartable;
+
+mod bilibili;
+use crate::ffmpeg::FilterHandle;
+use anyhow::Result;
+
+pub(crate) async fn bilibili_restartable(
+    url: &str,
+    lazy: bool,
+    filter: FilterHandle,
+) -> Result<Restartable> {
+    _bilibili_restartable(url, lazy, filter).await
+}
@@ -0,0 +1,104 @@
+//! `~bookmark`: songs saved with how far they had played, so long DJ
+//! programs and podcasts can be picked up again later, in any server.
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serenity::async_trait;
+use songbird::{Event, EventContext, EventHandler as VoiceEventHandler};
+use tracing::warn;
+
+use crate::{ffmpeg::FilterHandle, store};
+
+const BOOKMARKS: &str = "bookmarks";
+/// The oldest bookmark of a user goes once they have more.
+const BOOKMARKS_MAX: usize = 25;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Bookmark {
+    pub url: String,
+    pub title: String,
+    /// Into the song, not counting playback speed.
+    pub position: Duration,
+}
+
+/// Bookmarks by user id, oldest first.
+type Bookmarks = HashMap<u64, Vec<Bookmark>>;
+
+/// Adds `bookmark`, replacing one of the same song. Returns its number.
+fn push(bookmarks: &mut Vec<Bookmark>, bookmark: Bookmark) -> usize {
+    bookmarks.retain(|x| x.url != bookmark.url);
+    bookmarks.push(bookmark);
+    if bookmarks.len() > BOOKMARKS_MAX {
+        bookmarks.remove(0);
+    }
+
+    bookmarks.len()
+}
+
+/// Saves a bookmark of `user`, returns its number in `list`.
+pub(crate) async fn add(user: u64, bookmark: Bookmark) -> Result<usize> {
+    let mut bookmarks = store::load::<Bookmarks>(BOOKMARKS).await?;
+    let number = push(bookmarks.entry(user).or_default(), bookmark);
+    store::save(BOOKMARKS, &bookmarks).await?;
+
+    Ok(number)
+}
+
+pub(crate) async fn list(user: u64) -> Result<Vec<Bookmark>> {
+    let mut bookmarks = store::load::<Bookmarks>(BOOKMARKS).await?;
+
+    Ok(bookmarks.remove(&user).unwrap_or_default())
+}
+
+/// Bookmark `number` of `user`, counted from 1 like `~bookmarks` shows.
+pub(crate) async fn get(user: u64, number: usize) -> Result<Option<Bookmark>> {
+    let bookmarks = list(user).await?;
+
+    Ok(number
+        .checked_sub(1)
+        .and_then(|x| bookmarks.get(x))
+        .cloned())
+}
+
+/// Seeks a song to its bookmark once it starts playing.
+pub(crate) struct StartAt {
+    pub position: Duration,
+    pub filter: FilterHandle,
+}
+
+#[async_trait]
+impl VoiceEventHandler for StartAt {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if let EventContext::Track(&[(_, track)]) = ctx {
+            if let Err(e) = track.seek_time(self.filter.get().played(self.position)) {
+                warn!("Err seeking to bookmark: {:?}", e);
+            }
+        }
+
+        // Only on the first start, not after every pause.
+        Some(Event::Cancel)
+    }
+}
+
+#[test]
+fn test_push() {
+    let bookmark = |url: &str, secs| Bookmark {
+        url: url.to_string(),
+        title: url.to_string(),
+        position: Duration::from_secs(secs),
+    };
+    let mut bookmarks = vec![];
+
+    assert_eq!(push(&mut bookmarks, bookmark("a", 10)), 1);
+    assert_eq!(push(&mut bookmarks, bookmark("b", 20)), 2);
+    assert_eq!(push(&mut bookmarks, bookmark("a", 30)), 2);
+    assert_eq!(bookmarks[1].url, "a");
+    assert_eq!(bookmarks[1].position, Duration::from_secs(30));
+
+    for i in 0..BOOKMARKS_MAX {
+        push(&mut bookmarks, bookmark(&i.to_string(), 0));
+    }
+    assert_eq!(bookmarks.len(), BOOKMARKS_MAX);
+    assert_eq!(bookmarks[0].url, "0");
+}
@@ -0,0 +1,182 @@
+//! The command line: `bibicord run` starts the bot, the other subcommands
+//! are tools for whoever runs it and need no gateway connection.
+use std::{path::PathBuf, process::Command as StdCommand, time::Instant};
+
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+
+use crate::{downloader, ffmpeg, source, track_name};
+
+/// A Discord music bot.
+#[derive(Debug, PartialEq, Eq, Parser)]
+#[command(name = "bibicord")]
+pub(crate) struct Cli {
+    /// Read settings from PATH instead of config.toml
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// `bibicord --selftest` from before there were subcommands.
+    #[arg(long, hide = true)]
+    selftest: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, PartialEq, Eq, Subcommand)]
+pub(crate) enum Command {
+    /// Start the bot (the default)
+    Run {
+        /// Play test tones in SELFTEST_CHANNEL and exit
+        #[arg(long)]
+        selftest: bool,
+    },
+    /// Check that ffmpeg, ffprobe and yt-dlp can be run
+    CheckDeps,
+    /// Register the slash commands, in one server at once or globally
+    RegisterCommands {
+        /// Register them in the server with this id only
+        #[arg(long, value_name = "ID")]
+        guild: Option<u64>,
+        /// Remove the registered commands instead
+        #[arg(long)]
+        clear: bool,
+    },
+    /// Show what a source makes of URL, without Discord
+    Resolve { url: String },
+}
+
+impl Cli {
+    /// The subcommand to run, `run` when none was given.
+    pub(crate) fn command(self) -> Command {
+        self.command.unwrap_or(Command::Run {
+            selftest: self.selftest,
+        })
+    }
+}
+
+/// First line of `program --version`-like output, `None` if it won't run.
+fn version(program: &str, flag: &str) -> Option<String> {
+    let output = StdCommand::new(program).arg(flag).output().ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    output
+        .status
+        .success()
+        .then(|| stdout.lines().next().unwrap_or_default().trim().to_string())
+}
+
+/// Programs the bot needs which can't be found, after printing what was.
+pub(crate) fn missing_deps(verbose: bool) -> Vec<String> {
+    let mut missing = vec![];
+    for program in [ffmpeg::ffmpeg(), ffmpeg::ffprobe()] {
+        match which::which(program) {
+            Ok(path) if verbose => println!(
+                "{}: {} ({})",
+                program,
+                path.display(),
+                version(program, "-version").unwrap_or_default()
+            ),
+            Ok(_) => {}
+            Err(_) => missing.push(program.to_string()),
+        }
+    }
+    match downloader::detect() {
+        Some(program) if verbose => println!(
+            "downloader: {} ({})",
+            program,
+            version(program, "--version").unwrap_or_default()
+        ),
+        Some(_) => {}
+        None => missing.push("yt-dlp or youtube-dl (or YTDL_COMMAND)".to_string()),
+    }
+
+    missing
+}
+
+/// `bibicord check-deps`, returns the exit code.
+pub(crate) fn check_deps() -> i32 {
+    let missing = missing_deps(true);
+    for program in &missing {
+        eprintln!("Can not find {}", program);
+    }
+
+    i32::from(!missing.is_empty())
+}
+
+async fn try_resolve(url: &str) -> Result<()> {
+    let provider = source::provider(url);
+    println!("Source: {}", provider.name());
+    let started = Instant::now();
+
+    if provider.is_playlist(url) {
+        let urls = provider.resolve_playlist(url, 50).await?;
+        println!("Playlist of {} songs:", urls.len());
+        for url in urls {
+            println!("  {}", url);
+        }
+    } else {
+        let metadata = provider.metadata(url).await?;
+        println!("Title: {}", track_name(&metadata));
+        if let Some(duration) = metadata.duration {
+            println!("Duration: {}", crate::duration_formatter(&duration));
+        }
+        if let Some(source_url) = &metadata.source_url {
+            println!("URL: {}", source_url);
+        }
+        match provider.check_playable(url).await {
+            Ok(()) => println!("Playable: yes"),
+            Err(e) => println!("Playable: no, {}", e),
+        }
+    }
+    println!("Took {:.1?}", started.elapsed());
+
+    Ok(())
+}
+
+/// `bibicord resolve URL`, returns the exit code.
+pub(crate) async fn resolve(url: &str) -> i32 {
+    match try_resolve(url).await {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Err resolving {}: {:?}", url, e);
+            1
+        }
+    }
+}
+
+#[test]
+fn test_parse() {
+    let parse = |args: &[&str]| Cli::try_parse_from(["bibicord"].iter().chain(args));
+
+    assert_eq!(
+        parse(&[]).unwrap().command(),
+        Command::Run { selftest: false }
+    );
+    assert_eq!(
+        parse(&["--selftest"]).unwrap().command(),
+        Command::Run { selftest: true }
+    );
+    let cli = parse(&["run", "--config", "a.toml"]).unwrap();
+    assert_eq!(cli.config, Some("a.toml".into()));
+    assert_eq!(cli.command(), Command::Run { selftest: false });
+    let cli = parse(&["--config=b.toml", "register-commands", "--guild", "42"]).unwrap();
+    assert_eq!(cli.config, Some("b.toml".into()));
+    assert_eq!(
+        cli.command(),
+        Command::RegisterCommands {
+            guild: Some(42),
+            clear: false
+        }
+    );
+    assert_eq!(
+        parse(&["resolve", "https://example.com/a.mp3"])
+            .unwrap()
+            .command(),
+        Command::Resolve {
+            url: "https://example.com/a.mp3".to_string()
+        }
+    );
+    assert!(parse(&["resolve"]).is_err());
+    assert!(parse(&["register-commands", "--guild", "x"]).is_err());
+    assert!(parse(&["run", "--selftets"]).is_err());
+    assert!(parse(&["frobnicate"]).is_err());
+}
@@ -0,0 +1,281 @@
+//! `config.toml`, for running several bots without juggling environment
+//! variables. It is read from `--config PATH`, `BIBICORD_CONFIG` or
+//! `config.toml` in the working directory, and fills in the environment
+//! variables the bot reads, like `.env` does: a variable which is already
+//! set wins over the file.
+//!
+//! ```toml
+//! token = "..."
+//! prefix = "!"
+//!
+//! [bin]
+//! ffmpeg = "/opt/ffmpeg/bin/ffmpeg"
+//! ytdl = "yt-dlp"
+//!
+//! [netease]
+//! phone = "..."
+//! password = "..."
+//!
+//! [dirs]
+//! data = "/var/lib/bibicord/music-bot-1"
+//!
+//! [limits]
+//! attachment_max_mb = 50
+//! ```
+use std::{collections::HashMap, env, path::PathBuf};
+
+use anyhow::{bail, Context as _, Result};
+use serde::Deserialize;
+
+use crate::credentials;
+
+const DEFAULT_PATH: &str = "config.toml";
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Config {
+    token: Option<String>,
+    prefix: Option<String>,
+    shard_count: Option<u64>,
+    message_content: Option<bool>,
+    credentials_key: Option<String>,
+    /// Hours from UTC of the times given to `~alarm`.
+    alarm_utc_offset: Option<f64>,
+    bin: Bin,
+    netease: Netease,
+    dirs: Dirs,
+    cache: Cache,
+    limits: Limits,
+    web: Web,
+    log: Log,
+    report: Report,
+    /// Service credentials by their `~credential` name.
+    credentials: HashMap<String, String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Bin {
+    ffmpeg: Option<String>,
+    ffprobe: Option<String>,
+    ytdl: Option<String>,
+    /// `ffmpeg` to decode every song with ffmpeg.
+    decoder: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Netease {
+    phone: Option<String>,
+    password: Option<String>,
+    country_code: Option<String>,
+    cookie: Option<String>,
+    /// `eapi` to try the app API first.
+    api: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Dirs {
+    data: Option<String>,
+    local_music: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Cache {
+    /// `memory` to keep the playing song for seeking.
+    seek_cache: Option<String>,
+    seek_cache_max_minutes: Option<u64>,
+    max_messages: Option<u64>,
+    low_memory: Option<bool>,
+    audio_cache_max_mb: Option<u64>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Limits {
+    attachment_max_mb: Option<u64>,
+    allow_download: Option<bool>,
+    download_max_bytes: Option<u64>,
+    playlist_max: Option<u64>,
+    my_playlist_max: Option<u64>,
+    max_processes: Option<u64>,
+    resolve_workers: Option<u64>,
+    resolve_guild_workers: Option<u64>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Web {
+    metrics_addr: Option<String>,
+    api_addr: Option<String>,
+    dashboard_addr: Option<String>,
+    dashboard_url: Option<String>,
+    discord_client_id: Option<String>,
+    discord_client_secret: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Log {
+    /// `json` for a JSON object per line.
+    format: Option<String>,
+    /// Filter like `RUST_LOG`, e.g. `info,serenity=warn`.
+    level: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Report {
+    /// Discord webhook URL of the channel errors are posted to.
+    webhook: Option<String>,
+    sentry_dsn: Option<String>,
+}
+
+/// Environment variables set by a config value.
+struct Vars(Vec<(String, String)>);
+
+impl Vars {
+    fn set(&mut self, name: &str, value: &Option<impl ToString>) {
+        if let Some(value) = value {
+            self.0.push((name.to_string(), value.to_string()));
+        }
+    }
+
+    fn flag(&mut self, name: &str, value: Option<bool>) {
+        self.set(name, &value.map(|x| if x { "1" } else { "0" }));
+    }
+}
+
+impl Config {
+    fn vars(&self) -> Result<Vec<(String, String)>> {
+        let mut vars = Vars(vec![]);
+        vars.set("DISCORD_TOKEN", &self.token);
+        vars.set("PREFIX", &self.prefix);
+        vars.set("SHARD_COUNT", &self.shard_count);
+        vars.flag("MESSAGE_CONTENT", self.message_content);
+        vars.set("CREDENTIALS_KEY", &self.credentials_key);
+        vars.set("ALARM_UTC_OFFSET", &self.alarm_utc_offset);
+        vars.set("FFMPEG_PATH", &self.bin.ffmpeg);
+        vars.set("FFPROBE_PATH", &self.bin.ffprobe);
+        vars.set("YTDL_COMMAND", &self.bin.ytdl);
+        vars.set("DECODER", &self.bin.decoder);
+        vars.set("NETEASE_PHONE", &self.netease.phone);
+        vars.set("NETEASE_PASSWORD", &self.netease.password);
+        vars.set("NETEASE_COUNTRY_CODE", &self.netease.country_code);
+        vars.set("NETEASE_COOKIE", &self.netease.cookie);
+        vars.set("NETEASE_API", &self.netease.api);
+        vars.set("DATA_DIR", &self.dirs.data);
+        vars.set("LOCAL_MUSIC_DIR", &self.dirs.local_music);
+        vars.set("SEEK_CACHE", &self.cache.seek_cache);
+        vars.set("SEEK_CACHE_MAX_MINUTES", &self.cache.seek_cache_max_minutes);
+        vars.set("CACHE_MAX_MESSAGES", &self.cache.max_messages);
+        vars.flag("LOW_MEMORY", self.cache.low_memory);
+        vars.set("AUDIO_CACHE_MAX_MB", &self.cache.audio_cache_max_mb);
+        vars.set("ATTACHMENT_MAX_MB", &self.limits.attachment_max_mb);
+        vars.flag("ALLOW_DOWNLOAD", self.limits.allow_download);
+        vars.set("DOWNLOAD_MAX_BYTES", &self.limits.download_max_bytes);
+        vars.set("PLAYLIST_MAX", &self.limits.playlist_max);
+        vars.set("MY_PLAYLIST_MAX", &self.limits.my_playlist_max);
+        vars.set("MAX_PROCESSES", &self.limits.max_processes);
+        vars.set("RESOLVE_WORKERS", &self.limits.resolve_workers);
+        vars.set("RESOLVE_GUILD_WORKERS", &self.limits.resolve_guild_workers);
+        vars.set("METRICS_ADDR", &self.web.metrics_addr);
+        vars.set("API_ADDR", &self.web.api_addr);
+        vars.set("DASHBOARD_ADDR", &self.web.dashboard_addr);
+        vars.set("DASHBOARD_URL", &self.web.dashboard_url);
+        vars.set("DISCORD_CLIENT_ID", &self.web.discord_client_id);
+        vars.set("DISCORD_CLIENT_SECRET", &self.web.discord_client_secret);
+        vars.set("LOG_FORMAT", &self.log.format);
+        vars.set("LOG_LEVEL", &self.log.level);
+        vars.set("ERROR_WEBHOOK", &self.report.webhook);
+        vars.set("SENTRY_DSN", &self.report.sentry_dsn);
+
+        let mut credentials = self.credentials.iter().collect::<Vec<_>>();
+        credentials.sort();
+        for (name, value) in credentials {
+            if !credentials::ALL.iter().any(|x| x.name() == name) {
+                bail!("Unknown credential {}", name);
+            }
+            vars.set(&name.to_uppercase(), &Some(value));
+        }
+
+        Ok(vars.0)
+    }
+}
+
+/// Where the config file is, `None` when there is none to read.
+fn path(explicit: Option<PathBuf>) -> Option<PathBuf> {
+    if explicit.is_some() {
+        return explicit;
+    }
+    if let Ok(path) = env::var("BIBICORD_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    let default = PathBuf::from(DEFAULT_PATH);
+
+    default.exists().then_some(default)
+}
+
+/// Reads the config file, if there is one, into the environment. Returns
+/// the file read.
+pub(crate) fn load(explicit: Option<PathBuf>) -> Result<Option<PathBuf>> {
+    let path = match path(explicit) {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let text = std::fs::read_to_string(&path)
+        .with_context(|| format!("Can not read {}", path.display()))?;
+    let config =
+        toml::from_str::<Config>(&text).with_context(|| format!("Invalid {}", path.display()))?;
+    for (name, value) in config.vars()? {
+        if env::var_os(&name).is_none() {
+            env::set_var(name, value);
+        }
+    }
+
+    Ok(Some(path))
+}
+
+#[test]
+fn test_parse() {
+    let text = r#"
+# A bot
+token = "abc#def" # the token
+shard_count = 2
+alarm_utc_offset = 8.5
+
+[netease]
+phone = '138 0000'
+country_code = "86"
+
+[limits]
+attachment_max_mb = 1_000
+allow_download = true
+
+[credentials]
+spotify_client_id = "id!\n"
+"#;
+    let config = toml::from_str::<Config>(text).unwrap();
+    assert_eq!(config.limits.attachment_max_mb, Some(1000));
+    let vars = config.vars().unwrap();
+    let var = |name: &str| vars.iter().find(|x| x.0 == name).map(|x| x.1.as_str());
+    assert_eq!(var("DISCORD_TOKEN"), Some("abc#def"));
+    assert_eq!(var("SHARD_COUNT"), Some("2"));
+    assert_eq!(var("ALARM_UTC_OFFSET"), Some("8.5"));
+    assert_eq!(var("NETEASE_PHONE"), Some("138 0000"));
+    assert_eq!(var("ALLOW_DOWNLOAD"), Some("1"));
+    assert_eq!(var("SPOTIFY_CLIENT_ID"), Some("id!\n"));
+    assert_eq!(var("PREFIX"), None);
+}
+
+#[test]
+fn test_parse_errors() {
+    assert!(toml::from_str::<Config>("token = abc").is_err());
+    assert!(toml::from_str::<Config>("shard_count = \"2\"").is_err());
+    assert!(toml::from_str::<Config>("[bin]\nffmpg = \"x\"").is_err());
+
+    let config = toml::from_str::<Config>("[credentials]\nnope = \"x\"").unwrap();
+    assert!(config.vars().is_err());
+}
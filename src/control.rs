@@ -0,0 +1,216 @@
+//! The player of a guild as both the commands and the web dashboard drive
+//! it, so a skip or a volume change follows the same rules wherever it
+//! comes from.
+use std::sync::Arc;
+
+use anyhow::Result;
+use serde::Serialize;
+use serenity::{
+    client::Context,
+    model::id::{ChannelId, GuildId},
+    prelude::Mutex,
+};
+use songbird::{tracks::PlayMode, Call};
+
+use crate::{limiter, playback, queue, soft_mute, track_name, SongVolume};
+
+/// One entry of a guild's queue as the dashboard shows it.
+#[derive(Clone, PartialEq, Serialize)]
+pub(crate) struct Entry {
+    pub title: String,
+    pub url: Option<String>,
+    /// Seconds, missing for streams.
+    pub duration: Option<f64>,
+    pub requester: Option<String>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct Snapshot {
+    /// Seconds into the current song, not counting playback speed.
+    pub position: Option<f64>,
+    pub paused: bool,
+    /// Percent the current song plays at.
+    pub volume: Option<f32>,
+    pub entries: Vec<Entry>,
+}
+
+/// What plays in a guild, without the rest of the queue.
+#[derive(Clone, PartialEq, Serialize)]
+pub(crate) struct Player {
+    pub current: Option<Entry>,
+    pub position: Option<f64>,
+    pub paused: bool,
+    pub volume: Option<f32>,
+}
+
+impl Player {
+    /// Whether `other` plays something else or differently, not only
+    /// further into the same song.
+    pub(crate) fn changed(&self, other: &Player) -> bool {
+        self.current != other.current || self.paused != other.paused || self.volume != other.volume
+    }
+}
+
+impl From<Snapshot> for Player {
+    fn from(snapshot: Snapshot) -> Self {
+        Self {
+            current: snapshot.entries.into_iter().next(),
+            position: snapshot.position,
+            paused: snapshot.paused,
+            volume: snapshot.volume,
+        }
+    }
+}
+
+pub(crate) async fn call(ctx: &Context, guild_id: GuildId) -> Option<Arc<Mutex<Call>>> {
+    songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .get(guild_id)
+}
+
+/// The queue of the guild with how far its current song has played,
+/// `None` when the bot is not in a voice channel there.
+pub(crate) async fn snapshot(ctx: &Context, guild_id: GuildId) -> Option<Snapshot> {
+    let tracks = call(ctx, guild_id)
+        .await?
+        .lock()
+        .await
+        .queue()
+        .current_queue();
+    let info = match tracks.first() {
+        Some(current) => current.get_info().await.ok(),
+        None => None,
+    };
+    let speed = playback::effects(ctx, guild_id.0).await.speed;
+    let mut entries = vec![];
+    for track in &tracks {
+        let metadata = track.metadata();
+        entries.push(Entry {
+            title: track_name(metadata),
+            url: metadata.source_url.clone(),
+            duration: metadata.duration.map(|x| x.as_secs_f64()),
+            requester: queue::requester(track).await.map(|x| x.name),
+        });
+    }
+
+    Some(Snapshot {
+        position: info
+            .as_ref()
+            .map(|x| x.position.mul_f32(speed).as_secs_f64()),
+        paused: info.as_ref().is_some_and(|x| x.playing == PlayMode::Pause),
+        volume: info.map(|x| (x.volume * 100.0).round()),
+        entries,
+    })
+}
+
+/// Skips the current song, `false` when nothing plays.
+pub(crate) async fn skip(ctx: &Context, guild_id: GuildId) -> bool {
+    let handler_lock = match call(ctx, guild_id).await {
+        Some(handler_lock) => handler_lock,
+        None => return false,
+    };
+    let handler = handler_lock.lock().await;
+    if handler.queue().is_empty() {
+        return false;
+    }
+    crate::skip_current(ctx, guild_id.0, handler.queue()).await;
+
+    true
+}
+
+/// Pauses or resumes the current song, `false` when nothing plays.
+pub(crate) async fn pause(ctx: &Context, guild_id: GuildId, paused: bool) -> Result<bool> {
+    let handler_lock = match call(ctx, guild_id).await {
+        Some(handler_lock) => handler_lock,
+        None => return Ok(false),
+    };
+    let queue = handler_lock.lock().await.queue().clone();
+    if queue.is_empty() {
+        return Ok(false);
+    }
+    if paused {
+        queue.pause()?;
+    } else {
+        queue.resume()?;
+    }
+
+    Ok(true)
+}
+
+/// Moves the entry at `from` to `to`, both counted from 0 like the queue
+/// is stored, `false` if either isn't a waiting entry.
+pub(crate) async fn move_entry(ctx: &Context, guild_id: GuildId, from: usize, to: usize) -> bool {
+    match call(ctx, guild_id).await {
+        Some(handler_lock) => {
+            let handler = handler_lock.lock().await;
+            let pinned = queue::pinned(handler.queue()).await;
+            queue::move_to(handler.queue(), &pinned, from, to)
+        }
+        None => false,
+    }
+}
+
+/// Sets the volume of the queue to `requested`, kept under the guild's
+/// ceiling, and remembers it for songs queued from `channel`. Returns the
+/// volume set, `None` when the bot is not in a voice channel.
+pub(crate) async fn set_volume(
+    ctx: &Context,
+    guild_id: GuildId,
+    channel: Option<ChannelId>,
+    requested: f32,
+) -> Result<Option<f32>> {
+    let handler_lock = match call(ctx, guild_id).await {
+        Some(handler_lock) => handler_lock,
+        None => return Ok(None),
+    };
+    let ceiling = playback::volume_ceiling(ctx, guild_id.0).await;
+    let volume = limiter::cap(requested, ceiling);
+    if let Some(channel) = channel {
+        let song_volume_lock = {
+            let read = ctx.data.read().await;
+
+            read.get::<SongVolume>()
+                .expect("Expected SongVolume in TypeMap.")
+                .clone()
+        };
+        song_volume_lock.write().await.insert(channel.0, volume);
+    }
+    let tracks = handler_lock.lock().await.queue().current_queue();
+    let playback = playback::playback_lock(ctx).await;
+    soft_mute::set_volume(&playback, guild_id.0, &tracks, volume).await?;
+
+    Ok(Some(volume))
+}
+
+#[test]
+fn test_player_changed() {
+    let entry = |title: &str| Entry {
+        title: title.to_string(),
+        url: None,
+        duration: Some(200.0),
+        requester: None,
+    };
+    let player = Player {
+        current: Some(entry("A")),
+        position: Some(10.0),
+        paused: false,
+        volume: Some(100.0),
+    };
+
+    let later = Player {
+        position: Some(11.0),
+        ..player.clone()
+    };
+    assert!(!player.changed(&later));
+    let next = Player {
+        current: Some(entry("B")),
+        ..player.clone()
+    };
+    assert!(player.changed(&next));
+    let paused = Player {
+        paused: true,
+        ..player.clone()
+    };
+    assert!(player.changed(&paused));
+}
@@ -0,0 +1,206 @@
+//! Secrets of the services we talk to. Each one comes from its environment
+//! variable unless the owner set it at runtime with `~credential`, which
+//! stores it encrypted with `CREDENTIALS_KEY`.
+use std::{
+    collections::HashMap,
+    env, fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock,
+    },
+};
+
+use aes_gcm::{
+    aead::{Aead, KeyInit},
+    Aes256Gcm, Nonce,
+};
+use anyhow::{anyhow, bail, Result};
+use lazy_static::lazy_static;
+use openssl::{rand::rand_bytes, sha::sha256};
+
+use crate::store;
+
+const CREDENTIALS: &str = "credentials";
+/// AES-GCM's 96-bit nonce.
+const NONCE_LEN: usize = 12;
+
+lazy_static! {
+    /// Decrypted runtime values, by `Credential::name`.
+    static ref STORED: RwLock<HashMap<String, String>> = RwLock::new(HashMap::new());
+}
+
+/// Bumped on every change so cached clients and tokens know to rebuild.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Credential {
+    NeteaseCookie,
+    SpotifyClientId,
+    SpotifyClientSecret,
+    SoundCloudClientId,
+    AuddApiToken,
+}
+
+pub(crate) const ALL: &[Credential] = &[
+    Credential::NeteaseCookie,
+    Credential::SpotifyClientId,
+    Credential::SpotifyClientSecret,
+    Credential::SoundCloudClientId,
+    Credential::AuddApiToken,
+];
+
+impl Credential {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Credential::NeteaseCookie => "netease_cookie",
+            Credential::SpotifyClientId => "spotify_client_id",
+            Credential::SpotifyClientSecret => "spotify_client_secret",
+            Credential::SoundCloudClientId => "soundcloud_client_id",
+            Credential::AuddApiToken => "audd_api_token",
+        }
+    }
+
+    fn env_var(&self) -> String {
+        self.name().to_uppercase()
+    }
+}
+
+impl FromStr for Credential {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ALL.iter()
+            .find(|x| x.name() == s)
+            .copied()
+            .ok_or_else(|| anyhow!("Unknown credential {}", s))
+    }
+}
+
+impl fmt::Display for Credential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Where a credential's value comes from.
+pub(crate) enum Origin {
+    Stored,
+    Env,
+    Unset,
+}
+
+pub(crate) fn get(credential: Credential) -> Option<String> {
+    let stored = STORED.read().unwrap().get(credential.name()).cloned();
+
+    stored.or_else(|| env::var(credential.env_var()).ok())
+}
+
+pub(crate) fn origin(credential: Credential) -> Origin {
+    if STORED.read().unwrap().contains_key(credential.name()) {
+        Origin::Stored
+    } else if env::var(credential.env_var()).is_ok() {
+        Origin::Env
+    } else {
+        Origin::Unset
+    }
+}
+
+pub(crate) fn generation() -> u64 {
+    GENERATION.load(Ordering::SeqCst)
+}
+
+fn key() -> Result<[u8; 32]> {
+    match env::var("CREDENTIALS_KEY") {
+        Ok(key) if !key.is_empty() => Ok(sha256(key.as_bytes())),
+        _ => bail!("CREDENTIALS_KEY is not set, refusing to store secrets unencrypted"),
+    }
+}
+
+/// Encrypts `value` with AES-256-GCM, which also tells on opening whether
+/// it was tampered with.
+fn seal(key: &[u8; 32], value: &str) -> Result<String> {
+    let mut nonce = [0u8; NONCE_LEN];
+    rand_bytes(&mut nonce)?;
+    let mut sealed = nonce.to_vec();
+    sealed.extend(
+        Aes256Gcm::new(key.into())
+            .encrypt(Nonce::from_slice(&nonce), value.as_bytes())
+            .map_err(|_| anyhow!("Err sealing credential"))?,
+    );
+
+    Ok(base64::encode(sealed))
+}
+
+fn open(key: &[u8; 32], sealed: &str) -> Result<String> {
+    let sealed = base64::decode(sealed)?;
+    if sealed.len() <= NONCE_LEN {
+        bail!("Sealed credential is too short");
+    }
+    let (nonce, data) = sealed.split_at(NONCE_LEN);
+    let value = Aes256Gcm::new(key.into())
+        .decrypt(Nonce::from_slice(nonce), data)
+        .map_err(|_| {
+            anyhow!("Err opening credential, is CREDENTIALS_KEY the one it was sealed with?")
+        })?;
+
+    Ok(String::from_utf8(value)?)
+}
+
+/// Reads the stored credentials. Without `CREDENTIALS_KEY` only the
+/// environment is used.
+pub(crate) async fn load() -> Result<()> {
+    let sealed = store::load::<HashMap<String, String>>(CREDENTIALS).await?;
+    if sealed.is_empty() {
+        return Ok(());
+    }
+
+    let key = key()?;
+    let mut values = HashMap::new();
+    for (name, sealed) in sealed {
+        values.insert(name, open(&key, &sealed)?);
+    }
+    *STORED.write().unwrap() = values;
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+
+    Ok(())
+}
+
+/// Stores a value, or drops the stored one with `None` so the environment
+/// applies again.
+pub(crate) async fn set(credential: Credential, value: Option<String>) -> Result<()> {
+    let key = key()?;
+    let sealed = {
+        let mut stored = STORED.write().unwrap();
+        match value {
+            Some(value) => stored.insert(credential.name().to_string(), value),
+            None => stored.remove(credential.name()),
+        };
+
+        stored
+            .iter()
+            .map(|(name, value)| Ok((name.to_owned(), seal(&key, value)?)))
+            .collect::<Result<HashMap<_, _>>>()?
+    };
+    store::save(CREDENTIALS, &sealed).await?;
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+
+    Ok(())
+}
+
+#[test]
+fn test_seal() {
+    let key = sha256(b"secret");
+    let sealed = seal(&key, "MUSIC_U=abc").unwrap();
+
+    assert_ne!(sealed, seal(&key, "MUSIC_U=abc").unwrap());
+    assert_eq!(open(&key, &sealed).unwrap(), "MUSIC_U=abc");
+    assert!(open(&sha256(b"wrong"), &sealed).is_err());
+    let mut tampered = base64::decode(&sealed).unwrap();
+    tampered[NONCE_LEN] ^= 1;
+    assert!(open(&key, &base64::encode(tampered)).is_err());
+    assert_eq!(
+        "spotify_client_secret".parse::<Credential>().unwrap(),
+        Credential::SpotifyClientSecret
+    );
+}
@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+use songbird::tracks::{TrackHandle, TrackQueue, TrackResult};
+
+/// Number of volume changes used for a single fade.
+const FADE_STEPS: u32 = 10;
+
+pub(crate) async fn ramp_volume(
+    track: &TrackHandle,
+    from: f32,
+    to: f32,
+    duration: Duration,
+) -> TrackResult<()> {
+    let step = duration / FADE_STEPS;
+
+    for i in 1..=FADE_STEPS {
+        tokio::time::sleep(step).await;
+        track.set_volume(from + (to - from) * i as f32 / FADE_STEPS as f32)?;
+    }
+
+    Ok(())
+}
+
+/// Fades the current track out, advances the queue and fades the next track in.
+///
+/// A user asked for the skip, so both fades take half of the configured
+/// crossfade instead of the full length.
+pub(crate) async fn skip(queue: &TrackQueue, crossfade: Duration) -> TrackResult<()> {
+    let tracks = queue.current_queue();
+    let current = match tracks.first() {
+        Some(current) => current,
+        None => return Ok(()),
+    };
+    let fade = crossfade / 2;
+
+    let volume = current.get_info().await?.volume;
+    ramp_volume(current, volume, 0.0, fade).await?;
+
+    let next = match tracks.get(1) {
+        Some(next) => next,
+        None => return queue.skip(),
+    };
+    let target = next.get_info().await?.volume;
+    next.set_volume(0.0)?;
+
+    // The track may have run out on its own while fading.
+    if queue.current().map(|t| t.uuid()) == Some(current.uuid()) {
+        queue.skip()?;
+    }
+
+    ramp_volume(next, 0.0, target, fade).await
+}
@@ -0,0 +1,481 @@
+//! A web page where server admins see the live queue and skip, pause,
+//! reorder and set the volume, logged in with Discord. Served on
+//! `DASHBOARD_ADDR`, e.g. `0.0.0.0:8080`, and off unless it is set. Discord
+//! needs the app's `DISCORD_CLIENT_ID` and `DISCORD_CLIENT_SECRET`, and
+//! `DASHBOARD_URL`, where users reach the page, with `/callback` added as
+//! a redirect of the app.
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    env,
+    net::SocketAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use hyper::{
+    header,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use lazy_static::lazy_static;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use serenity::{
+    client::Context,
+    model::id::{GuildId, UserId},
+};
+use tracing::{info, warn};
+
+use crate::{control, dj, playback};
+
+const PAGE: &str = include_str!("../static/dashboard.html");
+const SESSION_COOKIE: &str = "bibicord_session";
+const SESSION_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// Time a user has to finish logging in on Discord.
+const LOGIN_TTL: Duration = Duration::from_secs(10 * 60);
+/// Largest request body the API reads.
+const MAX_BODY: u64 = 4096;
+
+struct OAuth {
+    client_id: String,
+    client_secret: String,
+    /// Where users reach the dashboard, without a trailing slash.
+    url: String,
+}
+
+impl OAuth {
+    fn from_env() -> Option<Self> {
+        Some(Self {
+            client_id: env::var("DISCORD_CLIENT_ID").ok()?,
+            client_secret: env::var("DISCORD_CLIENT_SECRET").ok()?,
+            url: env::var("DASHBOARD_URL")
+                .ok()?
+                .trim_end_matches('/')
+                .to_string(),
+        })
+    }
+
+    fn redirect_uri(&self) -> String {
+        format!("{}/callback", self.url)
+    }
+}
+
+lazy_static! {
+    static ref OAUTH: Option<OAuth> = OAuth::from_env();
+    /// Logged in users by session token.
+    static ref SESSIONS: Mutex<HashMap<String, (UserId, Instant)>> = Mutex::new(HashMap::new());
+    /// `state` of the logins under way, so a callback can't be forged.
+    static ref LOGINS: Mutex<HashMap<String, Instant>> = Mutex::new(HashMap::new());
+}
+
+fn token() -> String {
+    hex::encode(rand::random::<[u8; 32]>())
+}
+
+fn new_session(user: UserId) -> String {
+    let token = token();
+    let mut sessions = SESSIONS.lock().unwrap();
+    sessions.retain(|_, (_, expires)| *expires > Instant::now());
+    sessions.insert(token.clone(), (user, Instant::now() + SESSION_TTL));
+
+    token
+}
+
+/// Value of the cookie `name` in a `Cookie` header.
+fn cookie<'a>(header: &'a str, name: &str) -> Option<&'a str> {
+    header
+        .split(';')
+        .filter_map(|x| x.trim().split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v)
+}
+
+fn session_token(req: &Request<Body>) -> Option<String> {
+    let header = req.headers().get(header::COOKIE)?.to_str().ok()?;
+
+    cookie(header, SESSION_COOKIE).map(str::to_string)
+}
+
+/// The user logged in with the session of `req`.
+fn user(req: &Request<Body>) -> Option<UserId> {
+    let token = session_token(req)?;
+    let sessions = SESSIONS.lock().unwrap();
+
+    sessions
+        .get(&token)
+        .filter(|(_, expires)| *expires > Instant::now())
+        .map(|(user, _)| *user)
+}
+
+fn query(req: &Request<Body>, name: &str) -> Option<String> {
+    let url = Url::parse(&format!("http://localhost{}", req.uri())).ok()?;
+    let value = url.query_pairs().find(|(k, _)| k == name)?.1;
+
+    Some(value.into_owned())
+}
+
+pub(crate) fn respond(
+    status: StatusCode,
+    content_type: &str,
+    body: impl Into<Body>,
+) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, content_type)
+        .body(body.into())
+        .expect("valid response")
+}
+
+pub(crate) fn json_response(status: StatusCode, value: &impl Serialize) -> Response<Body> {
+    let body = serde_json::to_string(value).unwrap_or_default();
+
+    respond(status, "application/json", body)
+}
+
+pub(crate) fn error(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(status, &json!({ "error": message }))
+}
+
+fn redirect(to: &str, cookie: Option<String>) -> Response<Body> {
+    let mut builder = Response::builder()
+        .status(StatusCode::FOUND)
+        .header(header::LOCATION, to);
+    if let Some(cookie) = cookie {
+        builder = builder.header(header::SET_COOKIE, cookie);
+    }
+
+    builder.body(Body::empty()).expect("valid response")
+}
+
+fn session_cookie(oauth: &OAuth, token: &str, max_age: Duration) -> String {
+    let secure = if oauth.url.starts_with("https://") {
+        "; Secure"
+    } else {
+        ""
+    };
+
+    format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Lax; Max-Age={}{}",
+        SESSION_COOKIE,
+        token,
+        max_age.as_secs(),
+        secure
+    )
+}
+
+/// Sends the user to Discord to log in.
+fn login(oauth: &OAuth) -> Response<Body> {
+    let state = token();
+    {
+        let mut logins = LOGINS.lock().unwrap();
+        logins.retain(|_, expires| *expires > Instant::now());
+        logins.insert(state.clone(), Instant::now() + LOGIN_TTL);
+    }
+    let mut url = Url::parse("https://discord.com/oauth2/authorize").expect("valid url");
+    url.query_pairs_mut()
+        .append_pair("client_id", &oauth.client_id)
+        .append_pair("redirect_uri", &oauth.redirect_uri())
+        .append_pair("response_type", "code")
+        .append_pair("scope", "identify")
+        .append_pair("state", &state);
+
+    redirect(url.as_str(), None)
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+#[derive(Deserialize)]
+struct DiscordUser {
+    id: String,
+}
+
+/// The Discord user who logged in with `code`.
+async fn identify(oauth: &OAuth, code: &str) -> Result<UserId> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()?;
+    let token = client
+        .post("https://discord.com/api/oauth2/token")
+        .form(&[
+            ("client_id", oauth.client_id.as_str()),
+            ("client_secret", oauth.client_secret.as_str()),
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &oauth.redirect_uri()),
+        ])
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<TokenResponse>()
+        .await?;
+    let user = client
+        .get("https://discord.com/api/users/@me")
+        .bearer_auth(token.access_token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<DiscordUser>()
+        .await?;
+
+    Ok(UserId(user.id.parse()?))
+}
+
+/// Where Discord sends the user back to after logging in.
+async fn callback(oauth: &OAuth, req: &Request<Body>) -> Response<Body> {
+    let known = query(req, "state")
+        .and_then(|x| LOGINS.lock().unwrap().remove(&x))
+        .is_some_and(|expires| expires > Instant::now());
+    let code = match query(req, "code") {
+        Some(code) if known => code,
+        _ => return respond(StatusCode::BAD_REQUEST, "text/plain", "Login expired"),
+    };
+    match identify(oauth, &code).await {
+        Ok(user) => {
+            let token = new_session(user);
+            redirect("/", Some(session_cookie(oauth, &token, SESSION_TTL)))
+        }
+        Err(e) => {
+            warn!("Err logging in to the dashboard: {:?}", e);
+            respond(
+                StatusCode::BAD_GATEWAY,
+                "text/plain",
+                "Discord login failed",
+            )
+        }
+    }
+}
+
+fn logout(oauth: &OAuth, req: &Request<Body>) -> Response<Body> {
+    if let Some(token) = session_token(req) {
+        SESSIONS.lock().unwrap().remove(&token);
+    }
+
+    redirect("/", Some(session_cookie(oauth, "", Duration::ZERO)))
+}
+
+#[derive(Serialize)]
+struct GuildEntry {
+    /// A string, JavaScript numbers can't hold snowflakes.
+    id: String,
+    name: String,
+    connected: bool,
+}
+
+/// Guilds of the bot which `user` may manage.
+async fn guilds(ctx: &Context, user: UserId) -> Vec<GuildEntry> {
+    let mut guilds = vec![];
+    for guild_id in ctx.cache.guilds() {
+        if !dj::is_member_admin(ctx, guild_id, user).await {
+            continue;
+        }
+        guilds.push(GuildEntry {
+            id: guild_id.0.to_string(),
+            name: guild_id.name(&ctx.cache).unwrap_or_default(),
+            connected: control::call(ctx, guild_id).await.is_some(),
+        });
+    }
+    guilds.sort_by(|a, b| a.name.cmp(&b.name));
+
+    guilds
+}
+
+#[derive(Deserialize)]
+pub(crate) struct MoveBody {
+    /// Queue positions as the commands count, 1 being the current song.
+    pub(crate) from: usize,
+    pub(crate) to: usize,
+}
+
+#[derive(Deserialize)]
+struct VolumeBody {
+    /// Percent, 0 to 200 like `~vol`.
+    volume: f32,
+}
+
+pub(crate) async fn read_json<T: for<'de> Deserialize<'de>>(req: Request<Body>) -> Option<T> {
+    // Browsers only send JSON to another site after asking, so requiring
+    // it keeps other pages from posting with the session cookie.
+    let is_json = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|x| x.to_str().ok())
+        .is_some_and(|x| x.starts_with("application/json"));
+    let small = hyper::body::HttpBody::size_hint(req.body())
+        .upper()
+        .is_some_and(|x| x <= MAX_BODY);
+    if !is_json || !small {
+        return None;
+    }
+    let bytes = hyper::body::to_bytes(req.into_body()).await.ok()?;
+
+    serde_json::from_slice(&bytes).ok()
+}
+
+pub(crate) fn done(ok: bool) -> Response<Body> {
+    if ok {
+        json_response(StatusCode::OK, &json!({ "ok": true }))
+    } else {
+        error(StatusCode::CONFLICT, "Nothing to do that to")
+    }
+}
+
+/// Requests on `/api/guilds/{id}/...` of a guild `user` manages.
+async fn guild_api(
+    ctx: &Context,
+    req: Request<Body>,
+    guild_id: GuildId,
+    action: &str,
+) -> Result<Response<Body>> {
+    let response = match (req.method(), action) {
+        (&Method::GET, "queue") => match control::snapshot(ctx, guild_id).await {
+            Some(snapshot) => json_response(StatusCode::OK, &snapshot),
+            None => error(StatusCode::NOT_FOUND, "Not in a voice channel"),
+        },
+        (&Method::POST, "skip") => done(control::skip(ctx, guild_id).await),
+        (&Method::POST, "pause") => done(control::pause(ctx, guild_id, true).await?),
+        (&Method::POST, "resume") => done(control::pause(ctx, guild_id, false).await?),
+        (&Method::POST, "move") => match read_json::<MoveBody>(req).await {
+            Some(body) => {
+                let (from, to) = (body.from.saturating_sub(1), body.to.saturating_sub(1));
+                done(control::move_entry(ctx, guild_id, from, to).await)
+            }
+            None => error(StatusCode::BAD_REQUEST, "Expected {\"from\", \"to\"}"),
+        },
+        (&Method::POST, "volume") => match read_json::<VolumeBody>(req).await {
+            Some(body) if (0.0..=200.0).contains(&body.volume) => {
+                let channel = playback::text_channel(ctx, guild_id.0).await;
+                match control::set_volume(ctx, guild_id, channel, body.volume / 100.0).await? {
+                    Some(volume) => json_response(
+                        StatusCode::OK,
+                        &json!({ "volume": (volume * 100.0).round() }),
+                    ),
+                    None => error(StatusCode::NOT_FOUND, "Not in a voice channel"),
+                }
+            }
+            _ => error(
+                StatusCode::BAD_REQUEST,
+                "Expected {\"volume\"} from 0 to 200",
+            ),
+        },
+        _ => error(StatusCode::NOT_FOUND, "Not found"),
+    };
+
+    Ok(response)
+}
+
+async fn route(ctx: &Context, oauth: &OAuth, req: Request<Body>) -> Result<Response<Body>> {
+    let path = req.uri().path().to_string();
+    let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+    let response = match (req.method(), segments.as_slice()) {
+        (&Method::GET, [""]) => respond(StatusCode::OK, "text/html; charset=utf-8", PAGE),
+        (&Method::GET, ["login"]) => login(oauth),
+        (&Method::GET, ["callback"]) => callback(oauth, &req).await,
+        (&Method::GET, ["logout"]) => logout(oauth, &req),
+        (_, ["api", ..]) => {
+            let user = match user(&req) {
+                Some(user) => user,
+                None => return Ok(error(StatusCode::UNAUTHORIZED, "Not logged in")),
+            };
+            match segments.as_slice() {
+                ["api", "guilds"] => json_response(StatusCode::OK, &guilds(ctx, user).await),
+                ["api", "guilds", id, action] => {
+                    let guild_id = match id.parse::<u64>() {
+                        Ok(id) => GuildId(id),
+                        Err(_) => return Ok(error(StatusCode::NOT_FOUND, "Not found")),
+                    };
+                    if !dj::is_member_admin(ctx, guild_id, user).await {
+                        return Ok(error(StatusCode::FORBIDDEN, "Not an admin of this server"));
+                    }
+                    guild_api(ctx, req, guild_id, action).await?
+                }
+                _ => error(StatusCode::NOT_FOUND, "Not found"),
+            }
+        }
+        _ => respond(StatusCode::NOT_FOUND, "text/plain", "not found"),
+    };
+
+    Ok(response)
+}
+
+async fn handle(
+    ctx: Context,
+    oauth: &OAuth,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    match route(&ctx, oauth, req).await {
+        Ok(response) => Ok(response),
+        Err(e) => {
+            warn!("Err serving the dashboard: {:?}", e);
+            Ok(error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Something went wrong",
+            ))
+        }
+    }
+}
+
+/// Serves the dashboard until the bot stops, if `DASHBOARD_ADDR` is set.
+pub(crate) async fn run(ctx: Context) {
+    let addr = match env::var("DASHBOARD_ADDR").map(|x| x.parse::<SocketAddr>()) {
+        Ok(Ok(addr)) => addr,
+        Ok(Err(e)) => {
+            warn!("Err parsing DASHBOARD_ADDR: {:?}", e);
+            return;
+        }
+        Err(_) => return,
+    };
+    let oauth = match OAUTH.as_ref() {
+        Some(oauth) => oauth,
+        None => {
+            warn!("The dashboard needs DISCORD_CLIENT_ID, DISCORD_CLIENT_SECRET and DASHBOARD_URL");
+            return;
+        }
+    };
+
+    let make_service = make_service_fn(move |_| {
+        let ctx = ctx.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| handle(ctx.clone(), oauth, req))) }
+    });
+    let server = match Server::try_bind(&addr) {
+        Ok(server) => server.serve(make_service),
+        Err(e) => {
+            warn!("Err serving the dashboard on {}: {:?}", addr, e);
+            return;
+        }
+    };
+    info!("Serving the dashboard on {}", addr);
+    if let Err(e) = server.await {
+        warn!("Err serving the dashboard: {:?}", e);
+    }
+}
+
+#[test]
+fn test_cookie() {
+    let header = "theme=dark; bibicord_session=abc123;other=x";
+
+    assert_eq!(cookie(header, SESSION_COOKIE), Some("abc123"));
+    assert_eq!(cookie(header, "other"), Some("x"));
+    assert_eq!(cookie(header, "missing"), None);
+}
+
+#[test]
+fn test_sessions() {
+    let token = new_session(UserId(7));
+    let req = Request::builder()
+        .header(header::COOKIE, format!("{}={}", SESSION_COOKIE, token))
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(user(&req), Some(UserId(7)));
+
+    let forged = Request::builder()
+        .header(header::COOKIE, format!("{}=nope", SESSION_COOKIE))
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(user(&forged), None);
+}
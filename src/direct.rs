@@ -0,0 +1,269 @@
+//! Audio files and internet radio played straight from their URL, without
+//! the downloader: `.mp3`, `.flac`, `.ogg` and the like, HLS playlists and
+//! Icecast/SHOUTcast streams. ffprobe reads what the stream says about
+//! itself, streams without a length are live and play until skipped.
+use std::{collections::HashMap, path::Path, process::Stdio, time::Duration};
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use percent_encoding::percent_decode_str;
+use reqwest::Url;
+use serde::Deserialize;
+use songbird::input::{restartable::Restart, Codec, Container, Input, Metadata, Restartable};
+use tokio::process::Command;
+use tracing::info;
+
+use crate::{
+    decode,
+    error::BibiError,
+    ffmpeg::{self, FilterHandle, Pipeline},
+    seek_cache,
+};
+
+const EXTENSIONS: &[&str] = &["mp3", "flac", "ogg", "opus", "m4a", "aac", "wav", "m3u8"];
+/// ffprobe gives up on servers which don't answer.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(15);
+/// Radio servers drop listeners now and then, ffmpeg reconnects.
+const RECONNECT_ARGS: &[&str] = &[
+    "-reconnect",
+    "1",
+    "-reconnect_streamed",
+    "1",
+    "-reconnect_delay_max",
+    "5",
+];
+
+/// Whether `url` is an audio file or a radio stream rather than a page.
+pub(crate) fn is_direct(url: &str) -> bool {
+    let url = match Url::parse(url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => url,
+        _ => return false,
+    };
+    let path = url.path();
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|x| x.to_str())
+        .map(|x| x.to_ascii_lowercase());
+
+    // SHOUTcast serves the stream at `/;` so browsers don't get the page.
+    extension.is_some_and(|x| EXTENSIONS.contains(&x.as_str())) || path.ends_with("/;")
+}
+
+fn is_hls(url: &str) -> bool {
+    Url::parse(url).is_ok_and(|x| x.path().to_ascii_lowercase().ends_with(".m3u8"))
+}
+
+#[derive(Deserialize, Debug)]
+struct Probe {
+    format: ProbeFormat,
+}
+
+#[derive(Deserialize, Debug)]
+struct ProbeFormat {
+    /// Seconds, missing for live streams.
+    duration: Option<String>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+impl ProbeFormat {
+    /// Tags are upper case in some containers.
+    fn tag(&self, name: &str) -> Option<String> {
+        self.tags
+            .iter()
+            .find(|(k, v)| k.eq_ignore_ascii_case(name) && !v.trim().is_empty())
+            .map(|(_, v)| v.trim().to_string())
+    }
+}
+
+/// Name of the file at the end of `url`.
+fn file_name(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let name = url.path_segments()?.next_back()?;
+    let name = percent_decode_str(name).decode_utf8().ok()?;
+
+    Path::new(&*name)
+        .file_stem()
+        .and_then(|x| x.to_str())
+        .filter(|x| !x.is_empty() && *x != ";")
+        .map(str::to_string)
+}
+
+fn metadata(url: &str, format: &ProbeFormat) -> Metadata {
+    let duration = format
+        .duration
+        .as_deref()
+        .and_then(|x| x.parse::<f64>().ok())
+        .filter(|x| x.is_finite() && *x > 0.0)
+        .map(Duration::from_secs_f64);
+    // Radio stations name themselves in `icy-name`.
+    let title = format
+        .tag("title")
+        .or_else(|| format.tag("icy-name"))
+        .or_else(|| file_name(url))
+        .or_else(|| Url::parse(url).ok()?.host_str().map(str::to_string));
+
+    Metadata {
+        title,
+        artist: format.tag("artist"),
+        duration,
+        channels: Some(2),
+        sample_rate: Some(48000),
+        source_url: Some(url.to_string()),
+        ..Default::default()
+    }
+}
+
+/// What ffprobe finds in `input`, a URL or a path, with `url` as source.
+async fn probe(input: &str, url: &str) -> Result<Metadata> {
+    let output = Command::new(ffmpeg::ffprobe())
+        .args([
+            "-v",
+            "quiet",
+            "-print_format",
+            "json",
+            "-show_format",
+            input,
+        ])
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = match tokio::time::timeout(PROBE_TIMEOUT, output).await {
+        Ok(output) => output?,
+        Err(_) => bail!("ffprobe timed out on {}", url),
+    };
+    if !output.status.success() {
+        return Err(BibiError::UnsupportedUrl(url.to_string()).into());
+    }
+    let probe = serde_json::from_slice::<Probe>(&output.stdout)?;
+
+    Ok(metadata(url, &probe.format))
+}
+
+struct DirectRestarter {
+    /// What ffmpeg reads, `url` or the path of a local file.
+    input: String,
+    url: String,
+    filter: FilterHandle,
+    metadata: Option<Metadata>,
+}
+
+impl DirectRestarter {
+    async fn metadata(&mut self) -> Result<Metadata> {
+        if let Some(metadata) = &self.metadata {
+            return Ok(metadata.clone());
+        }
+        let metadata = probe(&self.input, &self.url).await?;
+        self.metadata = Some(metadata.clone());
+
+        Ok(metadata)
+    }
+
+    async fn input(&mut self, time: Option<Duration>) -> Result<Input> {
+        let metadata = self.metadata().await?;
+        // Live streams can only start where they are now.
+        let time = time.filter(|_| metadata.duration.is_some());
+        if !is_hls(&self.input) {
+            if let Some(input) = decode::input(&self.input, time, &metadata, &self.filter).await {
+                return Ok(input);
+            }
+        }
+        let reconnect = if self.input.starts_with("http") && !is_hls(&self.input) {
+            RECONNECT_ARGS
+        } else {
+            &[][..]
+        };
+        let child = Pipeline::new(&self.input, self.filter.get())
+            .input_args(reconnect)
+            .seek(time)
+            .spawn()
+            .map_err(BibiError::FfmpegSpawn)?;
+        info!("direct stream metadata {:?}", metadata);
+
+        Ok(ffmpeg::input(
+            vec![child],
+            metadata,
+            self.filter.guild_id(),
+        )?)
+    }
+}
+
+#[async_trait]
+impl Restart for DirectRestarter {
+    async fn call_restart(
+        &mut self,
+        time: Option<Duration>,
+    ) -> songbird::input::error::Result<Input> {
+        Ok(self.input(time).await.map_err(std::io::Error::other)?)
+    }
+
+    async fn lazy_init(
+        &mut self,
+    ) -> songbird::input::error::Result<(Option<Metadata>, Codec, Container)> {
+        let metadata = self.metadata().await.map_err(std::io::Error::other)?;
+
+        Ok((Some(metadata), Codec::FloatPcm, Container::Raw))
+    }
+}
+
+pub(crate) async fn restartable(
+    url: &str,
+    lazy: bool,
+    filter: FilterHandle,
+) -> Result<Restartable> {
+    open(url, url, lazy, filter).await
+}
+
+/// Plays `input` as the song at `url`, for sources ffmpeg reads as they are.
+pub(crate) async fn open(
+    input: &str,
+    url: &str,
+    lazy: bool,
+    filter: FilterHandle,
+) -> Result<Restartable> {
+    let restarter = DirectRestarter {
+        input: input.to_string(),
+        url: url.to_string(),
+        filter: filter.clone(),
+        metadata: None,
+    };
+
+    Ok(seek_cache::restartable(restarter, filter, lazy).await?)
+}
+
+#[test]
+fn test_is_direct() {
+    assert!(is_direct("https://example.com/music/song.mp3"));
+    assert!(is_direct("http://example.com/a.FLAC?token=1"));
+    assert!(is_direct("https://example.com/live/index.m3u8"));
+    assert!(is_direct("http://radio.example.com:8000/;"));
+    assert!(!is_direct("https://www.youtube.com/watch?v=x"));
+    assert!(!is_direct("https://example.com/song.mp3.html"));
+    assert!(!is_direct("ftp://example.com/song.mp3"));
+    assert!(is_hls("https://example.com/live/index.m3u8"));
+}
+
+#[test]
+fn test_metadata() {
+    let file = serde_json::from_str::<Probe>(
+        r#"{"format":{"duration":"241.5","tags":{"TITLE":"Song","ARTIST":"Band"}}}"#,
+    )
+    .unwrap();
+    let song = metadata("https://example.com/a.flac", &file.format);
+    assert_eq!(song.title.as_deref(), Some("Song"));
+    assert_eq!(song.artist.as_deref(), Some("Band"));
+    assert_eq!(song.duration, Some(Duration::from_secs_f64(241.5)));
+
+    let radio = serde_json::from_str::<Probe>(
+        r#"{"format":{"tags":{"icy-name":"Radio Bibi","icy-genre":"Pop"}}}"#,
+    )
+    .unwrap();
+    let song = metadata("http://radio.example.com:8000/;", &radio.format);
+    assert_eq!(song.title.as_deref(), Some("Radio Bibi"));
+    assert_eq!(song.duration, None);
+
+    let bare = serde_json::from_str::<Probe>(r#"{"format":{"duration":"N/A"}}"#).unwrap();
+    let song = metadata("https://example.com/music/my%20song.mp3", &bare.format);
+    assert_eq!(song.title.as_deref(), Some("my song"));
+    assert_eq!(song.duration, None);
+}
@@ -0,0 +1,112 @@
+//! Per-guild options of how songs are shown in messages.
+use std::{collections::HashMap, sync::Arc};
+
+use deunicode::deunicode;
+use serenity::{client::Context, prelude::TypeMapKey};
+use tokio::sync::RwLock;
+
+#[derive(Clone)]
+pub(crate) struct DisplayOptions {
+    /// Follow CJK titles and artists with their romanized form.
+    pub romanize: bool,
+    /// Some servers don't want to expose who requested a song, or where it
+    /// comes from.
+    pub show_requester: bool,
+    pub show_url: bool,
+    pub show_thumbnail: bool,
+}
+
+impl Default for DisplayOptions {
+    fn default() -> Self {
+        Self {
+            romanize: false,
+            show_requester: true,
+            show_url: true,
+            show_thumbnail: true,
+        }
+    }
+}
+
+impl DisplayOptions {
+    /// Switches an option by name, returns `false` if there's no such option.
+    pub(crate) fn set(&mut self, name: &str, value: bool) -> bool {
+        let option = match name {
+            "romanize" => &mut self.romanize,
+            "requester" => &mut self.show_requester,
+            "url" => &mut self.show_url,
+            "thumbnail" => &mut self.show_thumbnail,
+            _ => return false,
+        };
+        *option = value;
+
+        true
+    }
+}
+
+pub(crate) type DisplayLock = Arc<RwLock<HashMap<u64, DisplayOptions>>>;
+
+pub(crate) struct GuildDisplay;
+
+impl TypeMapKey for GuildDisplay {
+    type Value = DisplayLock;
+}
+
+pub(crate) async fn display_lock(ctx: &Context) -> DisplayLock {
+    let read = ctx.data.read().await;
+
+    read.get::<GuildDisplay>()
+        .expect("Expected GuildDisplay in TypeMap.")
+        .clone()
+}
+
+pub(crate) async fn display_options(ctx: &Context, guild_id: u64) -> DisplayOptions {
+    let lock = display_lock(ctx).await;
+    let display = lock.read().await;
+
+    display.get(&guild_id).cloned().unwrap_or_default()
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}' // Hiragana and Katakana
+        | '\u{3400}'..='\u{4dbf}' // CJK Unified Ideographs Extension A
+        | '\u{4e00}'..='\u{9fff}' // CJK Unified Ideographs
+        | '\u{ac00}'..='\u{d7af}' // Hangul Syllables
+    )
+}
+
+/// Pinyin of Chinese, romaji of kana and so on, `None` when `text` has
+/// nothing to romanize.
+pub(crate) fn romanize(text: &str) -> Option<String> {
+    if !text.chars().any(is_cjk) {
+        return None;
+    }
+
+    Some(
+        deunicode(text)
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+/// Formats `text` as configured by `options`.
+pub(crate) fn show(text: &str, options: &DisplayOptions) -> String {
+    match options.romanize.then(|| romanize(text)).flatten() {
+        Some(romanized) => format!("{} ({})", text, romanized),
+        None => text.to_string(),
+    }
+}
+
+#[test]
+fn test_romanize() {
+    assert_eq!(romanize("海阔天空").as_deref(), Some("Hai Kuo Tian Kong"));
+    assert_eq!(romanize("Never Gonna Give You Up"), None);
+
+    let options = DisplayOptions {
+        romanize: true,
+        ..Default::default()
+    };
+    assert_eq!(show("海阔天空", &options), "海阔天空 (Hai Kuo Tian Kong)");
+    assert_eq!(show("海阔天空", &DisplayOptions::default()), "海阔天空");
+}
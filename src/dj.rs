@@ -0,0 +1,69 @@
+use serenity::{
+    client::Context,
+    model::{
+        channel::Message,
+        id::{GuildId, UserId},
+    },
+};
+
+use crate::settings;
+
+/// Members with a role of this name may use DJ commands, unless the guild
+/// picked a DJ role in its settings.
+const DJ_ROLE_NAME: &str = "DJ";
+
+/// Whether the author of `msg` is allowed to manage the guild.
+pub(crate) async fn is_admin(ctx: &Context, msg: &Message) -> bool {
+    match msg.member(ctx).await {
+        Ok(member) => member
+            .permissions(&ctx.cache)
+            .map(|p| p.manage_guild())
+            .unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
+/// Whether `user` is allowed to manage the guild.
+pub(crate) async fn is_member_admin(ctx: &Context, guild_id: GuildId, user: UserId) -> bool {
+    match guild_id.member(ctx, user).await {
+        Ok(member) => member
+            .permissions(&ctx.cache)
+            .map(|p| p.manage_guild())
+            .unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
+/// Checks whether the author of `msg` is a DJ.
+pub(crate) async fn is_dj(ctx: &Context, msg: &Message) -> bool {
+    match msg.guild_id {
+        Some(guild_id) => is_member_dj(ctx, guild_id, msg.author.id).await,
+        None => false,
+    }
+}
+
+/// Checks whether `user` is a DJ of the guild: either they hold the DJ role
+/// or they are allowed to manage the guild.
+pub(crate) async fn is_member_dj(ctx: &Context, guild_id: GuildId, user: UserId) -> bool {
+    let member = match guild_id.member(ctx, user).await {
+        Ok(member) => member,
+        Err(_) => return false,
+    };
+
+    if member
+        .permissions(&ctx.cache)
+        .map(|p| p.manage_guild())
+        .unwrap_or(false)
+    {
+        return true;
+    }
+
+    match settings::get(ctx, guild_id.0).await.dj_role {
+        Some(dj_role) => member.roles.iter().any(|id| id.0 == dj_role),
+        None => member
+            .roles
+            .iter()
+            .filter_map(|id| ctx.cache.role(guild_id, *id))
+            .any(|role| role.name.eq_ignore_ascii_case(DJ_ROLE_NAME)),
+    }
+}
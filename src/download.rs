@@ -0,0 +1,124 @@
+//! Encodes the playing song into a file small enough to upload. Only on
+//! bots whose owner allows it with `ALLOW_DOWNLOAD`, as most sources don't
+//! license their songs for redistribution.
+use std::{
+    env,
+    io::{self, Write},
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use lazy_static::lazy_static;
+use songbird::input::{Codec, Input};
+
+use crate::{
+    ffmpeg::{self, FilterHandle},
+    quality::Quality,
+    source,
+};
+
+/// Discord's upload limit for servers without boosts.
+const DEFAULT_DOWNLOAD_MAX_BYTES: u64 = 10 * 1024 * 1024;
+const BITRATE: &str = "96k";
+
+lazy_static! {
+    static ref ALLOW_DOWNLOAD: bool = env::var("ALLOW_DOWNLOAD")
+        .map(|x| x == "1" || x == "true")
+        .unwrap_or(false);
+    static ref DOWNLOAD_MAX_BYTES: u64 = env::var("DOWNLOAD_MAX_BYTES")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(DEFAULT_DOWNLOAD_MAX_BYTES);
+}
+
+pub(crate) fn enabled() -> bool {
+    *ALLOW_DOWNLOAD
+}
+
+/// Would the song fit the upload limit at our bitrate?
+pub(crate) fn fits(duration: Duration) -> bool {
+    // 96 kbit/s is 12000 bytes a second.
+    duration.as_secs() * 12000 < *DOWNLOAD_MAX_BYTES
+}
+
+/// The song at `url` as Ogg Opus.
+pub(crate) async fn encode(url: String) -> Result<Vec<u8>> {
+    let input: Input = source::open(&url, Quality::default(), FilterHandle::default())
+        .await?
+        .into();
+    if !matches!(input.kind, Codec::FloatPcm) {
+        bail!("Can not encode {:?} audio!", input.kind);
+    }
+
+    tokio::task::spawn_blocking(move || encode_blocking(input)).await?
+}
+
+fn encode_blocking(input: Input) -> Result<Vec<u8>> {
+    let max_bytes = DOWNLOAD_MAX_BYTES.to_string();
+    let output = transcode(
+        input,
+        &[
+            "-c:a", "libopus", "-b:a", BITRATE, "-fs", &max_bytes, "-f", "ogg", "-",
+        ],
+    )?;
+    if output.len() as u64 >= *DOWNLOAD_MAX_BYTES {
+        bail!("Song is larger than the upload limit");
+    }
+
+    Ok(output)
+}
+
+/// Feeds the PCM of `input` to ffmpeg with the output options `args`,
+/// returning what ffmpeg wrote to stdout.
+pub(crate) fn transcode(mut input: Input, args: &[&str]) -> Result<Vec<u8>> {
+    let mut ffmpeg = Command::new(ffmpeg::ffmpeg())
+        .args(["-f", "f32le", "-ar", "48000", "-ac", "2", "-i", "-"])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    let mut stdin = ffmpeg.stdin.take().expect("ffmpeg stdin is piped");
+    let feeder = std::thread::spawn(move || {
+        // ffmpeg closing its input at the size limit is expected.
+        match io::copy(&mut input, &mut stdin).and_then(|_| stdin.flush()) {
+            Err(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(e),
+            _ => Ok(()),
+        }
+    });
+    let output = ffmpeg.wait_with_output()?;
+    feeder.join().expect("Feeder thread panicked")?;
+
+    if !output.status.success() {
+        bail!("ffmpeg failed: {}", output.status);
+    }
+
+    Ok(output.stdout)
+}
+
+/// A file name Discord and every OS accept.
+pub(crate) fn file_name(title: &str) -> String {
+    let name = title
+        .chars()
+        .map(|x| match x {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            x if x.is_control() => '_',
+            x => x,
+        })
+        .collect::<String>();
+    let name = name.trim();
+
+    format!("{}.ogg", if name.is_empty() { "song" } else { name })
+}
+
+#[test]
+fn test_file_name() {
+    assert_eq!(
+        file_name("AC/DC: Back in Black"),
+        "AC_DC_ Back in Black.ogg"
+    );
+    assert_eq!(file_name("  "), "song.ogg");
+    assert_eq!(file_name("今、歩き出す君へ。"), "今、歩き出す君へ。.ogg");
+}
@@ -0,0 +1,74 @@
+//! The youtube-dl compatible program behind YouTube and the other sites:
+//! `YTDL_COMMAND` from the environment, or else yt-dlp, or youtube-dl which
+//! has stopped keeping up with YouTube.
+use std::{env, process::Command as StdCommand};
+
+use lazy_static::lazy_static;
+use tokio::process::Command;
+
+use crate::quality::Quality;
+
+/// Looked for in order when `YTDL_COMMAND` is not set.
+const CANDIDATES: &[&str] = &["yt-dlp", "youtube-dl"];
+/// Options of every run, so no user config changes the output.
+const COMMON_ARGS: &[&str] = &["--ignore-config", "--no-warnings"];
+
+lazy_static! {
+    static ref PROGRAM: Option<String> = pick(env::var("YTDL_COMMAND").ok(), |x| {
+        which::which(x).is_ok()
+    });
+}
+
+fn pick(configured: Option<String>, installed: impl Fn(&str) -> bool) -> Option<String> {
+    match configured {
+        Some(configured) => installed(&configured).then_some(configured),
+        None => CANDIDATES
+            .iter()
+            .find(|x| installed(x))
+            .map(|x| x.to_string()),
+    }
+}
+
+/// The downloader to run, `None` when none is installed.
+pub(crate) fn detect() -> Option<&'static str> {
+    PROGRAM.as_deref()
+}
+
+/// Name of the downloader, for errors.
+pub(crate) fn name() -> &'static str {
+    detect().unwrap_or(CANDIDATES[0])
+}
+
+pub(crate) fn command() -> Command {
+    let mut command = Command::new(name());
+    command.args(COMMON_ARGS);
+
+    command
+}
+
+/// A blocking `command`, for sources which are read synchronously.
+pub(crate) fn std_command() -> StdCommand {
+    let mut command = StdCommand::new(name());
+    command.args(COMMON_ARGS);
+
+    command
+}
+
+/// Options to get a single song as an audio-only stream in `quality`.
+pub(crate) fn audio_args(quality: Quality) -> [&'static str; 3] {
+    ["-f", quality.ytdl_format(), "--no-playlist"]
+}
+
+#[test]
+fn test_pick() {
+    let installed = |x: &str| x == "youtube-dl" || x == "/opt/yt-dlp";
+
+    assert_eq!(pick(None, installed).as_deref(), Some("youtube-dl"));
+    assert_eq!(pick(None, |_| true).as_deref(), Some("yt-dlp"));
+    assert_eq!(
+        pick(Some("/opt/yt-dlp".to_string()), installed).as_deref(),
+        Some("/opt/yt-dlp")
+    );
+    assert_eq!(pick(Some("ytdl".to_string()), installed), None);
+    assert_eq!(pick(None, |_| false), None);
+}
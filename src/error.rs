@@ -0,0 +1,101 @@
+//! Why a song could not be played, in words users can act on. Sources
+//! return a `BibiError` inside `anyhow::Error`, commands find it in the
+//! chain of causes and reply with it.
+use std::fmt;
+
+use crate::neteaseapi::Restricted;
+
+#[derive(Debug)]
+pub(crate) enum BibiError {
+    /// A service could not be reached or answered with an error.
+    Network {
+        service: &'static str,
+        source: reqwest::Error,
+    },
+    /// A link the service has no song for.
+    UnsupportedUrl(String),
+    /// The service does not stream the song where the bot runs.
+    RegionLocked { service: &'static str },
+    /// The song needs more than the bot's Netease account has.
+    Restricted(Restricted),
+    /// ffmpeg could not be started.
+    FfmpegSpawn(std::io::Error),
+    /// The service answered without `what`, e.g. the song's URL.
+    MetadataMissing {
+        service: &'static str,
+        what: &'static str,
+    },
+    /// `feature` needs the bot to be logged in.
+    LoginRequired(&'static str),
+}
+
+impl BibiError {
+    pub(crate) fn network(service: &'static str, source: reqwest::Error) -> Self {
+        Self::Network { service, source }
+    }
+}
+
+impl fmt::Display for BibiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Network { service, .. } => {
+                write!(f, "Can not reach {}, try again later", service)
+            }
+            Self::UnsupportedUrl(url) => write!(f, "Can not find a song at <{}>", url),
+            Self::RegionLocked { service } => write!(
+                f,
+                "This {} song is not available where the bot runs",
+                service
+            ),
+            Self::Restricted(restricted) => restricted.fmt(f),
+            Self::FfmpegSpawn(_) => write!(
+                f,
+                "Can not start ffmpeg, the bot's owner needs to check it is installed"
+            ),
+            Self::MetadataMissing { service, what } => {
+                write!(f, "{} gave no {} for this song", service, what)
+            }
+            Self::LoginRequired(feature) => {
+                write!(f, "{} needs the bot to be logged in", feature)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BibiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Network { source, .. } => Some(source),
+            Self::FfmpegSpawn(source) => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<Restricted> for BibiError {
+    fn from(restricted: Restricted) -> Self {
+        match restricted {
+            Restricted::Unavailable => Self::RegionLocked { service: "Netease" },
+            restricted => Self::Restricted(restricted),
+        }
+    }
+}
+
+/// What to tell users about `e`, `None` when it has no known cause.
+pub(crate) fn user_message(e: &anyhow::Error) -> Option<String> {
+    e.chain()
+        .find_map(|x| x.downcast_ref::<BibiError>())
+        .map(ToString::to_string)
+}
+
+#[test]
+fn test_user_message() {
+    let e = anyhow::Error::from(BibiError::from(Restricted::Unavailable))
+        .context("No source could play the song");
+    assert_eq!(
+        user_message(&e).as_deref(),
+        Some("This Netease song is not available where the bot runs")
+    );
+
+    assert_eq!(user_message(&anyhow::anyhow!("Something else")), None);
+}
@@ -0,0 +1,64 @@
+//! `~eta`: when a queue entry will start, from the lengths of the songs
+//! ahead of it.
+use std::time::Duration;
+
+use crate::looping::LoopMode;
+
+/// When an entry starts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Eta {
+    /// After this long, `unknown` songs ahead without a known length come
+    /// on top.
+    In { time: Duration, unknown: usize },
+    /// The playing song repeats until it is skipped.
+    Looped,
+}
+
+/// When the entry after `ahead` starts. `remaining` is what is left of the
+/// playing song, `ahead` the lengths of the waiting songs before the entry,
+/// both at the playback speed.
+///
+/// Crossfade only fades skipped songs, songs which end on their own don't
+/// overlap and take their full length. Looping the queue puts finished songs
+/// behind the waiting ones, so only looping the track holds them back.
+pub(crate) fn eta(
+    remaining: Option<Duration>,
+    ahead: &[Option<Duration>],
+    loop_mode: LoopMode,
+) -> Eta {
+    if loop_mode == LoopMode::Track {
+        return Eta::Looped;
+    }
+
+    let mut time = Duration::ZERO;
+    let mut unknown = 0;
+    for duration in std::iter::once(remaining).chain(ahead.iter().copied()) {
+        match duration {
+            Some(duration) => time += duration,
+            None => unknown += 1,
+        }
+    }
+
+    Eta::In { time, unknown }
+}
+
+#[test]
+fn test_eta() {
+    let min = |x: u64| Some(Duration::from_secs(x * 60));
+
+    assert_eq!(
+        eta(min(1), &[min(3), None, min(4)], LoopMode::Queue),
+        Eta::In {
+            time: Duration::from_secs(8 * 60),
+            unknown: 1
+        }
+    );
+    assert_eq!(
+        eta(None, &[], LoopMode::Off),
+        Eta::In {
+            time: Duration::ZERO,
+            unknown: 1
+        }
+    );
+    assert_eq!(eta(min(1), &[min(3)], LoopMode::Track), Eta::Looped);
+}
@@ -0,0 +1,346 @@
+//! The ffmpeg every source is decoded by, and the audio filters a guild
+//! can put on its songs with `~filter` and `~speed`.
+use std::{
+    env, fmt, io,
+    process::{Child, Command, Stdio},
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use lazy_static::lazy_static;
+use songbird::input::{Codec, Container, Input, Metadata, Reader};
+
+use crate::{
+    limiter, metrics,
+    readahead::{self, Readahead},
+    supervisor,
+};
+
+lazy_static! {
+    static ref FFMPEG: String = env::var("FFMPEG_PATH").unwrap_or_else(|_| "ffmpeg".to_string());
+    static ref FFPROBE: String = env::var("FFPROBE_PATH").unwrap_or_else(|_| "ffprobe".to_string());
+}
+
+/// The ffmpeg to run, `FFMPEG_PATH` or the one in `PATH`.
+pub(crate) fn ffmpeg() -> &'static str {
+    &FFMPEG
+}
+
+/// The ffprobe to run, `FFPROBE_PATH` or the one in `PATH`.
+pub(crate) fn ffprobe() -> &'static str {
+    &FFPROBE
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum Filter {
+    #[default]
+    Off,
+    BassBoost,
+    Nightcore,
+    Vaporwave,
+    Karaoke,
+}
+
+impl Filter {
+    /// ffmpeg filter graph of the effect, before the limiter.
+    fn graph(&self) -> Option<&'static str> {
+        match self {
+            Filter::Off => None,
+            Filter::BassBoost => Some("bass=g=10:f=110:w=0.6"),
+            // Resampled first, `asetrate` speeds up whatever rate it gets.
+            Filter::Nightcore => Some("aresample=48000,asetrate=60000"),
+            Filter::Vaporwave => Some("aresample=48000,asetrate=38400"),
+            // Vocals are usually mixed to the center.
+            Filter::Karaoke => Some("stereotools=mlev=0.015625"),
+        }
+    }
+}
+
+pub(crate) const MIN_SPEED: f32 = 0.5;
+pub(crate) const MAX_SPEED: f32 = 2.0;
+
+/// `atempo` filters for `speed`, chained as one only goes from 0.5 to 2.
+fn atempo(speed: f32) -> Vec<String> {
+    let mut left = speed;
+    let mut filters = vec![];
+    while left > MAX_SPEED {
+        filters.push(format!("atempo={}", MAX_SPEED));
+        left /= MAX_SPEED;
+    }
+    while left < MIN_SPEED {
+        filters.push(format!("atempo={}", MIN_SPEED));
+        left /= MIN_SPEED;
+    }
+    if left != 1.0 {
+        filters.push(format!("atempo={}", left));
+    }
+
+    filters
+}
+
+/// Single pass EBU R128 normalization, so songs from every service sound
+/// about as loud.
+const LOUDNORM: &str = "loudnorm=I=-16:TP=-1.5:LRA=11";
+
+/// Everything a guild changed about how its songs sound.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Effects {
+    pub filter: Filter,
+    /// Playback speed, the pitch stays.
+    pub speed: f32,
+    pub loudnorm: bool,
+}
+
+impl Default for Effects {
+    fn default() -> Self {
+        Self {
+            filter: Filter::Off,
+            speed: 1.0,
+            loudnorm: false,
+        }
+    }
+}
+
+impl Effects {
+    /// Full `-af` argument: the effects, then the limiter so no effect can
+    /// get past it.
+    pub(crate) fn af(&self) -> String {
+        let mut filters = self
+            .loudnorm
+            .then(|| LOUDNORM.to_string())
+            .into_iter()
+            .chain(self.filter.graph().map(str::to_string))
+            .chain(atempo(self.speed))
+            .collect::<Vec<_>>();
+        filters.push(limiter::FILTER.to_string());
+
+        filters.join(",")
+    }
+
+    /// How long `duration` of a song takes to play.
+    pub(crate) fn played(&self, duration: Duration) -> Duration {
+        duration.div_f32(self.speed)
+    }
+}
+
+impl FromStr for Filter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Filter::Off),
+            "bassboost" => Ok(Filter::BassBoost),
+            "nightcore" => Ok(Filter::Nightcore),
+            "vaporwave" => Ok(Filter::Vaporwave),
+            "karaoke" => Ok(Filter::Karaoke),
+            _ => Err(anyhow!(
+                "Unknown filter: {}, one of bassboost, nightcore, vaporwave, karaoke, off",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Filter::Off => "off",
+            Filter::BassBoost => "bassboost",
+            Filter::Nightcore => "nightcore",
+            Filter::Vaporwave => "vaporwave",
+            Filter::Karaoke => "karaoke",
+        };
+
+        write!(f, "{}", s)
+    }
+}
+
+/// The effects of a guild, shared with its queued sources. They read them
+/// whenever they (re)start, so a change reaches songs already queued.
+#[derive(Clone, Default)]
+pub(crate) struct FilterHandle {
+    effects: Arc<RwLock<Effects>>,
+    /// Whose songs the sources play, `None` for downloads and the like.
+    guild_id: Option<u64>,
+}
+
+impl FilterHandle {
+    pub(crate) fn for_guild(mut self, guild_id: u64) -> Self {
+        self.guild_id = Some(guild_id);
+        self
+    }
+
+    pub(crate) fn guild_id(&self) -> Option<u64> {
+        self.guild_id
+    }
+
+    pub(crate) fn get(&self) -> Effects {
+        *self.effects.read().unwrap()
+    }
+
+    pub(crate) fn set_filter(&self, filter: Filter) {
+        self.effects.write().unwrap().filter = filter;
+    }
+
+    pub(crate) fn set_speed(&self, speed: f32) {
+        self.effects.write().unwrap().speed = speed;
+    }
+
+    pub(crate) fn set_loudnorm(&self, loudnorm: bool) {
+        self.effects.write().unwrap().loudnorm = loudnorm;
+    }
+}
+
+/// ffmpeg turning `input` into the raw PCM songbird plays.
+pub(crate) struct Pipeline<'a> {
+    input: &'a str,
+    input_args: Vec<&'a str>,
+    seek: Duration,
+    effects: Effects,
+}
+
+impl<'a> Pipeline<'a> {
+    /// `input` is a URL, or `-` for stdin.
+    pub(crate) fn new(input: &'a str, effects: Effects) -> Self {
+        Self {
+            input,
+            input_args: vec![],
+            seek: Duration::ZERO,
+            effects,
+        }
+    }
+
+    /// Starts `time` into the song as played, at the pipeline's speed.
+    pub(crate) fn seek(mut self, time: Option<Duration>) -> Self {
+        self.seek = time.unwrap_or_default().mul_f32(self.effects.speed);
+        self
+    }
+
+    /// Options for reading the input, like headers the service wants.
+    pub(crate) fn input_args(mut self, args: &[&'a str]) -> Self {
+        self.input_args.extend_from_slice(args);
+        self
+    }
+
+    fn args(&self) -> Vec<String> {
+        let mut args = vec!["-ss".to_string(), format!("{:.3}", self.seek.as_secs_f64())];
+        args.extend(self.input_args.iter().map(|x| x.to_string()));
+        args.extend(["-i".to_string(), self.input.to_string()]);
+        args.extend(["-af".to_string(), self.effects.af()]);
+        args.extend(
+            [
+                "-acodec",
+                "pcm_f32le",
+                "-ac",
+                "2",
+                "-ar",
+                "48000",
+                "-f",
+                "s16le",
+                "-",
+            ]
+            .map(str::to_string),
+        );
+
+        args
+    }
+
+    pub(crate) fn command(&self) -> Command {
+        let mut command = Command::new(ffmpeg());
+        command.args(self.args()).stdout(Stdio::piped());
+
+        command
+    }
+
+    /// Starts ffmpeg on a URL input.
+    pub(crate) fn spawn(&self) -> std::io::Result<Child> {
+        self.command()
+            .stdin(Stdio::null())
+            .spawn()
+            .inspect_err(|_| metrics::ffmpeg_spawn_failed())
+    }
+}
+
+/// Songbird input reading the last of `children`, an ffmpeg pipeline
+/// playing a song of `guild_id`, a few seconds ahead unless `LOW_MEMORY` is
+/// set. The children are killed with the input.
+pub(crate) fn input(
+    children: Vec<Child>,
+    metadata: Metadata,
+    guild_id: Option<u64>,
+) -> io::Result<Input> {
+    let (stdout, guard) = supervisor::supervise(children, guild_id)?;
+    let readahead = Readahead::new(stdout, readahead::ahead(), Some(guard));
+
+    Ok(Input::new(
+        true,
+        Reader::Extension(Box::new(readahead)),
+        Codec::FloatPcm,
+        Container::Raw,
+        Some(metadata),
+    ))
+}
+
+#[test]
+fn test_pipeline_args() {
+    let effects = Effects {
+        filter: Filter::Nightcore,
+        ..Default::default()
+    };
+    let args = Pipeline::new("https://a/b.mp3", effects)
+        .seek(Some(Duration::from_millis(1500)))
+        .input_args(&["-user_agent", "x"])
+        .args();
+
+    assert_eq!(
+        args[..6],
+        ["-ss", "1.500", "-user_agent", "x", "-i", "https://a/b.mp3"]
+    );
+    assert_eq!(
+        args[7],
+        format!("aresample=48000,asetrate=60000,{}", limiter::FILTER)
+    );
+    assert_eq!(args.last().map(String::as_str), Some("-"));
+    assert_eq!(
+        Pipeline::new("-", Effects::default()).args()[5],
+        limiter::FILTER
+    );
+    assert_eq!("karaoke".parse::<Filter>().unwrap(), Filter::Karaoke);
+    assert!("echo".parse::<Filter>().is_err());
+}
+
+#[test]
+fn test_speed() {
+    assert!(atempo(1.0).is_empty());
+    assert_eq!(atempo(1.5), vec!["atempo=1.5"]);
+    assert_eq!(atempo(3.0), vec!["atempo=2", "atempo=1.5"]);
+    assert_eq!(atempo(0.25), vec!["atempo=0.5", "atempo=0.5"]);
+
+    let effects = Effects {
+        speed: 2.0,
+        ..Default::default()
+    };
+    assert_eq!(
+        effects.played(Duration::from_secs(60)),
+        Duration::from_secs(30)
+    );
+    let args = Pipeline::new("-", effects)
+        .seek(Some(Duration::from_secs(10)))
+        .args();
+    assert_eq!(args[1], "20.000");
+    assert_eq!(args[5], format!("atempo=2,{}", limiter::FILTER));
+}
+
+#[test]
+fn test_loudnorm() {
+    let effects = Effects {
+        filter: Filter::BassBoost,
+        loudnorm: true,
+        ..Default::default()
+    };
+
+    assert!(effects.af().starts_with("loudnorm="));
+    assert!(effects.af().ends_with(limiter::FILTER));
+}
@@ -0,0 +1,182 @@
+//! Endless radio from Netease's personal FM: whenever the queue runs low,
+//! the next songs of the FM are added to it. Songs and artists the guild
+//! requested often are more likely to be picked.
+use std::{collections::HashSet, sync::Mutex};
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+use rand::{distributions::WeightedIndex, prelude::Distribution, Rng};
+use serenity::async_trait;
+use songbird::{input::Input, Event, EventContext, EventHandler as VoiceEventHandler};
+use tracing::warn;
+
+use crate::{
+    hibernate,
+    history::{self, Favorites},
+    neteaseapi,
+    playback::GuildPlayer,
+    queue::{self, Requester},
+    source,
+};
+
+/// Refill once fewer songs than this are left, including the playing one.
+const FM_MIN_QUEUE: usize = 3;
+const FM_REQUESTER: &str = "Netease FM";
+const FAVORITES_REQUESTER: &str = "Server favorites";
+/// Most requested songs of the guild which may be picked on a refill.
+const FAVORITES_POOL: usize = 5;
+/// Weight of an FM song by an artist the guild never requested. A
+/// favorite requested this often is as likely to be picked.
+const FM_WEIGHT: u32 = 4;
+
+lazy_static! {
+    /// Guilds with a refill in flight, so ending tracks don't stack them.
+    static ref REFILLING: Mutex<HashSet<u64>> = Mutex::new(HashSet::new());
+}
+
+/// A song a refill may add.
+enum Candidate {
+    Fm(Input),
+    Favorite(String),
+}
+
+impl Candidate {
+    fn weight(&self, favorites: &Favorites, requests: u32) -> u32 {
+        match self {
+            Candidate::Fm(input) => {
+                let artist = input.metadata.artist.as_deref().unwrap_or_default();
+                FM_WEIGHT + favorites.artist_requests(artist)
+            }
+            Candidate::Favorite(_) => requests,
+        }
+    }
+}
+
+/// Draws up to `n` indexes without repeats, each as likely as its weight.
+fn draw(weights: &[u32], n: usize, rng: &mut impl Rng) -> Vec<usize> {
+    let mut weights = weights.to_vec();
+    let mut drawn = vec![];
+    while drawn.len() < n {
+        // Fails once every weight is zero.
+        let i = match WeightedIndex::new(&weights) {
+            Ok(index) => index.sample(rng),
+            Err(_) => break,
+        };
+        weights[i] = 0;
+        drawn.push(i);
+    }
+
+    drawn
+}
+
+/// Tops the queue up with FM songs, returns how many were added.
+pub(crate) async fn refill(player: &GuildPlayer, volume: f32) -> Result<usize> {
+    if player.call.lock().await.queue().len() >= FM_MIN_QUEUE
+        || !REFILLING.lock().unwrap().insert(player.guild_id)
+    {
+        return Ok(0);
+    }
+    let result = enqueue_next(player, volume).await;
+    REFILLING.lock().unwrap().remove(&player.guild_id);
+
+    result
+}
+
+async fn enqueue_next(player: &GuildPlayer, volume: f32) -> Result<usize> {
+    let quality = player.quality().await;
+    let filter = player.filter().await;
+    let favorites = history::favorites(&player.history, player.guild_id).await;
+    let queued = player
+        .call
+        .lock()
+        .await
+        .queue()
+        .current_queue()
+        .iter()
+        .filter_map(|x| x.metadata().source_url.clone())
+        .collect::<HashSet<_>>();
+
+    let mut candidates = vec![];
+    for url in neteaseapi::netease_fm().await? {
+        match source::restartable(&url, quality, filter.clone()).await {
+            Ok(source) => candidates.push((Candidate::Fm(source.into()), 0)),
+            Err(e) => warn!("Err starting FM song {}: {:?}", url, e),
+        }
+    }
+    // A refill is as long as the FM's batch, favorites take some places.
+    let batch = candidates.len();
+    candidates.extend(
+        favorites
+            .songs
+            .iter()
+            .filter(|x| !queued.contains(&x.0))
+            .take(FAVORITES_POOL)
+            .map(|(url, n)| (Candidate::Favorite(url.clone()), *n)),
+    );
+    let weights = candidates
+        .iter()
+        .map(|(x, n)| x.weight(&favorites, *n))
+        .collect::<Vec<_>>();
+    let drawn = draw(&weights, batch, &mut rand::thread_rng());
+
+    let mut candidates = candidates.into_iter().map(Some).collect::<Vec<_>>();
+    let mut added = 0;
+    for i in drawn {
+        let (input, name) = match candidates[i].take().map(|x| x.0) {
+            Some(Candidate::Fm(input)) => (input, FM_REQUESTER),
+            Some(Candidate::Favorite(url)) => {
+                match source::restartable(&url, quality, filter.clone()).await {
+                    Ok(source) => (source.into(), FAVORITES_REQUESTER),
+                    Err(e) => {
+                        warn!("Err starting favorite song {}: {:?}", url, e);
+                        continue;
+                    }
+                }
+            }
+            None => continue,
+        };
+        let (track, first) = {
+            let mut handler = player.call.lock().await;
+            let first = handler.queue().is_empty();
+            (handler.enqueue_source(input), first)
+        };
+        track.set_volume(volume)?;
+        queue::set_requester(&track, Requester::bot(name)).await;
+        player.attach(&track, first).await?;
+        added += 1;
+    }
+
+    Ok(added)
+}
+
+/// Refills the queue as tracks end while the FM is on.
+pub(crate) struct Refiller {
+    pub player: GuildPlayer,
+}
+
+#[async_trait]
+impl VoiceEventHandler for Refiller {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if let EventContext::Track(&[(state, _)]) = ctx {
+            // Songs end on hibernating, the queue comes back on waking up.
+            if !hibernate::is_hibernating() && self.player.state(|x| x.fm).await {
+                if let Err(e) = refill(&self.player, state.volume).await {
+                    warn!("Err refilling FM: {:?}", e);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[test]
+fn test_draw() {
+    let mut rng = rand::thread_rng();
+
+    let drawn = draw(&[1, 0, 5, 2], 3, &mut rng);
+    assert_eq!(drawn.len(), 3);
+    assert!(!drawn.contains(&1));
+    assert_eq!(draw(&[3, 0], 2, &mut rng), vec![0]);
+    assert!(draw(&[], 2, &mut rng).is_empty());
+}
@@ -0,0 +1,64 @@
+//! Gateway intents and cache settings, trimmed to what the bot uses.
+//!
+//! `MESSAGE_CONTENT=0` runs without the privileged message content intent:
+//! Discord then only shows the bot messages which mention it, so commands
+//! are given as `@bot play ...`. `LOW_MEMORY=1` keeps less in memory for
+//! small machines.
+use std::env;
+
+use lazy_static::lazy_static;
+use serenity::{cache::Settings, model::gateway::GatewayIntents};
+
+/// History entries kept per guild in low memory mode.
+const LOW_MEMORY_HISTORY_MAX: usize = 100;
+
+lazy_static! {
+    static ref MESSAGE_CONTENT: bool = env::var("MESSAGE_CONTENT")
+        .map(|x| x != "0")
+        .unwrap_or(true);
+    static ref LOW_MEMORY: bool = env::var("LOW_MEMORY")
+        .map(|x| x == "1")
+        .unwrap_or(false);
+    /// Messages cached per channel, nothing in the bot reads them back.
+    static ref CACHE_MAX_MESSAGES: usize = env::var("CACHE_MAX_MESSAGES")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(0);
+}
+
+pub(crate) fn message_content() -> bool {
+    *MESSAGE_CONTENT
+}
+
+pub(crate) fn low_memory() -> bool {
+    *LOW_MEMORY
+}
+
+/// Guilds and their channels for permissions, voice states to find and
+/// follow listeners, and messages for commands.
+pub(crate) fn intents() -> GatewayIntents {
+    let mut intents = GatewayIntents::GUILDS
+        | GatewayIntents::GUILD_VOICE_STATES
+        | GatewayIntents::GUILD_MESSAGES
+        | GatewayIntents::DIRECT_MESSAGES;
+    if message_content() {
+        intents |= GatewayIntents::MESSAGE_CONTENT;
+    }
+
+    intents
+}
+
+pub(crate) fn cache_settings(settings: &mut Settings) -> &mut Settings {
+    let max_messages = if low_memory() { 0 } else { *CACHE_MAX_MESSAGES };
+
+    settings.max_messages(max_messages)
+}
+
+/// How many songs of a guild's history are kept.
+pub(crate) fn history_max(default: usize) -> usize {
+    if low_memory() {
+        default.min(LOW_MEMORY_HISTORY_MAX)
+    } else {
+        default
+    }
+}
@@ -0,0 +1,52 @@
+//! The server a command came from, without panicking when it isn't there:
+//! a command sent in DMs, or a server not cached yet right after startup.
+//! Commands return these errors with `?`, the `after` hook answers them.
+use std::fmt;
+
+use serenity::{
+    client::Context,
+    model::{
+        channel::Message,
+        id::{ChannelId, GuildId},
+    },
+};
+
+#[derive(Debug)]
+pub(crate) enum GuildError {
+    /// Sent in DMs.
+    OutsideGuild,
+    /// The server's members and voice states aren't cached yet.
+    NotCached,
+}
+
+impl fmt::Display for GuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GuildError::OutsideGuild => write!(f, "This command only works in servers"),
+            GuildError::NotCached => write!(f, "The bot is still starting, try again in a moment"),
+        }
+    }
+}
+
+impl std::error::Error for GuildError {}
+
+pub(crate) fn guild_id(msg: &Message) -> Result<GuildId, GuildError> {
+    msg.guild_id.ok_or(GuildError::OutsideGuild)
+}
+
+/// Voice channel the author of `msg` is in. Voice states only come from the
+/// gateway, so there is no asking Discord for them instead of the cache.
+pub(crate) fn author_voice_channel(
+    ctx: &Context,
+    msg: &Message,
+) -> Result<Option<ChannelId>, GuildError> {
+    let guild_id = guild_id(msg)?;
+
+    ctx.cache
+        .guild_field(guild_id, |x| {
+            x.voice_states
+                .get(&msg.author.id)
+                .and_then(|x| x.channel_id)
+        })
+        .ok_or(GuildError::NotCached)
+}
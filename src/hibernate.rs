@@ -0,0 +1,129 @@
+//! Hibernation for small hosts. When no song has played and no command came
+//! for a while, e.g. while staying in a channel 24/7 with `~settings set
+//! idletimeout off`, paused songs end their processes, caches are dropped
+//! and background tasks slow down. The next command warms the bot up again.
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use lazy_static::lazy_static;
+use serenity::client::Context;
+use songbird::tracks::PlayMode;
+use tracing::{info, warn};
+
+use crate::{
+    resume::{self, Stowed},
+    select, soundcloudapi, spotify,
+};
+
+/// Quiet time before hibernating.
+const HIBERNATE_AFTER: Duration = Duration::from_secs(10 * 60);
+/// Background tasks run this many times less often while hibernating.
+const SLOWDOWN: u32 = 8;
+
+static HIBERNATING: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref LAST_ACTIVE: Mutex<Instant> = Mutex::new(Instant::now());
+    /// Queues put away per guild, queued again on waking up.
+    static ref STOWED: tokio::sync::Mutex<HashMap<u64, Stowed>> =
+        tokio::sync::Mutex::new(HashMap::new());
+}
+
+pub(crate) fn is_hibernating() -> bool {
+    HIBERNATING.load(Ordering::SeqCst)
+}
+
+/// Whether the guild's queue is put away, so its call isn't idle.
+pub(crate) async fn is_stowed(guild_id: u64) -> bool {
+    STOWED.lock().await.contains_key(&guild_id)
+}
+
+/// How long a background task with `tick` waits between runs.
+pub(crate) fn tick(tick: Duration) -> Duration {
+    if is_hibernating() {
+        tick * SLOWDOWN
+    } else {
+        tick
+    }
+}
+
+/// Hibernates when nothing played nor was asked for since `HIBERNATE_AFTER`.
+pub(crate) async fn check(ctx: &Context) {
+    let quiet = LAST_ACTIVE.lock().unwrap().elapsed() >= HIBERNATE_AFTER;
+    if is_hibernating() || !quiet {
+        return;
+    }
+
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    let calls = ctx
+        .cache
+        .guilds()
+        .into_iter()
+        .filter_map(|x| Some((x.0, manager.get(x)?)))
+        .collect::<Vec<_>>();
+    for (_, handler_lock) in &calls {
+        let current = handler_lock.lock().await.queue().current();
+        if let Some(current) = current {
+            if current
+                .get_info()
+                .await
+                .is_ok_and(|x| x.playing == PlayMode::Play)
+            {
+                touch();
+                return;
+            }
+        }
+    }
+
+    // Held until all is put away, so waking up meanwhile waits for it.
+    let mut stowed = STOWED.lock().await;
+    HIBERNATING.store(true, Ordering::SeqCst);
+    for (guild_id, handler_lock) in calls {
+        if let Some(queue) = resume::stow(&handler_lock).await {
+            stowed.insert(guild_id, queue);
+        }
+    }
+    select::forget_all(ctx).await;
+    spotify::forget_token().await;
+    soundcloudapi::forget_client_id().await;
+    info!("Hibernating, {} paused queues put away", stowed.len());
+}
+
+/// Marks the bot as in use, e.g. on a command.
+pub(crate) fn touch() {
+    *LAST_ACTIVE.lock().unwrap() = Instant::now();
+}
+
+/// Puts the stowed queues back if the bot hibernates.
+pub(crate) async fn wake(ctx: &Context) {
+    touch();
+    if !HIBERNATING.swap(false, Ordering::SeqCst) {
+        return;
+    }
+
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    let stowed = std::mem::take(&mut *STOWED.lock().await);
+    for (guild_id, queue) in stowed {
+        // Left meanwhile, e.g. as everyone else did.
+        let handler_lock = match manager.get(guild_id) {
+            Some(handler_lock) => handler_lock,
+            None => continue,
+        };
+        if let Err(e) = resume::unstow(ctx, guild_id, handler_lock, queue).await {
+            warn!("Err queueing stowed songs of {}: {:?}", guild_id, e);
+        }
+    }
+    info!("Woke up from hibernation");
+}
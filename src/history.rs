@@ -0,0 +1,344 @@
+//! Songs requested and songs played in every guild, kept across sessions.
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serenity::{client::Context, prelude::TypeMapKey};
+use songbird::{input::Metadata, tracks::TrackHandle};
+use tokio::sync::RwLock;
+use tracing::warn;
+
+use crate::{
+    gateway, profile,
+    queue::{self, Requester},
+    store, track_name,
+};
+
+const HISTORY: &str = "history";
+/// Older requests are dropped once a guild has this many.
+const HISTORY_MAX: usize = 1000;
+/// Songs requested this often count as the guild's favorites.
+const FAVORITE_MIN: u32 = 2;
+const PLAYED: &str = "played";
+/// Older plays are dropped once a guild has this many.
+const PLAYED_MAX: usize = 100;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct HistoryEntry {
+    /// Unique in the guild, buttons refer to entries by it.
+    pub id: u64,
+    pub url: String,
+    pub title: String,
+    /// Missing in entries from before artists were kept.
+    #[serde(default)]
+    pub artist: Option<String>,
+    pub requester: Requester,
+    /// Unix timestamp of the request.
+    pub time: u64,
+}
+
+type History = HashMap<u64, VecDeque<HistoryEntry>>;
+
+pub(crate) type HistoryLock = Arc<RwLock<History>>;
+
+pub(crate) struct GuildHistory;
+
+impl TypeMapKey for GuildHistory {
+    type Value = HistoryLock;
+}
+
+pub(crate) async fn load() -> Result<History> {
+    store::load(HISTORY).await
+}
+
+pub(crate) async fn history_lock(ctx: &Context) -> HistoryLock {
+    let read = ctx.data.read().await;
+
+    read.get::<GuildHistory>()
+        .expect("Expected GuildHistory in TypeMap.")
+        .clone()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or_default()
+}
+
+/// Adds songs requested together by their URL and saves the history.
+pub(crate) async fn record(
+    ctx: &Context,
+    guild_id: u64,
+    requester: &Requester,
+    tracks: Vec<(String, Metadata)>,
+) {
+    let time = now();
+
+    profile::record_requests(ctx, guild_id, requester.id, tracks.len() as u64).await;
+
+    let lock = history_lock(ctx).await;
+    let mut history = lock.write().await;
+    let entries = history.entry(guild_id).or_default();
+    let next_id = entries.back().map(|x| x.id + 1).unwrap_or_default();
+    for (id, (url, metadata)) in (next_id..).zip(tracks) {
+        entries.push_back(HistoryEntry {
+            id,
+            url,
+            title: track_name(&metadata),
+            artist: metadata.artist.filter(|x| !x.is_empty()),
+            requester: requester.clone(),
+            time,
+        });
+    }
+    while entries.len() > gateway::history_max(HISTORY_MAX) {
+        entries.pop_front();
+    }
+
+    if let Err(e) = store::save(HISTORY, &*history).await {
+        warn!("Err saving history: {:?}", e);
+    }
+}
+
+/// The last distinct songs a user requested, newest first.
+pub(crate) async fn recent(
+    ctx: &Context,
+    guild_id: u64,
+    user_id: u64,
+    limit: usize,
+) -> Vec<HistoryEntry> {
+    let lock = history_lock(ctx).await;
+    let history = lock.read().await;
+
+    history
+        .get(&guild_id)
+        .map(|x| latest_of_user(x, user_id, limit))
+        .unwrap_or_default()
+}
+
+fn latest_of_user(
+    entries: &VecDeque<HistoryEntry>,
+    user_id: u64,
+    limit: usize,
+) -> Vec<HistoryEntry> {
+    let mut seen = HashSet::new();
+
+    entries
+        .iter()
+        .rev()
+        .filter(|x| x.requester.id == user_id && seen.insert(x.url.as_str()))
+        .take(limit)
+        .cloned()
+        .collect()
+}
+
+/// How often a guild requested its songs and their artists.
+#[derive(Default)]
+pub(crate) struct Favorites {
+    /// Songs requested at least `FAVORITE_MIN` times, most requested first.
+    pub songs: Vec<(String, u32)>,
+    /// Requests per artist, names lowercased.
+    pub artists: HashMap<String, u32>,
+}
+
+impl Favorites {
+    pub(crate) fn artist_requests(&self, artist: &str) -> u32 {
+        self.artists
+            .get(&artist.to_lowercase())
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+fn count_favorites(entries: &VecDeque<HistoryEntry>) -> Favorites {
+    let mut songs = HashMap::<&str, u32>::new();
+    let mut artists = HashMap::new();
+    for entry in entries {
+        *songs.entry(&entry.url).or_default() += 1;
+        if let Some(artist) = &entry.artist {
+            *artists.entry(artist.to_lowercase()).or_default() += 1;
+        }
+    }
+
+    let mut songs = songs
+        .into_iter()
+        .filter(|x| x.1 >= FAVORITE_MIN)
+        .map(|(url, n)| (url.to_string(), n))
+        .collect::<Vec<_>>();
+    songs.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    Favorites { songs, artists }
+}
+
+pub(crate) async fn favorites(history: &HistoryLock, guild_id: u64) -> Favorites {
+    let history = history.read().await;
+
+    history
+        .get(&guild_id)
+        .map(count_favorites)
+        .unwrap_or_default()
+}
+
+pub(crate) async fn get(ctx: &Context, guild_id: u64, id: u64) -> Option<HistoryEntry> {
+    let lock = history_lock(ctx).await;
+    let history = lock.read().await;
+
+    history
+        .get(&guild_id)
+        .and_then(|x| x.iter().find(|x| x.id == id).cloned())
+}
+
+/// A song which started playing.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct PlayedEntry {
+    pub url: String,
+    pub title: String,
+    pub requester: Option<Requester>,
+    /// Unix timestamp of when it started.
+    pub time: u64,
+}
+
+type Played = HashMap<u64, VecDeque<PlayedEntry>>;
+
+pub(crate) type PlayedLock = Arc<RwLock<Played>>;
+
+pub(crate) struct GuildPlayed;
+
+impl TypeMapKey for GuildPlayed {
+    type Value = PlayedLock;
+}
+
+pub(crate) async fn load_played() -> Result<Played> {
+    store::load(PLAYED).await
+}
+
+pub(crate) async fn played_lock(ctx: &Context) -> PlayedLock {
+    let read = ctx.data.read().await;
+
+    read.get::<GuildPlayed>()
+        .expect("Expected GuildPlayed in TypeMap.")
+        .clone()
+}
+
+fn push_played(entries: &mut VecDeque<PlayedEntry>, entry: PlayedEntry, max: usize) {
+    entries.push_back(entry);
+    while entries.len() > max {
+        entries.pop_front();
+    }
+}
+
+/// Adds a track which started playing to the guild's plays and saves them.
+pub(crate) async fn record_play(lock: &PlayedLock, guild_id: u64, track: &TrackHandle) {
+    let url = match &track.metadata().source_url {
+        Some(url) => url.clone(),
+        None => return,
+    };
+    let entry = PlayedEntry {
+        url,
+        title: track_name(track.metadata()),
+        requester: queue::requester(track).await,
+        time: now(),
+    };
+
+    let mut played = lock.write().await;
+    push_played(
+        played.entry(guild_id).or_default(),
+        entry,
+        gateway::history_max(PLAYED_MAX),
+    );
+    if let Err(e) = store::save(PLAYED, &*played).await {
+        warn!("Err saving played songs: {:?}", e);
+    }
+}
+
+/// The last songs played in the guild, newest first.
+pub(crate) async fn played(ctx: &Context, guild_id: u64, limit: usize) -> Vec<PlayedEntry> {
+    let lock = played_lock(ctx).await;
+    let played = lock.read().await;
+
+    played
+        .get(&guild_id)
+        .map(|x| x.iter().rev().take(limit).cloned().collect())
+        .unwrap_or_default()
+}
+
+#[test]
+fn test_latest_of_user() {
+    let requester = |id| Requester {
+        id,
+        name: id.to_string(),
+        avatar: None,
+    };
+    let entries = [(1, "a"), (2, "b"), (1, "c"), (1, "a"), (1, "d")]
+        .iter()
+        .enumerate()
+        .map(|(i, (user, url))| HistoryEntry {
+            id: i as u64,
+            url: url.to_string(),
+            title: url.to_string(),
+            artist: None,
+            requester: requester(*user),
+            time: 0,
+        })
+        .collect::<VecDeque<_>>();
+    let urls = latest_of_user(&entries, 1, 10)
+        .into_iter()
+        .map(|x| x.url)
+        .collect::<Vec<_>>();
+
+    assert_eq!(urls, vec!["d", "a", "c"]);
+    assert_eq!(latest_of_user(&entries, 1, 2).len(), 2);
+    assert!(latest_of_user(&entries, 3, 10).is_empty());
+}
+
+#[test]
+fn test_count_favorites() {
+    let requester = Requester {
+        id: 1,
+        name: "a".to_string(),
+        avatar: None,
+    };
+    let entries = [
+        ("a", Some("Aimer")),
+        ("b", None),
+        ("a", Some("aimer")),
+        ("c", Some("YOASOBI")),
+    ]
+    .iter()
+    .enumerate()
+    .map(|(i, (url, artist))| HistoryEntry {
+        id: i as u64,
+        url: url.to_string(),
+        title: url.to_string(),
+        artist: artist.map(str::to_string),
+        requester: requester.clone(),
+        time: 0,
+    })
+    .collect::<VecDeque<_>>();
+    let favorites = count_favorites(&entries);
+
+    assert_eq!(favorites.songs, vec![("a".to_string(), 2)]);
+    assert_eq!(favorites.artist_requests("AIMER"), 2);
+    assert_eq!(favorites.artist_requests("LiSA"), 0);
+}
+
+#[test]
+fn test_push_played() {
+    let mut entries = VecDeque::new();
+    for url in ["a", "b", "c"] {
+        let entry = PlayedEntry {
+            url: url.to_string(),
+            title: url.to_string(),
+            requester: None,
+            time: 0,
+        };
+        push_played(&mut entries, entry, 2);
+    }
+
+    let urls = entries.iter().map(|x| x.url.as_str()).collect::<Vec<_>>();
+    assert_eq!(urls, ["b", "c"]);
+}
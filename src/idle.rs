@@ -0,0 +1,185 @@
+//! Leaves voice channels nobody uses: when the queue stayed empty for the
+//! guild's idle timeout, or when everyone else left.
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use serenity::{
+    client::Context,
+    model::id::{ChannelId, GuildId},
+};
+use tracing::warn;
+
+use crate::{check_msg, hibernate, playback, send_session_summary, session, settings, supervisor};
+
+/// How often idle voice channels are looked for.
+pub(crate) const IDLE_TICK: Duration = Duration::from_secs(15);
+
+/// Grace period after the last listener left, in case they come back.
+const ALONE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Why the bot left on its own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Reason {
+    Idle,
+    Alone,
+}
+
+impl Reason {
+    fn message(&self) -> &'static str {
+        match self {
+            Reason::Idle => "Left the voice channel as nothing was played for a while",
+            Reason::Alone => "Left the voice channel as everyone else left",
+        }
+    }
+}
+
+/// Whether anyone but bots is in the voice channel.
+fn has_listeners(ctx: &Context, guild_id: GuildId, channel: ChannelId) -> bool {
+    ctx.cache
+        .guild_field(guild_id, |guild| {
+            guild
+                .voice_states
+                .values()
+                .filter(|x| x.channel_id == Some(channel))
+                .any(|x| match &x.member {
+                    Some(member) => !member.user.bot,
+                    None => !ctx.cache.user(x.user_id).is_some_and(|x| x.bot),
+                })
+        })
+        .unwrap_or(false)
+}
+
+/// Starts or stops the alone timer of a guild after a voice state changed.
+pub(crate) async fn voice_state_changed(ctx: &Context, guild_id: GuildId) {
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    let channel = match manager.get(guild_id) {
+        Some(handler_lock) => handler_lock.lock().await.current_channel(),
+        None => return,
+    };
+    let alone = match channel {
+        Some(channel) => !has_listeners(ctx, guild_id, ChannelId(channel.0)),
+        None => return,
+    };
+
+    let lock = playback::playback_lock(ctx).await;
+    let mut playback = lock.write().await;
+    let state = playback.entry(guild_id.0).or_default();
+    if !alone {
+        state.alone_since = None;
+    } else if state.alone_since.is_none() {
+        state.alone_since = Some(Instant::now());
+    }
+}
+
+/// Checks every voice channel the bot is in, and whether it should leave.
+async fn check(ctx: &Context) -> Vec<(GuildId, Reason)> {
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    let mut calls = vec![];
+    for guild_id in ctx.cache.guilds() {
+        let empty = match manager.get(guild_id) {
+            Some(handler_lock) => {
+                let handler = handler_lock.lock().await;
+                handler
+                    .current_channel()
+                    .map(|_| handler.queue().is_empty())
+            }
+            None => None,
+        };
+        calls.push((guild_id, empty));
+    }
+
+    let mut leave = vec![];
+    for (guild_id, mut empty) in calls {
+        // Its queue comes back on waking up.
+        if empty.is_some() && hibernate::is_stowed(guild_id.0).await {
+            empty = Some(false);
+        }
+        let timeout = settings::get(ctx, guild_id.0).await.idle_timeout();
+        let lock = playback::playback_lock(ctx).await;
+        let mut playback = lock.write().await;
+        let empty = match empty {
+            Some(empty) => empty,
+            // Not in a voice channel, timers start over on the next join.
+            None => {
+                if let Some(state) = playback.get_mut(&guild_id.0) {
+                    state.idle_since = None;
+                    state.alone_since = None;
+                }
+                continue;
+            }
+        };
+        let state = playback.entry(guild_id.0).or_default();
+        if !empty {
+            state.idle_since = None;
+        } else if state.idle_since.is_none() {
+            state.idle_since = Some(Instant::now());
+        }
+
+        if state
+            .alone_since
+            .is_some_and(|x| x.elapsed() >= ALONE_TIMEOUT)
+        {
+            leave.push((guild_id, Reason::Alone));
+        } else if let (Some(since), Some(timeout)) = (state.idle_since, timeout) {
+            if since.elapsed() >= timeout {
+                leave.push((guild_id, Reason::Idle));
+            }
+        }
+    }
+
+    leave
+}
+
+/// Leaves the guild's voice channel and ends its session, saying why in
+/// the channel songs were last requested from.
+pub(crate) async fn leave(ctx: &Context, guild_id: GuildId, reason: Reason) -> Result<()> {
+    let text_channel = {
+        let lock = playback::playback_lock(ctx).await;
+        let mut playback = lock.write().await;
+        let state = playback.entry(guild_id.0).or_default();
+        state.idle_since = None;
+        state.alone_since = None;
+        state.fm = false;
+        state.skip_vote = None;
+
+        state.text_channel
+    };
+
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    manager.remove(guild_id).await?;
+    supervisor::kill_guild(guild_id.0);
+
+    if let Some(session) = playback::end_session(ctx, guild_id.0).await {
+        session::archive_log(&ctx.http, &session).await;
+        if let Some(channel) = text_channel {
+            send_session_summary(ctx, guild_id.0, channel, &session).await;
+        }
+    }
+    if let Some(channel) = text_channel {
+        check_msg(channel.say(&ctx.http, reason.message()).await);
+    }
+
+    Ok(())
+}
+
+/// Leaves idle voice channels and hibernates when all is quiet, forever.
+pub(crate) async fn run(ctx: Context) {
+    loop {
+        tokio::time::sleep(hibernate::tick(IDLE_TICK)).await;
+        for (guild_id, reason) in check(&ctx).await {
+            if let Err(e) = leave(&ctx, guild_id, reason).await {
+                warn!("Err leaving idle guild {}: {:?}", guild_id, e);
+            }
+        }
+        hibernate::check(&ctx).await;
+    }
+}
@@ -0,0 +1,62 @@
+//! Netease DJ programs often open with a long spoken intro, guilds can
+//! have them start further in to get to the music sooner.
+use std::time::Duration;
+
+use serenity::async_trait;
+use songbird::{tracks::TrackHandle, Event, EventContext, EventHandler as VoiceEventHandler};
+use tracing::warn;
+
+use crate::{neteaseapi, playback::GuildPlayer};
+
+/// Longest intro which can be skipped.
+pub(crate) const MAX_INTRO: Duration = Duration::from_secs(600);
+
+/// Seeks DJ programs past the configured intro once they start.
+pub(crate) struct IntroSkipper {
+    pub player: GuildPlayer,
+}
+
+impl IntroSkipper {
+    pub(crate) async fn skip(&self, track: &TrackHandle) {
+        let is_program = track
+            .metadata()
+            .source_url
+            .as_deref()
+            .map(neteaseapi::is_program)
+            .unwrap_or(false);
+        if !is_program {
+            return;
+        }
+        let intro = match self.player.state(|x| x.dj_intro).await {
+            Some(intro) => intro,
+            None => return,
+        };
+        // Programs shorter than the intro are played from the start.
+        if track.metadata().duration.is_some_and(|x| x <= intro) {
+            return;
+        }
+
+        // Seeking a song which already went past the intro, e.g. one that
+        // was restored, would move it back.
+        match track.get_info().await {
+            Ok(info) if info.position < intro => {
+                if let Err(e) = track.seek_time(intro) {
+                    warn!("Err skipping intro: {:?}", e);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+#[async_trait]
+impl VoiceEventHandler for IntroSkipper {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if let EventContext::Track(&[(_, track)]) = ctx {
+            self.skip(track).await;
+        }
+
+        // Only on the first start, not after every pause.
+        Some(Event::Cancel)
+    }
+}
@@ -0,0 +1,94 @@
+//! Ear protection: every source is compressed and limited, by ffmpeg or
+//! `Limiter` when decoded without it, and the volume a guild may set is
+//! capped.
+
+/// ffmpeg audio filter for all songs. The compressor tames sudden peaks,
+/// the limiter keeps already clipping sources just under full scale.
+pub(crate) const FILTER: &str =
+    "acompressor=threshold=-12dB:ratio=4:attack=5:release=100,alimiter=limit=0.9:level=false";
+
+/// Highest volume until a DJ raises the ceiling, the source's own level.
+pub(crate) const DEFAULT_CEILING: f32 = 1.0;
+
+/// The ceiling can't go past what `~vol` accepts.
+pub(crate) const MAX_CEILING: f32 = 2.0;
+
+/// Volume to actually play at given the guild's ceiling.
+pub(crate) fn cap(volume: f32, ceiling: Option<f32>) -> f32 {
+    volume.min(ceiling.unwrap_or(DEFAULT_CEILING))
+}
+
+/// -12 dB, where `FILTER` starts compressing.
+const THRESHOLD: f32 = 0.251;
+const RATIO: f32 = 4.0;
+const LIMIT: f32 = 0.9;
+const SAMPLE_RATE: f32 = 48000.0;
+
+/// `FILTER` for audio decoded without ffmpeg, on 48 kHz frames.
+pub(crate) struct Limiter {
+    envelope: f32,
+    attack: f32,
+    release: f32,
+}
+
+impl Default for Limiter {
+    fn default() -> Self {
+        let coefficient = |ms: f32| (-1000.0 / (ms * SAMPLE_RATE)).exp();
+        Self {
+            envelope: 0.0,
+            attack: coefficient(5.0),
+            release: coefficient(100.0),
+        }
+    }
+}
+
+impl Limiter {
+    /// Compresses the samples of one frame, then keeps them under the
+    /// limit.
+    pub(crate) fn process(&mut self, frame: &mut [f32]) {
+        let peak = frame.iter().fold(0.0f32, |max, x| max.max(x.abs()));
+        let coefficient = if peak > self.envelope {
+            self.attack
+        } else {
+            self.release
+        };
+        self.envelope = coefficient * self.envelope + (1.0 - coefficient) * peak;
+
+        let mut gain = if self.envelope > THRESHOLD {
+            THRESHOLD * (self.envelope / THRESHOLD).powf(1.0 / RATIO) / self.envelope
+        } else {
+            1.0
+        };
+        if peak * gain > LIMIT {
+            gain = LIMIT / peak;
+        }
+        for sample in frame {
+            *sample *= gain;
+        }
+    }
+}
+
+#[test]
+fn test_cap() {
+    assert_eq!(cap(2.0, None), DEFAULT_CEILING);
+    assert_eq!(cap(0.5, None), 0.5);
+    assert_eq!(cap(2.0, Some(1.5)), 1.5);
+}
+
+#[test]
+fn test_limiter() {
+    let mut limiter = Limiter::default();
+    let mut quiet = [0.1, -0.1];
+    limiter.process(&mut quiet);
+    assert_eq!(quiet, [0.1, -0.1]);
+
+    let mut loud = [1.0, -1.0];
+    for _ in 0..4800 {
+        loud = [1.0, -1.0];
+        limiter.process(&mut loud);
+        assert!(loud[0] <= LIMIT);
+    }
+    // Compressed well under the limit once the envelope caught up.
+    assert!(loud[0] < 0.5, "{}", loud[0]);
+    assert_eq!(loud[0], -loud[1]);
+}
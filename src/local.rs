@@ -0,0 +1,254 @@
+//! Songs from disk: audio files attached to `~play`, and with
+//! `LOCAL_MUSIC_DIR` set, the music in it through `~local`. Both are queued
+//! as `file://` URLs, which only ever play from these two directories.
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+use reqwest::Url;
+use serenity::model::channel::Attachment;
+use songbird::input::Restartable;
+use tracing::warn;
+
+use crate::{direct, error::BibiError, ffmpeg::FilterHandle, store};
+
+const AUDIO_EXTENSIONS: &[&str] = &["mp3", "flac", "ogg", "opus", "m4a", "aac", "wav"];
+const DEFAULT_MAX_MB: u64 = 25;
+/// Attachments are kept this long, so saved queues can still play them.
+const KEEP_ATTACHMENTS: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// How deep `~local` searches below `LOCAL_MUSIC_DIR`.
+const SEARCH_DEPTH: usize = 8;
+
+lazy_static! {
+    static ref MUSIC_DIR: Option<PathBuf> = env::var("LOCAL_MUSIC_DIR").ok().map(PathBuf::from);
+    static ref MAX_ATTACHMENT_BYTES: u64 = env::var("ATTACHMENT_MAX_MB")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(DEFAULT_MAX_MB)
+        * 1_000_000;
+}
+
+fn attachment_dir() -> PathBuf {
+    store::dir("attachments")
+}
+
+pub(crate) fn music_dir() -> Option<&'static Path> {
+    MUSIC_DIR.as_deref()
+}
+
+pub(crate) fn is_local(url: &str) -> bool {
+    url.starts_with("file://")
+}
+
+fn is_audio(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|x| x.to_str())
+        .is_some_and(|x| AUDIO_EXTENSIONS.contains(&x.to_ascii_lowercase().as_str()))
+}
+
+/// Whether `path` is inside `root`, after following `..` and links.
+fn within(root: &Path, path: &Path) -> bool {
+    match (root.canonicalize(), path.canonicalize()) {
+        (Ok(root), Ok(path)) => path.starts_with(root),
+        _ => false,
+    }
+}
+
+fn allowed(path: &Path) -> bool {
+    within(&attachment_dir(), path) || music_dir().is_some_and(|x| within(x, path))
+}
+
+fn file_url(path: &Path) -> Result<String> {
+    let path = path.canonicalize()?;
+    let url = Url::from_file_path(&path)
+        .map_err(|_| BibiError::UnsupportedUrl(path.display().to_string()))?;
+
+    Ok(url.to_string())
+}
+
+/// Why an attachment won't be played, `None` when it will.
+fn refusal(filename: &str, content_type: Option<&str>, size: u64, max: u64) -> Option<String> {
+    let audio = content_type.is_some_and(|x| x.starts_with("audio/")) || is_audio(filename);
+    if !audio {
+        Some(format!("{} is not an audio file", filename))
+    } else if size > max {
+        Some(format!(
+            "{} is larger than {} MB",
+            filename,
+            max / 1_000_000
+        ))
+    } else {
+        None
+    }
+}
+
+/// Why `attachment` won't be played, `None` when it will.
+pub(crate) fn check(attachment: &Attachment) -> Option<String> {
+    refusal(
+        &attachment.filename,
+        attachment.content_type.as_deref(),
+        attachment.size,
+        *MAX_ATTACHMENT_BYTES,
+    )
+}
+
+/// Removes attachments older than `KEEP_ATTACHMENTS`.
+async fn prune(dir: &Path) {
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let old = entry
+            .metadata()
+            .await
+            .and_then(|x| x.modified())
+            .ok()
+            .and_then(|x| SystemTime::now().duration_since(x).ok())
+            .is_some_and(|x| x > KEEP_ATTACHMENTS);
+        if old {
+            if let Err(e) = tokio::fs::remove_dir_all(entry.path()).await {
+                warn!("Err removing old attachment {:?}: {:?}", entry.path(), e);
+            }
+        }
+    }
+}
+
+/// Saves an attachment `check` let through, returns its URL to queue.
+pub(crate) async fn download(attachment: &Attachment) -> Result<String> {
+    let dir = attachment_dir();
+    prune(&dir).await;
+    let bytes = attachment.download().await?;
+    if bytes.len() as u64 > *MAX_ATTACHMENT_BYTES {
+        return Err(BibiError::UnsupportedUrl(attachment.url.clone()).into());
+    }
+    // One directory per attachment keeps the name it was sent with.
+    let name = Path::new(&attachment.filename)
+        .file_name()
+        .map(|x| x.to_os_string())
+        .unwrap_or_else(|| "attachment".into());
+    let dir = dir.join(attachment.id.to_string());
+    tokio::fs::create_dir_all(&dir).await?;
+    let path = dir.join(name);
+    tokio::fs::write(&path, bytes).await?;
+
+    file_url(&path)
+}
+
+/// Audio files below `dir`, at most `depth` directories down.
+fn walk(dir: &Path, depth: usize, files: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        match entry.file_type() {
+            Ok(x) if x.is_dir() && depth > 0 => walk(&path, depth - 1, files),
+            Ok(x) if x.is_file() && path.to_str().is_some_and(is_audio) => files.push(path),
+            _ => {}
+        }
+    }
+}
+
+/// The file at `query` below `root`, or the one whose path has every word
+/// of `query`, shortest path first.
+fn find_in(root: &Path, query: &str) -> Option<PathBuf> {
+    let exact = root.join(query);
+    if exact.is_file() && is_audio(query) {
+        return within(root, &exact).then_some(exact);
+    }
+
+    let words = query
+        .split_whitespace()
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>();
+    let mut files = vec![];
+    walk(root, SEARCH_DEPTH, &mut files);
+
+    files
+        .into_iter()
+        .filter(|x| {
+            let relative = x
+                .strip_prefix(root)
+                .unwrap_or(x)
+                .to_string_lossy()
+                .to_lowercase();
+            words.iter().all(|w| relative.contains(w.as_str()))
+        })
+        .min_by_key(|x| (x.as_os_str().len(), x.clone()))
+}
+
+/// URL of the song in `LOCAL_MUSIC_DIR` at or matching `query`.
+pub(crate) async fn find(query: &str) -> Result<Option<String>> {
+    let root = match music_dir() {
+        Some(root) => root,
+        None => return Ok(None),
+    };
+    let query = query.to_string();
+    let found = tokio::task::spawn_blocking(move || find_in(root, &query)).await?;
+
+    found.map(|x| file_url(&x)).transpose()
+}
+
+pub(crate) async fn restartable(
+    url: &str,
+    lazy: bool,
+    filter: FilterHandle,
+) -> Result<Restartable> {
+    let path = Url::parse(url)
+        .ok()
+        .and_then(|x| x.to_file_path().ok())
+        .filter(|x| allowed(x))
+        .ok_or_else(|| BibiError::UnsupportedUrl(url.to_string()))?;
+
+    direct::open(&path.to_string_lossy(), url, lazy, filter).await
+}
+
+#[test]
+fn test_refusal() {
+    let max = 25_000_000;
+
+    assert_eq!(refusal("a.mp3", None, 1_000, max), None);
+    assert_eq!(refusal("voice", Some("audio/ogg"), 1_000, max), None);
+    assert_eq!(
+        refusal("a.png", Some("image/png"), 1_000, max).as_deref(),
+        Some("a.png is not an audio file")
+    );
+    assert_eq!(
+        refusal("a.FLAC", None, 30_000_000, max).as_deref(),
+        Some("a.FLAC is larger than 25 MB")
+    );
+}
+
+#[test]
+fn test_find_in() {
+    let root = env::temp_dir().join(format!("bibicord-local-{}", std::process::id()));
+    fs::create_dir_all(root.join("Band/Album")).unwrap();
+    fs::write(root.join("Band/Album/01 Song.flac"), b"").unwrap();
+    fs::write(root.join("Band/Album/02 Other Song.mp3"), b"").unwrap();
+    fs::write(root.join("Band/cover.jpg"), b"").unwrap();
+    let name = |x: Option<PathBuf>| x.and_then(|x| Some(x.file_name()?.to_str()?.to_string()));
+
+    assert_eq!(
+        name(find_in(&root, "Band/Album/02 Other Song.mp3")).as_deref(),
+        Some("02 Other Song.mp3")
+    );
+    assert_eq!(
+        name(find_in(&root, "band song")).as_deref(),
+        Some("01 Song.flac")
+    );
+    assert_eq!(
+        name(find_in(&root, "other")).as_deref(),
+        Some("02 Other Song.mp3")
+    );
+    assert_eq!(find_in(&root, "cover"), None);
+    assert!(!within(&root.join("Band"), &root.join("Band/../Band/../")));
+
+    fs::remove_dir_all(&root).unwrap();
+}
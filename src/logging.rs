@@ -0,0 +1,124 @@
+//! Logging through `tracing`. `LOG_FORMAT=json` writes a JSON object per
+//! line for log collectors, anything else the readable default, and
+//! `LOG_LEVEL` filters like `RUST_LOG`, e.g. `info,serenity=warn`.
+//!
+//! Commands and interactions run in a span with the guild, channel, user
+//! and command, and songs are queued in one with their URL. The JSON lines
+//! carry the fields of their spans under `span` and `spans`, so a guild's
+//! lines can be searched by `span.guild_id`.
+use std::env;
+
+use serenity::{
+    async_trait,
+    client::Context,
+    framework::Framework,
+    model::{
+        channel::Message,
+        id::{ChannelId, GuildId, UserId},
+    },
+};
+use tracing::{field, info_span, Span};
+use tracing_futures::Instrument;
+use tracing_subscriber::EnvFilter;
+
+const DEFAULT_LEVEL: &str = "info";
+
+/// Sets up logging from `LOG_FORMAT` and `LOG_LEVEL`, or `RUST_LOG`.
+pub(crate) fn init() {
+    let level = env::var("LOG_LEVEL")
+        .or_else(|_| env::var("RUST_LOG"))
+        .unwrap_or_else(|_| DEFAULT_LEVEL.to_string());
+    let filter = EnvFilter::try_new(&level).unwrap_or_else(|e| {
+        eprintln!(
+            "Err parsing LOG_LEVEL {}, using {}: {}",
+            level, DEFAULT_LEVEL, e
+        );
+        EnvFilter::new(DEFAULT_LEVEL)
+    });
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    match env::var("LOG_FORMAT").as_deref() {
+        Ok("json") => builder
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(true)
+            .init(),
+        _ => builder.init(),
+    }
+}
+
+/// Span of a command sent by `user_id`.
+pub(crate) fn command_span(
+    guild_id: Option<GuildId>,
+    channel_id: ChannelId,
+    user_id: UserId,
+) -> Span {
+    info_span!(
+        "command",
+        guild_id = guild_id.map(|x| x.0),
+        channel_id = channel_id.0,
+        user_id = user_id.0,
+        command = field::Empty
+    )
+}
+
+/// Runs the commands of each message in its `command_span`.
+pub(crate) struct Traced<F>(pub F);
+
+#[async_trait]
+impl<F: Framework> Framework for Traced<F> {
+    async fn dispatch(&self, ctx: Context, msg: Message) {
+        let span = command_span(msg.guild_id, msg.channel_id, msg.author.id);
+
+        self.0.dispatch(ctx, msg).instrument(span).await
+    }
+}
+
+#[test]
+fn test_json() {
+    use serde_json::Value;
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let buffer = Buffer::default();
+    let writer = buffer.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .json()
+        .flatten_event(true)
+        .with_current_span(true)
+        .with_span_list(true)
+        .with_writer(move || writer.clone())
+        .finish();
+    tracing::subscriber::with_default(subscriber, || {
+        let span = command_span(Some(GuildId(1)), ChannelId(2), UserId(3));
+        let _entered = span.enter();
+        span.record("command", "play");
+        tracing::info!(url = "https://example.com/a.mp3", "Playing {}", "A");
+    });
+
+    let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let line = serde_json::from_str::<Value>(&output).unwrap();
+    assert_eq!(line["level"], "INFO");
+    assert_eq!(line["span"]["guild_id"], 1);
+    assert_eq!(line["span"]["user_id"], 3);
+    assert_eq!(line["span"]["command"], "play");
+    assert_eq!(line["url"], "https://example.com/a.mp3");
+    assert_eq!(line["message"], "Playing A");
+    assert_eq!(line["spans"][0]["name"], "command");
+}
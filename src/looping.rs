@@ -0,0 +1,145 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use serenity::async_trait;
+use songbird::{
+    tracks::{PlayMode, TrackHandle, TrackResult, TrackState},
+    Event, EventContext, EventHandler as VoiceEventHandler,
+};
+use tracing::warn;
+
+use crate::{playback::GuildPlayer, queue, soft_mute, source};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum LoopMode {
+    #[default]
+    Off,
+    /// Repeat the current track until it is skipped.
+    Track,
+    /// Put every finished track back at the end of the queue.
+    Queue,
+}
+
+impl FromStr for LoopMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(LoopMode::Off),
+            "track" => Ok(LoopMode::Track),
+            "queue" => Ok(LoopMode::Queue),
+            _ => Err(anyhow!("Unknown loop mode: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for LoopMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            LoopMode::Off => "off",
+            LoopMode::Track => "track",
+            LoopMode::Queue => "queue",
+        };
+
+        write!(f, "{}", s)
+    }
+}
+
+/// Applies a new loop mode to the track being played.
+pub(crate) fn apply(mode: LoopMode, current: &TrackHandle) -> TrackResult<()> {
+    if mode == LoopMode::Track {
+        current.enable_loop()
+    } else {
+        current.disable_loop()
+    }
+}
+
+/// Registered for both `TrackEvent::Play` and `TrackEvent::End` of every
+/// queued track.
+#[derive(Clone)]
+pub(crate) struct Looper {
+    pub player: GuildPlayer,
+}
+
+impl Looper {
+    /// A track queued into an empty queue starts right away without a
+    /// `TrackEvent::Play`, so it has to be looped here.
+    pub(crate) async fn on_enqueue(&self, track: &TrackHandle) -> TrackResult<()> {
+        let mode = self.player.state(|x| x.loop_mode).await;
+
+        if mode == LoopMode::Track && track.get_info().await?.playing == PlayMode::Play {
+            track.enable_loop()?;
+        }
+
+        Ok(())
+    }
+
+    async fn requeue(&self, state: &TrackState, track: &TrackHandle) -> anyhow::Result<()> {
+        let url = track
+            .metadata()
+            .source_url
+            .clone()
+            .ok_or_else(|| anyhow!("Track has no source url"))?;
+        let source = source::restartable(
+            &url,
+            self.player.quality().await,
+            self.player.filter().await,
+        )
+        .await?;
+        let requester = queue::requester(track).await;
+        let entry_volume = queue::entry_volume(track).await;
+
+        let (new, first) = {
+            let mut handler = self.player.call.lock().await;
+            let first = handler.queue().is_empty();
+            (handler.enqueue_source(source.into()), first)
+        };
+        let volume = soft_mute::volume_before(&self.player, track).await;
+        new.set_volume(volume.unwrap_or(state.volume))?;
+        if let Some(requester) = requester {
+            queue::set_requester(&new, requester).await;
+        }
+        if let Some(volume) = entry_volume {
+            queue::set_entry_volume(&new, volume).await;
+        }
+        self.player.attach(&new, first).await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl VoiceEventHandler for Looper {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if let EventContext::Track(&[(state, track)]) = ctx {
+            let mode = self.player.state(|x| x.loop_mode).await;
+
+            match (mode, state.playing) {
+                (LoopMode::Track, PlayMode::Play) => {
+                    let _ = track.enable_loop();
+                }
+                // Skipped or cleared tracks are stopped rather than ended,
+                // and don't come back.
+                (LoopMode::Queue, PlayMode::End) => {
+                    if let Err(e) = self.requeue(state, track).await {
+                        warn!("Err requeueing track: {:?}", e);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        None
+    }
+}
+
+#[test]
+fn test_loop_mode_from_str() {
+    for mode in [LoopMode::Off, LoopMode::Track, LoopMode::Queue] {
+        assert_eq!(mode.to_string().parse::<LoopMode>().unwrap(), mode);
+    }
+
+    assert!("all".parse::<LoopMode>().is_err());
+}
@@ -0,0 +1,420 @@
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serenity::{async_trait, http::Http, model::id::ChannelId, prelude::TypeMapKey};
+use songbird::{input::Metadata, Event, EventContext, EventHandler as VoiceEventHandler};
+
+use crate::{
+    check_msg,
+    neteaseapi::{self, Lyrics},
+    source::{self, SourceProvider},
+};
+
+/// Discord refuses longer messages.
+const MESSAGE_MAX_CHARS: usize = 2000;
+
+/// LRC lyrics for songs outside Netease.
+const LRCLIB_URL: &str = "https://lrclib.net/api/search";
+
+/// LRCLIB results this far off the song's length are other versions.
+const DURATION_TOLERANCE: Duration = Duration::from_secs(5);
+
+/// How often synced lyrics check the track position.
+pub(crate) const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// After a seek forward only the line being sung is posted, not the
+/// skipped ones, once more than this many lines are due at once.
+const SYNC_CATCH_UP: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum LyricsMode {
+    /// Lyrics as sung.
+    Original,
+    /// The Chinese translation only.
+    Translated,
+    /// Every original line followed by its translation.
+    Both,
+}
+
+impl FromStr for LyricsMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cn" | "orig" => Ok(LyricsMode::Original),
+            "trans" => Ok(LyricsMode::Translated),
+            "both" => Ok(LyricsMode::Both),
+            _ => Err(anyhow!("Unknown lyrics mode: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct LyricLine {
+    pub time: Duration,
+    pub text: String,
+}
+
+/// Parses `mm:ss.xx` of an LRC time tag.
+fn parse_time(tag: &str) -> Option<Duration> {
+    let (minutes, seconds) = tag.split_once(':')?;
+    let minutes = minutes.parse::<u64>().ok()?;
+    let seconds = seconds.parse::<f64>().ok()?;
+
+    Some(Duration::from_secs(minutes * 60) + Duration::from_secs_f64(seconds))
+}
+
+/// Parses LRC lyrics into lines sorted by time. Tags which aren't times
+/// (`[ar:...]`, `[by:...]`) and empty lines are dropped.
+pub(crate) fn parse_lrc(lrc: &str) -> Vec<LyricLine> {
+    let mut lines = vec![];
+
+    for line in lrc.lines() {
+        let mut rest = line.trim();
+        let mut times = vec![];
+
+        while let Some(tag) = rest.strip_prefix('[') {
+            let (tag, after) = match tag.split_once(']') {
+                Some(x) => x,
+                None => break,
+            };
+            if let Some(time) = parse_time(tag) {
+                times.push(time);
+            }
+            rest = after;
+        }
+
+        let text = rest.trim();
+        if text.is_empty() {
+            continue;
+        }
+
+        lines.extend(times.into_iter().map(|time| LyricLine {
+            time,
+            text: text.to_string(),
+        }));
+    }
+    lines.sort_by_key(|x| x.time);
+
+    lines
+}
+
+fn join_lines(lines: &[LyricLine]) -> String {
+    lines
+        .iter()
+        .map(|x| x.text.as_str())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Renders lyrics as text, `None` if the mode needs a translation the song
+/// doesn't have.
+pub(crate) fn render(lyrics: &Lyrics, mode: LyricsMode) -> Option<String> {
+    let original = parse_lrc(&lyrics.original);
+    let translated = lyrics.translated.as_deref().map(parse_lrc);
+
+    match mode {
+        // Some songs only have plain text lyrics without time tags.
+        LyricsMode::Original if original.is_empty() => Some(lyrics.original.trim().to_string()),
+        LyricsMode::Original => Some(join_lines(&original)),
+        LyricsMode::Translated => translated.map(|x| join_lines(&x)),
+        LyricsMode::Both => {
+            let translated = translated?;
+            let mut s = String::new();
+            for line in &original {
+                s.push_str(&line.text);
+                s.push('\n');
+                if let Some(t) = translated.iter().find(|x| x.time == line.time) {
+                    s.push_str(&t.text);
+                    s.push('\n');
+                }
+            }
+
+            Some(s.trim_end().to_string())
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct LrclibEntry {
+    duration: Option<f64>,
+    plain_lyrics: Option<String>,
+    synced_lyrics: Option<String>,
+}
+
+/// Picks the closest match in length, synced lyrics over plain text.
+fn pick_lrclib(entries: Vec<LrclibEntry>, duration: Option<Duration>) -> Option<String> {
+    let fits = |x: &LrclibEntry| match (x.duration, duration) {
+        (Some(a), Some(b)) => {
+            let a = Duration::from_secs_f64(a);
+            a.max(b) - a.min(b) <= DURATION_TOLERANCE
+        }
+        _ => true,
+    };
+    let mut entries = entries.into_iter().filter(fits).collect::<Vec<_>>();
+    // Stable, so LRCLIB's ranking decides among the synced ones.
+    entries.sort_by_key(|x| x.synced_lyrics.is_none());
+
+    entries
+        .into_iter()
+        .find_map(|x| x.synced_lyrics.or(x.plain_lyrics))
+}
+
+async fn lrclib(metadata: &Metadata) -> Result<Lyrics> {
+    let title = metadata
+        .track
+        .as_ref()
+        .or(metadata.title.as_ref())
+        .ok_or_else(|| anyhow!("Song has no title to search lyrics for"))?;
+    let mut query = vec![("track_name", title.as_str())];
+    if let Some(artist) = &metadata.artist {
+        query.push(("artist_name", artist.as_str()));
+    }
+
+    let entries = reqwest::Client::new()
+        .get(LRCLIB_URL)
+        .query(&query)
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<Vec<LrclibEntry>>()
+        .await?;
+
+    let original = pick_lrclib(entries, metadata.duration)
+        .ok_or_else(|| anyhow!("No lyrics found for {}", title))?;
+
+    Ok(Lyrics {
+        original,
+        translated: None,
+    })
+}
+
+/// Lyrics of a song: from Netease for its own songs, LRCLIB otherwise.
+pub(crate) async fn fetch(metadata: &Metadata) -> Result<Lyrics> {
+    match &metadata.source_url {
+        Some(url) if source::Netease.matches_url(url) => neteaseapi::netease_lyrics(url).await,
+        _ => lrclib(metadata).await,
+    }
+}
+
+/// Time tagged lines to show while the song plays, `None` if the lyrics
+/// aren't synced or lack the translation the mode needs.
+pub(crate) fn synced_lines(lyrics: &Lyrics, mode: LyricsMode) -> Option<Vec<LyricLine>> {
+    let original = parse_lrc(&lyrics.original);
+    let translated = lyrics.translated.as_deref().map(parse_lrc);
+
+    let lines = match mode {
+        LyricsMode::Original => original,
+        LyricsMode::Translated => translated?,
+        LyricsMode::Both => {
+            let translated = translated?;
+            original
+                .into_iter()
+                .map(|line| {
+                    let text = match translated.iter().find(|x| x.time == line.time) {
+                        Some(t) => format!("{}\n{}", line.text, t.text),
+                        None => line.text,
+                    };
+                    LyricLine { text, ..line }
+                })
+                .collect()
+        }
+    };
+
+    (!lines.is_empty()).then_some(lines)
+}
+
+/// Lines to post at `position` when `next` is the first line not posted
+/// yet. Returns the new `next` with the lines.
+fn due(lines: &[LyricLine], position: Duration, next: usize) -> (usize, &[LyricLine]) {
+    let end = lines.partition_point(|x| x.time <= position);
+
+    if end <= next {
+        // A seek back starts over from the line being sung.
+        return (end, &[]);
+    }
+    if end - next > SYNC_CATCH_UP {
+        return (end, &lines[end - 1..end]);
+    }
+
+    (end, &lines[next..end])
+}
+
+/// Marks tracks which already have lyrics synced to them.
+pub(crate) struct Syncing;
+
+impl TypeMapKey for Syncing {
+    type Value = ();
+}
+
+/// Posts lyric lines to a channel as the track reaches them. Meant to run
+/// as a periodic track event.
+pub(crate) struct LyricsSync {
+    channel: ChannelId,
+    http: Arc<Http>,
+    lines: Vec<LyricLine>,
+    next: AtomicUsize,
+}
+
+impl LyricsSync {
+    pub(crate) fn new(channel: ChannelId, http: Arc<Http>, lines: Vec<LyricLine>) -> Self {
+        Self {
+            channel,
+            http,
+            lines,
+            next: AtomicUsize::new(0),
+        }
+    }
+}
+
+#[async_trait]
+impl VoiceEventHandler for LyricsSync {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if let EventContext::Track(&[(state, _)]) = ctx {
+            let (next, lines) = due(
+                &self.lines,
+                state.position,
+                self.next.load(Ordering::Relaxed),
+            );
+            self.next.store(next, Ordering::Relaxed);
+
+            if !lines.is_empty() {
+                check_msg(self.channel.say(&self.http, join_lines(lines)).await);
+            }
+        }
+
+        None
+    }
+}
+
+/// Splits text at line ends into chunks Discord accepts as single messages.
+pub(crate) fn split_message(text: &str) -> Vec<String> {
+    let mut chunks = vec![];
+    let mut chunk = String::new();
+
+    for line in text.lines() {
+        if !chunk.is_empty() && chunk.chars().count() + line.chars().count() + 1 > MESSAGE_MAX_CHARS
+        {
+            chunks.push(std::mem::take(&mut chunk));
+        }
+        chunk.push_str(line);
+        chunk.push('\n');
+    }
+    if !chunk.trim().is_empty() {
+        chunks.push(chunk);
+    }
+
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGINAL: &str = "[by:someone]\n[00:01.50]Hello\n[00:03.00][01:03.00]World\n[00:05.00]\n";
+    const TRANSLATED: &str = "[00:01.50]你好\n[00:03.00]世界\n";
+
+    #[test]
+    fn test_parse_lrc() {
+        let lines = parse_lrc(ORIGINAL);
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].time, Duration::from_millis(1500));
+        assert_eq!(lines[0].text, "Hello");
+        assert_eq!(lines[2].time, Duration::from_secs(63));
+        assert_eq!(lines[2].text, "World");
+    }
+
+    #[test]
+    fn test_render() {
+        let lyrics = Lyrics {
+            original: ORIGINAL.to_string(),
+            translated: Some(TRANSLATED.to_string()),
+        };
+
+        assert_eq!(
+            render(&lyrics, LyricsMode::Original).unwrap(),
+            "Hello\nWorld\nWorld"
+        );
+        assert_eq!(
+            render(&lyrics, LyricsMode::Translated).unwrap(),
+            "你好\n世界"
+        );
+        assert_eq!(
+            render(&lyrics, LyricsMode::Both).unwrap(),
+            "Hello\n你好\nWorld\n世界\nWorld"
+        );
+
+        let lyrics = Lyrics {
+            original: "plain text".to_string(),
+            translated: None,
+        };
+
+        assert_eq!(render(&lyrics, LyricsMode::Original).unwrap(), "plain text");
+        assert_eq!(render(&lyrics, LyricsMode::Both), None);
+    }
+
+    #[test]
+    fn test_synced_lines() {
+        let lyrics = Lyrics {
+            original: ORIGINAL.to_string(),
+            translated: Some(TRANSLATED.to_string()),
+        };
+        let lines = synced_lines(&lyrics, LyricsMode::Both).unwrap();
+
+        assert_eq!(lines[0].text, "Hello\n你好");
+        assert_eq!(lines[2].text, "World");
+
+        let (next, posted) = due(&lines, Duration::from_secs(4), 0);
+        assert_eq!(next, 2);
+        assert_eq!(posted.len(), 2);
+        assert_eq!(due(&lines, Duration::from_secs(4), 2).1, &[]);
+        // Seeked back before the first line.
+        assert_eq!(due(&lines, Duration::from_secs(1), 2), (0, &[][..]));
+
+        let plain = Lyrics {
+            original: "plain text".to_string(),
+            translated: None,
+        };
+        assert!(synced_lines(&plain, LyricsMode::Original).is_none());
+    }
+
+    #[test]
+    fn test_pick_lrclib() {
+        let entries = serde_json::from_str::<Vec<LrclibEntry>>(
+            r#"[
+                {"duration": 300.0, "plainLyrics": "live", "syncedLyrics": "[00:01.00]live"},
+                {"duration": 200.0, "plainLyrics": "plain", "syncedLyrics": null},
+                {"duration": 201.0, "plainLyrics": "b", "syncedLyrics": "[00:01.00]b"}
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            pick_lrclib(entries, Some(Duration::from_secs(200))).as_deref(),
+            Some("[00:01.00]b")
+        );
+    }
+
+    #[test]
+    fn test_split_message() {
+        let line = "a".repeat(999);
+        let text = format!("{}\n{}\n{}", line, line, line);
+        let chunks = split_message(&text);
+
+        assert_eq!(chunks.len(), 2);
+        assert!(chunks
+            .iter()
+            .all(|x| x.chars().count() <= MESSAGE_MAX_CHARS));
+    }
+}
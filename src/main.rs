@@ -9,50 +9,391 @@
 //! git = "https://github.com/serenity-rs/serenity.git"
 //! features = ["cache", "framework", "standard_framework", "voice"]
 //! ```
-use std::{collections::HashMap, env, sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    ops::RangeInclusive,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
+mod alarm;
+mod api;
+mod args;
+mod audio_cache;
+mod autoplay;
+mod bandwidth;
+mod bilibiliapi;
+mod bookmark;
+mod cli;
+mod config;
+mod control;
+mod credentials;
+mod crossfade;
+mod dashboard;
+mod decode;
+mod direct;
+mod display;
+mod dj;
+mod download;
+mod downloader;
+mod error;
+mod eta;
+mod ffmpeg;
+mod fm;
+mod gateway;
+mod guild;
+mod hibernate;
+mod history;
+mod idle;
+mod intro;
+mod limiter;
+mod local;
+mod logging;
+mod looping;
+mod lyrics;
+mod metrics;
+#[cfg(test)]
+mod mock;
+mod my_playlist;
 mod neteaseapi;
-
+mod now_playing;
+mod playback;
+mod playlist;
+mod prefetch;
+mod preflight;
+mod profile;
+mod quality;
+mod queue;
+mod radio_dj;
+mod readahead;
+mod recognize;
+mod reconnect;
+mod reply;
+mod report;
+mod resolve;
+mod resolve_pool;
+mod resume;
+mod retry;
+mod scrape;
+mod search;
+mod seek_cache;
+mod select;
+mod selftest;
+mod session;
+mod setlist;
+mod settings;
+mod shard;
+mod shutdown;
+mod slash;
+mod soft_mute;
+mod soundcloudapi;
+mod source;
+mod spotify;
+mod stage;
+mod store;
+mod stream_cache;
+mod supervisor;
+mod tts;
+mod vote;
+mod ytdl;
+
+use clap::Parser;
+use futures_util::StreamExt;
+use rand::seq::SliceRandom;
 use serenity::{
     async_trait,
+    builder::CreateComponents,
     client::{Client, Context, EventHandler},
     framework::{
         standard::{
-            macros::{command, group},
-            Args, CommandResult,
+            macros::{command, group, hook},
+            Args, CommandResult, DispatchError,
         },
         StandardFramework,
     },
     http::Http,
-    model::{channel::Message, gateway::Ready, prelude::ChannelId},
-    prelude::{TypeMapKey, GatewayIntents, Mentionable},
+    model::{
+        application::{
+            component::ButtonStyle,
+            interaction::{
+                message_component::MessageComponentInteraction, Interaction,
+                InteractionResponseType,
+            },
+        },
+        channel::{AttachmentType, Message},
+        gateway::Ready,
+        prelude::{ChannelId, GuildId, UserId},
+        voice::VoiceState,
+    },
+    prelude::{Mentionable, TypeMap, TypeMapKey},
     Result as SerenityResult,
 };
 
 use songbird::{
-    input::{self, restartable::Restartable},
-    Event, EventContext, EventHandler as VoiceEventHandler, SerenityInit, TrackEvent,
+    error::TrackResult,
+    input::{Input, Metadata},
+    tracks::{TrackHandle, TrackQueue},
+    Call, Event, EventContext, EventHandler as VoiceEventHandler, SerenityInit, TrackEvent,
 };
 
-use anyhow::anyhow;
-use tokio::sync::RwLock;
+use args::{ArgsExt, Percent, Span, Switch, Usage};
+use eta::Eta;
+use ffmpeg::{Effects, Filter, FilterHandle};
+use looping::LoopMode;
+use lyrics::LyricsMode;
+use playback::GuildPlayer;
+use quality::Quality;
+use queue::Requester;
+use select::SelectAction;
+use settings::Settings;
+use tokio::sync::{Mutex, RwLock};
+use tracing::{info, warn};
+use tracing_futures::Instrument;
+use vote::Vote;
 
 struct Handler;
 
 #[async_trait]
 impl EventHandler for Handler {
     async fn ready(&self, _: Context, ready: Ready) {
-        println!("{} is connected!", ready.user.name);
+        match ready.shard {
+            Some([id, count]) => info!(
+                "{} is connected on shard {} of {}!",
+                ready.user.name, id, count
+            ),
+            None => info!("{} is connected!", ready.user.name),
+        }
+    }
+
+    /// Guilds are only known once a shard cached them all, which is when the
+    /// voice channels it had in the last run can be restored or cleaned up.
+    async fn cache_ready(&self, ctx: Context, guilds: Vec<GuildId>) {
+        let shard = ctx.shard_id;
+        // This fires again after every reconnect, only restore once.
+        if !shard::first_ready(shard) {
+            return;
+        }
+        info!("Shard {} cached {} guilds", shard, guilds.len());
+        let restore_ctx = ctx.clone();
+        tokio::spawn(async move {
+            let ctx = restore_ctx;
+            if let Err(e) = resume::restore(&ctx, &guilds).await {
+                warn!("Err restoring queues of shard {}: {:?}", shard, e);
+            }
+            resume::disconnect_stale(&ctx, &guilds).await;
+            if shard::all_ready(ctx.cache.shard_count()) {
+                resume::forget_unrestored().await;
+            }
+        });
+
+        // The tasks for every guild only start with the first shard.
+        if TASKS_STARTED.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        tokio::spawn(async move {
+            tokio::spawn(alarm::run(ctx.clone()));
+            tokio::spawn(idle::run(ctx.clone()));
+            tokio::spawn(shutdown::run(ctx.clone()));
+            tokio::spawn(metrics::run(ctx.clone()));
+            tokio::spawn(dashboard::run(ctx.clone()));
+            tokio::spawn(api::run(ctx.clone()));
+            tokio::spawn(supervisor::run());
+            loop {
+                tokio::time::sleep(hibernate::tick(resume::SAVE_INTERVAL)).await;
+                if shutdown::is_stopping() {
+                    break;
+                }
+                // The queues are put away, saving now would lose them.
+                if hibernate::is_hibernating() {
+                    continue;
+                }
+                if let Err(e) = resume::save(&ctx).await {
+                    warn!("Err saving queues: {:?}", e);
+                }
+            }
+        });
+    }
+
+    async fn voice_state_update(&self, ctx: Context, _: Option<VoiceState>, new: VoiceState) {
+        if let Some(guild_id) = new.guild_id {
+            idle::voice_state_changed(&ctx, guild_id).await;
+            stage::voice_state_changed(&ctx, guild_id, &new).await;
+        }
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        match interaction {
+            Interaction::MessageComponent(component) => {
+                let span = logging::command_span(
+                    component.guild_id,
+                    component.channel_id,
+                    component.user.id,
+                );
+                span.record("command", component.data.custom_id.as_str());
+                press_button(&ctx, &component).instrument(span).await;
+            }
+            Interaction::ApplicationCommand(command) => {
+                let span =
+                    logging::command_span(command.guild_id, command.channel_id, command.user.id);
+                span.record("command", command.data.name.as_str());
+                async {
+                    hibernate::wake(&ctx).await;
+                    slash::handle(&ctx, &command).await;
+                }
+                .instrument(span)
+                .await;
+            }
+            _ => {}
+        }
+    }
+}
+
+async fn press_button(ctx: &Context, component: &MessageComponentInteraction) {
+    hibernate::wake(ctx).await;
+    if let Some(id) = component.data.custom_id.strip_prefix(REQUEUE_BUTTON) {
+        requeue(ctx, component, id).await;
+    } else if let Some(page) = component.data.custom_id.strip_prefix(LIST_BUTTON) {
+        turn_list_page(ctx, component, page).await;
+    } else if let Some(id) = component.data.custom_id.strip_prefix(retry::RETRY_BUTTON) {
+        retry_request(ctx, component, id).await;
+    } else if component.data.custom_id == vote::VOTE_BUTTON {
+        vote_skip(ctx, component).await;
     }
 }
 
 #[group]
 #[commands(
-    deafen, join, leave, mute, play_fade, play, skip, clear, ping, undeafen, unmute, list, destroy,
-    now, vol, help
+    deafen,
+    join,
+    leave,
+    mute,
+    play_fade,
+    play,
+    playnext,
+    local,
+    insert,
+    skip,
+    clear,
+    ping,
+    shardinfo,
+    undeafen,
+    unmute,
+    list,
+    destroy,
+    now,
+    vol,
+    help,
+    boost,
+    remove,
+    dedupe,
+    eta,
+    crossfade,
+    search,
+    radiodj,
+    loop_mode,
+    lyrics,
+    romanize,
+    display,
+    move_song,
+    swap,
+    recent,
+    bookmark,
+    bookmarks,
+    resume_bookmark,
+    export,
+    import,
+    apitoken,
+    history_command,
+    replay,
+    profile_command,
+    stats,
+    whatsong,
+    credential,
+    download,
+    fm,
+    ncloud,
+    autoplay_command,
+    sessionlog,
+    tracklist,
+    nowplaying,
+    softmute,
+    softunmute,
+    ceiling,
+    suspend,
+    resume_queue,
+    playlist_command,
+    myplaylist,
+    scrape,
+    djintro,
+    voteskip,
+    alarm_command,
+    quality_command,
+    filter,
+    speed,
+    settings_command,
+    prefix,
+    select
 )]
 struct General;
 
+/// Guilds may change the prefix with `~settings`.
+#[hook]
+async fn dynamic_prefix(ctx: &Context, msg: &Message) -> Option<String> {
+    Some(settings::prefix(ctx, msg).await)
+}
+
+/// Bad arguments are answered with the command's usage, commands which
+/// need a server with why they can't run.
+#[hook]
+async fn after(ctx: &Context, msg: &Message, command_name: &str, result: CommandResult) {
+    if let Err(why) = result {
+        if let Some(usage) = why.downcast_ref::<Usage>() {
+            check_msg(msg.channel_id.say(&ctx.http, usage.to_string()).await);
+        } else if let Some(e) = why.downcast_ref::<guild::GuildError>() {
+            check_msg(msg.channel_id.say(&ctx.http, e.to_string()).await);
+        } else {
+            warn!("Err in command {}: {:?}", command_name, why);
+            report::error(
+                "command",
+                msg.guild_id.map(|x| x.0),
+                format!("{}: {:?}", command_name, why),
+            );
+        }
+    }
+}
+
+/// Server only commands sent in DMs are answered instead of ignored.
+#[hook]
+async fn dispatch_error(ctx: &Context, msg: &Message, error: DispatchError, _command_name: &str) {
+    if let DispatchError::OnlyForGuilds = error {
+        check_msg(
+            msg.channel_id
+                .say(&ctx.http, guild::GuildError::OutsideGuild.to_string())
+                .await,
+        );
+    }
+}
+
+/// Commands typed in a voice channel's text chat move announcements there.
+#[hook]
+async fn before(ctx: &Context, msg: &Message, command_name: &str) -> bool {
+    tracing::Span::current().record("command", command_name);
+    hibernate::wake(ctx).await;
+    if let Some(guild_id) = msg.guild_id {
+        if playback::is_voice_chat(ctx, msg.channel_id) {
+            playback::announce_in(ctx, guild_id.0, msg.channel_id).await;
+        }
+    }
+
+    true
+}
+
+static TASKS_STARTED: AtomicBool = AtomicBool::new(false);
+
 struct SongVolume;
 
 impl TypeMapKey for SongVolume {
@@ -64,12 +405,15 @@ macro_rules! unwrap_or_show_error {
         match $f {
             Ok(source) => source,
             Err(why) => {
-                println!("Err starting source: {:?}", why);
-                check_msg(
-                    $msg.channel_id
-                        .say(&$ctx.http, "Error sourcing ffmpeg")
-                        .await,
-                );
+                warn!("Err starting source: {:?}", why);
+                let s = match error::user_message(&why) {
+                    Some(s) => s,
+                    None => {
+                        report::error("resolve", $msg.guild_id.map(|x| x.0), format!("{:?}", why));
+                        "Error sourcing ffmpeg".to_string()
+                    }
+                };
+                check_msg($msg.channel_id.say(&$ctx.http, s).await);
 
                 return Ok(());
             }
@@ -77,32 +421,111 @@ macro_rules! unwrap_or_show_error {
     };
 }
 
-const DEP_APP_LIST: &[&str] = &["ffmpeg", "ffprobe", "youtube-dl"];
-
 #[tokio::main]
 async fn main() {
+    let cli = cli::Cli::parse();
     dotenv::dotenv().ok();
-
-    for app in DEP_APP_LIST {
-        if which::which(app).is_err() {
-            eprintln!("Can not find {} in PATH!", app);
+    let config = match config::load(cli.config.clone()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Err loading the config file: {:?}", e);
             std::process::exit(1);
         }
+    };
+
+    let selftest = match cli.command() {
+        cli::Command::Run { selftest } => selftest,
+        cli::Command::CheckDeps => std::process::exit(cli::check_deps()),
+        cli::Command::Resolve { url } => {
+            if let Err(e) = credentials::load().await {
+                eprintln!("Err loading credentials: {:?}", e);
+                std::process::exit(1);
+            }
+            std::process::exit(cli::resolve(&url).await);
+        }
+        cli::Command::RegisterCommands { guild, clear } => {
+            let token = env::var("DISCORD_TOKEN").expect("Expected a token in the environment");
+            match slash::register(&Http::new(&token), guild.map(GuildId), clear).await {
+                Ok(count) => println!("{} slash commands registered", count),
+                Err(e) => {
+                    eprintln!("Err registering slash commands: {:?}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+    };
+
+    let missing = cli::missing_deps(false);
+    if !missing.is_empty() {
+        for program in missing {
+            eprintln!("Can not find {}!", program);
+        }
+        std::process::exit(1);
     }
-    tracing_subscriber::fmt::init();
+    let downloader = downloader::detect().expect("checked by missing_deps");
+    logging::init();
+    report::install_panic_hook();
+    if let Some(config) = config {
+        info!("Read {}", config.display());
+    }
+    info!("Using {} for YouTube and other sites", downloader);
 
     // Configure the client with your Discord bot token in the environment.
     let token = env::var("DISCORD_TOKEN").expect("Expected a token in the environment");
+    if selftest {
+        selftest::main(&token).await;
+    }
+
+    if let Err(e) = credentials::load().await {
+        eprintln!("Err loading credentials: {:?}", e);
+        std::process::exit(1);
+    }
+
+    if let Err(e) = neteaseapi::netease_login_from_env().await {
+        warn!("Err logging in to Netease, VIP songs won't play: {:?}", e);
+    }
+
+    // Owners manage the bot itself, e.g. its credentials.
+    let http = Http::new(&token);
+    let owners = match http.get_current_application_info().await {
+        Ok(info) => {
+            let mut owners = HashSet::new();
+            owners.insert(info.owner.id);
+            if let Some(team) = info.team {
+                owners.extend(team.members.into_iter().map(|x| x.user.id));
+            }
+
+            owners
+        }
+        Err(why) => panic!("Could not access application info: {:?}", why),
+    };
+    let bot_id = match http.get_current_user().await {
+        Ok(user) => user.id,
+        Err(why) => panic!("Could not access the bot user: {:?}", why),
+    };
+    if !gateway::message_content() {
+        info!("Running without message content, commands need to mention the bot");
+    }
 
     let framework = StandardFramework::new()
-        .configure(|c| c.prefix("~"))
+        .configure(|c| {
+            c.prefix("")
+                .dynamic_prefix(dynamic_prefix)
+                .on_mention(Some(bot_id))
+                .owners(owners)
+        })
+        .before(before)
+        .after(after)
+        .on_dispatch_error(dispatch_error)
         .group(&GENERAL_GROUP);
 
-    let intents = GatewayIntents::non_privileged() | GatewayIntents::MESSAGE_CONTENT;
+    let intents = gateway::intents();
 
     let mut client = Client::builder(&token, intents)
+        .cache_settings(gateway::cache_settings)
         .event_handler(Handler)
-        .framework(framework)
+        .framework(logging::Traced(framework))
         .register_songbird()
         .await
         .expect("Err creating client");
@@ -110,23 +533,52 @@ async fn main() {
     {
         // Open the data lock in write mode, so keys can be inserted to it.
         let mut data = client.data.write().await;
-
-        // The CommandCounter Value has the following type:
-        // Arc<RwLock<HashMap<String, u64>>>
-        // So, we have to insert the same type to it.
-        data.insert::<SongVolume>(Arc::new(RwLock::new(HashMap::default())));
+        insert_data(&mut data).await;
+        data.insert::<shutdown::ShardManagerContainer>(client.shard_manager.clone());
     }
 
-    let _ = client
-        .start()
+    let _ = shard::start(&mut client)
         .await
-        .map_err(|why| println!("Client ended: {:?}", why));
+        .map_err(|why| warn!("Client ended: {:?}", why));
+}
+
+/// Puts the state commands share into the client's data, loading what
+/// was saved.
+async fn insert_data(data: &mut TypeMap) {
+    // The CommandCounter Value has the following type:
+    // Arc<RwLock<HashMap<String, u64>>>
+    // So, we have to insert the same type to it.
+    data.insert::<SongVolume>(Arc::new(RwLock::new(HashMap::default())));
+    data.insert::<playback::GuildPlayback>(Arc::new(RwLock::new(HashMap::default())));
+    data.insert::<display::GuildDisplay>(Arc::new(RwLock::new(HashMap::default())));
+    data.insert::<history::GuildHistory>(Arc::new(RwLock::new(
+        history::load().await.expect("Err loading history"),
+    )));
+    data.insert::<history::GuildPlayed>(Arc::new(RwLock::new(
+        history::load_played()
+            .await
+            .expect("Err loading played songs"),
+    )));
+    data.insert::<profile::GuildProfiles>(Arc::new(RwLock::new(
+        profile::load().await.expect("Err loading profiles"),
+    )));
+    data.insert::<bandwidth::GuildBandwidth>(Arc::new(RwLock::new(
+        bandwidth::load().await.expect("Err loading bandwidth"),
+    )));
+    data.insert::<retry::PendingRetries>(Arc::new(RwLock::new(HashMap::default())));
+    data.insert::<select::Selections>(Arc::new(RwLock::new(HashMap::default())));
+    data.insert::<settings::GuildSettings>(Arc::new(RwLock::new(
+        settings::load().await.expect("Err loading settings"),
+    )));
+    data.insert::<alarm::Alarms>(Arc::new(RwLock::new(
+        alarm::load().await.expect("Err loading alarms"),
+    )));
 }
 
 #[command]
+#[only_in(guilds)]
 async fn deafen(ctx: &Context, msg: &Message) -> CommandResult {
-    let guild = msg.guild(&ctx.cache).unwrap();
-    let guild_id = guild.id;
+    let guild_id = guild::guild_id(msg)?;
 
     let manager = songbird::get(ctx)
         .await
@@ -172,34 +624,145 @@ fn duration_formatter(duration: &Duration) -> String {
     )
 }
 
+pub(crate) async fn send_session_summary(
+    ctx: &Context,
+    guild_id: u64,
+    channel: ChannelId,
+    session: &session::Session,
+) {
+    if session.played() == 0 {
+        return;
+    }
+
+    let options = display::display_options(ctx, guild_id).await;
+    let top_requester = session
+        .top_requester()
+        .map(|(name, n)| format!("{} ({} songs)", name, n))
+        .unwrap_or_else(|| "-".to_string());
+    let most_skipped = session
+        .most_skipped()
+        .map(|(title, n)| format!("{} ({} times)", display::show(title, &options), n))
+        .unwrap_or_else(|| "-".to_string());
+
+    check_msg(
+        channel
+            .send_message(&ctx.http, |m| {
+                m.embed(|e| {
+                    e.title("Session summary")
+                        .field(
+                            "Duration",
+                            duration_formatter(&session.started.elapsed()),
+                            true,
+                        )
+                        .field("Tracks played", session.played(), true)
+                        .field("Top requester", top_requester, false)
+                        .field("Most skipped", most_skipped, false)
+                })
+            })
+            .await,
+    );
+}
+
+fn track_name(metadata: &Metadata) -> String {
+    if let Some(title) = &metadata.title {
+        title.to_owned()
+    } else if let Some(url) = &metadata.source_url {
+        url.to_owned()
+    } else {
+        "song".to_string()
+    }
+}
+
 #[command]
 #[only_in(guilds)]
 async fn help(ctx: &Context, msg: &Message) -> CommandResult {
     let help = r#"Usage:
 ~join             join to voice channel
-~play [URL]       play audio from URL
-~now              See now playing
-~list             See current audio queue
+~play [URL]       play audio from URL or playlist
+~play [PLAYLIST URL] shuffled  add the playlist in random order
+~play [KEYWORDS]  play the best match of keywords
+~play             with audio files attached, play them
+~local [PATH|KEYWORDS] play from the bot's music folder (LOCAL_MUSIC_DIR)
+~playnext [URL|KEYWORDS] play right after the current song
+~insert [POS] [URL|KEYWORDS] play at that queue position
+~search [WORDS]   Search songs and pick one to play
+~now [live]       See now playing, live keeps updating it
+~list [PAGE]      See current audio queue
+~skip [INDEX]     Skip current song, or remove an entry (its requester or a DJ)
+~remove [FROM-TO|@USER] Remove a range of entries, or every entry of a user
+~dedupe           Remove songs queued more than once (DJ)
+~eta [INDEX|@USER] When an entry, or the next song of a user, starts
 ~clean            Clean current audio queue
 ~destroy          Clean current audio queue and leave
 ~leave            Leave voice channel
 ~vol [VOL]        Set volume (0~200)
+~vol [INDEX] [VOL] Set volume of one queue entry only
+~ceiling [VOL]    Highest volume allowed (DJ to change, 100 by default)
+~voteskip [PERCENT|on|off] Listeners vote to skip, DJs still skip at once (DJ to change)
+~boost [INDEX]    Play queue entry right after the current one and pin it (DJ)
+~select [POS...] [remove|boost|pin] Act on several positions of your last ~list
+~move [FROM] [TO] Move queue entry to another position
+~swap [A] [B]     Swap two queue entries
+~crossfade [SEC]  Fade between songs on skip (1~12 or off)
+~quality [MODE]   Stream quality (low, normal, high), auto follows the channel bitrate
+~filter [NAME]    Audio filter of every song (bassboost, nightcore, vaporwave, karaoke, off)
+~speed [SPEED]    Playback speed of every song (0.5~2.0), the pitch stays
+~djintro [SEC]    Start Netease DJ programs SEC in to skip intros (off to disable)
+~radiodj on [LANG] Announce every song before it plays (off to disable)
+~loop [MODE]      Repeat current song or whole queue (off, track, queue)
+~lyrics [sync] [MODE] Lyrics of current song (cn, trans, both), sync posts lines as they are sung
+~romanize [on|off] Show pinyin/romaji of CJK titles in ~now and ~list
+~display [OPTION] [on|off] Show requester, url or thumbnail of songs
+~recent [@USER]   Songs you (or USER) requested lately, to queue again
+~history          Songs played lately in this server
+~bookmark         Save the playing song and how far it got
+~bookmarks        See your bookmarks
+~resume-bookmark N Queue bookmark N from where it was saved
+~export [json|m3u] Upload the queue as a file
+~import [URL]     Queue a file from ~export, attached or on a paste site
+~apitoken [revoke] DM a token for the HTTP/WebSocket API of this server (admins)
+~replay N         Queue song N of ~history again
+~profile [@USER]  Listening streak and badges of you (or USER)
+~stats            Estimated traffic of this server this month, per source
+~whatsong         Identify the playing song from its audio
+~download         Upload the playing song as a file (if the bot allows it)
+~fm [on|off]      Endless radio from Netease personal FM, mixed with this server's favorites
+~ncloud [search WORDS|play N] Songs in the Netease cloud disk of the bot's account
+~autoplay [on|off] Keep playing songs like the last one when the queue runs out (Netease, YouTube)
+~sessionlog [on|off] Log played songs to a thread per session
+~tracklist [on|off] Keep a pinned list of the songs played this session
+~nowplaying [on|off] Post every song as it starts playing
+~softmute         Turn the songs almost silent while they keep playing
+~softunmute       Give the songs their volume back
+~suspend          Save the queue to your profile and stop it (DJ)
+~resume           Queue what you suspended, in any server
+~playlist [save|load|delete] [NAME] [shuffled] Server playlists saved from the queue (list to show them)
+~myplaylist [add URL|play|remove N|clear] Your own playlist, in any server (add a playlist URL to import it)
+~scrape [MESSAGE LINK|#CHANNEL] [COUNT] Queue the songs shared in the last COUNT messages (DJ)
+~alarm [HH:MM] [URL] Join your voice channel and play URL at that time (list, cancel ID)
+~prefix [set|reset] [PREFIX] Command prefix of this server (admins to change)
+~settings [set|reset] [KEY] [VALUE] Server options: prefix, volume, maxqueue, maxduration (minutes or off), djrole, announce, idletimeout (minutes or off), profiles, loudnorm, endwarning (on or off), bandwidthcap (GB a month or off) (admins to change)
+~shardinfo        Gateway shards of the bot with their latency
+~credential [list|set|clear] [NAME] [VALUE] Manage service credentials (owners, DM only)
+
+中文命令: ~播放 ~跳过 ~列表 ~音量 ~加入 ~离开 ~正在播放 ~搜索 ~歌词
 "#;
+    // Only the `~` starting a command, not the ones in ranges like 0~200.
+    let prefix = settings::prefix(ctx, msg).await;
+    let help = help
+        .replace("\n~", &format!("\n{}", prefix))
+        .replace(" ~", &format!(" {}", prefix));
     check_msg(msg.channel_id.say(&ctx.http, help).await);
 
     Ok(())
 }
 
 #[command]
+#[aliases("加入")]
 #[only_in(guilds)]
 async fn join(ctx: &Context, msg: &Message) -> CommandResult {
-    let guild = msg.guild(&ctx.cache).unwrap();
-    let guild_id = guild.id;
-
-    let channel_id = guild
-        .voice_states
-        .get(&msg.author.id)
-        .and_then(|voice_state| voice_state.channel_id);
+    let guild_id = guild::guild_id(msg)?;
+    let channel_id = guild::author_voice_channel(ctx, msg)?;
 
     let connect_to = match channel_id {
         Some(channel) => channel,
@@ -215,9 +778,18 @@ async fn join(ctx: &Context, msg: &Message) -> CommandResult {
         .expect("Songbird Voice client placed in at initialisation.")
         .clone();
 
-    let (_, success) = manager.join(guild_id, connect_to).await;
+    if let Err(why) = preflight::check(&ctx.cache, guild_id, connect_to) {
+        check_msg(msg.channel_id.say(&ctx.http, why.to_string()).await);
+
+        return Ok(());
+    }
+
+    let (handler_lock, success) = manager.join(guild_id, connect_to).await;
 
     if let Ok(_channel) = success {
+        playback::start_session(ctx, guild_id.0).await;
+        reconnect::watch(ctx, guild_id.0, &handler_lock).await;
+        playback::reset_announcements(ctx, guild_id.0, msg.channel_id).await;
         check_msg(
             msg.channel_id
                 .say(&ctx.http, &format!("Joined {}", connect_to.mention()))
@@ -235,10 +807,10 @@ async fn join(ctx: &Context, msg: &Message) -> CommandResult {
 }
 
 #[command]
+#[aliases("离开")]
 #[only_in(guilds)]
 async fn leave(ctx: &Context, msg: &Message) -> CommandResult {
-    let guild = msg.guild(&ctx.cache).unwrap();
-    let guild_id = guild.id;
+    let guild_id = guild::guild_id(msg)?;
 
     let manager = songbird::get(ctx)
         .await
@@ -260,6 +832,11 @@ async fn leave(ctx: &Context, msg: &Message) -> CommandResult {
     }
 
     if has_handler {
+        if let Some(session) = playback::end_session(ctx, guild_id.0).await {
+            session::archive_log(&ctx.http, &session).await;
+            send_session_summary(ctx, guild_id.0, msg.channel_id, &session).await;
+        }
+
         if let Err(e) = manager.remove(guild_id).await {
             check_msg(
                 msg.channel_id
@@ -267,6 +844,7 @@ async fn leave(ctx: &Context, msg: &Message) -> CommandResult {
                     .await,
             );
         }
+        supervisor::kill_guild(guild_id.0);
 
         check_msg(msg.channel_id.say(&ctx.http, "Left voice channel").await);
     } else {
@@ -279,8 +857,7 @@ async fn leave(ctx: &Context, msg: &Message) -> CommandResult {
 #[command]
 #[only_in(guilds)]
 async fn mute(ctx: &Context, msg: &Message) -> CommandResult {
-    let guild = msg.guild(&ctx.cache).unwrap();
-    let guild_id = guild.id;
+    let guild_id = guild::guild_id(msg)?;
 
     let manager = songbird::get(ctx)
         .await
@@ -324,20 +901,27 @@ async fn ping(ctx: &Context, msg: &Message) -> CommandResult {
 
 #[command]
 #[only_in(guilds)]
-async fn play_fade(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
-    let url = match args.single::<String>() {
-        Ok(url) => url,
-        Err(_) => {
-            check_msg(
-                msg.channel_id
-                    .say(&ctx.http, "Must provide a URL to a video or audio")
-                    .await,
-            );
+async fn shardinfo(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    let count = ctx.cache.shard_count();
+    let this = shard::shard_of(guild_id, count);
+    let guilds = shard::guild_counts(&ctx.cache.guilds(), count);
+    let statuses = shard::statuses(ctx).await;
+    let s = format!(
+        "This server is on shard {} of {}\n{}",
+        this,
+        count,
+        shard::describe(&statuses, &guilds, this)
+    );
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
 
-            return Ok(());
-        }
-    };
+    Ok(())
+}
 
+#[command]
+#[only_in(guilds)]
+async fn play_fade(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let url = args.required::<String>("~play_fade [URL]")?;
     if !url.starts_with("http") {
         check_msg(
             msg.channel_id
@@ -348,8 +932,7 @@ async fn play_fade(ctx: &Context, msg: &Message, mut args: Args) -> CommandResul
         return Ok(());
     }
 
-    let guild = msg.guild(&ctx.cache).unwrap();
-    let guild_id = guild.id;
+    let guild_id = guild::guild_id(msg)?;
 
     let manager = songbird::get(ctx)
         .await
@@ -357,21 +940,19 @@ async fn play_fade(ctx: &Context, msg: &Message, mut args: Args) -> CommandResul
         .clone();
 
     if let Some(handler_lock) = manager.get(guild_id) {
+        let player = GuildPlayer::new(ctx, guild_id.0, handler_lock.clone()).await;
+        let (quality, filter) = (player.quality().await, player.filter().await);
         let mut handler = handler_lock.lock().await;
-        let t = if url.contains("music.163.com") {
-            SourceType::Netease
-        } else {
-            SourceType::Ytdl
-        };
-
-        let source = match t {
-            SourceType::Netease => unwrap_or_show_error!(neteaseapi::netease(&url).await, msg, ctx),
-            SourceType::Ytdl => unwrap_or_show_error!(input::ytdl(&url).await, msg, ctx),
-        };
+        let provider = source::provider(&url);
+        let source = unwrap_or_show_error!(
+            provider.resolve(&url, false, quality, filter).await,
+            msg,
+            ctx
+        );
 
         // This handler object will allow you to, as needed,
         // control the audio track via events and further commands.
-        let song = handler.play_source(source);
+        let song = handler.play_source(source.into());
         let send_http = ctx.http.clone();
         let chan_id = msg.channel_id;
 
@@ -409,6 +990,48 @@ async fn play_fade(ctx: &Context, msg: &Message, mut args: Args) -> CommandResul
     Ok(())
 }
 
+/// How often `~now live` updates its message.
+const LIVE_NOW_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Keeps a `~now` message up to date until its track ends.
+struct NowUpdater {
+    message: Message,
+    http: Arc<Http>,
+    requester: Option<Requester>,
+    options: display::DisplayOptions,
+    filter: FilterHandle,
+}
+
+#[async_trait]
+impl VoiceEventHandler for NowUpdater {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if let EventContext::Track(&[(state, track)]) = ctx {
+            let edited = self
+                .message
+                .channel_id
+                .edit_message(&self.http, self.message.id, |m| {
+                    m.embed(|e| {
+                        reply::now(
+                            e,
+                            track.metadata(),
+                            state.position,
+                            self.requester.as_ref(),
+                            &self.options,
+                            &self.filter.get(),
+                        )
+                    })
+                })
+                .await;
+            // The message is gone, stop updating it.
+            if edited.is_err() {
+                return Some(Event::Cancel);
+            }
+        }
+
+        None
+    }
+}
+
 struct SongFader {
     chan_id: ChannelId,
     http: Arc<Http>,
@@ -452,426 +1075,4117 @@ impl VoiceEventHandler for SongEndNotifier {
     }
 }
 
-enum SourceType {
-    Ytdl,
-    Netease,
+#[command]
+#[aliases("播放")]
+#[only_in(guilds)]
+async fn play(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    play_query(ctx, msg, args.message().trim(), None).await
 }
 
 #[command]
 #[only_in(guilds)]
-async fn play(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
-    let url = match args.single::<String>() {
-        Ok(url) => url,
-        Err(_) => {
-            check_msg(
-                msg.channel_id
-                    .say(&ctx.http, "Must provide a URL to a video or audio")
-                    .await,
-            );
+async fn playnext(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    play_query(ctx, msg, args.message().trim(), Some(1)).await
+}
 
-            return Ok(());
-        }
-    };
+#[command]
+#[only_in(guilds)]
+async fn insert(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    // Position 1 is the playing song.
+    let position = args.within(2..=usize::MAX, "~insert [POS] [URL|KEYWORDS] (POS from 2)")?;
 
-    if !url.starts_with("http") {
+    play_query(ctx, msg, args.rest().trim(), Some(position - 1)).await
+}
+
+/// Plays the URL or the best match of the keywords in `query`, at queue
+/// index `position` or the end.
+async fn play_query(
+    ctx: &Context,
+    msg: &Message,
+    query: &str,
+    position: Option<usize>,
+) -> CommandResult {
+    if query.is_empty() && !msg.attachments.is_empty() {
+        return play_attachments(ctx, msg, position).await;
+    }
+    if query.is_empty() {
         check_msg(
             msg.channel_id
-                .say(&ctx.http, "Must provide a valid URL")
+                .say(
+                    &ctx.http,
+                    "Must provide a URL or keywords to a video or audio",
+                )
                 .await,
         );
 
         return Ok(());
     }
 
-    let song_volume_lock = {
-        let read = ctx.data.read().await;
-
-        read.get::<SongVolume>()
-            .expect("Expected SongVolume in TypeMap.")
-            .clone()
-    };
-
-    let volume = {
-        let mut song_volume = song_volume_lock.write().await;
-        let entry = song_volume
-            .entry(msg.channel_id.0)
-            .or_insert(1.0)
-            .to_owned();
-
-        entry
-    };
-
-    let guild = msg.guild(&ctx.cache).unwrap();
-    let guild_id = guild.id;
+    let mut shuffled = false;
+    let url = if query.starts_with("http") {
+        let mut words = query.split_whitespace();
+        let url = words.next().unwrap_or(query).to_string();
+        shuffled = words.any(|x| x == "shuffled");
 
-    let manager = songbird::get(ctx)
-        .await
-        .expect("Songbird Voice client placed in at initialisation.")
-        .clone();
-
-    if let Some(handler_lock) = manager.get(guild_id) {
-        let mut handler = handler_lock.lock().await;
-        let t = if url.contains("music.163.com") {
-            SourceType::Netease
-        } else {
-            SourceType::Ytdl
-        };
+        url
+    } else {
+        let songs = match search::search(query, 1).await {
+            Ok(songs) => songs,
+            Err(why) => {
+                warn!("Err searching songs: {:?}", why);
+                check_msg(msg.channel_id.say(&ctx.http, "Error searching songs").await);
 
-        // Here, we use lazy restartable sources to make sure that we don't pay
-        // for decoding, playback on tracks which aren't actually live yet.
-        let source = match t {
-            SourceType::Ytdl => unwrap_or_show_error!(Restartable::ytdl(url, true).await, msg, ctx),
-            SourceType::Netease => {
-                unwrap_or_show_error!(neteaseapi::netease_restartable(&url, true).await, msg, ctx)
+                return Ok(());
             }
         };
 
-        handler.enqueue_source(source.into());
-        let queue = handler.queue().current_queue();
-        let last = queue.last().ok_or_else(|| anyhow!("Can not get last!"))?;
-        last.set_volume(volume)?;
-        let metadata = last.metadata();
-        let title = metadata.title.clone();
-        let url = metadata.source_url.clone();
-        let s = if let Some(title) = title {
-            title
-        } else if let Some(url) = url {
-            url
-        } else {
-            "song".to_string()
-        };
+        match songs.into_iter().next().and_then(|x| x.source_url) {
+            Some(url) => url,
+            None => {
+                check_msg(msg.channel_id.say(&ctx.http, "No song found").await);
 
-        check_msg(
-            msg.channel_id
-                .say(&ctx.http, format!("Added {} to queue", s))
-                .await,
-        );
-    } else {
-        check_msg(
-            msg.channel_id
-                .say(&ctx.http, "Not in a voice channel to play in")
-                .await,
-        );
-    }
+                return Ok(());
+            }
+        }
+    };
 
-    Ok(())
-}
+    let request = Request {
+        position,
+        ..Request::try_from(msg)?
+    };
 
-#[command]
-#[only_in(guilds)]
-async fn skip(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
-    let guild = msg.guild(&ctx.cache).unwrap();
-    let guild_id = guild.id;
+    enqueue(ctx, &request, url, shuffled).await
+}
 
-    let manager = songbird::get(ctx)
-        .await
-        .expect("Songbird Voice client placed in at initialisation.")
-        .clone();
+/// Queues the audio files attached to `msg`, in order from `position`.
+async fn play_attachments(ctx: &Context, msg: &Message, position: Option<usize>) -> CommandResult {
+    let mut request = Request {
+        position,
+        ..Request::try_from(msg)?
+    };
+    for attachment in &msg.attachments {
+        if let Some(why) = local::check(attachment) {
+            check_msg(msg.channel_id.say(&ctx.http, why).await);
 
-    if let Some(handler_lock) = manager.get(guild_id) {
-        let handler = handler_lock.lock().await;
-        let queue = handler.queue();
-        if args.is_empty() {
-            let _ = queue.skip();
-        } else if let Ok(index) = args.single::<usize>() {
-            if index < 1 || index > queue.current_queue().len() {
+            continue;
+        }
+        match local::download(attachment).await {
+            Ok(url) => {
+                enqueue(ctx, &request, url, false).await?;
+                request.position = request.position.map(|x| x + 1);
+            }
+            Err(why) => {
+                warn!("Err downloading attachment: {:?}", why);
                 check_msg(
                     msg.channel_id
-                        .say(&ctx.http, "Index must 1 to queue length!".to_string())
+                        .say(
+                            &ctx.http,
+                            format!("Error downloading {}", attachment.filename),
+                        )
                         .await,
-                )
-            } else {
-                queue.dequeue(index - 1);
+                );
             }
         }
+    }
+
+    Ok(())
+}
 
+#[command]
+#[only_in(guilds)]
+async fn local(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let query = args.message().trim();
+    if local::music_dir().is_none() {
         check_msg(
             msg.channel_id
                 .say(
                     &ctx.http,
-                    format!("Song skipped: {} in queue.", queue.len()),
+                    "Local music is off, the bot's owner can turn it on with LOCAL_MUSIC_DIR",
                 )
                 .await,
         );
-    } else {
+
+        return Ok(());
+    }
+    if query.is_empty() {
         check_msg(
             msg.channel_id
-                .say(&ctx.http, "Not in a voice channel to play in")
+                .say(&ctx.http, "Usage: ~local [PATH|KEYWORDS]")
                 .await,
         );
+
+        return Ok(());
     }
 
-    Ok(())
+    match local::find(query).await {
+        Ok(Some(url)) => enqueue(ctx, &Request::try_from(msg)?, url, false).await,
+        Ok(None) => {
+            check_msg(msg.channel_id.say(&ctx.http, "No local song found").await);
+
+            Ok(())
+        }
+        Err(why) => {
+            warn!("Err searching local music: {:?}", why);
+            check_msg(
+                msg.channel_id
+                    .say(&ctx.http, "Error searching local music")
+                    .await,
+            );
+
+            Ok(())
+        }
+    }
 }
 
+const SEARCH_LIMIT: usize = 10;
+const SEARCH_REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
 #[command]
+#[aliases("搜索")]
 #[only_in(guilds)]
-async fn clear(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
-    let guild = msg.guild(&ctx.cache).unwrap();
-    let guild_id = guild.id;
+async fn search(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let keywords = args.message().trim();
 
-    let manager = songbird::get(ctx)
-        .await
-        .expect("Songbird Voice client placed in at initialisation.")
-        .clone();
+    if keywords.is_empty() {
+        check_msg(
+            msg.channel_id
+                .say(&ctx.http, "Must provide keywords to search")
+                .await,
+        );
 
-    if let Some(handler_lock) = manager.get(guild_id) {
+        return Ok(());
+    }
+
+    let songs = match search::search(keywords, SEARCH_LIMIT).await {
+        Ok(songs) => songs,
+        Err(why) => {
+            warn!("Err searching songs: {:?}", why);
+            check_msg(msg.channel_id.say(&ctx.http, "Error searching songs").await);
+
+            return Ok(());
+        }
+    };
+
+    if songs.is_empty() {
+        check_msg(msg.channel_id.say(&ctx.http, "No song found").await);
+
+        return Ok(());
+    }
+
+    let mut s = String::new();
+    for (i, song) in songs.iter().enumerate() {
+        s.push_str(&format!("{}. {}", i + 1, track_name(song)));
+        if let Some(artist) = song.artist.as_ref().filter(|x| !x.is_empty()) {
+            s.push_str(&format!(" - {}", artist));
+        }
+        if let Some(duration) = &song.duration {
+            s.push_str(&format!(" {}", duration_formatter(duration)));
+        }
+        s.push('\n');
+    }
+    s.push_str("Reply with a number to add it to queue");
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+    let reply = msg
+        .channel_id
+        .await_reply(ctx)
+        .author_id(msg.author.id)
+        .timeout(SEARCH_REPLY_TIMEOUT)
+        .await;
+    let url = reply
+        // Without message content the pick has to mention the bot first.
+        .and_then(|x| x.content.split_whitespace().last()?.parse::<usize>().ok())
+        .and_then(|x| x.checked_sub(1))
+        .and_then(|x| songs.into_iter().nth(x))
+        .and_then(|x| x.source_url);
+
+    match url {
+        Some(url) => enqueue(ctx, &Request::try_from(msg)?, url, false).await,
+        None => {
+            check_msg(msg.channel_id.say(&ctx.http, "No song selected").await);
+
+            Ok(())
+        }
+    }
+}
+
+/// Where a song request came from.
+struct Request {
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    requester: Requester,
+    /// Queue index to put the songs at, the end when `None`.
+    position: Option<usize>,
+    /// How far into the song it starts, for bookmarks.
+    start: Option<Duration>,
+}
+
+impl TryFrom<&Message> for Request {
+    type Error = guild::GuildError;
+
+    fn try_from(msg: &Message) -> Result<Self, Self::Error> {
+        Ok(Self {
+            guild_id: guild::guild_id(msg)?,
+            channel_id: msg.channel_id,
+            requester: Requester::from(msg),
+            position: None,
+            start: None,
+        })
+    }
+}
+
+/// Tells why `url` could not be queued, with a button to try again when
+/// the error may go away.
+async fn say_failure(
+    ctx: &Context,
+    request: &Request,
+    url: String,
+    shuffled: bool,
+    why: &anyhow::Error,
+    s: String,
+) {
+    if !retry::is_transient(why) {
+        check_msg(request.channel_id.say(&ctx.http, s).await);
+
+        return;
+    }
+
+    let id = retry::remember(
+        ctx,
+        url,
+        shuffled,
+        request.requester.clone(),
+        request.position,
+        request.start,
+    )
+    .await;
+    check_msg(
+        request
+            .channel_id
+            .send_message(&ctx.http, |m| {
+                m.content(format!("{}, the service may be busy", s))
+                    .components(|c| retry::button(c, id))
+            })
+            .await,
+    );
+}
+
+/// Runs a failed request again from its "Retry" button.
+async fn retry_request(ctx: &Context, component: &MessageComponentInteraction, id: &str) {
+    let retry = retry::take(ctx, id).await;
+    let content = match &retry {
+        Some(_) => "Retrying...",
+        None => "This request can no longer be retried",
+    };
+    if let Err(e) = component
+        .create_interaction_response(&ctx.http, |r| {
+            r.kind(InteractionResponseType::UpdateMessage)
+                .interaction_response_data(|d| d.content(content).components(|c| c))
+        })
+        .await
+    {
+        warn!("Err answering retry: {:?}", e);
+    }
+
+    let (guild_id, retry) = match (component.guild_id, retry) {
+        (Some(guild_id), Some(retry)) => (guild_id, retry),
+        _ => return,
+    };
+    let request = Request {
+        guild_id,
+        channel_id: component.channel_id,
+        requester: retry.requester,
+        position: retry.position,
+        start: retry.start,
+    };
+    if let Err(e) = enqueue(ctx, &request, retry.url, retry.shuffled).await {
+        warn!("Err retrying request: {:?}", e);
+    }
+}
+
+/// Whether the queue holds as many songs as the guild allows.
+pub(crate) async fn queue_full(handler_lock: &Arc<Mutex<Call>>, settings: &Settings) -> bool {
+    let len = handler_lock.lock().await.queue().len();
+
+    settings.max_queue.is_some_and(|max| len >= max)
+}
+
+/// Adds the song (or every song of the playlist) at `url` to the queue,
+/// the songs of a playlist in random order when `shuffled`.
+#[tracing::instrument(skip_all, fields(url = %url))]
+async fn enqueue(ctx: &Context, request: &Request, url: String, shuffled: bool) -> CommandResult {
+    let guild_id = request.guild_id;
+    let settings = settings::get(ctx, guild_id.0).await;
+    let song_volume_lock = {
+        let read = ctx.data.read().await;
+
+        read.get::<SongVolume>()
+            .expect("Expected SongVolume in TypeMap.")
+            .clone()
+    };
+
+    let volume = {
+        let mut song_volume = song_volume_lock.write().await;
+        let entry = song_volume
+            .entry(request.channel_id.0)
+            .or_insert(settings.default_volume())
+            .to_owned();
+
+        entry
+    };
+
+    let volume = limiter::cap(volume, playback::volume_ceiling(ctx, guild_id.0).await);
+
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+
+    let handler_lock = match manager.get(guild_id) {
+        Some(handler_lock) => handler_lock,
+        None => {
+            check_msg(
+                request
+                    .channel_id
+                    .say(&ctx.http, "Not in a voice channel to play in")
+                    .await,
+            );
+
+            return Ok(());
+        }
+    };
+
+    playback::announce_in(ctx, guild_id.0, request.channel_id).await;
+
+    let player = GuildPlayer::new(ctx, guild_id.0, handler_lock.clone()).await;
+    // DJs may queue songs of any length.
+    let length_limited = settings.max_duration().is_some()
+        && !dj::is_member_dj(ctx, guild_id, UserId(request.requester.id)).await;
+
+    let provider = source::provider(&url);
+    if provider.is_playlist(&url) {
+        let quality = player.quality().await;
+        let filter = player.filter().await;
+        let mut urls = match playlist::expand(provider, &url).await {
+            Ok(urls) => urls,
+            Err(why) => {
+                warn!("Err expanding playlist: {:?}", why);
+                let s = error::user_message(&why)
+                    .unwrap_or_else(|| "Error sourcing ffmpeg".to_string());
+                say_failure(ctx, request, url, shuffled, &why, s).await;
+
+                return Ok(());
+            }
+        };
+        if shuffled {
+            urls.shuffle(&mut rand::thread_rng());
+        }
+        let adding = request
+            .channel_id
+            .say(
+                &ctx.http,
+                format!(
+                    "Adding {} songs from playlist{}...",
+                    urls.len(),
+                    if shuffled { " in random order" } else { "" }
+                ),
+            )
+            .await;
+        if let Err(why) = &adding {
+            warn!("Error sending message: {:?}", why);
+        }
+
+        let mut progress = resolve_pool::Progress::new(urls.len());
+        // A few songs resolve at once, they are still added in order.
+        let mut resolving = futures_util::stream::iter(urls)
+            .map(|url| {
+                let filter = filter.clone();
+                async move {
+                    let resolved =
+                        resolve_pool::resolve(guild_id.0, url.clone(), quality, filter).await;
+                    (url, resolved)
+                }
+            })
+            .buffered(*resolve_pool::GUILD_WORKERS);
+        let mut added = vec![];
+        let mut fallbacks = 0;
+        let mut too_long = 0;
+        let mut full = false;
+        while let Some((url, resolved)) = resolving.next().await {
+            if let (Some(s), Ok(adding)) = (progress.step(Instant::now()), &adding) {
+                check_msg(
+                    adding
+                        .channel_id
+                        .edit_message(&ctx.http, adding.id, |m| m.content(s))
+                        .await,
+                );
+            }
+            if queue_full(&handler_lock, &settings).await {
+                full = true;
+                break;
+            }
+            match resolved {
+                Ok(resolved)
+                    if length_limited && settings.too_long(resolved.input.metadata.duration) =>
+                {
+                    too_long += 1;
+                }
+                Ok(resolved) => {
+                    if resolved.fallback.is_some() {
+                        fallbacks += 1;
+                    }
+                    // Behind the songs added before, keeping the playlist order.
+                    let position = request.position.map(|at| at + added.len());
+                    let track = add_track(
+                        &player,
+                        resolved.input,
+                        position,
+                        volume,
+                        request.requester.clone(),
+                    )
+                    .await?;
+                    added.push((url, track.metadata().clone()));
+                }
+                Err(why) => {
+                    warn!("Err starting source {}: {:?}", url, why);
+                    if error::user_message(&why).is_none() {
+                        report::error("resolve", Some(guild_id.0), format!("{}: {:?}", url, why));
+                    }
+                }
+            }
+        }
+
+        let mut s = format!("Added {} songs to queue", added.len());
+        if fallbacks > 0 {
+            s.push_str(&format!(
+                " ({} found on other services as their links failed)",
+                fallbacks
+            ));
+        }
+        if too_long > 0 {
+            s.push_str(&format!(
+                ", {} longer than {} minutes left out",
+                too_long,
+                settings.max_duration().unwrap_or_default().as_secs() / 60
+            ));
+        }
+        if full {
+            s.push_str(", the queue is full");
+        }
+        check_msg(request.channel_id.say(&ctx.http, s).await);
+        history::record(ctx, guild_id.0, &request.requester, added).await;
+
+        return Ok(());
+    }
+
+    if queue_full(&handler_lock, &settings).await {
+        check_msg(request.channel_id.say(&ctx.http, "The queue is full").await);
+
+        return Ok(());
+    }
+
+    let resolved = resolve_pool::resolve(
+        guild_id.0,
+        url.clone(),
+        player.quality().await,
+        player.filter().await,
+    )
+    .await;
+    let resolved = match resolved {
+        Ok(resolved) => resolved,
+        Err(why) => {
+            warn!("Err starting source: {:?}", why);
+            let s = match error::user_message(&why) {
+                Some(s) => s,
+                None => {
+                    report::error("resolve", Some(guild_id.0), format!("{}: {:?}", url, why));
+                    "Error sourcing ffmpeg".to_string()
+                }
+            };
+            say_failure(ctx, request, url, shuffled, &why, s).await;
+
+            return Ok(());
+        }
+    };
+    if length_limited && settings.too_long(resolved.input.metadata.duration) {
+        let s = format!(
+            "Songs may be up to {} minutes long, only DJs can queue longer ones",
+            settings.max_duration().unwrap_or_default().as_secs() / 60
+        );
+        check_msg(request.channel_id.say(&ctx.http, s).await);
+
+        return Ok(());
+    }
+    let track = add_track(
+        &player,
+        resolved.input,
+        request.position,
+        volume,
+        request.requester.clone(),
+    )
+    .await?;
+    if let Some(position) = request.start {
+        track.add_event(
+            Event::Track(TrackEvent::Play),
+            bookmark::StartAt {
+                position,
+                filter: player.filter().await,
+            },
+        )?;
+    }
+    let metadata = track.metadata().clone();
+    history::record(
+        ctx,
+        guild_id.0,
+        &request.requester,
+        vec![(url, metadata.clone())],
+    )
+    .await;
+
+    let options = display::display_options(ctx, guild_id.0).await;
+    check_msg(
+        request
+            .channel_id
+            .send_message(&ctx.http, |m| {
+                m.embed(|e| {
+                    reply::added(
+                        e,
+                        &metadata,
+                        Some(&request.requester),
+                        resolved.fallback.map(|x| x.name()),
+                        &options,
+                    )
+                })
+            })
+            .await,
+    );
+
+    Ok(())
+}
+
+/// Queues `input` at `position`, the end when `None`, and hooks it up to
+/// the guild's playback like every song.
+pub(crate) async fn add_track(
+    player: &GuildPlayer,
+    input: Input,
+    position: Option<usize>,
+    volume: f32,
+    requester: Requester,
+) -> TrackResult<TrackHandle> {
+    // `attach` locks the call itself.
+    let (track, first) = {
+        let mut handler = player.call.lock().await;
+        let first = handler.queue().is_empty();
+        let track = handler.enqueue_source(input);
+        if let Some(at) = position {
+            let pinned = queue::pinned(handler.queue()).await;
+            queue::insert_at(handler.queue(), &pinned, at);
+        }
+        (track, first)
+    };
+    track.set_volume(volume)?;
+    queue::set_requester(&track, requester).await;
+    player.attach(&track, first).await?;
+
+    Ok(track)
+}
+
+/// Skips the playing song, fading it out if the guild has crossfade on.
+async fn skip_current(ctx: &Context, guild_id: u64, queue: &TrackQueue) {
+    if let Some(current) = queue.current() {
+        playback::record_skip(ctx, guild_id, track_name(current.metadata())).await;
+    }
+    match playback::crossfade(ctx, guild_id).await {
+        Some(fade) => {
+            let queue = queue.clone();
+            tokio::spawn(async move {
+                if let Err(e) = crossfade::skip(&queue, fade).await {
+                    warn!("Err crossfading: {:?}", e);
+                }
+            });
+        }
+        None => {
+            let _ = queue.skip();
+        }
+    }
+}
+
+pub(crate) async fn vote_skip_on(ctx: &Context, guild_id: u64) -> bool {
+    let lock = playback::playback_lock(ctx).await;
+    let playback = lock.read().await;
+
+    playback.get(&guild_id).and_then(|x| x.vote_skip).is_some()
+}
+
+fn vote_button(c: &mut CreateComponents) -> &mut CreateComponents {
+    c.create_action_row(|r| {
+        r.create_button(|b| {
+            b.custom_id(vote::VOTE_BUTTON)
+                .label("Vote skip")
+                .style(ButtonStyle::Primary)
+        })
+    })
+}
+
+async fn vote_skip(ctx: &Context, component: &MessageComponentInteraction) {
+    let guild_id = match component.guild_id {
+        Some(guild_id) => guild_id,
+        None => return,
+    };
+
+    let vote = vote::vote(ctx, guild_id, component.user.id.0).await;
+    let response = component
+        .create_interaction_response(&ctx.http, |r| match &vote {
+            Vote::NotListening => r
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|d| {
+                    d.content("Only listeners can vote to skip").ephemeral(true)
+                }),
+            Vote::Pending { votes, required } => r
+                .kind(InteractionResponseType::UpdateMessage)
+                .interaction_response_data(|d| {
+                    d.content(format!("Vote to skip: {}/{}", votes, required))
+                }),
+            Vote::Passed => r
+                .kind(InteractionResponseType::UpdateMessage)
+                .interaction_response_data(|d| {
+                    d.content("Vote passed, song skipped").components(|c| c)
+                }),
+            Vote::Off => r
+                .kind(InteractionResponseType::UpdateMessage)
+                .interaction_response_data(|d| d.content("Vote ended").components(|c| c)),
+        })
+        .await;
+    if let Err(e) = response {
+        warn!("Err answering vote: {:?}", e);
+    }
+
+    if let Vote::Passed = vote {
+        let manager = songbird::get(ctx)
+            .await
+            .expect("Songbird Voice client placed in at initialisation.")
+            .clone();
+        if let Some(handler_lock) = manager.get(guild_id) {
+            let handler = handler_lock.lock().await;
+            skip_current(ctx, guild_id.0, handler.queue()).await;
+        }
+    }
+}
+
+#[command]
+#[aliases("跳过")]
+#[only_in(guilds)]
+async fn skip(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+
+    if args.is_empty() && vote_skip_on(ctx, guild_id.0).await && !dj::is_dj(ctx, msg).await {
+        match vote::vote(ctx, guild_id, msg.author.id.0).await {
+            Vote::Off | Vote::Passed => (),
+            Vote::NotListening => {
+                check_msg(msg.reply(ctx, "Only listeners can vote to skip").await);
+
+                return Ok(());
+            }
+            Vote::Pending { votes, required } => {
+                check_msg(
+                    msg.channel_id
+                        .send_message(&ctx.http, |m| {
+                            m.content(format!("Vote to skip: {}/{}", votes, required))
+                                .components(vote_button)
+                        })
+                        .await,
+                );
+
+                return Ok(());
+            }
+        }
+    }
+
+    if let Some(handler_lock) = manager.get(guild_id) {
         let handler = handler_lock.lock().await;
         let queue = handler.queue();
-        let _ = queue.stop();
+        if args.is_empty() {
+            skip_current(ctx, guild_id.0, queue).await;
+        } else if let Some(index) = args.optional::<usize>() {
+            let list = queue.current_queue();
+            if let Some(entry) = queue::entry(&list, index) {
+                if let Some(requester) = queue::requester(entry).await {
+                    if !requester.may_remove(msg.author.id.0) && !dj::is_dj(ctx, msg).await {
+                        check_msg(
+                            msg.reply(
+                                ctx,
+                                format!("Only {} or a DJ can remove this song", requester.name),
+                            )
+                            .await,
+                        );
+
+                        return Ok(());
+                    }
+                }
+                if let Some(removed) = queue.dequeue(index - 1) {
+                    let title = track_name(removed.handle().metadata());
+                    playback::record_skip(ctx, guild_id.0, title).await;
+                }
+            } else {
+                check_msg(
+                    msg.channel_id
+                        .say(&ctx.http, "Index must 1 to queue length!".to_string())
+                        .await,
+                )
+            }
+        }
+
+        check_msg(
+            msg.channel_id
+                .say(
+                    &ctx.http,
+                    format!("Song skipped: {} in queue.", queue.len()),
+                )
+                .await,
+        );
+    } else {
+        check_msg(
+            msg.channel_id
+                .say(&ctx.http, "Not in a voice channel to play in")
+                .await,
+        );
+    }
+
+    Ok(())
+}
+
+/// Takes the `tracks` the user may remove out of the queue. Returns how many
+/// were removed and how many were requested by others and need a DJ.
+async fn remove_entries(
+    ctx: &Context,
+    guild_id: u64,
+    user: u64,
+    is_dj: bool,
+    queue: &TrackQueue,
+    tracks: &[TrackHandle],
+) -> (usize, usize) {
+    let mut allowed = vec![];
+    let mut not_allowed = 0;
+    for track in tracks {
+        if let Some(requester) = queue::requester(track).await {
+            if !requester.may_remove(user) && !is_dj {
+                not_allowed += 1;
+                continue;
+            }
+        }
+        allowed.push(track.clone());
+    }
+
+    let pinned = queue::pinned(queue).await;
+    let removed = queue::remove(queue, &pinned, &allowed);
+    for track in &removed {
+        let _ = track.stop();
+        playback::record_skip(ctx, guild_id, track_name(track.metadata())).await;
+    }
+
+    (removed.len(), not_allowed)
+}
+
+const REMOVE_USAGE: &str = "~remove [FROM-TO|@USER]";
+
+#[command]
+#[only_in(guilds)]
+async fn remove(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    let span = args.optional::<Span>();
+    let user = match span {
+        Some(_) => None,
+        None => {
+            let target = args.required::<String>(REMOVE_USAGE)?;
+            Some(serenity::utils::parse_username(target).ok_or(Usage(REMOVE_USAGE.into()))?)
+        }
+    };
+
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    let handler_lock = match manager.get(guild_id) {
+        Some(handler_lock) => handler_lock,
+        None => {
+            check_msg(
+                msg.channel_id
+                    .say(&ctx.http, "Not in a voice channel to play in")
+                    .await,
+            );
+
+            return Ok(());
+        }
+    };
+    let handler = handler_lock.lock().await;
+    let queue = handler.queue();
+    let current = queue.current_queue();
+    // The playing song is left to ~skip.
+    let tracks = match (span, user) {
+        (Some(Span { first, last }), _) => current
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| *i > 0 && (first - 1..last).contains(i))
+            .map(|(_, x)| x)
+            .collect(),
+        (None, Some(user)) => {
+            let mut tracks = vec![];
+            for track in current.into_iter().skip(1) {
+                if queue::requester(&track).await.is_some_and(|x| x.id == user) {
+                    tracks.push(track);
+                }
+            }
+            tracks
+        }
+        (None, None) => vec![],
+    };
+
+    let is_dj = dj::is_dj(ctx, msg).await;
+    let (removed, not_allowed) =
+        remove_entries(ctx, guild_id.0, msg.author.id.0, is_dj, queue, &tracks).await;
+    let mut s = format!("Removed {} songs, {} in queue", removed, queue.len());
+    if not_allowed > 0 {
+        s.push_str(&format!(", {} requested by others need a DJ", not_allowed));
+    }
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn dedupe(ctx: &Context, msg: &Message) -> CommandResult {
+    if !dj::is_dj(ctx, msg).await {
+        check_msg(msg.reply(ctx, "Only DJs can dedupe the queue").await);
+
+        return Ok(());
+    }
+
+    let guild_id = guild::guild_id(msg)?;
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    let handler_lock = match manager.get(guild_id) {
+        Some(handler_lock) => handler_lock,
+        None => {
+            check_msg(
+                msg.channel_id
+                    .say(&ctx.http, "Not in a voice channel to play in")
+                    .await,
+            );
+
+            return Ok(());
+        }
+    };
+    let handler = handler_lock.lock().await;
+    let queue = handler.queue();
+    let current = queue.current_queue();
+    let duplicates = {
+        let urls = current
+            .iter()
+            .map(|x| x.metadata().source_url.as_deref())
+            .collect::<Vec<_>>();
+        queue::later_duplicates(&urls)
+    };
+    let tracks = duplicates
+        .into_iter()
+        .map(|i| current[i].clone())
+        .collect::<Vec<_>>();
+
+    let (removed, _) = remove_entries(ctx, guild_id.0, msg.author.id.0, true, queue, &tracks).await;
+    check_msg(
+        msg.channel_id
+            .say(
+                &ctx.http,
+                format!(
+                    "Removed {} duplicate songs, {} in queue",
+                    removed,
+                    queue.len()
+                ),
+            )
+            .await,
+    );
+
+    Ok(())
+}
+
+const ETA_USAGE: &str = "~eta [INDEX|@USER]";
+
+#[command]
+#[only_in(guilds)]
+async fn eta(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    let index = args.optional::<usize>();
+    let user = match index {
+        Some(_) => None,
+        None => {
+            let target = args.required::<String>(ETA_USAGE)?;
+            Some(serenity::utils::parse_username(target).ok_or(Usage(ETA_USAGE.into()))?)
+        }
+    };
+
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    let tracks = match manager.get(guild_id) {
+        Some(handler_lock) => handler_lock.lock().await.queue().current_queue(),
+        None => vec![],
+    };
+    // 0-based index of the entry in the queue.
+    let found = match (index, user) {
+        (Some(index), _) => index.checked_sub(1).filter(|x| *x < tracks.len()),
+        (None, Some(user)) => {
+            let mut found = None;
+            for (i, track) in tracks.iter().enumerate() {
+                if queue::requester(track).await.is_some_and(|x| x.id == user) {
+                    found = Some(i);
+                    break;
+                }
+            }
+            found
+        }
+        (None, None) => None,
+    };
+    let i = match found {
+        Some(i) => i,
+        None => {
+            let s = match user {
+                Some(_) => "No song of theirs in the queue",
+                None => "Index out of range",
+            };
+            check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+            return Ok(());
+        }
+    };
+
+    let effects = playback::effects(ctx, guild_id.0).await;
+    let remaining = match tracks[0].metadata().duration {
+        Some(duration) => tracks[0]
+            .get_info()
+            .await
+            .ok()
+            .map(|x| effects.played(duration).saturating_sub(x.position)),
+        None => None,
+    };
+    let ahead = tracks[1..i.max(1)]
+        .iter()
+        .map(|x| x.metadata().duration.map(|x| effects.played(x)))
+        .collect::<Vec<_>>();
+    let loop_mode = {
+        let lock = playback::playback_lock(ctx).await;
+        let playback = lock.read().await;
+        playback
+            .get(&guild_id.0)
+            .map(|x| x.loop_mode)
+            .unwrap_or_default()
+    };
+
+    let name = track_name(tracks[i].metadata());
+    let s = match (i, eta::eta(remaining, &ahead, loop_mode)) {
+        (0, _) => format!("{} is playing now", name),
+        (_, Eta::Looped) => format!(
+            "{} starts once the looped song is skipped, or ~loop off",
+            name
+        ),
+        (_, Eta::In { time, unknown: 0 }) => {
+            format!("{} starts in {}", name, duration_formatter(&time))
+        }
+        (_, Eta::In { time, unknown }) => format!(
+            "{} starts in {} at least, {} songs before it have no known length",
+            name,
+            duration_formatter(&time),
+            unknown
+        ),
+    };
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn boost(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    if !dj::is_dj(ctx, msg).await {
+        check_msg(msg.reply(ctx, "Only DJs can boost songs").await);
+
+        return Ok(());
+    }
+
+    let index = args.required::<usize>("~boost [INDEX]")?;
+
+    let guild_id = guild::guild_id(msg)?;
+
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+
+    if let Some(handler_lock) = manager.get(guild_id) {
+        let handler = handler_lock.lock().await;
+
+        // `~list` counts from 1 and entry 1 is the song being played.
+        let boosted = if index > 1 {
+            let pinned = queue::pinned(handler.queue()).await;
+            queue::play_next(handler.queue(), &pinned, index - 1)
+        } else {
+            None
+        };
+
+        match boosted {
+            Some(track) => {
+                queue::pin(&track).await;
+                check_msg(
+                    msg.channel_id
+                        .say(
+                            &ctx.http,
+                            format!("{} will play next", track_name(track.metadata())),
+                        )
+                        .await,
+                );
+            }
+            None => check_msg(
+                msg.channel_id
+                    .say(&ctx.http, "Index must 2 to queue length!")
+                    .await,
+            ),
+        }
+    } else {
+        check_msg(
+            msg.channel_id
+                .say(&ctx.http, "Not in a voice channel to play in")
+                .await,
+        );
+    }
+
+    Ok(())
+}
+
+const CROSSFADE_RANGE: RangeInclusive<u64> = 1..=12;
+
+#[command]
+#[only_in(guilds)]
+async fn crossfade(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    let playback_lock = playback::playback_lock(ctx).await;
+
+    if args.is_empty() {
+        let s = match playback::crossfade(ctx, guild_id.0).await {
+            Some(fade) => format!("Crossfade is {}s", fade.as_secs()),
+            None => "Crossfade is off".to_string(),
+        };
+        check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+        return Ok(());
+    }
+
+    let fade = if args.flag("off") {
+        None
+    } else {
+        let secs = args.within(CROSSFADE_RANGE, "~crossfade [1~12|off]")?;
+        Some(Duration::from_secs(secs))
+    };
+
+    {
+        let mut playback = playback_lock.write().await;
+        playback.entry(guild_id.0).or_default().crossfade = fade;
+    }
+
+    let s = match fade {
+        Some(fade) => format!("Crossfade set to {}s", fade.as_secs()),
+        None => "Crossfade disabled".to_string(),
+    };
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn radiodj(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    let playback_lock = playback::playback_lock(ctx).await;
+
+    let language = match args.optional::<Switch>() {
+        Some(Switch(true)) => Some(
+            args.optional::<String>()
+                .unwrap_or_else(|| radio_dj::DEFAULT_LANGUAGE.to_string()),
+        ),
+        Some(Switch(false)) => None,
+        _ => {
+            let s = {
+                let playback = playback_lock.read().await;
+                match playback.get(&guild_id.0).and_then(|x| x.radio_dj.as_ref()) {
+                    Some(language) => format!("Radio DJ is on ({})", language),
+                    None => "Radio DJ is off".to_string(),
+                }
+            };
+            check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+            return Ok(());
+        }
+    };
+
+    let s = match &language {
+        Some(language) => format!("Radio DJ enabled ({})", language),
+        None => "Radio DJ disabled".to_string(),
+    };
+
+    {
+        let mut playback = playback_lock.write().await;
+        playback.entry(guild_id.0).or_default().radio_dj = language;
+    }
+
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+    Ok(())
+}
+
+#[command("loop")]
+#[only_in(guilds)]
+async fn loop_mode(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    let playback_lock = playback::playback_lock(ctx).await;
+
+    if args.is_empty() {
+        let mode = {
+            let playback = playback_lock.read().await;
+            playback
+                .get(&guild_id.0)
+                .map(|x| x.loop_mode)
+                .unwrap_or_default()
+        };
+        check_msg(
+            msg.channel_id
+                .say(&ctx.http, format!("Loop mode is {}", mode))
+                .await,
+        );
+
+        return Ok(());
+    }
+
+    let mode = args.required::<LoopMode>("~loop [off|track|queue]")?;
+
+    {
+        let mut playback = playback_lock.write().await;
+        playback.entry(guild_id.0).or_default().loop_mode = mode;
+    }
+
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+
+    if let Some(handler_lock) = manager.get(guild_id) {
+        let handler = handler_lock.lock().await;
+        if let Some(current) = handler.queue().current() {
+            looping::apply(mode, &current)?;
+        }
+    }
+
+    check_msg(
+        msg.channel_id
+            .say(&ctx.http, format!("Loop mode set to {}", mode))
+            .await,
+    );
+
+    Ok(())
+}
+
+#[command]
+#[aliases("歌词")]
+#[only_in(guilds)]
+async fn lyrics(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let sync = args.flag("sync");
+    let mode = if args.is_empty() {
+        LyricsMode::Original
+    } else {
+        args.required::<LyricsMode>("~lyrics [sync] [cn|trans|both]")?
+    };
+
+    let guild_id = guild::guild_id(msg)?;
+
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+
+    let current = match manager.get(guild_id) {
+        Some(handler_lock) => handler_lock.lock().await.queue().current(),
+        None => None,
+    };
+    let current = match current {
+        Some(current) => current,
+        None => {
+            check_msg(msg.channel_id.say(&ctx.http, "Nothing is playing").await);
+
+            return Ok(());
+        }
+    };
+
+    let lyrics = match lyrics::fetch(current.metadata()).await {
+        Ok(lyrics) => lyrics,
+        Err(why) => {
+            warn!("Err getting lyrics: {:?}", why);
+            check_msg(msg.channel_id.say(&ctx.http, "Can not get lyrics").await);
+
+            return Ok(());
+        }
+    };
+
+    if sync {
+        let lines = match lyrics::synced_lines(&lyrics, mode) {
+            Some(lines) => lines,
+            None => {
+                check_msg(
+                    msg.channel_id
+                        .say(&ctx.http, "This song has no synced lyrics in that mode")
+                        .await,
+                );
+
+                return Ok(());
+            }
+        };
+
+        {
+            let mut typemap = current.typemap().write().await;
+            if typemap.contains_key::<lyrics::Syncing>() {
+                check_msg(
+                    msg.channel_id
+                        .say(&ctx.http, "Lyrics are already synced to this song")
+                        .await,
+                );
+
+                return Ok(());
+            }
+            typemap.insert::<lyrics::Syncing>(());
+        }
+
+        current.add_event(
+            Event::Periodic(lyrics::SYNC_INTERVAL, None),
+            lyrics::LyricsSync::new(msg.channel_id, ctx.http.clone(), lines),
+        )?;
+        check_msg(
+            msg.channel_id
+                .say(&ctx.http, "Lyrics will follow the song")
+                .await,
+        );
+
+        return Ok(());
+    }
+
+    match lyrics::render(&lyrics, mode) {
+        Some(text) => {
+            for chunk in lyrics::split_message(&text) {
+                check_msg(msg.channel_id.say(&ctx.http, chunk).await);
+            }
+        }
+        None => check_msg(
+            msg.channel_id
+                .say(&ctx.http, "This song has no translated lyrics")
+                .await,
+        ),
+    }
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn romanize(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+
+    let romanize = match args.optional::<Switch>() {
+        Some(Switch(on)) => on,
+        None => {
+            let options = display::display_options(ctx, guild_id.0).await;
+            let s = if options.romanize {
+                "Romanization is on"
+            } else {
+                "Romanization is off"
+            };
+            check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+            return Ok(());
+        }
+    };
+
+    {
+        let display_lock = display::display_lock(ctx).await;
+        let mut display = display_lock.write().await;
+        display.entry(guild_id.0).or_default().romanize = romanize;
+    }
+
+    let s = if romanize {
+        "Romanization enabled"
+    } else {
+        "Romanization disabled"
+    };
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+    Ok(())
+}
+
+const DISPLAY_USAGE: &str = "~display [requester|url|thumbnail|romanize] [on|off]";
+
+#[command]
+#[only_in(guilds)]
+async fn display(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+
+    if args.is_empty() {
+        let options = display::display_options(ctx, guild_id.0).await;
+        let on_off = |x: bool| if x { "on" } else { "off" };
+        let s = format!(
+            "requester: {}\nurl: {}\nthumbnail: {}\nromanize: {}",
+            on_off(options.show_requester),
+            on_off(options.show_url),
+            on_off(options.show_thumbnail),
+            on_off(options.romanize)
+        );
+        check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+        return Ok(());
+    }
+
+    let name = args.required::<String>(DISPLAY_USAGE)?;
+    let Switch(value) = args.required::<Switch>(DISPLAY_USAGE)?;
+
+    let known = {
+        let display_lock = display::display_lock(ctx).await;
+        let mut display = display_lock.write().await;
+        display.entry(guild_id.0).or_default().set(&name, value)
+    };
+
+    if known {
+        check_msg(
+            msg.channel_id
+                .say(&ctx.http, format!("Display of {} set", name))
+                .await,
+        );
+    } else {
+        check_msg(
+            msg.channel_id
+                .say(
+                    &ctx.http,
+                    "Option must be requester, url, thumbnail or romanize",
+                )
+                .await,
+        );
+    }
+
+    Ok(())
+}
+
+/// Reads two queue entry numbers as shown by `~list` and returns them as
+/// queue indexes.
+fn two_indexes(args: &mut Args, usage: &str) -> Result<(usize, usize), Usage> {
+    let a = args.within(1..=usize::MAX, usage)? - 1;
+    let b = args.within(1..=usize::MAX, usage)? - 1;
+
+    Ok((a, b))
+}
+
+#[command("move")]
+#[only_in(guilds)]
+async fn move_song(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let usage = "~move [FROM] [TO]";
+    reorder(ctx, msg, &mut args, usage, queue::move_to, "Song moved").await
+}
+
+#[command]
+#[only_in(guilds)]
+async fn swap(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let usage = "~swap [A] [B]";
+    reorder(ctx, msg, &mut args, usage, queue::swap, "Songs swapped").await
+}
+
+async fn reorder(
+    ctx: &Context,
+    msg: &Message,
+    args: &mut Args,
+    usage: &str,
+    f: fn(&TrackQueue, &[TrackHandle], usize, usize) -> bool,
+    done: &str,
+) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    let (a, b) = two_indexes(args, usage)?;
+
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+
+    if let Some(handler_lock) = manager.get(guild_id) {
+        let handler = handler_lock.lock().await;
+        let pinned = queue::pinned(handler.queue()).await;
+        if f(handler.queue(), &pinned, a, b) {
+            check_msg(msg.channel_id.say(&ctx.http, done).await);
+        } else {
+            check_msg(
+                msg.channel_id
+                    .say(&ctx.http, "Index must 2 to queue length!")
+                    .await,
+            );
+        }
+    } else {
+        check_msg(
+            msg.channel_id
+                .say(&ctx.http, "Not in a voice channel to play in")
+                .await,
+        );
+    }
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn clear(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+
+    if let Some(handler_lock) = manager.get(guild_id) {
+        let handler = handler_lock.lock().await;
+        let queue = handler.queue();
+        let pinned = queue::pinned(queue).await;
+        if pinned.is_empty() {
+            queue.stop();
+            supervisor::kill_guild(guild_id.0);
+
+            check_msg(msg.channel_id.say(&ctx.http, "Queue cleared.").await);
+        } else {
+            // Pinned songs stay, the playing one too if it is pinned.
+            let waiting = queue.current_queue().split_off(1);
+            for track in queue::remove(queue, &pinned, &waiting) {
+                let _ = track.stop();
+            }
+            if queue
+                .current()
+                .is_some_and(|x| !pinned.iter().any(|p| p.uuid() == x.uuid()))
+            {
+                let _ = queue.skip();
+            }
+
+            check_msg(
+                msg.channel_id
+                    .say(
+                        &ctx.http,
+                        format!("Queue cleared, {} pinned songs stay.", pinned.len()),
+                    )
+                    .await,
+            );
+        }
+    } else {
+        check_msg(
+            msg.channel_id
+                .say(&ctx.http, "Not in a voice channel to play in")
+                .await,
+        );
+    }
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn destroy(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    clear(ctx, msg, args.clone()).await?;
+    leave(ctx, msg, args).await?;
+
+    Ok(())
+}
+
+#[command]
+#[aliases("正在播放")]
+#[only_in(guilds)]
+async fn now(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    let live = args.flag("live");
+
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+
+    if let Some(handler_lock) = manager.get(guild_id) {
+        let handler = handler_lock.lock().await;
+        let list = handler.queue().current_queue();
+        let current = match list.first() {
+            Some(current) => current,
+            None => {
+                check_msg(msg.channel_id.say(&ctx.http, "List is empty!").await);
+
+                return Ok(());
+            }
+        };
+        let options = display::display_options(ctx, guild_id.0).await;
+        let filter = GuildPlayer::new(ctx, guild_id.0, handler_lock.clone())
+            .await
+            .filter()
+            .await;
+        let position = current.get_info().await?.position;
+        let requester = queue::requester(current).await;
+        let sent = msg
+            .channel_id
+            .send_message(&ctx.http, |m| {
+                m.embed(|e| {
+                    reply::now(
+                        e,
+                        current.metadata(),
+                        position,
+                        requester.as_ref(),
+                        &options,
+                        &filter.get(),
+                    )
+                })
+            })
+            .await;
+
+        match sent {
+            Ok(sent) if live => {
+                current.add_event(
+                    Event::Periodic(LIVE_NOW_INTERVAL, None),
+                    NowUpdater {
+                        message: sent,
+                        http: ctx.http.clone(),
+                        requester,
+                        options,
+                        filter,
+                    },
+                )?;
+            }
+            sent => check_msg(sent),
+        }
+    }
+
+    Ok(())
+}
+
+#[command]
+#[aliases("音量")]
+#[only_in(guilds)]
+async fn vol(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    if let Some(handler_lock) = manager.get(guild_id) {
+        let handler = handler_lock.lock().await;
+        let list = handler.queue().current_queue();
+        if args.is_empty() {
+            let entry = list.first();
+            match entry {
+                Some(entry) => {
+                    let vol = entry.get_info().await?.volume;
+                    check_msg(
+                        msg.channel_id
+                            .say(&ctx.http, format!("Volume is {:.0}", (vol * 100.0).round()))
+                            .await,
+                    );
+                }
+                None => {
+                    check_msg(msg.channel_id.say(&ctx.http, "Queue is empty!").await);
+                }
+            }
+
+            return Ok(());
+        }
+        if args.len() > 1 {
+            drop(handler);
+
+            return entry_vol(ctx, msg, args, list).await;
+        }
+        drop(handler);
+        let Percent(vol) = args.within(VOLUME_RANGE, "~vol [VOL] (0~200)")?;
+        let requested = vol / 100.0;
+        let vol = control::set_volume(ctx, guild_id, Some(msg.channel_id), requested)
+            .await?
+            .unwrap_or(requested);
+        let mut s = format!("Volume set to {:.0}", (vol * 100.0).round());
+        if requested > vol {
+            s.push_str(" (volume ceiling, a DJ can raise it with ~ceiling)");
+        }
+        check_msg(msg.channel_id.say(&ctx.http, s).await);
+    }
+
+    Ok(())
+}
+
+const VOLUME_RANGE: RangeInclusive<Percent> = Percent(0.0)..=Percent(200.0);
+const ENTRY_VOL_USAGE: &str = "~vol [INDEX] [VOL] (0~200)";
+
+/// `~vol INDEX VOL`: the volume of one entry, kept when it starts playing
+/// and when the volume of the whole queue changes.
+async fn entry_vol(
+    ctx: &Context,
+    msg: &Message,
+    mut args: Args,
+    list: Vec<TrackHandle>,
+) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    let index = args.required::<usize>(ENTRY_VOL_USAGE)?;
+    let Percent(vol) = args.within(VOLUME_RANGE, ENTRY_VOL_USAGE)?;
+    let track = match queue::entry(&list, index) {
+        Some(track) => track,
+        None => {
+            check_msg(msg.channel_id.say(&ctx.http, "Index out of range").await);
+
+            return Ok(());
+        }
+    };
+
+    let requested = vol / 100.0;
+    let vol = limiter::cap(requested, playback::volume_ceiling(ctx, guild_id.0).await);
+    track.set_volume(vol)?;
+    queue::set_entry_volume(track, vol).await;
+
+    let mut s = format!(
+        "Volume of {} set to {:.0}",
+        track_name(track.metadata()),
+        (vol * 100.0).round()
+    );
+    if requested > vol {
+        s.push_str(" (volume ceiling, a DJ can raise it with ~ceiling)");
+    }
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn ceiling(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+
+    if args.is_empty() {
+        let ceiling = playback::volume_ceiling(ctx, guild_id.0).await;
+        let s = format!(
+            "Volume ceiling is {:.0}",
+            (ceiling.unwrap_or(limiter::DEFAULT_CEILING) * 100.0).round()
+        );
+        check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+        return Ok(());
+    }
+
+    if !dj::is_dj(ctx, msg).await {
+        check_msg(
+            msg.reply(ctx, "Only DJs can change the volume ceiling")
+                .await,
+        );
+
+        return Ok(());
+    }
+
+    let max = Percent(limiter::MAX_CEILING * 100.0);
+    let Percent(ceiling) = args.within(Percent(1.0)..=max, "~ceiling [VOL] (1~200)")?;
+    let ceiling = ceiling / 100.0;
+
+    {
+        let playback_lock = playback::playback_lock(ctx).await;
+        let mut playback = playback_lock.write().await;
+        playback.entry(guild_id.0).or_default().volume_ceiling = Some(ceiling);
+    }
+
+    // Songs already louder than the new ceiling are turned down right away.
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    if let Some(handler_lock) = manager.get(guild_id) {
+        let handler = handler_lock.lock().await;
+        for track in handler.queue().current_queue() {
+            if track.get_info().await?.volume > ceiling {
+                track.set_volume(ceiling)?;
+            }
+        }
+    }
+
+    check_msg(
+        msg.channel_id
+            .say(
+                &ctx.http,
+                format!("Volume ceiling set to {:.0}", (ceiling * 100.0).round()),
+            )
+            .await,
+    );
+
+    Ok(())
+}
+
+const LIST_BUTTON: &str = "list:";
+
+/// The queue with the requester of every entry, `None` when not in a voice
+/// channel.
+async fn queue_entries(
+    ctx: &Context,
+    guild_id: GuildId,
+) -> Option<Vec<(Metadata, Option<Requester>)>> {
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    let list = manager.get(guild_id)?.lock().await.queue().current_queue();
+
+    let mut entries = vec![];
+    for track in &list {
+        entries.push((track.metadata().clone(), queue::requester(track).await));
+    }
+
+    Some(entries)
+}
+
+fn list_buttons(c: &mut CreateComponents, page: usize, pages: usize) -> &mut CreateComponents {
+    c.create_action_row(|r| {
+        r.create_button(|b| {
+            b.custom_id(format!("{}{}", LIST_BUTTON, page.saturating_sub(1)))
+                .label("Prev")
+                .style(ButtonStyle::Secondary)
+                .disabled(page == 0)
+        })
+        .create_button(|b| {
+            b.custom_id(format!("{}{}", LIST_BUTTON, page + 1))
+                .label("Next")
+                .style(ButtonStyle::Secondary)
+                .disabled(page + 1 >= pages)
+        })
+    })
+}
+
+#[command]
+#[aliases("列表")]
+#[only_in(guilds)]
+async fn list(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    let entries = match queue_entries(ctx, guild_id).await {
+        Some(entries) if !entries.is_empty() => entries,
+        Some(_) => {
+            check_msg(msg.channel_id.say(&ctx.http, "List is empty!").await);
+
+            return Ok(());
+        }
+        None => return Ok(()),
+    };
+
+    let pages = reply::queue_pages(entries.len());
+    let page = if args.is_empty() {
+        0
+    } else {
+        args.within(1..=pages, &format!("~list [PAGE] (1~{})", pages))? - 1
+    };
+
+    select::remember(ctx, guild_id, msg.author.id.0).await;
+    let options = display::display_options(ctx, guild_id.0).await;
+    let effects = playback::effects(ctx, guild_id.0).await;
+    check_msg(
+        msg.channel_id
+            .send_message(&ctx.http, |m| {
+                m.embed(|e| reply::queue(e, &entries, page, &options, &effects));
+                if pages > 1 {
+                    m.components(|c| list_buttons(c, page, pages));
+                }
+                m
+            })
+            .await,
+    );
+
+    Ok(())
+}
+
+/// Turns the page of a `~list` message, showing the queue as it is now.
+async fn turn_list_page(ctx: &Context, component: &MessageComponentInteraction, page: &str) {
+    let guild_id = match component.guild_id {
+        Some(guild_id) => guild_id,
+        None => return,
+    };
+    let entries = queue_entries(ctx, guild_id).await.unwrap_or_default();
+    let pages = reply::queue_pages(entries.len());
+    // The queue may have shrunk since the buttons were made.
+    let page = page.parse::<usize>().unwrap_or(0).min(pages - 1);
+    select::remember(ctx, guild_id, component.user.id.0).await;
+    let options = display::display_options(ctx, guild_id.0).await;
+    let effects = playback::effects(ctx, guild_id.0).await;
+
+    if let Err(e) = component
+        .create_interaction_response(&ctx.http, |r| {
+            r.kind(InteractionResponseType::UpdateMessage)
+                .interaction_response_data(|d| {
+                    d.embed(|e| reply::queue(e, &entries, page, &options, &effects))
+                        .components(|c| list_buttons(c, page, pages))
+                })
+        })
+        .await
+    {
+        warn!("Err turning list page: {:?}", e);
+    }
+}
+
+#[command]
+#[only_in(guilds)]
+async fn undeafen(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+
+    if let Some(handler_lock) = manager.get(guild_id) {
+        let mut handler = handler_lock.lock().await;
+        if let Err(e) = handler.deafen(false).await {
+            check_msg(
+                msg.channel_id
+                    .say(&ctx.http, format!("Failed: {:?}", e))
+                    .await,
+            );
+        }
+
+        check_msg(msg.channel_id.say(&ctx.http, "Undeafened").await);
+    } else {
+        check_msg(
+            msg.channel_id
+                .say(&ctx.http, "Not in a voice channel to undeafen in")
+                .await,
+        );
+    }
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn unmute(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+
+    if let Some(handler_lock) = manager.get(guild_id) {
+        let mut handler = handler_lock.lock().await;
+        if let Err(e) = handler.mute(false).await {
+            check_msg(
+                msg.channel_id
+                    .say(&ctx.http, format!("Failed: {:?}", e))
+                    .await,
+            );
+        }
+
+        check_msg(msg.channel_id.say(&ctx.http, "Unmuted").await);
+    } else {
+        check_msg(
+            msg.channel_id
+                .say(&ctx.http, "Not in a voice channel to unmute in")
+                .await,
+        );
+    }
+
+    Ok(())
+}
+
+/// Checks that a message successfully sent; if not, then logs why to stdout.
+fn check_msg(result: SerenityResult<Message>) {
+    if let Err(why) = result {
+        warn!("Error sending message: {:?}", why);
+    }
+}
+
+const RECENT_LIMIT: usize = 10;
+/// Songs `~history` shows and `~replay` picks from.
+const PLAYED_LIMIT: usize = 15;
+/// Prefix of the custom id of "queue again" buttons, followed by the
+/// history entry id.
+const REQUEUE_BUTTON: &str = "requeue:";
+
+#[command]
+#[only_in(guilds)]
+async fn recent(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    let user = msg.mentions.first().unwrap_or(&msg.author);
+    let entries = history::recent(ctx, guild_id.0, user.id.0, RECENT_LIMIT).await;
+
+    if entries.is_empty() {
+        check_msg(
+            msg.channel_id
+                .say(
+                    &ctx.http,
+                    format!("{} has not requested any songs", user.name),
+                )
+                .await,
+        );
+
+        return Ok(());
+    }
+
+    let options = display::display_options(ctx, guild_id.0).await;
+    let mut s = format!("Recently requested by {}:\n", user.name);
+    for (i, entry) in entries.iter().enumerate() {
+        s.push_str(&format!(
+            "{}: {} <t:{}:R>\n",
+            i + 1,
+            display::show(&entry.title, &options),
+            entry.time
+        ));
+    }
+
+    check_msg(
+        msg.channel_id
+            .send_message(&ctx.http, |m| {
+                m.content(s).components(|c| {
+                    // Discord allows five buttons in a row.
+                    for (row, chunk) in entries.chunks(5).enumerate() {
+                        c.create_action_row(|r| {
+                            for (i, entry) in chunk.iter().enumerate() {
+                                r.create_button(|b| {
+                                    b.custom_id(format!("{}{}", REQUEUE_BUTTON, entry.id))
+                                        .label(row * 5 + i + 1)
+                                        .style(ButtonStyle::Secondary)
+                                });
+                            }
+                            r
+                        });
+                    }
+                    c
+                })
+            })
+            .await,
+    );
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn bookmark(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    let playing = match manager.get(guild_id) {
+        Some(handler_lock) => {
+            let current = handler_lock.lock().await.queue().current();
+            current.map(|x| (handler_lock, x))
+        }
+        None => None,
+    };
+    let (handler_lock, current) = match playing {
+        Some(playing) => playing,
+        None => {
+            check_msg(msg.channel_id.say(&ctx.http, "Nothing is playing").await);
+
+            return Ok(());
+        }
+    };
+    let url = match &current.metadata().source_url {
+        Some(url) => url.to_owned(),
+        None => {
+            check_msg(
+                msg.channel_id
+                    .say(&ctx.http, "This song can not be bookmarked")
+                    .await,
+            );
+
+            return Ok(());
+        }
+    };
+
+    let effects = GuildPlayer::new(ctx, guild_id.0, handler_lock)
+        .await
+        .filter()
+        .await
+        .get();
+    // Where the song is, whatever speed it plays at.
+    let position = current.get_info().await?.position.mul_f32(effects.speed);
+    let title = track_name(current.metadata());
+    let number = bookmark::add(
+        msg.author.id.0,
+        bookmark::Bookmark {
+            url,
+            title: title.clone(),
+            position,
+        },
+    )
+    .await?;
+
+    check_msg(
+        msg.channel_id
+            .say(
+                &ctx.http,
+                format!(
+                    "Bookmarked {} at {}, ~resume-bookmark {} plays it from there",
+                    title,
+                    duration_formatter(&position),
+                    number
+                ),
+            )
+            .await,
+    );
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn bookmarks(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    let bookmarks = bookmark::list(msg.author.id.0).await?;
+    if bookmarks.is_empty() {
+        check_msg(
+            msg.channel_id
+                .say(
+                    &ctx.http,
+                    "You have no bookmarks, ~bookmark saves where the playing song is",
+                )
+                .await,
+        );
+
+        return Ok(());
+    }
+
+    let options = display::display_options(ctx, guild_id.0).await;
+    let mut s = format!("Bookmarks of {}:\n", msg.author.name);
+    for (i, bookmark) in bookmarks.iter().enumerate() {
+        s.push_str(&format!(
+            "{}: {} at {}\n",
+            i + 1,
+            display::show(&bookmark.title, &options),
+            duration_formatter(&bookmark.position)
+        ));
+    }
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+    Ok(())
+}
+
+#[command("resume-bookmark")]
+#[only_in(guilds)]
+async fn resume_bookmark(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let number = args.required::<usize>("~resume-bookmark [N]")?;
+    let bookmark = match bookmark::get(msg.author.id.0, number).await? {
+        Some(bookmark) => bookmark,
+        None => {
+            check_msg(
+                msg.channel_id
+                    .say(&ctx.http, format!("No bookmark {}, see ~bookmarks", number))
+                    .await,
+            );
+
+            return Ok(());
+        }
+    };
+    let request = Request {
+        start: Some(bookmark.position),
+        ..Request::try_from(msg)?
+    };
+
+    enqueue(ctx, &request, bookmark.url, false).await
+}
+
+#[command]
+#[only_in(guilds)]
+async fn export(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    let format = match args.optional::<String>().as_deref() {
+        Some("m3u") => setlist::Format::M3u,
+        Some("json") | None => setlist::Format::Json,
+        Some(_) => return Err(Usage("~export [json|m3u]".to_string()).into()),
+    };
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    let songs = match manager.get(guild_id) {
+        Some(handler_lock) => handler_lock
+            .lock()
+            .await
+            .queue()
+            .current_queue()
+            .iter()
+            .map(|x| x.metadata().clone())
+            .collect::<Vec<_>>(),
+        None => vec![],
+    };
+    if songs.is_empty() {
+        check_msg(msg.channel_id.say(&ctx.http, "Queue is empty!").await);
+
+        return Ok(());
+    }
+
+    let data = setlist::export(&songs, format)?;
+    check_msg(
+        msg.channel_id
+            .send_files(
+                &ctx.http,
+                vec![AttachmentType::Bytes {
+                    data: data.into_bytes().into(),
+                    filename: format!("queue.{}", format.extension()),
+                }],
+                |m| m.content(format!("{} songs, ~import queues them again", songs.len())),
+            )
+            .await,
+    );
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn import(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    let text = match (msg.attachments.first(), args.optional::<String>()) {
+        (Some(attachment), _) if attachment.size <= setlist::MAX_BYTES => attachment
+            .download()
+            .await
+            .map(|x| String::from_utf8_lossy(&x).into_owned())
+            .map_err(anyhow::Error::from),
+        (Some(_), _) => {
+            check_msg(msg.channel_id.say(&ctx.http, "The file is too large").await);
+
+            return Ok(());
+        }
+        (None, Some(url)) if url.starts_with("http") => setlist::fetch(&url).await,
+        (None, _) => {
+            check_msg(
+                msg.channel_id
+                    .say(
+                        &ctx.http,
+                        "Attach a file from ~export, or give a link to one: ~import [URL]",
+                    )
+                    .await,
+            );
+
+            return Ok(());
+        }
+    };
+    let urls = match text.and_then(|x| setlist::import(&x)) {
+        Ok(urls) => urls,
+        Err(why) => {
+            warn!("Err reading setlist: {:?}", why);
+            check_msg(
+                msg.channel_id
+                    .say(&ctx.http, "Can not read songs from that file")
+                    .await,
+            );
+
+            return Ok(());
+        }
+    };
+
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    let handler_lock = match manager.get(guild_id) {
+        Some(handler_lock) => handler_lock,
+        None => {
+            check_msg(
+                msg.channel_id
+                    .say(&ctx.http, "Not in a voice channel to play in")
+                    .await,
+            );
+
+            return Ok(());
+        }
+    };
+    let found = urls.len();
+
+    let volume = channel_volume(ctx, guild_id, msg.channel_id).await;
+    playback::announce_in(ctx, guild_id.0, msg.channel_id).await;
+    let s = match resume::enqueue_urls(
+        ctx,
+        guild_id.0,
+        handler_lock,
+        urls,
+        volume,
+        Requester::from(msg),
+    )
+    .await
+    {
+        Ok(n) if n < found => format!(
+            "Imported {} of {} songs, the rest did not fit or failed",
+            n, found
+        ),
+        Ok(n) => format!("Imported {} songs", n),
+        Err(why) => {
+            warn!("Err importing setlist: {:?}", why);
+            "Can not queue the songs".to_string()
+        }
+    };
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn apitoken(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    if !dj::is_admin(ctx, msg).await {
+        check_msg(
+            msg.reply(
+                ctx,
+                "Only members who can manage the server can get API tokens",
+            )
+            .await,
+        );
+
+        return Ok(());
+    }
+
+    if args.current() == Some("revoke") {
+        let s = if api::revoke(guild_id.0).await? {
+            "API token revoked"
+        } else {
+            "This server has no API token"
+        };
+        check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+        return Ok(());
+    }
+
+    let token = api::new_token(guild_id.0).await?;
+    let sent = msg
+        .author
+        .direct_message(ctx, |m| {
+            m.content(format!(
+                "API token of {}, the old one stopped working:\n||{}||",
+                guild_id.name(&ctx.cache).unwrap_or_default(),
+                token
+            ))
+        })
+        .await;
+    let s = match sent {
+        Ok(_) => "Sent you a new API token in a DM",
+        Err(e) => {
+            warn!("Err sending API token: {:?}", e);
+            api::revoke(guild_id.0).await?;
+            "Could not DM you the token, allow DMs from server members and try again"
+        }
+    };
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+    Ok(())
+}
+
+#[command("history")]
+#[only_in(guilds)]
+async fn history_command(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    let entries = history::played(ctx, guild_id.0, PLAYED_LIMIT).await;
+    if entries.is_empty() {
+        check_msg(msg.channel_id.say(&ctx.http, "No songs played yet").await);
+
+        return Ok(());
+    }
+
+    let options = display::display_options(ctx, guild_id.0).await;
+    let mut s = "Played lately, ~replay N to queue one again:\n".to_string();
+    for (i, entry) in entries.iter().enumerate() {
+        s.push_str(&format!(
+            "{}: {} <t:{}:R>",
+            i + 1,
+            display::show(&entry.title, &options),
+            entry.time
+        ));
+        if let Some(requester) = entry.requester.as_ref().filter(|x| !x.is_bot()) {
+            s.push_str(&format!(" ({})", requester.name));
+        }
+        s.push('\n');
+    }
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn replay(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    let n = args.within(1..=PLAYED_LIMIT, &format!("~replay N (1~{})", PLAYED_LIMIT))?;
+
+    match history::played(ctx, guild_id.0, n)
+        .await
+        .into_iter()
+        .nth(n - 1)
+    {
+        Some(entry) => enqueue(ctx, &Request::try_from(msg)?, entry.url, false).await,
+        None => {
+            check_msg(
+                msg.channel_id
+                    .say(&ctx.http, format!("No song {} in ~history", n))
+                    .await,
+            );
+
+            Ok(())
+        }
+    }
+}
+
+async fn requeue(ctx: &Context, component: &MessageComponentInteraction, id: &str) {
+    if let Err(e) = component
+        .create_interaction_response(&ctx.http, |r| {
+            r.kind(InteractionResponseType::DeferredUpdateMessage)
+        })
+        .await
+    {
+        warn!("Err acknowledging button: {:?}", e);
+    }
+
+    let guild_id = match component.guild_id {
+        Some(guild_id) => guild_id,
+        None => return,
+    };
+    let entry = match id.parse() {
+        Ok(id) => history::get(ctx, guild_id.0, id).await,
+        Err(_) => None,
+    };
+    let entry = match entry {
+        Some(entry) => entry,
+        None => {
+            check_msg(
+                component
+                    .channel_id
+                    .say(&ctx.http, "That song is no longer in the history")
+                    .await,
+            );
+
+            return;
+        }
+    };
+
+    let request = Request {
+        guild_id,
+        channel_id: component.channel_id,
+        requester: Requester::from(&component.user),
+        position: None,
+        start: None,
+    };
+    if let Err(e) = enqueue(ctx, &request, entry.url, false).await {
+        warn!("Err queueing again: {:?}", e);
+    }
+}
+
+#[command("profile")]
+#[only_in(guilds)]
+async fn profile_command(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    if !settings::get(ctx, guild_id.0).await.profiles() {
+        check_msg(
+            msg.channel_id
+                .say(&ctx.http, "Profiles are turned off in this server")
+                .await,
+        );
+
+        return Ok(());
+    }
+
+    let user = msg.mentions.first().unwrap_or(&msg.author);
+    let profile = profile::get(ctx, guild_id.0, user.id.0).await;
+    let badges = profile.badges();
+    check_msg(
+        msg.channel_id
+            .send_message(&ctx.http, |m| {
+                m.embed(|e| {
+                    e.author(|a| {
+                        a.name(&user.name);
+                        if let Some(avatar) = user.avatar_url() {
+                            a.icon_url(avatar);
+                        }
+                        a
+                    })
+                    .field("Requests", profile.requests, true)
+                    .field("Listened", duration_formatter(&profile.listened()), true)
+                    .field(
+                        "Streak",
+                        format!(
+                            "{} days (best {})",
+                            profile.streak_on(profile::today()),
+                            profile.best_streak
+                        ),
+                        true,
+                    )
+                    .field(
+                        "Badges",
+                        if badges.is_empty() {
+                            "None yet".to_string()
+                        } else {
+                            badges.join("\n")
+                        },
+                        false,
+                    )
+                })
+            })
+            .await,
+    );
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn stats(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    let lock = bandwidth::bandwidth_lock(ctx).await;
+    let usage = bandwidth::usage(&lock, guild_id.0).await;
+    let month = bandwidth::month(profile::today());
+
+    let mut sources = usage.sources.iter().collect::<Vec<_>>();
+    sources.sort_by(|a, b| b.1.cmp(a.1));
+    let mut s = format!(
+        "Traffic in {}: ~{}\n",
+        bandwidth::month_name(month),
+        bandwidth::format_bytes(usage.total())
+    );
+    for (source, bytes) in sources {
+        s.push_str(&format!(
+            "{}: ~{}\n",
+            source,
+            bandwidth::format_bytes(*bytes)
+        ));
+    }
+    if let Some(cap) = settings::get(ctx, guild_id.0).await.bandwidth_cap {
+        s.push_str(&format!("Cap: {} GB a month", cap));
+        if usage.total() >= cap.saturating_mul(bandwidth::GB) {
+            s.push_str(", reached so streams are low quality");
+        }
+    }
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn whatsong(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    if !recognize::enabled() {
+        check_msg(
+            msg.channel_id
+                .say(&ctx.http, "Song recognition is not enabled on this bot")
+                .await,
+        );
+
+        return Ok(());
+    }
+
+    let guild_id = guild::guild_id(msg)?;
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    let current = match manager.get(guild_id) {
+        Some(handler_lock) => handler_lock.lock().await.queue().current(),
+        None => {
+            check_msg(
+                msg.channel_id
+                    .say(&ctx.http, "Not in a voice channel to play in")
+                    .await,
+            );
+
+            return Ok(());
+        }
+    };
+    let current = match current {
+        Some(current) => current,
+        None => {
+            check_msg(msg.channel_id.say(&ctx.http, "Nothing is playing").await);
+
+            return Ok(());
+        }
+    };
+    let url = match current.metadata().source_url.clone() {
+        Some(url) => url,
+        None => {
+            check_msg(
+                msg.channel_id
+                    .say(&ctx.http, "Can not listen to this song")
+                    .await,
+            );
+
+            return Ok(());
+        }
+    };
+    let position = current.get_info().await?.position;
+
+    check_msg(msg.channel_id.say(&ctx.http, "Listening...").await);
+    let song = unwrap_or_show_error!(recognize::recognize(url, position).await, msg, ctx);
+    let s = match song {
+        Some(song) => {
+            let mut s = format!("That is {} by {}", song.title, song.artist);
+            if let Some(album) = song.album {
+                s.push_str(&format!(" from {}", album));
+            }
+            if let Some(link) = song.song_link {
+                s.push_str(&format!("\n{}", link));
+            }
+
+            s
+        }
+        None => "Could not recognize this song".to_string(),
+    };
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+    Ok(())
+}
+
+#[command]
+#[owners_only]
+#[only_in(dms)]
+async fn credential(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let action = args
+        .single::<String>()
+        .unwrap_or_else(|_| "list".to_string());
+    if action == "list" {
+        let mut s = String::from("Credentials:\n");
+        for credential in credentials::ALL {
+            let origin = match credentials::origin(*credential) {
+                credentials::Origin::Stored => "set at runtime",
+                credentials::Origin::Env => "from environment",
+                credentials::Origin::Unset => "not set",
+            };
+            s.push_str(&format!("{}: {}\n", credential, origin));
+        }
+        check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+        return Ok(());
+    }
+
+    let credential = match args.optional::<credentials::Credential>() {
+        Some(credential) => credential,
+        None => {
+            check_msg(
+                msg.channel_id
+                    .say(&ctx.http, "Unknown credential, see ~credential list")
+                    .await,
+            );
+
+            return Ok(());
+        }
+    };
+    let value = match action.as_str() {
+        "set" => match args.remains() {
+            Some(value) => Some(value.to_string()),
+            None => {
+                check_msg(msg.channel_id.say(&ctx.http, "Must provide a value").await);
+
+                return Ok(());
+            }
+        },
+        "clear" => None,
+        _ => {
+            check_msg(
+                msg.channel_id
+                    .say(
+                        &ctx.http,
+                        "Usage: ~credential [list|set|clear] [NAME] [VALUE]",
+                    )
+                    .await,
+            );
+
+            return Ok(());
+        }
+    };
+
+    let s = match credentials::set(credential, value).await {
+        Ok(()) => format!(
+            "Updated {}, clients will pick it up on their next request",
+            credential
+        ),
+        Err(e) => format!("Could not update {}: {}", credential, e),
+    };
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn download(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    if !download::enabled() {
+        check_msg(
+            msg.channel_id
+                .say(&ctx.http, "Downloads are not allowed on this bot")
+                .await,
+        );
+
+        return Ok(());
+    }
+
+    let guild_id = guild::guild_id(msg)?;
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    let current = match manager.get(guild_id) {
+        Some(handler_lock) => handler_lock.lock().await.queue().current(),
+        None => None,
+    };
+    let metadata = match current {
+        Some(current) => current.metadata().clone(),
+        None => {
+            check_msg(msg.channel_id.say(&ctx.http, "Nothing is playing").await);
+
+            return Ok(());
+        }
+    };
+    let url = match &metadata.source_url {
+        Some(url) => url.to_owned(),
+        None => {
+            check_msg(
+                msg.channel_id
+                    .say(&ctx.http, "Can not download this song")
+                    .await,
+            );
+
+            return Ok(());
+        }
+    };
+    if let Some(duration) = metadata.duration.filter(|x| !download::fits(*x)) {
+        check_msg(
+            msg.channel_id
+                .say(
+                    &ctx.http,
+                    format!(
+                        "Song is too long to upload ({})",
+                        duration_formatter(&duration)
+                    ),
+                )
+                .await,
+        );
+
+        return Ok(());
+    }
+
+    check_msg(msg.channel_id.say(&ctx.http, "Encoding...").await);
+    let data = unwrap_or_show_error!(download::encode(url).await, msg, ctx);
+    let filename = download::file_name(&track_name(&metadata));
+    check_msg(
+        msg.channel_id
+            .send_files(
+                &ctx.http,
+                vec![AttachmentType::Bytes {
+                    data: data.into(),
+                    filename,
+                }],
+                |m| m,
+            )
+            .await,
+    );
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn fm(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    let playback_lock = playback::playback_lock(ctx).await;
+
+    let on = match args.optional::<Switch>() {
+        Some(Switch(on)) => on,
+        None => {
+            let on = {
+                let playback = playback_lock.read().await;
+                playback.get(&guild_id.0).map(|x| x.fm).unwrap_or(false)
+            };
+            let s = if on { "FM is on" } else { "FM is off" };
+            check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+            return Ok(());
+        }
+    };
+
+    if !on {
+        {
+            let mut playback = playback_lock.write().await;
+            playback.entry(guild_id.0).or_default().fm = false;
+        }
+        check_msg(
+            msg.channel_id
+                .say(&ctx.http, "FM off, queued songs will still play")
+                .await,
+        );
+
+        return Ok(());
+    }
+
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    let handler_lock = match manager.get(guild_id) {
+        Some(handler_lock) => handler_lock,
+        None => {
+            check_msg(
+                msg.channel_id
+                    .say(&ctx.http, "Not in a voice channel to play in")
+                    .await,
+            );
+
+            return Ok(());
+        }
+    };
+    let volume = {
+        let read = ctx.data.read().await;
+        let song_volume = read
+            .get::<SongVolume>()
+            .expect("Expected SongVolume in TypeMap.")
+            .clone();
+        let volume = song_volume.read().await.get(&msg.channel_id.0).copied();
+
+        volume.unwrap_or(settings::get(ctx, guild_id.0).await.default_volume())
+    };
+    let volume = limiter::cap(volume, playback::volume_ceiling(ctx, guild_id.0).await);
+
+    {
+        let mut playback = playback_lock.write().await;
+        playback.entry(guild_id.0).or_default().fm = true;
+    }
+    playback::announce_in(ctx, guild_id.0, msg.channel_id).await;
+
+    let player = GuildPlayer::new(ctx, guild_id.0, handler_lock).await;
+    match fm::refill(&player, volume).await {
+        Ok(added) => check_msg(
+            msg.channel_id
+                .say(&ctx.http, format!("FM on, added {} songs", added))
+                .await,
+        ),
+        Err(e) => {
+            {
+                let mut playback = playback_lock.write().await;
+                playback.entry(guild_id.0).or_default().fm = false;
+            }
+            check_msg(
+                msg.channel_id
+                    .say(&ctx.http, format!("Could not start FM: {}", e))
+                    .await,
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn tracklist(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    let playback_lock = playback::playback_lock(ctx).await;
+
+    let on = match args.optional::<Switch>() {
+        Some(Switch(on)) => on,
+        None => {
+            let on = {
+                let playback = playback_lock.read().await;
+                playback
+                    .get(&guild_id.0)
+                    .map(|x| x.tracklist)
+                    .unwrap_or(false)
+            };
+            let s = if on {
+                "Tracklist is on"
+            } else {
+                "Tracklist is off"
+            };
+            check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+            return Ok(());
+        }
+    };
+
+    {
+        let mut playback = playback_lock.write().await;
+        playback.entry(guild_id.0).or_default().tracklist = on;
+    }
+    let s = if on {
+        "Tracklist enabled, a pinned message will list every song of the session"
+    } else {
+        "Tracklist disabled"
+    };
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn sessionlog(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    let playback_lock = playback::playback_lock(ctx).await;
+
+    let on = match args.optional::<Switch>() {
+        Some(Switch(on)) => on,
+        None => {
+            let on = {
+                let playback = playback_lock.read().await;
+                playback
+                    .get(&guild_id.0)
+                    .map(|x| x.session_log)
+                    .unwrap_or(false)
+            };
+            let s = if on {
+                "Session log is on"
+            } else {
+                "Session log is off"
+            };
+            check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+            return Ok(());
+        }
+    };
+
+    {
+        let mut playback = playback_lock.write().await;
+        playback.entry(guild_id.0).or_default().session_log = on;
+    }
+    let s = if on {
+        "Session log enabled, songs will be logged to a thread"
+    } else {
+        "Session log disabled"
+    };
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+    Ok(())
+}
+
+/// Songs `~ncloud` lists at once.
+const CLOUD_PAGE: usize = 20;
+/// Songs of the cloud disk `~ncloud` can search and play.
+const CLOUD_MAX: usize = 1000;
+
+#[command]
+#[only_in(guilds)]
+async fn ncloud(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let s = match args.optional::<String>().as_deref() {
+        Some("play") => {
+            let n = args.within(1..=CLOUD_MAX, "~ncloud play N")?;
+            match neteaseapi::netease_cloud(CLOUD_MAX).await {
+                Ok(songs) => match songs.into_iter().nth(n - 1).and_then(|x| x.source_url) {
+                    Some(url) => return enqueue(ctx, &Request::try_from(msg)?, url, false).await,
+                    None => format!("No song {} in the cloud disk", n),
+                },
+                Err(why) => why.to_string(),
+            }
+        }
+        Some("search") => match search::search_cloud(args.rest(), CLOUD_MAX).await {
+            Ok(songs) if songs.is_empty() => "No song found".to_string(),
+            Ok(songs) => cloud_list(songs.into_iter().take(CLOUD_PAGE)),
+            Err(why) => why.to_string(),
+        },
+        _ => match neteaseapi::netease_cloud(CLOUD_PAGE).await {
+            Ok(songs) if songs.is_empty() => "The cloud disk is empty".to_string(),
+            Ok(songs) => cloud_list((1..).zip(songs)),
+            Err(why) => why.to_string(),
+        },
+    };
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+    Ok(())
+}
+
+fn cloud_list(songs: impl Iterator<Item = (usize, Metadata)>) -> String {
+    let mut s = String::new();
+    for (i, song) in songs {
+        s.push_str(&format!("{}. {}", i, track_name(&song)));
+        if let Some(artist) = &song.artist {
+            s.push_str(&format!(" - {}", artist));
+        }
+        s.push('\n');
+    }
+    s.push_str("~ncloud play N to add one to the queue");
+
+    s
+}
+
+#[command("autoplay")]
+#[only_in(guilds)]
+async fn autoplay_command(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    let playback_lock = playback::playback_lock(ctx).await;
+
+    let on = match args.optional::<Switch>() {
+        Some(Switch(on)) => on,
+        None => {
+            let on = {
+                let playback = playback_lock.read().await;
+                playback
+                    .get(&guild_id.0)
+                    .map(|x| x.autoplay)
+                    .unwrap_or(false)
+            };
+            let s = if on {
+                "Autoplay is on"
+            } else {
+                "Autoplay is off"
+            };
+            check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+            return Ok(());
+        }
+    };
+
+    {
+        let mut playback = playback_lock.write().await;
+        playback.entry(guild_id.0).or_default().autoplay = on;
+    }
+    let s = if on {
+        "Autoplay on, songs like the last one will follow when the queue runs out"
+    } else {
+        "Autoplay off"
+    };
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn nowplaying(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    let playback_lock = playback::playback_lock(ctx).await;
+
+    let on = match args.optional::<Switch>() {
+        Some(Switch(on)) => on,
+        None => {
+            let on = {
+                let playback = playback_lock.read().await;
+                playback
+                    .get(&guild_id.0)
+                    .map(|x| x.now_playing)
+                    .unwrap_or(false)
+            };
+            let s = if on {
+                "Now playing posts are on"
+            } else {
+                "Now playing posts are off"
+            };
+            check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+            return Ok(());
+        }
+    };
+
+    {
+        let mut playback = playback_lock.write().await;
+        playback.entry(guild_id.0).or_default().now_playing = on;
+    }
+    let s = if on {
+        "Every song will be posted here as it starts"
+    } else {
+        "Now playing posts disabled"
+    };
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn softmute(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    let handler_lock = match manager.get(guild_id) {
+        Some(handler_lock) => handler_lock,
+        None => {
+            check_msg(
+                msg.channel_id
+                    .say(&ctx.http, "Not in a voice channel")
+                    .await,
+            );
+
+            return Ok(());
+        }
+    };
+    let player = GuildPlayer::new(ctx, guild_id.0, handler_lock).await;
+
+    let s = if soft_mute::mute(&player).await? {
+        "Soft muted, ~softunmute to turn it back up"
+    } else {
+        "Already soft muted"
+    };
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn softunmute(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    let handler_lock = match manager.get(guild_id) {
+        Some(handler_lock) => handler_lock,
+        None => {
+            check_msg(
+                msg.channel_id
+                    .say(&ctx.http, "Not in a voice channel")
+                    .await,
+            );
+
+            return Ok(());
+        }
+    };
+    let player = GuildPlayer::new(ctx, guild_id.0, handler_lock).await;
+
+    let s = if soft_mute::unmute(&player).await? {
+        "Volume restored"
+    } else {
+        "Not soft muted"
+    };
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn suspend(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    // It takes the queue away from everyone listening.
+    if !dj::is_dj(ctx, msg).await {
+        check_msg(msg.reply(ctx, "Only DJs can suspend the queue").await);
+
+        return Ok(());
+    }
+
+    let s = match resume::suspend(ctx, guild_id.0, msg.author.id.0).await {
+        Ok(0) => "Queue is empty!".to_string(),
+        Ok(n) => format!("Suspended {} songs, ~resume them in any server", n),
+        Err(why) => {
+            warn!("Err suspending queue: {:?}", why);
+            "Can not suspend the queue".to_string()
+        }
+    };
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+    Ok(())
+}
+
+#[command("resume")]
+#[only_in(guilds)]
+async fn resume_queue(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    let handler_lock = match manager.get(guild_id) {
+        Some(handler_lock) => handler_lock,
+        None => {
+            check_msg(
+                msg.channel_id
+                    .say(&ctx.http, "Not in a voice channel to play in")
+                    .await,
+            );
+
+            return Ok(());
+        }
+    };
+    playback::announce_in(ctx, guild_id.0, msg.channel_id).await;
+
+    let s = match resume::resume(ctx, guild_id.0, msg.author.id.0, handler_lock).await {
+        Ok(Some(n)) => format!("Resumed {} songs", n),
+        Ok(None) => "You have no suspended queue".to_string(),
+        Err(why) => {
+            warn!("Err resuming queue: {:?}", why);
+            "Can not resume the queue".to_string()
+        }
+    };
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+    Ok(())
+}
+
+#[command("playlist")]
+#[only_in(guilds)]
+async fn playlist_command(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    let action = args
+        .single::<String>()
+        .unwrap_or_else(|_| "list".to_string());
+    if action == "list" {
+        let s = match resume::playlists(guild_id.0).await {
+            Ok(playlists) if playlists.is_empty() => {
+                "No saved playlists, ~playlist save NAME saves the queue".to_string()
+            }
+            Ok(playlists) => playlists
+                .iter()
+                .map(|(name, n)| format!("{} ({} songs)", name, n))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Err(why) => {
+                warn!("Err listing playlists: {:?}", why);
+                "Can not list playlists".to_string()
+            }
+        };
+        check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+        return Ok(());
+    }
+
+    let rest = args.rest().trim();
+    let (name, shuffled) = match rest.strip_suffix(" shuffled") {
+        Some(name) if action == "load" => (name.trim().to_string(), true),
+        _ => (rest.to_string(), false),
+    };
+    if name.is_empty() {
+        check_msg(
+            msg.channel_id
+                .say(&ctx.http, "Usage: ~playlist save|load|delete NAME")
+                .await,
+        );
+
+        return Ok(());
+    }
+
+    let s = match action.as_str() {
+        "save" | "load" => {
+            let manager = songbird::get(ctx)
+                .await
+                .expect("Songbird Voice client placed in at initialisation.")
+                .clone();
+            let handler_lock = match manager.get(guild_id) {
+                Some(handler_lock) => handler_lock,
+                None => {
+                    check_msg(
+                        msg.channel_id
+                            .say(&ctx.http, "Not in a voice channel")
+                            .await,
+                    );
+
+                    return Ok(());
+                }
+            };
+
+            if action == "save" {
+                let replace = dj::is_dj(ctx, msg).await;
+                match resume::save_playlist(guild_id.0, &name, handler_lock, replace).await {
+                    Ok(0) => "Queue is empty!".to_string(),
+                    Ok(n) => format!("Saved {} songs as {}", n, name),
+                    Err(why) => why.to_string(),
+                }
+            } else {
+                playback::announce_in(ctx, guild_id.0, msg.channel_id).await;
+                match resume::load_playlist(ctx, guild_id.0, &name, handler_lock, shuffled).await {
+                    Ok(Some((n, total))) if n < total => format!(
+                        "Added {} of {} songs from {}, the rest did not fit or failed",
+                        n, total, name
+                    ),
+                    Ok(Some((n, _))) => format!("Added {} songs from {}", n, name),
+                    Ok(None) => format!("No playlist named {}", name),
+                    Err(why) => {
+                        warn!("Err loading playlist: {:?}", why);
+                        "Can not load the playlist".to_string()
+                    }
+                }
+            }
+        }
+        "delete" if !dj::is_dj(ctx, msg).await => "Only DJs can delete playlists".to_string(),
+        "delete" => match resume::delete_playlist(guild_id.0, &name).await {
+            Ok(true) => format!("Deleted {}", name),
+            Ok(false) => format!("No playlist named {}", name),
+            Err(why) => why.to_string(),
+        },
+        _ => "Usage: ~playlist save|load|delete NAME".to_string(),
+    };
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn myplaylist(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    let user = msg.author.id.0;
+
+    let s = match args.optional::<String>().as_deref() {
+        None | Some("list") => match my_playlist::get(user).await {
+            Ok(urls) if urls.is_empty() => {
+                "Your playlist is empty, ~myplaylist add URL to add songs".to_string()
+            }
+            Ok(urls) => {
+                // Keeps the reply within Discord's message length.
+                let mut lines = urls
+                    .iter()
+                    .enumerate()
+                    .take(20)
+                    .map(|(i, url)| format!("{}. <{}>", i + 1, url))
+                    .collect::<Vec<_>>();
+                if urls.len() > lines.len() {
+                    lines.push(format!("...and {} more", urls.len() - lines.len()));
+                }
+
+                lines.join("\n")
+            }
+            Err(why) => {
+                warn!("Err reading personal playlist: {:?}", why);
+                "Can not read your playlist".to_string()
+            }
+        },
+        Some("add") => match args.optional::<String>() {
+            Some(url) if url.starts_with("http") => match my_playlist::add(user, url).await {
+                Ok(0) => "Already in your playlist".to_string(),
+                Ok(n) => format!("Added {} songs to your playlist", n),
+                Err(why) => why.to_string(),
+            },
+            _ => "Must provide a valid URL".to_string(),
+        },
+        Some("remove") => {
+            let position = args.required::<usize>("~myplaylist remove N")?;
+            match my_playlist::remove(user, position).await {
+                Ok(Some(url)) => format!("Removed <{}>", url),
+                Ok(None) => "Index out of range".to_string(),
+                Err(why) => {
+                    warn!("Err removing from personal playlist: {:?}", why);
+                    "Can not remove the song".to_string()
+                }
+            }
+        }
+        Some("clear") => match my_playlist::clear(user).await {
+            Ok(n) => format!("Removed {} songs from your playlist", n),
+            Err(why) => {
+                warn!("Err clearing personal playlist: {:?}", why);
+                "Can not clear your playlist".to_string()
+            }
+        },
+        Some("play") => {
+            let shuffled = args.flag("shuffled");
+            let manager = songbird::get(ctx)
+                .await
+                .expect("Songbird Voice client placed in at initialisation.")
+                .clone();
+            let handler_lock = match manager.get(guild_id) {
+                Some(handler_lock) => handler_lock,
+                None => {
+                    check_msg(
+                        msg.channel_id
+                            .say(&ctx.http, "Not in a voice channel to play in")
+                            .await,
+                    );
+
+                    return Ok(());
+                }
+            };
+            let mut urls = match my_playlist::get(user).await {
+                Ok(urls) => urls,
+                Err(why) => {
+                    warn!("Err reading personal playlist: {:?}", why);
+                    check_msg(
+                        msg.channel_id
+                            .say(&ctx.http, "Can not read your playlist")
+                            .await,
+                    );
+
+                    return Ok(());
+                }
+            };
+            if shuffled {
+                urls.shuffle(&mut rand::thread_rng());
+            }
+            let volume = channel_volume(ctx, guild_id, msg.channel_id).await;
+            playback::announce_in(ctx, guild_id.0, msg.channel_id).await;
+
+            let found = urls.len();
+            match resume::enqueue_urls(
+                ctx,
+                guild_id.0,
+                handler_lock,
+                urls,
+                volume,
+                Requester::from(msg),
+            )
+            .await
+            {
+                Ok(_) if found == 0 => {
+                    "Your playlist is empty, ~myplaylist add URL to add songs".to_string()
+                }
+                Ok(n) if n < found => format!(
+                    "Added {} of {} songs from your playlist, the rest did not fit or failed",
+                    n, found
+                ),
+                Ok(n) => format!("Added {} songs from your playlist", n),
+                Err(why) => {
+                    warn!("Err playing personal playlist: {:?}", why);
+                    "Can not play your playlist".to_string()
+                }
+            }
+        }
+        Some(_) => "Usage: ~myplaylist [add URL|play|remove N|clear]".to_string(),
+    };
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+    Ok(())
+}
+
+/// Volume of songs requested from `channel_id`, the server default until
+/// someone sets one.
+async fn channel_volume(ctx: &Context, guild_id: GuildId, channel_id: ChannelId) -> f32 {
+    let song_volume = {
+        let read = ctx.data.read().await;
+
+        read.get::<SongVolume>()
+            .expect("Expected SongVolume in TypeMap.")
+            .clone()
+    };
+    let volume = song_volume.read().await.get(&channel_id.0).copied();
+
+    volume.unwrap_or(settings::get(ctx, guild_id.0).await.default_volume())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn scrape(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    if !dj::is_dj(ctx, msg).await {
+        check_msg(msg.reply(ctx, "Only DJs can scrape channels").await);
+
+        return Ok(());
+    }
+
+    let guild_id = guild::guild_id(msg)?;
+    let target = args.optional::<String>().unwrap_or_default();
+    let (channel_id, from) = match serenity::utils::parse_message_url(&target) {
+        Some((guild, channel, message)) if guild == guild_id => (channel, Some(message)),
+        Some(_) => {
+            check_msg(
+                msg.channel_id
+                    .say(&ctx.http, "The message must be in this server")
+                    .await,
+            );
+
+            return Ok(());
+        }
+        None => match serenity::utils::parse_channel(&target).or_else(|| target.parse().ok()) {
+            Some(channel) => (ChannelId(channel), None),
+            None => {
+                check_msg(
+                    msg.channel_id
+                        .say(&ctx.http, "Usage: ~scrape [MESSAGE LINK|#CHANNEL] [COUNT]")
+                        .await,
+                );
+
+                return Ok(());
+            }
+        },
+    };
+    let count = args.within(
+        1..=scrape::SCRAPE_MAX,
+        &format!(
+            "~scrape [MESSAGE LINK|#CHANNEL] [COUNT] (1~{})",
+            scrape::SCRAPE_MAX
+        ),
+    )?;
+
+    // Only channels of this server the DJ can read themselves.
+    let readable = ctx
+        .cache
+        .guild_channel(channel_id)
+        .filter(|x| x.guild_id == guild_id)
+        .and_then(|x| x.permissions_for_user(&ctx.cache, msg.author.id).ok())
+        .is_some_and(|x| x.view_channel() && x.read_message_history());
+    if !readable {
+        check_msg(
+            msg.channel_id
+                .say(&ctx.http, "Can not read that channel")
+                .await,
+        );
+
+        return Ok(());
+    }
+
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    let handler_lock = match manager.get(guild_id) {
+        Some(handler_lock) => handler_lock,
+        None => {
+            check_msg(
+                msg.channel_id
+                    .say(&ctx.http, "Not in a voice channel to play in")
+                    .await,
+            );
+
+            return Ok(());
+        }
+    };
+
+    check_msg(
+        msg.channel_id
+            .say(
+                &ctx.http,
+                format!("Scanning {} messages for songs...", count),
+            )
+            .await,
+    );
+    let urls = match scrape::scrape(ctx, channel_id, from, count).await {
+        Ok(urls) => urls,
+        Err(why) => {
+            warn!("Err scraping channel: {:?}", why);
+            check_msg(
+                msg.channel_id
+                    .say(&ctx.http, "Can not read the messages")
+                    .await,
+            );
+
+            return Ok(());
+        }
+    };
+    let found = urls.len();
+
+    let volume = channel_volume(ctx, guild_id, msg.channel_id).await;
+    playback::announce_in(ctx, guild_id.0, msg.channel_id).await;
+    let s = match resume::enqueue_urls(
+        ctx,
+        guild_id.0,
+        handler_lock,
+        urls,
+        volume,
+        Requester::from(msg),
+    )
+    .await
+    {
+        Ok(_) if found == 0 => "No songs found in those messages".to_string(),
+        Ok(n) if n < found => format!(
+            "Added {} of {} songs found, the rest did not fit or failed",
+            n, found
+        ),
+        Ok(n) => format!("Added {} songs to queue", n),
+        Err(why) => {
+            warn!("Err queueing scraped songs: {:?}", why);
+            "Can not queue the songs".to_string()
+        }
+    };
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn djintro(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    let playback_lock = playback::playback_lock(ctx).await;
+
+    if args.is_empty() {
+        let intro = {
+            let playback = playback_lock.read().await;
+            playback.get(&guild_id.0).and_then(|x| x.dj_intro)
+        };
+        let s = match intro {
+            Some(intro) => format!("DJ programs start {}s in", intro.as_secs()),
+            None => "DJ programs play from the start".to_string(),
+        };
+        check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+        return Ok(());
+    }
+
+    let intro = if args.flag("off") {
+        None
+    } else {
+        let max = intro::MAX_INTRO.as_secs();
+        let secs = args.within(1..=max, &format!("~djintro [1~{}|off]", max))?;
+        Some(Duration::from_secs(secs))
+    };
+
+    {
+        let mut playback = playback_lock.write().await;
+        playback.entry(guild_id.0).or_default().dj_intro = intro;
+    }
+
+    let s = match intro {
+        Some(intro) => format!("DJ programs will start {}s in", intro.as_secs()),
+        None => "DJ programs will play from the start".to_string(),
+    };
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn voteskip(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    let playback_lock = playback::playback_lock(ctx).await;
+
+    if args.is_empty() {
+        let fraction = {
+            let playback = playback_lock.read().await;
+            playback.get(&guild_id.0).and_then(|x| x.vote_skip)
+        };
+        let s = match fraction {
+            Some(fraction) => format!(
+                "Vote skip is on, {:.0}% of listeners are needed",
+                fraction * 100.0
+            ),
+            None => "Vote skip is off".to_string(),
+        };
+        check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+        return Ok(());
+    }
+
+    if !dj::is_dj(ctx, msg).await {
+        check_msg(msg.reply(ctx, "Only DJs can change vote skip").await);
+
+        return Ok(());
+    }
+
+    let fraction = match args.optional::<Switch>() {
+        Some(Switch(on)) => on.then_some(vote::DEFAULT_FRACTION),
+        None => {
+            let percent = args.within(1..=100, "~voteskip [PERCENT|on|off] (1~100)")?;
+            Some(percent as f32 / 100.0)
+        }
+    };
+
+    {
+        let mut playback = playback_lock.write().await;
+        let state = playback.entry(guild_id.0).or_default();
+        state.vote_skip = fraction;
+        state.skip_vote = None;
+    }
+
+    let s = match fraction {
+        Some(fraction) => format!(
+            "Vote skip enabled, {:.0}% of listeners are needed",
+            fraction * 100.0
+        ),
+        None => "Vote skip disabled".to_string(),
+    };
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+    Ok(())
+}
 
-        check_msg(msg.channel_id.say(&ctx.http, "Queue cleared.").await);
-    } else {
-        check_msg(
-            msg.channel_id
-                .say(&ctx.http, "Not in a voice channel to play in")
-                .await,
-        );
+const ALARM_USAGE: &str = "~alarm [HH:MM] [URL]";
+
+#[command("alarm")]
+#[only_in(guilds)]
+async fn alarm_command(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+
+    match args.current() {
+        None | Some("list") => {
+            let alarms = alarm::list(ctx, guild_id.0, msg.author.id.0).await;
+            let s = if alarms.is_empty() {
+                "You have no alarms".to_string()
+            } else {
+                alarms
+                    .iter()
+                    .map(|x| format!("{}. <t:{}:t> <{}>", x.id, x.time, x.url))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            };
+            check_msg(msg.channel_id.say(&ctx.http, s).await);
+        }
+        Some("cancel") => {
+            args.advance();
+            let id = args.required::<u64>("~alarm cancel ID")?;
+            let s = match alarm::cancel(ctx, guild_id.0, msg.author.id.0, id).await {
+                Ok(true) => format!("Canceled alarm {}", id),
+                Ok(false) => format!("You have no alarm {}", id),
+                Err(why) => {
+                    warn!("Err canceling alarm: {:?}", why);
+                    "Can not cancel the alarm".to_string()
+                }
+            };
+            check_msg(msg.channel_id.say(&ctx.http, s).await);
+        }
+        Some(_) => {
+            let time = args.required::<String>(ALARM_USAGE)?;
+            let url = match args.optional::<String>() {
+                Some(url) if url.starts_with("http") => url,
+                _ => {
+                    check_msg(
+                        msg.channel_id
+                            .say(&ctx.http, "Must provide a valid URL")
+                            .await,
+                    );
+
+                    return Ok(());
+                }
+            };
+            let s = match alarm::set(
+                ctx,
+                guild_id.0,
+                msg.channel_id,
+                Requester::from(msg),
+                &time,
+                url,
+            )
+            .await
+            {
+                Ok(alarm) => format!(
+                    "Alarm {} set for <t:{}:t> (<t:{}:R>)",
+                    alarm.id, alarm.time, alarm.time
+                ),
+                Err(why) => why.to_string(),
+            };
+            check_msg(msg.channel_id.say(&ctx.http, s).await);
+        }
     }
 
     Ok(())
 }
 
-#[command]
+#[command("quality")]
 #[only_in(guilds)]
-async fn destroy(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
-    clear(ctx, msg, args.clone()).await?;
-    leave(ctx, msg, args).await?;
+async fn quality_command(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+    let playback_lock = playback::playback_lock(ctx).await;
+
+    let quality = match args.current() {
+        None => {
+            let quality = {
+                let playback = playback_lock.read().await;
+                playback.get(&guild_id.0).and_then(|x| x.quality)
+            };
+            let s = match quality {
+                Some(quality) => format!("Streams are {} quality", quality),
+                None => "Stream quality follows the voice channel bitrate".to_string(),
+            };
+            check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+            return Ok(());
+        }
+        Some("auto") => None,
+        Some(quality) => match quality.parse::<Quality>() {
+            Ok(quality) => Some(quality),
+            Err(_) => {
+                check_msg(
+                    msg.channel_id
+                        .say(&ctx.http, "Quality must be low, normal, high or auto")
+                        .await,
+                );
+
+                return Ok(());
+            }
+        },
+    };
+
+    {
+        let mut playback = playback_lock.write().await;
+        playback.entry(guild_id.0).or_default().quality = quality;
+    }
+
+    let s = match quality {
+        Some(quality) => format!("Songs queued from now on will be {} quality", quality),
+        None => "Stream quality will follow the voice channel bitrate".to_string(),
+    };
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
 
     Ok(())
 }
 
 #[command]
 #[only_in(guilds)]
-async fn now(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
-    let guild = msg.guild(&ctx.cache).unwrap();
-    let guild_id = guild.id;
-
+async fn filter(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
     let manager = songbird::get(ctx)
         .await
         .expect("Songbird Voice client placed in at initialisation.")
         .clone();
+    let handler_lock = match manager.get(guild_id) {
+        Some(handler_lock) => handler_lock,
+        None => {
+            check_msg(
+                msg.channel_id
+                    .say(&ctx.http, "Not in a voice channel")
+                    .await,
+            );
 
-    if let Some(handler_lock) = manager.get(guild_id) {
-        let handler = handler_lock.lock().await;
-        let list = handler.queue().current_queue();
-        if list.last().is_none() {
-            check_msg(msg.channel_id.say(&ctx.http, "List is empty!").await);
-        }
-        let metadata = list.first().unwrap().metadata();
-        let title = metadata.title.as_ref();
-        let artist = metadata.artist.as_ref();
-        let url = metadata.source_url.as_ref();
-        let duration = metadata.duration.as_ref();
-        let mut s = String::from("Now Playing:\n");
-        if let Some(title) = title {
-            s.push_str(&format!("{}\n", title));
-        }
-        if let Some(artist) = artist {
-            s.push_str(&format!("{}\n", artist));
+            return Ok(());
         }
-        if let Some(url) = url {
-            s.push_str(&format!("{}\n", url))
+    };
+    let player = GuildPlayer::new(ctx, guild_id.0, handler_lock.clone()).await;
+    let handle = player.filter().await;
+
+    let filter = match args.current().map(str::parse::<Filter>) {
+        None => {
+            let s = match handle.get().filter {
+                Filter::Off => "No filter is on".to_string(),
+                filter => format!("Songs play with the {} filter", filter),
+            };
+            check_msg(msg.channel_id.say(&ctx.http, s).await);
+
+            return Ok(());
         }
-        if let Some(duration) = duration {
-            s.push_str(&format!("{}\n", duration_formatter(duration)))
+        Some(Ok(filter)) => filter,
+        Some(Err(e)) => {
+            check_msg(msg.channel_id.say(&ctx.http, e.to_string()).await);
+
+            return Ok(());
         }
-        check_msg(msg.channel_id.say(&ctx.http, s).await);
+    };
+    let old = handle.get();
+    handle.set_filter(filter);
+    if let Err(e) = restart_playing(&handler_lock, old, handle.get()).await {
+        warn!("Err restarting song with filter: {:?}", e);
     }
 
+    let s = match filter {
+        Filter::Off => "Filter turned off".to_string(),
+        filter => format!("Songs will play with the {} filter", filter),
+    };
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
+
     Ok(())
 }
 
 #[command]
 #[only_in(guilds)]
-async fn vol(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
-    let guild = msg.guild(&ctx.cache).unwrap();
-    let guild_id = guild.id;
+async fn speed(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
     let manager = songbird::get(ctx)
         .await
         .expect("Songbird Voice client placed in at initialisation.")
         .clone();
-    if let Some(handler_lock) = manager.get(guild_id) {
-        let handler = handler_lock.lock().await;
-        let list = handler.queue().current_queue();
-        if args.is_empty() {
-            let entry = list.first();
-            match entry {
-                Some(entry) => {
-                    let vol = entry.get_info().await?.volume;
-                    check_msg(
-                        msg.channel_id
-                            .say(&ctx.http, format!("Volume is {:.0}", (vol * 100.0).round()))
-                            .await,
-                    );
-                }
-                None => {
-                    check_msg(msg.channel_id.say(&ctx.http, "Queue is empty!").await);
-                }
-            }
-
-            return Ok(());
-        }
-        let s = args.parse::<String>()?;
-        if s.to_lowercase().contains('e') || s.contains('-') || s.contains('+') {
+    let handler_lock = match manager.get(guild_id) {
+        Some(handler_lock) => handler_lock,
+        None => {
             check_msg(
                 msg.channel_id
-                    .say(&ctx.http, "你他妈故意找茬是不是？你设不设音量吧？")
+                    .say(&ctx.http, "Not in a voice channel")
                     .await,
             );
+
+            return Ok(());
         }
-        let vol = args.single::<f32>();
-        if let Ok(vol) = vol {
-            if !(0.0..=200.0).contains(&vol) {
-                check_msg(
-                    msg.channel_id
-                        .say(&ctx.http, "Volume must in 0 ~ 200")
-                        .await,
-                );
+    };
+    let player = GuildPlayer::new(ctx, guild_id.0, handler_lock.clone()).await;
+    let handle = player.filter().await;
+    let old = handle.get();
 
-                return Ok(());
-            }
-            let vol = vol / 100.0;
-            let song_volume_lock = {
-                let read = ctx.data.read().await;
+    if args.is_empty() {
+        check_msg(
+            msg.channel_id
+                .say(&ctx.http, format!("Songs play at {}x speed", old.speed))
+                .await,
+        );
 
-                read.get::<SongVolume>()
-                    .expect("Expected SongVolume in TypeMap.")
-                    .clone()
-            };
-            {
-                let mut song_volume = song_volume_lock.write().await;
-                let entry = song_volume.entry(msg.channel_id.0).or_insert(1.0);
+        return Ok(());
+    }
+    let speed = args.within(
+        ffmpeg::MIN_SPEED..=ffmpeg::MAX_SPEED,
+        &format!("~speed [{}~{}]", ffmpeg::MIN_SPEED, ffmpeg::MAX_SPEED),
+    )?;
+
+    handle.set_speed(speed);
+    if let Err(e) = restart_playing(&handler_lock, old, handle.get()).await {
+        warn!("Err restarting song at new speed: {:?}", e);
+    }
+    check_msg(
+        msg.channel_id
+            .say(&ctx.http, format!("Songs will play at {}x speed", speed))
+            .await,
+    );
 
-                *entry = vol;
-            }
-            for i in list {
-                i.set_volume(vol)?;
-            }
+    Ok(())
+}
+
+/// Restarts the playing song where it is, so its ffmpeg picks up the new
+/// effects. Positions count played time, which changes with the speed.
+async fn restart_playing(
+    handler_lock: &Arc<Mutex<Call>>,
+    old: Effects,
+    new: Effects,
+) -> anyhow::Result<()> {
+    let current = handler_lock.lock().await.queue().current();
+    if let Some(current) = current {
+        let position = current.get_info().await?.position;
+        current.seek_time(new.played(position.mul_f32(old.speed)))?;
+    }
+
+    Ok(())
+}
+
+#[command("settings")]
+#[only_in(guilds)]
+async fn settings_command(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+
+    let reset = match args.current() {
+        None => {
+            let settings = settings::get(ctx, guild_id.0).await;
             check_msg(
                 msg.channel_id
-                    .say(
-                        &ctx.http,
-                        format!("Volume set to {:.0}", (vol * 100.0).round()),
-                    )
+                    .send_message(&ctx.http, |m| {
+                        m.embed(|e| e.title("Settings").description(settings.describe()))
+                    })
                     .await,
             );
-        } else {
+
+            return Ok(());
+        }
+        Some("set") => false,
+        Some("reset") => true,
+        Some(_) => {
             check_msg(
                 msg.channel_id
-                    .say(&ctx.http, "Volume must in 0 ~ 200")
+                    .say(&ctx.http, "Usage: ~settings [set|reset] [KEY] [VALUE]")
                     .await,
             );
+
+            return Ok(());
         }
+    };
+    args.advance();
+
+    if !dj::is_admin(ctx, msg).await {
+        check_msg(
+            msg.reply(
+                ctx,
+                "Only members who can manage the server can change settings",
+            )
+            .await,
+        );
+
+        return Ok(());
     }
 
-    Ok(())
-}
+    let key = match args.optional::<String>() {
+        Some(key) => key.to_lowercase(),
+        None => {
+            check_msg(
+                msg.channel_id
+                    .say(
+                        &ctx.http,
+                        format!("Must provide a setting: {}", settings::KEYS.join(", ")),
+                    )
+                    .await,
+            );
 
-#[command]
-#[only_in(guilds)]
-async fn list(ctx: &Context, msg: &Message) -> CommandResult {
-    let guild = msg.guild(&ctx.cache).unwrap();
-    let guild_id = guild.id;
-    let manager = songbird::get(ctx)
-        .await
-        .expect("Songbird Voice client placed in at initialisation.")
-        .clone();
-    if let Some(handler_lock) = manager.get(guild_id) {
-        let handler = handler_lock.lock().await;
-        let queue = handler.queue();
-        let list = queue.current_queue();
-        let mut s = String::new();
-        for (i, c) in list.iter().enumerate() {
-            let time = &c.metadata().duration;
-            if let Some(title) = &c.metadata().title {
-                s.push_str(&format!("{}. {}", i + 1, title));
-            } else if let Some(url) = &c.metadata().source_url {
-                s.push_str(&format!("{}. {}", i + 1, url));
-            }
-            if let Some(t) = time {
-                s.push_str(&format!(" {}", duration_formatter(t)));
-            }
-            s.push('\n');
-        }
-        if !s.is_empty() {
-            check_msg(msg.channel_id.say(&ctx.http, s).await);
-        } else {
-            check_msg(msg.channel_id.say(&ctx.http, "List is empty!").await)
+            return Ok(());
         }
+    };
+    let value = if reset {
+        None
+    } else {
+        Some(args.required::<String>("~settings set [KEY] [VALUE]")?)
+    };
+
+    let changed = settings::set(ctx, guild_id.0, &key, value.as_deref()).await;
+    if let Ok(settings) = &changed {
+        // Queued songs pick the change up when they start.
+        playback::set_loudnorm(ctx, guild_id.0, settings.loudnorm()).await;
     }
+    let s = match changed {
+        Ok(_) if reset => format!("Reset {}", key),
+        Ok(_) => format!("Set {} to {}", key, value.unwrap_or_default()),
+        Err(why) => why.to_string(),
+    };
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
 
     Ok(())
 }
 
+const PREFIX_USAGE: &str = "~prefix [set PREFIX|reset]";
+
 #[command]
 #[only_in(guilds)]
-async fn undeafen(ctx: &Context, msg: &Message) -> CommandResult {
-    let guild = msg.guild(&ctx.cache).unwrap();
-    let guild_id = guild.id;
-
-    let manager = songbird::get(ctx)
-        .await
-        .expect("Songbird Voice client placed in at initialisation.")
-        .clone();
+async fn prefix(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
 
-    if let Some(handler_lock) = manager.get(guild_id) {
-        let mut handler = handler_lock.lock().await;
-        if let Err(e) = handler.deafen(false).await {
+    let prefix = match args.current() {
+        None => {
+            let prefix = settings::prefix(ctx, msg).await;
             check_msg(
                 msg.channel_id
-                    .say(&ctx.http, format!("Failed: {:?}", e))
+                    .say(&ctx.http, format!("Prefix is {}", prefix))
                     .await,
             );
+
+            return Ok(());
+        }
+        Some("reset") => None,
+        Some("set") => {
+            args.advance();
+            Some(args.required::<String>(PREFIX_USAGE)?)
         }
+        Some(_) => return Err(Usage(PREFIX_USAGE.to_string()).into()),
+    };
 
-        check_msg(msg.channel_id.say(&ctx.http, "Undeafened").await);
-    } else {
+    if !dj::is_admin(ctx, msg).await {
         check_msg(
-            msg.channel_id
-                .say(&ctx.http, "Not in a voice channel to undeafen in")
-                .await,
+            msg.reply(
+                ctx,
+                "Only members who can manage the server can change the prefix",
+            )
+            .await,
         );
+
+        return Ok(());
     }
 
+    let s = match settings::set(ctx, guild_id.0, "prefix", prefix.as_deref()).await {
+        Ok(settings) => format!("Prefix set to {}", settings.prefix()),
+        Err(why) => why.to_string(),
+    };
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
+
     Ok(())
 }
 
+const SELECT_USAGE: &str = "~select 3 5 7 remove|boost|pin";
+
 #[command]
 #[only_in(guilds)]
-async fn unmute(ctx: &Context, msg: &Message) -> CommandResult {
-    let guild = msg.guild(&ctx.cache).unwrap();
-    let guild_id = guild.id;
+async fn select(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = guild::guild_id(msg)?;
+
+    let mut positions = vec![];
+    while let Some(position) = args.optional::<usize>() {
+        positions.push(position);
+    }
+    let action = args.required::<SelectAction>(SELECT_USAGE)?;
+    if positions.is_empty() {
+        return Err(Usage(SELECT_USAGE.to_string()).into());
+    }
+    let is_dj = dj::is_dj(ctx, msg).await;
+    if action != SelectAction::Remove && !is_dj {
+        check_msg(msg.reply(ctx, "Only DJs can boost or pin songs").await);
+
+        return Ok(());
+    }
+
     let manager = songbird::get(ctx)
         .await
         .expect("Songbird Voice client placed in at initialisation.")
         .clone();
-
-    if let Some(handler_lock) = manager.get(guild_id) {
-        let mut handler = handler_lock.lock().await;
-        if let Err(e) = handler.mute(false).await {
+    let handler_lock = match manager.get(guild_id) {
+        Some(handler_lock) => handler_lock,
+        None => {
             check_msg(
                 msg.channel_id
-                    .say(&ctx.http, format!("Failed: {:?}", e))
+                    .say(&ctx.http, "Not in a voice channel to play in")
                     .await,
             );
+
+            return Ok(());
         }
+    };
+    let handler = handler_lock.lock().await;
+    let queue = handler.queue();
+    let tracks = match select::resolve(ctx, guild_id, msg.author.id.0, &positions, queue).await {
+        Ok(tracks) => tracks,
+        Err(why) => {
+            check_msg(msg.channel_id.say(&ctx.http, why.to_string()).await);
 
-        check_msg(msg.channel_id.say(&ctx.http, "Unmuted").await);
-    } else {
-        check_msg(
-            msg.channel_id
-                .say(&ctx.http, "Not in a voice channel to unmute in")
-                .await,
-        );
+            return Ok(());
+        }
+    };
+
+    let mut done = 0;
+    let mut not_allowed = 0;
+    match action {
+        SelectAction::Remove => {
+            (done, not_allowed) =
+                remove_entries(ctx, guild_id.0, msg.author.id.0, is_dj, queue, &tracks).await;
+        }
+        SelectAction::Boost => {
+            // Each one plays after the ones boosted before, as they are
+            // pinned, so the order is kept.
+            for track in &tracks {
+                let pinned = queue::pinned(queue).await;
+                let boosted = select::position(queue, track)
+                    .filter(|x| *x > 0)
+                    .and_then(|x| queue::play_next(queue, &pinned, x));
+                if let Some(boosted) = boosted {
+                    queue::pin(&boosted).await;
+                    done += 1;
+                }
+            }
+        }
+        SelectAction::Pin => {
+            for track in &tracks {
+                queue::pin(track).await;
+                done += 1;
+            }
+        }
+    }
+
+    let verb = match action {
+        SelectAction::Remove => "Removed",
+        SelectAction::Boost => "Boosted",
+        SelectAction::Pin => "Pinned",
+    };
+    let mut s = format!("{} {} songs", verb, done);
+    let gone = positions.iter().collect::<HashSet<_>>().len() - tracks.len();
+    if gone > 0 {
+        s.push_str(&format!(", {} were no longer queued", gone));
+    }
+    if not_allowed > 0 {
+        s.push_str(&format!(", {} requested by others need a DJ", not_allowed));
     }
+    check_msg(msg.channel_id.say(&ctx.http, s).await);
 
     Ok(())
 }
 
-/// Checks that a message successfully sent; if not, then logs why to stdout.
-fn check_msg(result: SerenityResult<Message>) {
-    if let Err(why) = result {
-        println!("Error sending message: {:?}", why);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+
+    #[tokio::test]
+    async fn test_enqueue() {
+        let guild_id = GuildId(1);
+        let ctx = mock::context(guild_id).await;
+        let mut request = Request {
+            guild_id,
+            channel_id: ChannelId(2),
+            requester: Requester {
+                id: 3,
+                name: "alice".to_string(),
+                avatar: None,
+            },
+            position: None,
+            start: None,
+        };
+        for (song, position) in [("song", None), ("song2", None), ("song3", Some(1))] {
+            request.position = position;
+            let url = format!("http://127.0.0.1:9/{}.wav", song);
+            let enqueued = enqueue(&ctx, &request, url, false);
+            tokio::time::timeout(Duration::from_secs(10), enqueued)
+                .await
+                .unwrap()
+                .unwrap();
+        }
+
+        let manager = songbird::get(&ctx).await.unwrap();
+        let handler_lock = manager.get(guild_id).unwrap();
+        let queue = handler_lock.lock().await.queue().current_queue();
+        let titles: Vec<_> = queue
+            .iter()
+            .map(|track| track.metadata().title.clone().unwrap_or_default())
+            .collect();
+        assert_eq!(titles, ["song", "song3", "song2"]);
+        for track in &queue {
+            assert_eq!(queue::requester(track).await.unwrap().id, 3);
+        }
     }
 }
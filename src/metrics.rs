@@ -0,0 +1,417 @@
+//! `/healthz` and Prometheus `/metrics` over HTTP, for running the bot under
+//! Kubernetes or systemd with monitoring. Served on `METRICS_ADDR`, e.g.
+//! `0.0.0.0:9000`, and off unless it is set.
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    env,
+    fmt::Write,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server, StatusCode,
+};
+use lazy_static::lazy_static;
+use serenity::{async_trait, client::Context, gateway::ConnectionStage};
+use songbird::{tracks::PlayMode, Event, EventContext, EventHandler as VoiceEventHandler};
+use tracing::{info, warn};
+
+use crate::{bandwidth, playback::GuildPlayer, profile, shard, shutdown};
+
+/// Upper bounds in seconds of the resolve latency buckets.
+const RESOLVE_BUCKETS: &[f64] = &[0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+/// A track ending on its own this much before its length was cut short.
+const CUT_SHORT_SLACK: Duration = Duration::from_secs(5);
+
+static TRACKS_PLAYED: AtomicU64 = AtomicU64::new(0);
+static TRACKS_CUT_SHORT: AtomicU64 = AtomicU64::new(0);
+static FFMPEG_SPAWN_FAILURES: AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+    /// Songs a source could not open, by source name.
+    static ref RESOLVE_ERRORS: Mutex<BTreeMap<&'static str, u64>> = Mutex::new(BTreeMap::new());
+    /// Time sources took to open songs, by source name.
+    static ref RESOLVE_SECONDS: Mutex<BTreeMap<&'static str, Histogram>> =
+        Mutex::new(BTreeMap::new());
+}
+
+/// Counts of observations at or below each of `RESOLVE_BUCKETS`.
+#[derive(Clone, Default)]
+struct Histogram {
+    buckets: [u64; RESOLVE_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(RESOLVE_BUCKETS) {
+            if value <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// Counts a track starting to play.
+pub(crate) fn track_played() {
+    TRACKS_PLAYED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts a song `source` could not open.
+pub(crate) fn resolve_failed(source: &'static str) {
+    *RESOLVE_ERRORS.lock().unwrap().entry(source).or_default() += 1;
+}
+
+/// Records how long `source` took to open a song.
+pub(crate) fn resolved(source: &'static str, took: Duration) {
+    RESOLVE_SECONDS
+        .lock()
+        .unwrap()
+        .entry(source)
+        .or_default()
+        .observe(took.as_secs_f64());
+}
+
+/// Counts ffmpeg failing to start.
+pub(crate) fn ffmpeg_spawn_failed() {
+    FFMPEG_SPAWN_FAILURES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts tracks which stopped playing well before their end. Songbird
+/// has no error event, a stream which breaks off just ends the track.
+pub(crate) struct CutShortCounter {
+    pub player: GuildPlayer,
+}
+
+#[async_trait]
+impl VoiceEventHandler for CutShortCounter {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if let EventContext::Track(&[(state, track)]) = ctx {
+            // Stopped ones were skipped or removed.
+            if state.playing != PlayMode::End {
+                return None;
+            }
+            let effects = self.player.state(|x| x.filter.get()).await;
+            if let Some(duration) = track.metadata().duration {
+                if state.position + CUT_SHORT_SLACK < effects.played(duration) {
+                    TRACKS_CUT_SHORT.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// Writes one metric in the Prometheus text format. `values` are label
+/// values with their value, an empty label for a metric without labels.
+fn write_metric<'a>(
+    s: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    label: &str,
+    values: impl IntoIterator<Item = (&'a str, u64)>,
+) {
+    let _ = writeln!(s, "# HELP {} {}", name, help);
+    let _ = writeln!(s, "# TYPE {} {}", name, kind);
+    for (value_label, value) in values {
+        if value_label.is_empty() {
+            let _ = writeln!(s, "{} {}", name, value);
+        } else {
+            let _ = writeln!(s, "{}{{{}=\"{}\"}} {}", name, label, value_label, value);
+        }
+    }
+}
+
+/// Writes a histogram per label value in the Prometheus text format.
+fn write_histogram(
+    s: &mut String,
+    name: &str,
+    help: &str,
+    label: &str,
+    values: &BTreeMap<&str, Histogram>,
+) {
+    let _ = writeln!(s, "# HELP {} {}", name, help);
+    let _ = writeln!(s, "# TYPE {} histogram", name);
+    for (value_label, histogram) in values {
+        for (bound, count) in RESOLVE_BUCKETS.iter().zip(histogram.buckets) {
+            let _ = writeln!(
+                s,
+                "{}_bucket{{{}=\"{}\",le=\"{}\"}} {}",
+                name, label, value_label, bound, count
+            );
+        }
+        let _ = writeln!(
+            s,
+            "{}_bucket{{{}=\"{}\",le=\"+Inf\"}} {}",
+            name, label, value_label, histogram.count
+        );
+        let _ = writeln!(
+            s,
+            "{}_sum{{{}=\"{}\"}} {}",
+            name, label, value_label, histogram.sum
+        );
+        let _ = writeln!(
+            s,
+            "{}_count{{{}=\"{}\"}} {}",
+            name, label, value_label, histogram.count
+        );
+    }
+}
+
+/// Parent pid of a `/proc/<pid>/stat` line, if the process is `command`.
+fn parse_stat(stat: &str, command: &str) -> Option<u32> {
+    // The command name is in parentheses and may hold spaces itself.
+    let (start, end) = (stat.find('(')?, stat.rfind(')')?);
+    if &stat[start + 1..end] != command {
+        return None;
+    }
+
+    // State, then the parent pid.
+    stat[end + 1..].split_whitespace().nth(1)?.parse().ok()
+}
+
+/// ffmpeg processes the bot started which are still running.
+fn ffmpeg_processes() -> u64 {
+    let pid = std::process::id();
+    let entries = match std::fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+
+    entries
+        .filter_map(|x| std::fs::read_to_string(x.ok()?.path().join("stat")).ok())
+        .filter(|x| parse_stat(x, "ffmpeg") == Some(pid))
+        .count() as u64
+}
+
+async fn render(ctx: &Context) -> String {
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    let guilds = ctx.cache.guilds();
+    let mut voice_connections = 0;
+    let mut queue_lengths = vec![];
+    for guild_id in &guilds {
+        if let Some(handler_lock) = manager.get(*guild_id) {
+            let handler = handler_lock.lock().await;
+            if handler.current_channel().is_some() {
+                voice_connections += 1;
+                queue_lengths.push((guild_id.0.to_string(), handler.queue().len() as u64));
+            }
+        }
+    }
+
+    let mut bandwidth = BTreeMap::<String, u64>::new();
+    let month = bandwidth::month(profile::today());
+    for usage in bandwidth::bandwidth_lock(ctx).await.read().await.values() {
+        for (source, bytes) in usage.clone().in_month(month).sources {
+            *bandwidth.entry(source).or_default() += bytes;
+        }
+    }
+
+    let mut s = String::new();
+    write_metric(
+        &mut s,
+        "bibicord_guilds",
+        "gauge",
+        "Servers the bot is in.",
+        "",
+        [("", guilds.len() as u64)],
+    );
+    write_metric(
+        &mut s,
+        "bibicord_voice_connections",
+        "gauge",
+        "Voice channels the bot is in.",
+        "",
+        [("", voice_connections)],
+    );
+    write_metric(
+        &mut s,
+        "bibicord_tracks_played_total",
+        "counter",
+        "Tracks which started playing.",
+        "",
+        [("", TRACKS_PLAYED.load(Ordering::Relaxed))],
+    );
+    write_metric(
+        &mut s,
+        "bibicord_resolve_errors_total",
+        "counter",
+        "Songs a source could not open.",
+        "source",
+        RESOLVE_ERRORS.lock().unwrap().clone(),
+    );
+    write_histogram(
+        &mut s,
+        "bibicord_resolve_seconds",
+        "Time sources took to open songs.",
+        "source",
+        &RESOLVE_SECONDS.lock().unwrap(),
+    );
+    write_metric(
+        &mut s,
+        "bibicord_ffmpeg_spawn_failures_total",
+        "counter",
+        "Times ffmpeg could not be started.",
+        "",
+        [("", FFMPEG_SPAWN_FAILURES.load(Ordering::Relaxed))],
+    );
+    write_metric(
+        &mut s,
+        "bibicord_tracks_cut_short_total",
+        "counter",
+        "Tracks which ended well before their length, e.g. as their stream broke off.",
+        "",
+        [("", TRACKS_CUT_SHORT.load(Ordering::Relaxed))],
+    );
+    write_metric(
+        &mut s,
+        "bibicord_queue_length",
+        "gauge",
+        "Tracks queued in each voice channel the bot is in.",
+        "guild",
+        queue_lengths
+            .iter()
+            .map(|(guild, len)| (guild.as_str(), *len)),
+    );
+    write_metric(
+        &mut s,
+        "bibicord_ffmpeg_processes",
+        "gauge",
+        "ffmpeg processes running.",
+        "",
+        [("", ffmpeg_processes())],
+    );
+    write_metric(
+        &mut s,
+        "bibicord_bandwidth_bytes",
+        "gauge",
+        "Estimated traffic this month.",
+        "source",
+        bandwidth
+            .iter()
+            .map(|(source, bytes)| (source.as_str(), *bytes)),
+    );
+
+    s
+}
+
+/// Healthy while every shard is connected to the gateway.
+async fn healthy(ctx: &Context) -> bool {
+    if shutdown::is_stopping() {
+        return false;
+    }
+
+    shard::statuses(ctx)
+        .await
+        .iter()
+        .all(|x| x.stage == ConnectionStage::Connected)
+}
+
+async fn handle(ctx: Context, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let (status, body) = match req.uri().path() {
+        "/healthz" if healthy(&ctx).await => (StatusCode::OK, "ok".to_string()),
+        "/healthz" => (StatusCode::SERVICE_UNAVAILABLE, "unhealthy".to_string()),
+        "/metrics" => (StatusCode::OK, render(&ctx).await),
+        _ => (StatusCode::NOT_FOUND, "not found".to_string()),
+    };
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+
+    Ok(response)
+}
+
+/// Serves the endpoints until the bot stops, if `METRICS_ADDR` is set.
+pub(crate) async fn run(ctx: Context) {
+    let addr = match env::var("METRICS_ADDR").map(|x| x.parse::<SocketAddr>()) {
+        Ok(Ok(addr)) => addr,
+        Ok(Err(e)) => {
+            warn!("Err parsing METRICS_ADDR: {:?}", e);
+            return;
+        }
+        Err(_) => return,
+    };
+
+    let make_service = make_service_fn(move |_| {
+        let ctx = ctx.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| handle(ctx.clone(), req))) }
+    });
+    let server = match Server::try_bind(&addr) {
+        Ok(server) => server.serve(make_service),
+        Err(e) => {
+            warn!("Err serving metrics on {}: {:?}", addr, e);
+            return;
+        }
+    };
+    info!("Serving /healthz and /metrics on {}", addr);
+    if let Err(e) = server.await {
+        warn!("Err serving metrics: {:?}", e);
+    }
+}
+
+#[test]
+fn test_write_metric() {
+    let mut s = String::new();
+    write_metric(&mut s, "x_total", "counter", "Xs.", "", [("", 3)]);
+    write_metric(
+        &mut s,
+        "y",
+        "gauge",
+        "Ys.",
+        "source",
+        [("Netease", 1), ("YouTube", 2)],
+    );
+
+    assert_eq!(
+        s,
+        "# HELP x_total Xs.\n# TYPE x_total counter\nx_total 3\n\
+         # HELP y Ys.\n# TYPE y gauge\ny{source=\"Netease\"} 1\ny{source=\"YouTube\"} 2\n"
+    );
+}
+
+#[test]
+fn test_write_histogram() {
+    let mut histogram = Histogram::default();
+    histogram.observe(0.2);
+    histogram.observe(3.0);
+    let mut s = String::new();
+    write_histogram(
+        &mut s,
+        "r",
+        "Rs.",
+        "source",
+        &BTreeMap::from([("Netease", histogram)]),
+    );
+
+    assert!(s.contains("r_bucket{source=\"Netease\",le=\"0.1\"} 0\n"));
+    assert!(s.contains("r_bucket{source=\"Netease\",le=\"0.25\"} 1\n"));
+    assert!(s.contains("r_bucket{source=\"Netease\",le=\"5\"} 2\n"));
+    assert!(s.contains("r_bucket{source=\"Netease\",le=\"+Inf\"} 2\n"));
+    assert!(s.contains("r_sum{source=\"Netease\"} 3.2\n"));
+    assert!(s.contains("r_count{source=\"Netease\"} 2\n"));
+}
+
+#[test]
+fn test_parse_stat() {
+    assert_eq!(
+        parse_stat("4242 (ffmpeg) S 100 4242 100 0 -1", "ffmpeg"),
+        Some(100)
+    );
+    assert_eq!(parse_stat("7 (my prog) R 1 7 7 0 -1", "my prog"), Some(1));
+    assert_eq!(parse_stat("8 (bash) S 100 8 8 0 -1", "ffmpeg"), None);
+}
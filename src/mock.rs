@@ -0,0 +1,117 @@
+//! A voice driver without a connection and silent tracks, so queue and
+//! volume logic runs in tests without a token or a voice channel, and a
+//! `Context` to run commands end to end.
+//!
+//! Without a connection songbird's mixer neither plays nor answers track
+//! commands, so tests must not wait on `TrackHandle::get_info`.
+use std::{env, fs, os::unix::fs::PermissionsExt, process, sync::Arc, sync::Once};
+
+use serenity::{
+    cache::Cache,
+    client::{bridge::gateway::ShardMessenger, Context},
+    futures::channel::mpsc,
+    http::HttpBuilder,
+    model::id::{GuildId, UserId},
+    prelude::{RwLock, TypeMap},
+};
+use songbird::{
+    input::{reader::Reader, Codec, Container, Input, Metadata},
+    serenity::SongbirdKey,
+    tracks::TrackHandle,
+    Driver, Songbird,
+};
+
+/// A second of silence.
+const SILENCE: usize = 48_000 * 2 * 4;
+
+/// A driver of its own, like every guild gets.
+pub(crate) fn driver() -> Driver {
+    Driver::new(Default::default())
+}
+
+/// A silent track titled `title`, from `url`.
+pub(crate) fn track(title: &str, url: &str) -> Input {
+    let metadata = Metadata {
+        title: Some(title.to_string()),
+        source_url: Some(url.to_string()),
+        ..Default::default()
+    };
+
+    Input::new(
+        true,
+        Reader::from_memory(vec![0; SILENCE]),
+        Codec::FloatPcm,
+        Container::Raw,
+        Some(metadata),
+    )
+}
+
+/// A driver with `titles` queued, the first one is the current track.
+pub(crate) fn queued(titles: &[&str]) -> Driver {
+    let mut driver = driver();
+    for title in titles {
+        driver.enqueue_source(track(title, &format!("https://example.com/{}", title)));
+    }
+
+    driver
+}
+
+/// Titles in the queue of `driver`, in order.
+pub(crate) fn titles(driver: &Driver) -> Vec<String> {
+    titles_of(&driver.queue().current_queue())
+}
+
+pub(crate) fn titles_of(tracks: &[TrackHandle]) -> Vec<String> {
+    tracks
+        .iter()
+        .map(|x| x.metadata().title.clone().unwrap_or_default())
+        .collect()
+}
+
+/// Points the store at a directory of the test run and ffprobe at a stub
+/// which finds every song a minute long, before either is first used.
+fn setup() {
+    static SETUP: Once = Once::new();
+    SETUP.call_once(|| {
+        let dir = env::temp_dir().join(format!("bibicord-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let ffprobe = dir.join("ffprobe");
+        fs::write(
+            &ffprobe,
+            "#!/bin/sh\necho '{\"format\":{\"duration\":\"60.0\"}}'\n",
+        )
+        .unwrap();
+        fs::set_permissions(&ffprobe, fs::Permissions::from_mode(0o755)).unwrap();
+        env::set_var("DATA_DIR", dir.join("data"));
+        env::set_var("FFPROBE_PATH", ffprobe);
+    });
+}
+
+/// A `Context` with the bot's data and a call in `guild_id` which never
+/// connects, so commands run end to end. Discord is never reached: every
+/// request fails at once and is logged like an outage.
+pub(crate) async fn context(guild_id: GuildId) -> Context {
+    setup();
+    let http = HttpBuilder::new("Bot test")
+        // Nothing listens on the discard port.
+        .proxy("http://127.0.0.1:9")
+        .unwrap()
+        .ratelimiter_disabled(true)
+        .build();
+    let songbird = Songbird::serenity();
+    songbird.initialise_client_data(1, UserId(1));
+    songbird.get_or_insert(guild_id);
+
+    let mut data = TypeMap::new();
+    crate::insert_data(&mut data).await;
+    data.insert::<SongbirdKey>(songbird);
+    let (shard, _) = mpsc::unbounded();
+
+    Context {
+        data: Arc::new(RwLock::new(data)),
+        shard: ShardMessenger::new(shard),
+        shard_id: 0,
+        http: Arc::new(http),
+        cache: Arc::new(Cache::new()),
+    }
+}
@@ -0,0 +1,99 @@
+//! `~myplaylist`: a playlist of every user, kept with their id so it can be
+//! played in any guild.
+use std::{collections::HashMap, env};
+
+use anyhow::{bail, Result};
+use lazy_static::lazy_static;
+
+use crate::{source, store};
+
+const MY_PLAYLISTS: &str = "myplaylists";
+const DEFAULT_MY_PLAYLIST_MAX: usize = 200;
+
+lazy_static! {
+    /// Most songs in a personal playlist, from `MY_PLAYLIST_MAX` in the environment.
+    static ref MY_PLAYLIST_MAX: usize = env::var("MY_PLAYLIST_MAX")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(DEFAULT_MY_PLAYLIST_MAX);
+}
+
+type MyPlaylists = HashMap<u64, Vec<String>>;
+
+/// Appends the `urls` not in `list` yet, until it holds `max`. Returns how
+/// many were added.
+fn append(list: &mut Vec<String>, urls: Vec<String>, max: usize) -> usize {
+    let before = list.len();
+    for url in urls {
+        if list.len() >= max {
+            break;
+        }
+        if !list.contains(&url) {
+            list.push(url);
+        }
+    }
+
+    list.len() - before
+}
+
+/// Adds the song at `url` to the user's playlist, or every song when it is
+/// a playlist. Returns how many were added.
+pub(crate) async fn add(user: u64, url: String) -> Result<usize> {
+    let len = get(user).await?.len();
+    let room = MY_PLAYLIST_MAX.saturating_sub(len);
+    if room == 0 {
+        bail!(
+            "Your playlist is full ({} songs), remove some first",
+            *MY_PLAYLIST_MAX
+        );
+    }
+
+    let provider = source::provider(&url);
+    let urls = if provider.is_playlist(&url) {
+        // Songs already in the list don't take room, ask for some more.
+        provider.resolve_playlist(&url, room + len).await?
+    } else {
+        provider.check_playable(&url).await?;
+        vec![url]
+    };
+
+    store::update(MY_PLAYLISTS, |playlists: &mut MyPlaylists| {
+        append(playlists.entry(user).or_default(), urls, *MY_PLAYLIST_MAX)
+    })
+    .await
+}
+
+pub(crate) async fn get(user: u64) -> Result<Vec<String>> {
+    let mut playlists = store::load::<MyPlaylists>(MY_PLAYLISTS).await?;
+
+    Ok(playlists.remove(&user).unwrap_or_default())
+}
+
+/// Removes the song at 1-based `position`, returns its URL.
+pub(crate) async fn remove(user: u64, position: usize) -> Result<Option<String>> {
+    store::update(MY_PLAYLISTS, |playlists: &mut MyPlaylists| {
+        let list = playlists.entry(user).or_default();
+        position
+            .checked_sub(1)
+            .filter(|x| *x < list.len())
+            .map(|i| list.remove(i))
+    })
+    .await
+}
+
+/// Empties the user's playlist, returns how many songs it had.
+pub(crate) async fn clear(user: u64) -> Result<usize> {
+    store::update(MY_PLAYLISTS, |playlists: &mut MyPlaylists| {
+        playlists.remove(&user).map(|x| x.len()).unwrap_or_default()
+    })
+    .await
+}
+
+#[test]
+fn test_append() {
+    let mut list = vec!["a".to_string()];
+    let urls = ["a", "b", "c", "d"].map(str::to_string).to_vec();
+
+    assert_eq!(append(&mut list, urls, 3), 2);
+    assert_eq!(list, ["a", "b", "c"]);
+}
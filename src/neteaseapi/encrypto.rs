@@ -5,12 +5,10 @@ use openssl::rsa::{Padding, Rsa};
 use openssl::symm::{encrypt, Cipher};
 use rand::rngs::OsRng;
 use rand::RngCore;
-use urlqstring::QueryParams;
 
 lazy_static! {
     static ref IV: Vec<u8> = "0102030405060708".as_bytes().to_vec();
     static ref PRESET_KEY: Vec<u8> = "0CoJUm6Qyw8W8jud".as_bytes().to_vec();
-    static ref LINUX_API_KEY: Vec<u8> = "rFgB&h#%2?^eDg:Q".as_bytes().to_vec();
     static ref BASE62: Vec<u8> = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789".as_bytes().to_vec();
     static ref RSA_PUBLIC_KEY: Vec<u8> = "-----BEGIN PUBLIC KEY-----\nMIGfMA0GCSqGSIb3DQEBAQUAA4GNADCBiQKBgQDgtQn2JZ34ZC28NWYpAUd98iZ37BUrX/aKzmFbt7clFSs6sXqHauqKWqdtLkF2KexO40H1YTX8z2lSgBBOAxLsvaklV8k4cBFK9snQXE9/DDaFt6Rr7iVZMldczhC0JNgTz+SHXT6CBHuX3e9SdB1Ua44oncaTWz7OBGLbCiK45wIDAQAB\n-----END PUBLIC KEY-----".as_bytes().to_vec();
     static ref EAPIKEY: Vec<u8> = "e82ckenh8dichen8".as_bytes().to_vec();
@@ -19,7 +17,7 @@ lazy_static! {
 #[allow(non_snake_case)]
 pub struct Crypto;
 
-#[allow(non_camel_case_types)]
+#[allow(dead_code, non_camel_case_types)]
 pub enum HashType {
     md5,
 }
@@ -31,25 +29,19 @@ pub enum AesMode {
 }
 
 impl Crypto {
-    pub fn hex_random_bytes(n: usize) -> String {
-        let mut data: Vec<u8> = Vec::with_capacity(n);
-        OsRng.fill_bytes(&mut data);
-        hex::encode(data)
-    }
-
-    pub fn eapi(url: &str, text: &str) -> String {
+    /// Signs `text` for the eapi endpoint `url`, e.g. `/api/song/detail`.
+    pub fn eapi(url: &str, text: &str) -> Vec<(String, String)> {
         let message = format!("nobody{}use{}md5forencrypt", url, text);
         let digest = hex::encode(hash(MessageDigest::md5(), message.as_bytes()).unwrap());
         let data = format!("{}-36cd479b6b5-{}-36cd479b6b5-{}", url, text, digest);
-        let params = Crypto::aes_encrypt(&data, &*EAPIKEY, ecb, Some(&*IV), |t: &Vec<u8>| {
+        let params = Crypto::aes_encrypt(&data, &EAPIKEY, ecb, None, |t: &Vec<u8>| {
             hex::encode_upper(t)
         });
-        println!("params={}", params);
-        QueryParams::from(vec![("params", params.as_str())]).stringify()
+
+        vec![("params".to_string(), params)]
     }
 
     pub fn weapi(text: &str) -> Vec<(String, String)> {
-        println!("text={}", text);
         let mut secret_key = [0u8; 16];
         OsRng.fill_bytes(&mut secret_key);
         let key: Vec<u8> = secret_key
@@ -57,9 +49,7 @@ impl Crypto {
             .map(|i| BASE62[(i % 62) as usize])
             .collect();
 
-        println!("key={}", String::from_utf8(key.clone()).unwrap());
-
-        let params1 = Crypto::aes_encrypt(text, &*PRESET_KEY, cbc, Some(&*IV), |t: &Vec<u8>| {
+        let params1 = Crypto::aes_encrypt(text, &PRESET_KEY, cbc, Some(&*IV), |t: &Vec<u8>| {
             base64::encode(t)
         });
 
@@ -69,7 +59,7 @@ impl Crypto {
 
         let enc_sec_key = Crypto::rsa_encrypt(
             std::str::from_utf8(&key.iter().rev().copied().collect::<Vec<u8>>()).unwrap(),
-            &*RSA_PUBLIC_KEY,
+            &RSA_PUBLIC_KEY,
         );
 
         vec![
@@ -78,15 +68,6 @@ impl Crypto {
         ]
     }
 
-    pub fn linuxapi(text: &str) -> String {
-        let params = Crypto::aes_encrypt(text, &*LINUX_API_KEY, ecb, None, |t: &Vec<u8>| {
-            hex::encode(t)
-        })
-        .to_uppercase();
-        println!("text={},prams={}", text, params);
-        QueryParams::from(vec![("eparams", params.as_str())]).stringify()
-    }
-
     pub fn aes_encrypt(
         data: &str,
         key: &[u8],
@@ -117,6 +98,7 @@ impl Crypto {
         hex::encode(buf)
     }
 
+    #[allow(dead_code)]
     pub fn hash_encrypt(
         data: &str,
         algorithm: HashType,
@@ -132,7 +114,8 @@ impl Crypto {
 mod tests {
 
     use super::Crypto;
-    use crate::neteaseapi::encrypto::{AesMode, HashType, IV, PRESET_KEY, RSA_PUBLIC_KEY};
+    use crate::neteaseapi::encrypto::{AesMode, HashType, EAPIKEY, IV, PRESET_KEY, RSA_PUBLIC_KEY};
+    use openssl::symm::{decrypt, Cipher};
     use urlqstring::QueryParams;
 
     #[test]
@@ -141,7 +124,7 @@ mod tests {
         let key1 = "gLiwKFot44HYFRAy";
         let res = Crypto::aes_encrypt(
             msg1,
-            &*PRESET_KEY,
+            &PRESET_KEY,
             AesMode::cbc,
             Some(&*IV),
             |t: &Vec<u8>| base64::encode(t),
@@ -150,7 +133,7 @@ mod tests {
 
         let res2 = Crypto::aes_encrypt(
             &res,
-            &key1.as_bytes().to_vec(),
+            key1.as_bytes(),
             AesMode::cbc,
             Some(&*IV),
             |t: &Vec<u8>| base64::encode(t),
@@ -164,7 +147,7 @@ mod tests {
         let key2 = "05EBdrdgLjgiqaRc";
         let res = Crypto::aes_encrypt(
             msg2,
-            &*PRESET_KEY,
+            &PRESET_KEY,
             AesMode::cbc,
             Some(&*IV),
             |t: &Vec<u8>| base64::encode(t),
@@ -173,7 +156,7 @@ mod tests {
 
         let res2 = Crypto::aes_encrypt(
             &res,
-            &key2.as_bytes().to_vec(),
+            key2.as_bytes(),
             AesMode::cbc,
             Some(&*IV),
             |t: &Vec<u8>| base64::encode(t),
@@ -187,7 +170,7 @@ mod tests {
     #[test]
     fn test_rsa_encrypt() {
         let key2 = "yARFYH44toFKwiLg";
-        let res = Crypto::rsa_encrypt(key2, &*RSA_PUBLIC_KEY);
+        let res = Crypto::rsa_encrypt(key2, &RSA_PUBLIC_KEY);
         assert_eq!(res, "5ff8bdb3ed3dd15a26e9025e9abcff0d7a3764dafbc70e33859a892584c681f1aab314b8ad1f3418650ff851bdb0685fc5136a88e059c592da104bbeaba666fbe89eb405c7b66eab4db8ee3ab13a3f98cb41b2ac9981ed4e441ed8e1870524d001ee6ebc1c09f7a945677e5b56a3e964a224c3ee75ac43fbf513f6a8bf7472ee");
     }
 
@@ -207,6 +190,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_eapi() {
+        let text = r#"{"id":"26209670"}"#;
+        let params = Crypto::eapi("/api/song/detail", text);
+        assert_eq!(params[0].0, "params");
+
+        let data = decrypt(
+            Cipher::aes_128_ecb(),
+            &EAPIKEY,
+            None,
+            &hex::decode(&params[0].1).unwrap(),
+        )
+        .unwrap();
+        let data = String::from_utf8(data).unwrap();
+        let parts = data.split("-36cd479b6b5-").collect::<Vec<_>>();
+        assert_eq!(parts[..2], ["/api/song/detail", text]);
+        assert_eq!(
+            parts[2],
+            Crypto::hash_encrypt(
+                r#"nobody/api/song/detailuse{"id":"26209670"}md5forencrypt"#,
+                HashType::md5,
+                hex::encode
+            )
+        );
+    }
+
     #[test]
     fn test_weapi() {
         let text = r#"{"ids":"[\"89ADDE33C0AAE8EC14B99F6750DB954D\"]","resolution":"1080"}"#;
@@ -214,7 +223,7 @@ mod tests {
 
         let params1 = Crypto::aes_encrypt(
             text,
-            &*PRESET_KEY,
+            &PRESET_KEY,
             AesMode::cbc,
             Some(&*IV),
             |t: &Vec<u8>| base64::encode(t),
@@ -229,7 +238,7 @@ mod tests {
 
         let enc_sec_key = Crypto::rsa_encrypt(
             std::str::from_utf8(&key.iter().rev().copied().collect::<Vec<u8>>()).unwrap(),
-            &*RSA_PUBLIC_KEY,
+            &RSA_PUBLIC_KEY,
         );
 
         let res = QueryParams::from(vec![
@@ -1,15 +1,65 @@
-use songbird::input::{Input, Restartable};
+use songbird::input::{Metadata, Restartable};
 
-use self::netease::{_netease, _netease_restartable};
+use self::netease::{
+    _netease_cloud, _netease_fm, _netease_login, _netease_lyrics, _netease_playlist,
+    _netease_related, _netease_restartable, _netease_search, _netease_stream_url,
+};
+
+pub(crate) use self::netease::{is_program, Lyrics, Restricted};
 
 mod encrypto;
 mod netease;
+use crate::{ffmpeg::FilterHandle, quality::Quality};
 use anyhow::Result;
+use std::env;
+
+pub(crate) async fn netease_restartable(
+    url: &str,
+    lazy: bool,
+    quality: Quality,
+    filter: FilterHandle,
+) -> Result<Restartable> {
+    _netease_restartable(url, lazy, quality, filter).await
+}
+
+/// Logs in with `NETEASE_PHONE` and `NETEASE_PASSWORD` when they are set.
+pub(crate) async fn netease_login_from_env() -> Result<()> {
+    let (phone, password) = match (env::var("NETEASE_PHONE"), env::var("NETEASE_PASSWORD")) {
+        (Ok(phone), Ok(password)) => (phone, password),
+        _ => return Ok(()),
+    };
+    let country_code = env::var("NETEASE_COUNTRY_CODE").unwrap_or_else(|_| "86".to_string());
+
+    _netease_login(&phone, &password, &country_code).await
+}
+
+/// Where the song streams from, fails when Netease won't serve it. The
+/// URL is kept until it expires, so asking ahead saves the wait when the
+/// song starts.
+pub(crate) async fn netease_stream_url(url: &str, quality: Quality) -> Result<String> {
+    _netease_stream_url(url, quality).await
+}
+
+pub(crate) async fn netease_playlist(url: &str) -> Result<Vec<String>> {
+    _netease_playlist(url).await
+}
+
+pub(crate) async fn netease_fm() -> Result<Vec<String>> {
+    _netease_fm().await
+}
+
+pub(crate) async fn netease_related(url: &str, limit: usize) -> Result<Vec<String>> {
+    _netease_related(url, limit).await
+}
+
+pub(crate) async fn netease_cloud(limit: usize) -> Result<Vec<Metadata>> {
+    _netease_cloud(limit).await
+}
 
-pub(crate) async fn netease(url: &str) -> Result<Input> {
-    _netease(url, None).await
+pub(crate) async fn netease_search(keywords: &str, limit: usize) -> Result<Vec<Metadata>> {
+    _netease_search(keywords, limit).await
 }
 
-pub(crate) async fn netease_restartable(url: &str, lazy: bool) -> Result<Restartable> {
-    _netease_restartable(url, lazy).await
+pub(crate) async fn netease_lyrics(url: &str) -> Result<Lyrics> {
+    _netease_lyrics(url).await
 }
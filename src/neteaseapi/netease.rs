@@ -1,19 +1,26 @@
-use std::{
-    collections::HashMap,
-    io::ErrorKind,
-    process::{Command, Stdio},
-    time::Duration,
-};
+use std::{collections::HashMap, env, fmt, sync::RwLock, time::Duration};
 
-use crate::neteaseapi::encrypto::Crypto;
+use crate::{
+    credentials::{self, Credential},
+    decode,
+    error::BibiError,
+    ffmpeg::{self, FilterHandle, Pipeline},
+    neteaseapi::encrypto::Crypto,
+    quality::Quality,
+    seek_cache,
+    stream_cache::{self, Stream},
+};
 use anyhow::{anyhow, bail, Result};
 use async_trait::async_trait;
-use reqwest::{Client, Response, Url};
-use serde::{Deserialize, Serialize};
-use songbird::input::{
-    children_to_reader, restartable::Restart, Codec, Container, Input, Metadata, Restartable,
+use lazy_static::lazy_static;
+use openssl::hash::{hash, MessageDigest};
+use reqwest::{
+    header::{HeaderMap, HeaderValue, COOKIE, SET_COOKIE},
+    Client, Url,
 };
-use tracing::{debug, info};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use songbird::input::{restartable::Restart, Codec, Container, Input, Metadata, Restartable};
+use tracing::{debug, info, warn};
 
 #[derive(Deserialize, Serialize)]
 struct SongResult {
@@ -23,23 +30,246 @@ struct SongResult {
 
 #[derive(Deserialize, Serialize)]
 struct SongDataResult {
+    /// `null` for songs which are paid or pulled in our region.
+    url: Option<String>,
+    /// 1 for VIP songs, 4 for songs sold in paid albums.
+    fee: Option<i64>,
+    /// Set when `url` is only a short preview of the song.
+    #[serde(rename(deserialize = "freeTrialInfo"))]
+    free_trial_info: Option<serde_json::Value>,
+    /// Seconds until `url` stops working.
+    expi: Option<u64>,
+}
+
+/// How long stream URLs work when Netease doesn't say.
+const DEFAULT_URL_TTL: Duration = Duration::from_secs(20 * 60);
+
+#[derive(Debug)]
+struct SongUrl {
     url: String,
+    ttl: Duration,
+}
+
+#[derive(Deserialize, Debug)]
+struct FmResult {
+    code: i64,
+    #[serde(default)]
+    data: Vec<SongDetailSong>,
 }
 
+#[derive(Deserialize, Debug)]
+struct CloudResult {
+    code: i64,
+    #[serde(default)]
+    data: Vec<CloudSong>,
+}
+
+/// A song the account uploaded to its cloud disk.
+#[derive(Deserialize, Debug)]
+struct CloudSong {
+    #[serde(rename(deserialize = "songId"))]
+    song_id: u64,
+    #[serde(rename(deserialize = "songName"))]
+    song_name: Option<String>,
+    artist: Option<String>,
+    /// The matching catalog song, when Netease found one.
+    #[serde(rename(deserialize = "simpleSong"))]
+    simple_song: Option<CloudSimpleSong>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CloudSimpleSong {
+    al: Option<SongDetailSongAlbum>,
+    dt: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SimiSongResult {
+    #[serde(default)]
+    songs: Vec<SongDetailSong>,
+}
+
+#[derive(Deserialize, Debug)]
+struct LoginResult {
+    code: i64,
+    msg: Option<String>,
+    message: Option<String>,
+}
+
+/// Why Netease won't stream a song to us.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Restricted {
+    Vip,
+    PaidAlbum,
+    Preview,
+    Unavailable,
+}
+
+impl Restricted {
+    fn from_fee(fee: Option<i64>) -> Self {
+        match fee {
+            Some(1) => Restricted::Vip,
+            Some(4) => Restricted::PaidAlbum,
+            _ => Restricted::Unavailable,
+        }
+    }
+}
+
+impl fmt::Display for Restricted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Restricted::Vip => {
+                "This Netease song needs a VIP membership the bot's account doesn't have"
+            }
+            Restricted::PaidAlbum => "This Netease song is only sold as part of a paid album",
+            Restricted::Preview => "Netease only gives the bot's account a preview of this song",
+            Restricted::Unavailable => "This Netease song is unavailable, it may be region locked",
+        };
+
+        write!(f, "{}", s)
+    }
+}
+
+impl std::error::Error for Restricted {}
+
 #[derive(Deserialize, Serialize, Debug)]
 struct Ids {
     id: String,
 }
 
-#[derive(Deserialize, Serialize)]
-struct SongDetailRequest {
-    c: Vec<Ids>,
-    ids: Vec<u64>,
+/// What an API endpoint answered.
+struct Reply {
+    body: Vec<u8>,
+    /// `Set-Cookie` headers.
+    cookies: Vec<String>,
 }
-struct NeteaseClient {
+
+/// Sends API requests, tests swap in fixtures for Netease.
+#[async_trait]
+trait Backend: Send + Sync {
+    /// Posts `params` to the endpoint at `path`, e.g. `/song/detail`.
+    async fn post(&self, path: &str, params: &HashMap<&str, &str>) -> Result<Reply>;
+}
+
+/// How requests are signed. Netease breaks or blocks one of them now and
+/// then, so each endpoint falls back to the other and keeps using the one
+/// that worked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Api {
+    /// What the web player uses.
+    Weapi,
+    /// What the apps use.
+    Eapi,
+}
+
+impl Api {
+    fn other(self) -> Self {
+        match self {
+            Api::Weapi => Api::Eapi,
+            Api::Eapi => Api::Weapi,
+        }
+    }
+
+    fn url(self, path: &str) -> String {
+        match self {
+            Api::Weapi => format!("{}{}", BASE_URL, path),
+            Api::Eapi => format!("{}{}", EAPI_URL, path),
+        }
+    }
+}
+
+/// Which api an endpoint was last answered over.
+fn preferred(path: &str) -> Api {
+    PREFERRED_API
+        .read()
+        .unwrap()
+        .get(path)
+        .copied()
+        .unwrap_or(*DEFAULT_API)
+}
+
+fn set_preferred(path: &str, api: Api) {
+    PREFERRED_API.write().unwrap().insert(path.to_string(), api);
+}
+
+/// Whether Netease refused to answer a request it found suspicious. Blocked
+/// requests get a 200 with code -460 or -462, or something other than JSON.
+fn blocked(body: &[u8]) -> bool {
+    #[derive(Deserialize)]
+    struct Code {
+        code: Option<i64>,
+    }
+
+    match serde_json::from_slice::<Code>(body) {
+        Ok(x) => matches!(x.code, Some(-460 | -462)),
+        Err(_) => true,
+    }
+}
+
+/// Netease over HTTPS.
+struct Http {
     client: Client,
 }
 
+impl Http {
+    async fn send(&self, api: Api, path: &str, params: &HashMap<&str, &str>) -> Result<Reply> {
+        let request = self.client.post(api.url(path));
+        let request = match api {
+            Api::Weapi => request.query(&crypto_params(params)?),
+            Api::Eapi => request.form(&eapi_params(path, params)?),
+        };
+        let response = request
+            .send()
+            .await
+            .and_then(|x| x.error_for_status())
+            .map_err(|e| BibiError::network("Netease", e))?;
+        let cookies = response
+            .headers()
+            .get_all(SET_COOKIE)
+            .iter()
+            .filter_map(|x| x.to_str().ok())
+            .map(|x| x.to_string())
+            .collect();
+        let body = response
+            .bytes()
+            .await
+            .map_err(|e| BibiError::network("Netease", e))?
+            .to_vec();
+        if blocked(&body) {
+            bail!("Netease blocked {} over the {:?}", path, api);
+        }
+
+        Ok(Reply { body, cookies })
+    }
+}
+
+#[async_trait]
+impl Backend for Http {
+    async fn post(&self, path: &str, params: &HashMap<&str, &str>) -> Result<Reply> {
+        let api = preferred(path);
+        let e = match self.send(api, path, params).await {
+            Ok(reply) => return Ok(reply),
+            Err(e) => e,
+        };
+        warn!(
+            "Netease {} failed over the {:?}, trying the {:?}: {:?}",
+            path,
+            api,
+            api.other(),
+            e
+        );
+        // The first error says more, the other api is only a fallback.
+        let reply = self.send(api.other(), path, params).await.map_err(|_| e)?;
+        set_preferred(path, api.other());
+
+        Ok(reply)
+    }
+}
+
+struct NeteaseClient {
+    backend: Box<dyn Backend>,
+}
+
 #[derive(Deserialize, Debug)]
 struct SongDetailResult {
     songs: Vec<SongDetailSong>,
@@ -52,6 +282,13 @@ struct SongDetailSong {
     #[serde(default)]
     artists: Vec<SongDetailSongArtist>,
     duration: Option<u64>,
+    album: Option<SongDetailSongAlbum>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SongDetailSongAlbum {
+    #[serde(rename(deserialize = "picUrl"))]
+    pic_url: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -68,16 +305,88 @@ struct DjDetail {
 struct DjDetailProgram {
     #[serde(rename(deserialize = "mainSong"))]
     main_song: Option<SongDetailSong>,
+    #[serde(rename(deserialize = "coverUrl"))]
+    cover_url: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PlaylistDetailResult {
+    playlist: Option<PlaylistDetail>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PlaylistDetail {
+    #[serde(rename(deserialize = "trackIds"), default)]
+    track_ids: Vec<PlaylistTrackId>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PlaylistTrackId {
+    id: u64,
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchResult {
+    result: Option<SearchResultSongs>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchResultSongs {
+    #[serde(default)]
+    songs: Vec<SongDetailSong>,
+}
+
+#[derive(Deserialize, Debug)]
+struct LyricResult {
+    lrc: Option<LyricContent>,
+    tlyric: Option<LyricContent>,
+}
+
+#[derive(Deserialize, Debug)]
+struct LyricContent {
+    lyric: Option<String>,
+}
+
+/// Lyrics of a song in LRC format.
+#[derive(Debug)]
+pub(crate) struct Lyrics {
+    pub original: String,
+    /// Chinese translation, only there for foreign songs.
+    pub translated: Option<String>,
 }
 
 enum NeteaseTyoe {
     Normal,
     Dj,
+    /// In the cloud disk of the logged in account, maybe not in the catalog.
+    Cloud,
+}
+
+fn netease_type(url: &str) -> NeteaseTyoe {
+    if is_program(url) {
+        NeteaseTyoe::Dj
+    } else if is_cloud(url) {
+        NeteaseTyoe::Cloud
+    } else {
+        NeteaseTyoe::Normal
+    }
 }
 
 const USER_AGENT: &str = "Mozilla/5.0 (iPhone; CPU iPhone OS 9_1 like Mac OS X) AppleWebKit/601.1.46 (KHTML, like Gecko) Version/9.0 Mobile/13B143 Safari/601.1";
 const BASE_URL: &str = "https://music.163.com/weapi";
-const BIT_RATE_LIST: &[&str] = &["320000", "192000", "128000"];
+const EAPI_URL: &str = "https://interface3.music.163.com/eapi";
+lazy_static! {
+    /// `NETEASE_API=eapi` tries the eapi first.
+    static ref DEFAULT_API: Api = match env::var("NETEASE_API").as_deref() {
+        Ok("eapi") => Api::Eapi,
+        _ => Api::Weapi,
+    };
+    static ref PREFERRED_API: RwLock<HashMap<String, Api>> = RwLock::new(HashMap::new());
+
+    /// Session from logging in with a phone number, used when no cookie
+    /// is configured.
+    static ref LOGIN_COOKIE: RwLock<Option<String>> = RwLock::new(None);
+}
 
 impl From<&SongDetailSong> for Metadata {
     fn from(song: &SongDetailSong) -> Self {
@@ -93,9 +402,28 @@ impl From<&SongDetailSong> for Metadata {
             start_time: None,
             duration,
             sample_rate: Some(48000),
-            source_url: None,
+            source_url: song.id.map(song_url),
             title: song.name.to_owned(),
-            thumbnail: None,
+            thumbnail: song.album.as_ref().and_then(|x| x.pic_url.clone()),
+        }
+    }
+}
+
+impl From<&CloudSong> for Metadata {
+    fn from(song: &CloudSong) -> Self {
+        let simple_song = song.simple_song.as_ref();
+
+        Self {
+            artist: song.artist.clone().filter(|x| !x.is_empty()),
+            channels: Some(2),
+            duration: simple_song.and_then(|x| x.dt).map(Duration::from_millis),
+            sample_rate: Some(48000),
+            source_url: Some(cloud_url(song.song_id)),
+            title: song.song_name.clone(),
+            thumbnail: simple_song
+                .and_then(|x| x.al.as_ref())
+                .and_then(|x| x.pic_url.clone()),
+            ..Default::default()
         }
     }
 }
@@ -112,31 +440,54 @@ fn artist_trans(artists: &[SongDetailSongArtist]) -> String {
 
 impl NeteaseClient {
     fn new() -> Result<Self> {
+        let mut headers = HeaderMap::new();
+        // Logged in sessions get songs which are hidden from guests.
+        let cookie = credentials::get(Credential::NeteaseCookie)
+            .or_else(|| LOGIN_COOKIE.read().unwrap().clone());
+        if let Some(cookie) = cookie {
+            headers.insert(COOKIE, HeaderValue::from_str(&cookie)?);
+        }
         let client = reqwest::Client::builder()
             .user_agent(USER_AGENT)
+            .default_headers(headers)
             .timeout(Duration::from_secs(10))
             .build()?;
 
-        Ok(Self { client })
+        Ok(Self::with_backend(Http { client }))
     }
 
-    async fn post(&self, url: &str, params: &HashMap<&str, &str>) -> Result<Response> {
-        let params = crypto_params(params)?;
+    fn with_backend(backend: impl Backend + 'static) -> Self {
+        Self {
+            backend: Box::new(backend),
+        }
+    }
 
-        Ok(self.client.post(url).query(&params).send().await?)
+    /// Posts `params` to the endpoint at `path` and parses the answer.
+    async fn post<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        params: &HashMap<&str, &str>,
+    ) -> Result<T> {
+        let reply = self.backend.post(path, params).await?;
+
+        Ok(serde_json::from_slice(&reply.body)?)
     }
 }
 
 struct NeteaseRestarter {
     url: String,
     client: NeteaseClient,
+    quality: Quality,
+    filter: FilterHandle,
 }
 
 impl NeteaseRestarter {
-    fn new(url: &str, client: NeteaseClient) -> Self {
+    fn new(url: &str, client: NeteaseClient, quality: Quality, filter: FilterHandle) -> Self {
         Self {
             url: url.to_string(),
             client,
+            quality,
+            filter,
         }
     }
 }
@@ -147,44 +498,52 @@ impl Restart for NeteaseRestarter {
         &mut self,
         time: Option<Duration>,
     ) -> songbird::input::error::Result<Input> {
-        Ok(_netease(&self.url, time)
+        Ok(_netease(&self.url, time, self.quality, &self.filter)
             .await
-            .map_err(|e| std::io::Error::new(ErrorKind::Other, e))?)
+            .map_err(std::io::Error::other)?)
     }
 
     async fn lazy_init(
         &mut self,
     ) -> songbird::input::error::Result<(Option<Metadata>, Codec, Container)> {
         let url = &self.url;
-        let t = if url.contains("program") {
-            NeteaseTyoe::Dj
-        } else {
-            NeteaseTyoe::Normal
-        };
 
-        let metadata = match t {
+        let metadata = match netease_type(url) {
             NeteaseTyoe::Normal => get_song_metadata(
                 &self.client,
-                &[get_music_id(url).map_err(|e| std::io::Error::new(ErrorKind::Other, e))?],
+                &[get_music_id(url).map_err(std::io::Error::other)?],
             )
             .await
-            .map_err(|e| std::io::Error::new(ErrorKind::Other, e))?,
+            .map_err(std::io::Error::other)?,
             NeteaseTyoe::Dj => {
-                get_dj_music_url_and_detail(&self.client, url)
+                get_dj_music_url_and_detail(&self.client, url, self.quality)
                     .await
-                    .map_err(|e| std::io::Error::new(ErrorKind::Other, e))?
+                    .map_err(std::io::Error::other)?
                     .1
             }
+            NeteaseTyoe::Cloud => get_cloud_metadata(
+                &self.client,
+                get_music_id(url).map_err(std::io::Error::other)?,
+            )
+            .await
+            .map_err(std::io::Error::other)?,
         };
 
         Ok((Some(metadata), Codec::FloatPcm, Container::Raw))
     }
 }
 
-pub async fn _netease_restartable(url: &str, lazy: bool) -> Result<Restartable> {
+pub async fn _netease_restartable(
+    url: &str,
+    lazy: bool,
+    quality: Quality,
+    filter: FilterHandle,
+) -> Result<Restartable> {
     let client = NeteaseClient::new()?;
 
-    Ok(Restartable::new(NeteaseRestarter::new(url, client), lazy).await?)
+    let restarter = NeteaseRestarter::new(url, client, quality, filter.clone());
+
+    Ok(seek_cache::restartable(restarter, filter, lazy).await?)
 }
 
 fn crypto_params(params: &HashMap<&str, &str>) -> Result<Vec<(String, String)>> {
@@ -194,24 +553,44 @@ fn crypto_params(params: &HashMap<&str, &str>) -> Result<Vec<(String, String)>>
     Ok(params)
 }
 
-async fn get_song_url(client: &NeteaseClient, ids: &[u64]) -> Result<Vec<String>> {
-    let url = format!("{}/song/enhance/player/url/", BASE_URL);
+/// The eapi signs the path too, and wants to know which app is asking.
+fn eapi_params(path: &str, params: &HashMap<&str, &str>) -> Result<Vec<(String, String)>> {
+    let mut data = serde_json::to_value(params)?;
+    data["header"] = serde_json::json!({ "os": "pc", "appver": "2.9.7" });
+    let data = serde_json::to_string(&data)?;
+
+    Ok(Crypto::eapi(&format!("/api{}", path), &data))
+}
+
+async fn get_song_url(
+    client: &NeteaseClient,
+    ids: &[u64],
+    quality: Quality,
+) -> Result<Vec<SongUrl>> {
     let ids = serde_json::to_string(ids)?;
     let mut params = HashMap::new();
     params.insert("ids", &ids[..]);
-    for i in BIT_RATE_LIST {
+    let mut restricted = None;
+    for i in quality.netease_bit_rates() {
         params.insert("br", i);
         let song_result = client
-            .post(&url, &params)
-            .await?
-            .json::<SongResult>()
+            .post::<SongResult>("/song/enhance/player/url/", &params)
             .await?;
         if song_result.code == 200 {
-            let urls = song_result
-                .data
-                .into_iter()
-                .map(|x| x.url)
-                .collect::<Vec<_>>();
+            let mut urls = vec![];
+            for data in song_result.data {
+                match (data.url, data.free_trial_info) {
+                    (Some(url), None) => urls.push(SongUrl {
+                        url,
+                        ttl: data
+                            .expi
+                            .map(Duration::from_secs)
+                            .unwrap_or(DEFAULT_URL_TTL),
+                    }),
+                    (Some(_), Some(_)) => restricted = Some(Restricted::Preview),
+                    (None, _) => restricted = restricted.or(Some(Restricted::from_fee(data.fee))),
+                }
+            }
             if urls.is_empty() {
                 continue;
             }
@@ -220,11 +599,18 @@ async fn get_song_url(client: &NeteaseClient, ids: &[u64]) -> Result<Vec<String>
         }
     }
 
-    bail!("Can not get song url!")
+    if let Some(restricted) = restricted {
+        return Err(BibiError::from(restricted).into());
+    }
+
+    Err(BibiError::MetadataMissing {
+        service: "Netease",
+        what: "stream URL",
+    }
+    .into())
 }
 
 async fn get_song_metadata(client: &NeteaseClient, ids: &[u64]) -> Result<Metadata> {
-    let url = format!("{}/song/detail", BASE_URL);
     let c = ids
         .iter()
         .map(|x| Ids { id: x.to_string() })
@@ -236,28 +622,27 @@ async fn get_song_metadata(client: &NeteaseClient, ids: &[u64]) -> Result<Metada
     params.insert("c", &c[..]);
     params.insert("ids", &ids[..]);
     let result = client
-        .post(&url, &params)
-        .await?
-        .json::<SongDetailResult>()
+        .post::<SongDetailResult>("/song/detail", &params)
         .await?;
     debug!("{:?}", result);
-    let result = result
-        .songs
-        .first()
-        .ok_or_else(|| anyhow!("Can not get song list!"))?;
+    let result = result.songs.first().ok_or(BibiError::MetadataMissing {
+        service: "Netease",
+        what: "details",
+    })?;
     let result = Metadata::from(result);
 
     Ok(result)
 }
 
 fn get_music_id(url: &str) -> Result<u64> {
-    let url = url.replace("/#", "");
-    let url = Url::parse(&url)?;
-    let parms = url.query().ok_or_else(|| anyhow!("Url is not right!"))?;
-    let id = parms
+    let unsupported = || BibiError::UnsupportedUrl(url.to_string());
+    let parsed = Url::parse(&url.replace("/#", "")).map_err(|_| unsupported())?;
+    let id = parsed
+        .query()
+        .ok_or_else(unsupported)?
         .split('&')
         .find(|x| x.starts_with("id="))
-        .ok_or_else(|| anyhow!("Url is not right!"))?
+        .ok_or_else(unsupported)?
         .strip_prefix("id=")
         .unwrap()
         .parse::<u64>()?;
@@ -268,75 +653,336 @@ fn get_music_id(url: &str) -> Result<u64> {
 async fn get_dj_music_url_and_detail(
     client: &NeteaseClient,
     url: &str,
-) -> Result<(String, Metadata)> {
+    quality: Quality,
+) -> Result<(SongUrl, Metadata)> {
     let dj_id = get_music_id(url)?.to_string();
-    let url = format!("{}/{}", BASE_URL, "/dj/program/detail");
     let mut params = HashMap::new();
     params.insert("id", dj_id.as_str());
-    let dj_detail = client.post(&url, &params).await?.json::<DjDetail>().await?;
+    let dj_detail = client
+        .post::<DjDetail>("/dj/program/detail", &params)
+        .await?;
     let main_song = dj_detail
         .program
         .as_ref()
         .and_then(|x| x.main_song.as_ref());
     let id = main_song.and_then(|x| x.id);
     let id = id.ok_or_else(|| anyhow!("Can not get song id from dj detail!"))?;
-    let song_url = get_song_url(client, &[id]).await?;
-    let metadata = Metadata::from(main_song.ok_or_else(|| anyhow!("Can not get metadata!"))?);
+    let song_url = get_song_url(client, &[id], quality).await?.remove(0);
+    let mut metadata = Metadata::from(main_song.ok_or_else(|| anyhow!("Can not get metadata!"))?);
+    // Programs have their own cover, the album picture is often missing.
+    if let Some(cover) = dj_detail.program.and_then(|x| x.cover_url) {
+        metadata.thumbnail = Some(cover);
+    }
+    // Keep the program's link so it opens as a program again, e.g. when
+    // looped or restored.
+    metadata.source_url = Some(url.to_string());
     debug!("{:?}", metadata);
 
-    Ok((song_url[0].to_owned(), metadata))
+    Ok((song_url, metadata))
 }
 
-pub(crate) async fn _netease(uri: &str, time: Option<Duration>) -> Result<Input> {
+async fn get_playlist_song_ids(client: &NeteaseClient, url: &str) -> Result<Vec<u64>> {
+    let playlist_id = get_music_id(url)?.to_string();
+    let mut params = HashMap::new();
+    params.insert("id", playlist_id.as_str());
+    params.insert("n", "100000");
+    params.insert("s", "8");
+    let result = client
+        .post::<PlaylistDetailResult>("/v6/playlist/detail", &params)
+        .await?;
+    let playlist = result
+        .playlist
+        .ok_or_else(|| anyhow!("Can not get playlist detail!"))?;
+
+    Ok(playlist.track_ids.into_iter().map(|x| x.id).collect())
+}
+
+pub(crate) async fn _netease_playlist(url: &str) -> Result<Vec<String>> {
     let client = NeteaseClient::new()?;
-    let t = if uri.contains("program") {
-        NeteaseTyoe::Dj
-    } else {
-        NeteaseTyoe::Normal
-    };
-    let (url, metadata) = match t {
-        NeteaseTyoe::Dj => get_dj_music_url_and_detail(&client, uri).await?,
+    let ids = get_playlist_song_ids(&client, url).await?;
+
+    Ok(ids.into_iter().map(song_url).collect())
+}
+
+pub(crate) async fn _netease_search(keywords: &str, limit: usize) -> Result<Vec<Metadata>> {
+    let client = NeteaseClient::new()?;
+    let limit = limit.to_string();
+    let mut params = HashMap::new();
+    params.insert("s", keywords);
+    // 1 means searching songs.
+    params.insert("type", "1");
+    params.insert("limit", &limit[..]);
+    params.insert("offset", "0");
+    let result = client.post::<SearchResult>("/search/get", &params).await?;
+    debug!("{:?}", result);
+    let songs = result.result.map(|x| x.songs).unwrap_or_default();
+
+    Ok(songs.iter().map(Metadata::from).collect())
+}
+
+pub(crate) async fn _netease_lyrics(url: &str) -> Result<Lyrics> {
+    let client = NeteaseClient::new()?;
+    let id = get_music_id(url)?.to_string();
+    let mut params = HashMap::new();
+    params.insert("id", id.as_str());
+    params.insert("lv", "-1");
+    params.insert("tv", "-1");
+    let result = client.post::<LyricResult>("/song/lyric", &params).await?;
+    let original = result
+        .lrc
+        .and_then(|x| x.lyric)
+        .filter(|x| !x.is_empty())
+        .ok_or_else(|| anyhow!("Can not get lyrics!"))?;
+    let translated = result
+        .tlyric
+        .and_then(|x| x.lyric)
+        .filter(|x| !x.is_empty());
+
+    Ok(Lyrics {
+        original,
+        translated,
+    })
+}
+
+/// The next few songs of the account's personal FM.
+pub(crate) async fn _netease_fm() -> Result<Vec<String>> {
+    let client = NeteaseClient::new()?;
+    let result = client
+        .post::<FmResult>("/v1/radio/get", &HashMap::new())
+        .await?;
+    match result.code {
+        200 => Ok(result
+            .data
+            .iter()
+            .filter_map(|x| x.id)
+            .map(song_url)
+            .collect()),
+        301 => Err(BibiError::LoginRequired("Netease personal FM").into()),
+        code => bail!("Netease personal FM failed ({})", code),
+    }
+}
+
+/// Songs Netease finds similar to the song at `url`.
+pub(crate) async fn _netease_related(url: &str, limit: usize) -> Result<Vec<String>> {
+    if is_program(url) {
+        bail!("Netease has no similar songs for DJ programs");
+    }
+    let client = NeteaseClient::new()?;
+    let id = get_music_id(url)?.to_string();
+    let limit = limit.to_string();
+    let mut params = HashMap::new();
+    params.insert("songid", id.as_str());
+    params.insert("limit", &limit[..]);
+    params.insert("offset", "0");
+    let result = client
+        .post::<SimiSongResult>("/v1/discovery/simiSong", &params)
+        .await?;
+
+    Ok(result
+        .songs
+        .iter()
+        .filter_map(|x| x.id)
+        .map(song_url)
+        .collect())
+}
+
+/// Logs in with a phone number and keeps the session for later clients.
+pub(crate) async fn _netease_login(phone: &str, password: &str, country_code: &str) -> Result<()> {
+    let client = NeteaseClient::new()?;
+    let password = hex::encode(hash(MessageDigest::md5(), password.as_bytes())?);
+    let mut params = HashMap::new();
+    params.insert("phone", phone);
+    params.insert("countrycode", country_code);
+    params.insert("password", password.as_str());
+    params.insert("rememberLogin", "true");
+    let reply = client.backend.post("/login/cellphone", &params).await?;
+    let cookie = session_cookie(reply.cookies.iter().map(|x| x.as_str()));
+    let result = serde_json::from_slice::<LoginResult>(&reply.body)?;
+    if result.code != 200 {
+        bail!(
+            "Netease login failed ({}): {}",
+            result.code,
+            result.msg.or(result.message).unwrap_or_default()
+        );
+    }
+
+    *LOGIN_COOKIE.write().unwrap() = Some(cookie);
+    info!("logged in to Netease");
+
+    Ok(())
+}
+
+/// Joins the `name=value` parts of `Set-Cookie` headers into a `Cookie`.
+fn session_cookie<'a>(set_cookies: impl Iterator<Item = &'a str>) -> String {
+    set_cookies
+        .filter_map(|x| x.split(';').next())
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+pub(crate) fn is_program(url: &str) -> bool {
+    url.contains("program")
+}
+
+fn song_url(id: u64) -> String {
+    format!("https://music.163.com/#/song?id={}", id)
+}
+
+/// Cloud songs are marked so their metadata comes from the cloud disk.
+pub(crate) fn is_cloud(url: &str) -> bool {
+    url.contains("&cloud=1")
+}
+
+fn cloud_url(id: u64) -> String {
+    format!("{}&cloud=1", song_url(id))
+}
+
+fn cloud_songs(result: CloudResult) -> Result<Vec<Metadata>> {
+    match result.code {
+        200 => Ok(result.data.iter().map(Metadata::from).collect()),
+        301 => Err(BibiError::LoginRequired("The Netease cloud disk").into()),
+        code => bail!("Netease cloud disk failed ({})", code),
+    }
+}
+
+async fn get_cloud_metadata(client: &NeteaseClient, id: u64) -> Result<Metadata> {
+    let ids = serde_json::to_string(&[id])?;
+    let mut params = HashMap::new();
+    params.insert("songIds", &ids[..]);
+    let result = client
+        .post::<CloudResult>("/v1/cloud/get/byids", &params)
+        .await?;
+
+    cloud_songs(result)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("Song is not in the cloud disk"))
+}
+
+/// Songs in the cloud disk of the logged in account, newest first.
+pub(crate) async fn _netease_cloud(limit: usize) -> Result<Vec<Metadata>> {
+    let client = NeteaseClient::new()?;
+    let limit = limit.to_string();
+    let mut params = HashMap::new();
+    params.insert("limit", &limit[..]);
+    params.insert("offset", "0");
+    let result = client.post::<CloudResult>("/v1/cloud/get", &params).await?;
+
+    cloud_songs(result)
+}
+
+async fn get_stream_url_and_metadata(
+    client: &NeteaseClient,
+    uri: &str,
+    quality: Quality,
+) -> Result<Stream> {
+    if let Some(stream) = stream_cache::get(uri, quality) {
+        return Ok(stream);
+    }
+    let (song_url, metadata) = match netease_type(uri) {
+        NeteaseTyoe::Dj => get_dj_music_url_and_detail(client, uri, quality).await?,
+        NeteaseTyoe::Cloud => {
+            let id = get_music_id(uri)?;
+            let song_url = get_song_url(client, &[id], quality).await?.remove(0);
+            let metadata = get_cloud_metadata(client, id).await?;
+
+            (song_url, metadata)
+        }
         NeteaseTyoe::Normal => {
             let id = get_music_id(uri)?;
-            let urls = get_song_url(&client, &[id]).await?;
-            let url = urls[0].to_owned();
-            let metadata = get_song_metadata(&client, &[id]).await?;
+            let song_url = get_song_url(client, &[id], quality).await?.remove(0);
+            let metadata = get_song_metadata(client, &[id]).await?;
 
-            (url, metadata)
+            (song_url, metadata)
         }
     };
-    let time = time.unwrap_or_else(|| Duration::from_secs(0));
-    let time = format!("{:.3}", time.as_secs_f64());
-    let from_pipe_args = vec![
-        "-ss",
-        time.as_str(),
-        "-i",
-        url.as_str(),
-        "-acodec",
-        "pcm_f32le",
-        "-ac",
-        "2",
-        "-ar",
-        "48000",
-        "-f",
-        "s16le",
-        "-",
-    ];
-
-    let ffmpeg_command = Command::new("ffmpeg")
-        .args(from_pipe_args)
-        .stdin(Stdio::null())
-        .stdout(Stdio::piped())
-        .spawn()?;
+    let stream = Stream {
+        url: song_url.url,
+        input_args: vec![],
+        metadata,
+    };
+    stream_cache::insert(uri, quality, stream.clone(), song_url.ttl);
+
+    Ok(stream)
+}
+
+pub(crate) async fn _netease_stream_url(uri: &str, quality: Quality) -> Result<String> {
+    let client = NeteaseClient::new()?;
+
+    Ok(get_stream_url_and_metadata(&client, uri, quality)
+        .await?
+        .url)
+}
+
+pub(crate) async fn _netease(
+    uri: &str,
+    time: Option<Duration>,
+    quality: Quality,
+    filter: &FilterHandle,
+) -> Result<Input> {
+    let client = NeteaseClient::new()?;
+    let Stream { url, metadata, .. } = get_stream_url_and_metadata(&client, uri, quality).await?;
+    if let Some(input) = decode::input(&url, time, &metadata, filter).await {
+        info!("netease music metadata {:?}", metadata);
+        return Ok(input);
+    }
+    let ffmpeg_command = Pipeline::new(&url, filter.get())
+        .seek(time)
+        .spawn()
+        .map_err(BibiError::FfmpegSpawn)?;
     info!("netease music metadata {:?}", metadata);
 
-    Ok(Input::new(
-        true,
-        children_to_reader::<f32>(vec![ffmpeg_command]),
-        Codec::FloatPcm,
-        Container::Raw,
-        Some(metadata),
-    ))
+    Ok(ffmpeg::input(
+        vec![ffmpeg_command],
+        metadata,
+        filter.guild_id(),
+    )?)
+}
+
+#[test]
+fn test_song_url_restrictions() {
+    let result = serde_json::from_str::<SongResult>(
+        r#"{"code":200,"data":[{"url":null,"fee":1},{"url":"a.mp3","fee":1,"freeTrialInfo":{"start":0,"end":30}}]}"#,
+    )
+    .unwrap();
+
+    assert!(result.data[0].url.is_none());
+    assert_eq!(Restricted::from_fee(result.data[0].fee), Restricted::Vip);
+    assert!(result.data[1].free_trial_info.is_some());
+    assert_eq!(Restricted::from_fee(Some(0)), Restricted::Unavailable);
+}
+
+#[test]
+fn test_session_cookie() {
+    let cookie = session_cookie(
+        [
+            "MUSIC_U=abc; Max-Age=1296000; Path=/",
+            "__csrf=def; Path=/; HTTPOnly",
+        ]
+        .into_iter(),
+    );
+
+    assert_eq!(cookie, "MUSIC_U=abc; __csrf=def");
+}
+
+#[test]
+fn test_cloud_songs() {
+    let result = serde_json::from_str::<CloudResult>(
+        r#"{"code":200,"data":[{"songId":123,"songName":"demo","artist":"","simpleSong":{"al":{"picUrl":"a.jpg"},"dt":60000}}]}"#,
+    )
+    .unwrap();
+    let songs = cloud_songs(result).unwrap();
+    let url = songs[0].source_url.as_deref().unwrap();
+
+    assert!(is_cloud(url));
+    assert_eq!(get_music_id(url).unwrap(), 123);
+    assert_eq!(songs[0].artist, None);
+    assert_eq!(songs[0].duration, Some(Duration::from_secs(60)));
+
+    let result = serde_json::from_str::<CloudResult>(r#"{"code":301}"#).unwrap();
+    assert!(cloud_songs(result).is_err());
 }
 
 #[test]
@@ -347,32 +993,121 @@ fn test_get_music_id() {
     assert_eq!(id, 26209670);
 }
 
+/// Answers with fixture JSON instead of asking Netease.
+#[cfg(test)]
+struct Fixtures(fn(&str, &HashMap<&str, &str>) -> &'static str);
+
+#[cfg(test)]
+#[async_trait]
+impl Backend for Fixtures {
+    async fn post(&self, path: &str, params: &HashMap<&str, &str>) -> Result<Reply> {
+        Ok(Reply {
+            body: (self.0)(path, params).as_bytes().to_vec(),
+            cookies: vec![],
+        })
+    }
+}
+
+#[cfg(test)]
+const SONG_DETAIL: &str = r#"{"code":200,"songs":[{"name":"今、歩き出す君へ。","id":26209670,"artists":[{"name":"Aqua Timez"}],"duration":271000,"album":{"picUrl":"https://p1.music.126.net/a.jpg"}}]}"#;
+
 #[tokio::test]
 async fn test_get_song_url() {
-    let client = NeteaseClient::new().unwrap();
-    let url = get_song_url(&client, &[26209670]).await.unwrap();
-    let filename = url[0].split('/').last();
+    // Only the lower bit rate has the song.
+    let client = NeteaseClient::with_backend(Fixtures(|path, params| {
+        assert_eq!(path, "/song/enhance/player/url/");
+        assert_eq!(params["ids"], "[26209670]");
+        match params["br"] {
+            "320000" => r#"{"code":200,"data":[{"url":null,"fee":0}]}"#,
+            _ => {
+                r#"{"code":200,"data":[{"url":"https://m7.music.126.net/a/fa0240b65deaf3360c8812c629fe1820.mp3","fee":0}]}"#
+            }
+        }
+    }));
+    let url = get_song_url(&client, &[26209670], Quality::Normal)
+        .await
+        .unwrap();
+    let filename = url[0].url.split('/').next_back();
 
     assert_eq!(filename, Some("fa0240b65deaf3360c8812c629fe1820.mp3"));
+
+    let client = NeteaseClient::with_backend(Fixtures(
+        |_, _| r#"{"code":200,"data":[{"url":null,"fee":1}]}"#,
+    ));
+    let e = get_song_url(&client, &[26209670], Quality::Normal)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        e.downcast_ref::<BibiError>(),
+        Some(BibiError::Restricted(Restricted::Vip))
+    ));
 }
 
 #[tokio::test]
 async fn test_get_song_detail() {
-    let client = NeteaseClient::new().unwrap();
+    let client = NeteaseClient::with_backend(Fixtures(|path, params| {
+        assert_eq!(path, "/song/detail");
+        assert_eq!(params["ids"], r#"["26209670"]"#);
+        SONG_DETAIL
+    }));
     let metadata = get_song_metadata(&client, &[26209670]).await.unwrap();
 
-    assert_eq!(metadata.title, Some("今、歩き出す君へ。".to_string()))
+    assert_eq!(metadata.title, Some("今、歩き出す君へ。".to_string()));
+    assert_eq!(metadata.artist, Some("Aqua Timez".to_string()));
+    assert_eq!(metadata.duration, Some(Duration::from_secs(271)));
+    assert_eq!(
+        metadata.source_url,
+        Some("https://music.163.com/#/song?id=26209670".to_string())
+    );
+
+    let client = NeteaseClient::with_backend(Fixtures(|_, _| r#"{"code":200,"songs":[]}"#));
+    assert!(get_song_metadata(&client, &[26209670]).await.is_err());
 }
 
 #[tokio::test]
 async fn test_get_dj_detail() {
-    let client = NeteaseClient::new().unwrap();
+    let client = NeteaseClient::with_backend(Fixtures(|path, params| match path {
+        "/dj/program/detail" => {
+            assert_eq!(params["id"], "2493262449");
+            r#"{"code":200,"program":{"coverUrl":"https://p1.music.126.net/cover.jpg","mainSong":{"name":"原来你什么都不想要","id":1901371647,"artists":[{"name":"DJ"}],"duration":1800000}}}"#
+        }
+        "/song/enhance/player/url/" => {
+            assert_eq!(params["ids"], "[1901371647]");
+            r#"{"code":200,"data":[{"url":"https://m8.music.126.net/b/19716f882ebc8a95bc2abdfe346268c7.mp3","expi":1200}]}"#
+        }
+        path => panic!("unexpected request to {}", path),
+    }));
     let url = "https://music.163.com/#/program?id=2493262449";
-    let (song_url, metadata) = get_dj_music_url_and_detail(&client, url).await.unwrap();
+    let (song_url, metadata) = get_dj_music_url_and_detail(&client, url, Quality::default())
+        .await
+        .unwrap();
 
     assert_eq!(
-        song_url.split('/').last().unwrap(),
+        song_url.url.split('/').next_back().unwrap(),
         "19716f882ebc8a95bc2abdfe346268c7.mp3"
     );
+    assert_eq!(song_url.ttl, Duration::from_secs(1200));
     assert_eq!(metadata.title, Some("原来你什么都不想要".to_string()));
+    assert_eq!(
+        metadata.thumbnail,
+        Some("https://p1.music.126.net/cover.jpg".to_string())
+    );
+    assert_eq!(metadata.source_url, Some(url.to_string()));
+}
+
+#[test]
+fn test_api_fallback() {
+    assert!(blocked(br#"{"code":-460,"message":"Cheating"}"#));
+    assert!(blocked(b"<html></html>"));
+    assert!(!blocked(br#"{"code":301}"#));
+    assert!(!blocked(SONG_DETAIL.as_bytes()));
+
+    assert_eq!(
+        Api::Eapi.url("/song/detail"),
+        "https://interface3.music.163.com/eapi/song/detail"
+    );
+    assert_eq!(Api::Weapi.other(), Api::Eapi);
+    assert_eq!(preferred("/test/fallback"), *DEFAULT_API);
+    set_preferred("/test/fallback", DEFAULT_API.other());
+    assert_eq!(preferred("/test/fallback"), DEFAULT_API.other());
 }
@@ -0,0 +1,86 @@
+//! Posts every song to the announcement channel as the queue starts it,
+//! when the guild turned it on with `~nowplaying`, and warns when the
+//! last one starts.
+use serenity::async_trait;
+use songbird::{tracks::TrackHandle, Event, EventContext, EventHandler as VoiceEventHandler};
+
+use crate::{check_msg, looping::LoopMode, playback::GuildPlayer, queue, reply};
+
+pub(crate) struct NowPlaying {
+    pub player: GuildPlayer,
+}
+
+impl NowPlaying {
+    pub(crate) async fn announce(&self, track: &TrackHandle) {
+        let channel = match self
+            .player
+            .state(|x| x.text_channel.filter(|_| x.now_playing))
+            .await
+        {
+            Some(channel) => channel,
+            None => return,
+        };
+        let requester = queue::requester(track).await;
+        let options = self.player.display_options().await;
+
+        check_msg(
+            channel
+                .send_message(&self.player.http, |m| {
+                    m.embed(|e| reply::started(e, track.metadata(), requester.as_ref(), &options))
+                })
+                .await,
+        );
+    }
+}
+
+#[async_trait]
+impl VoiceEventHandler for NowPlaying {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if let EventContext::Track(&[(_, track)]) = ctx {
+            self.announce(track).await;
+        }
+
+        // Resuming after a pause fires `Play` again, post each track once.
+        Some(Event::Cancel)
+    }
+}
+
+/// Warns that the queue runs out after the track which just started,
+/// unless something refills or repeats it.
+pub(crate) struct EndWarner {
+    pub player: GuildPlayer,
+}
+
+impl EndWarner {
+    pub(crate) async fn warn(&self) {
+        let settings = self.player.settings().await;
+        if !settings.end_warning() || self.player.call.lock().await.queue().len() > 1 {
+            return;
+        }
+        let channel = self
+            .player
+            .state(|x| {
+                x.text_channel
+                    .filter(|_| !x.fm && !x.autoplay && x.loop_mode == LoopMode::Off)
+            })
+            .await;
+
+        if let Some(channel) = channel {
+            let prefix = settings.prefix();
+            let s = format!(
+                "The queue ends after this song, add more with {}play or turn on {}fm",
+                prefix, prefix
+            );
+            check_msg(channel.say(&self.player.http, s).await);
+        }
+    }
+}
+
+#[async_trait]
+impl VoiceEventHandler for EndWarner {
+    async fn act(&self, _ctx: &EventContext<'_>) -> Option<Event> {
+        self.warn().await;
+
+        Some(Event::Cancel)
+    }
+}
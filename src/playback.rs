@@ -0,0 +1,436 @@
+//! Per-guild playback state which outlives single tracks.
+use std::{
+    collections::HashMap,
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
+};
+
+use serenity::{
+    cache::Cache,
+    client::Context,
+    http::Http,
+    model::{channel::ChannelType, id::ChannelId},
+    prelude::TypeMapKey,
+};
+use songbird::{
+    tracks::{TrackHandle, TrackResult},
+    Call, Event, TrackEvent,
+};
+use tokio::sync::{Mutex, RwLock};
+
+use crate::{
+    autoplay::Continuer,
+    bandwidth::{self, BandwidthCounter, BandwidthLock},
+    display::{self, DisplayLock, DisplayOptions},
+    ffmpeg::{Effects, FilterHandle},
+    fm::Refiller,
+    history::{self, HistoryLock, PlayedLock},
+    intro::IntroSkipper,
+    looping::LoopMode,
+    looping::Looper,
+    metrics::CutShortCounter,
+    now_playing::{EndWarner, NowPlaying},
+    prefetch::{Prefetcher, PreloadTimer},
+    profile::{self, ListenCounter, ProfileLock},
+    quality::Quality,
+    radio_dj::Announcer,
+    session::{EndLogger, Recorder, Session},
+    settings::{self, Settings, SettingsLock},
+    soft_mute::SoftMuter,
+    stage::StageGate,
+    vote::SkipVote,
+};
+
+#[derive(Default)]
+pub(crate) struct PlaybackState {
+    /// Length of the fade between two tracks, `None` when crossfade is off.
+    pub crossfade: Option<Duration>,
+    /// Language of the radio DJ announcements, `None` when they are off.
+    pub radio_dj: Option<String>,
+    pub loop_mode: LoopMode,
+    /// Netease personal FM keeps the queue topped up.
+    pub fm: bool,
+    /// Songs like the last one are queued when the queue runs out.
+    pub autoplay: bool,
+    /// Where announcements go: the channel songs are requested from.
+    pub text_channel: Option<ChannelId>,
+    /// `text_channel` is the text chat of a voice channel. It then stays
+    /// the announcement channel until the bot joins somewhere again.
+    pub voice_chat: bool,
+    pub session: Option<Session>,
+    /// Log every session to a thread under `text_channel`.
+    pub session_log: bool,
+    /// Keep a pinned tracklist of every session in `text_channel`.
+    pub tracklist: bool,
+    /// Post every song to `text_channel` as it starts.
+    pub now_playing: bool,
+    /// Highest volume songs may play at, `None` for the default ceiling.
+    pub volume_ceiling: Option<f32>,
+    /// Netease DJ programs start this far in, `None` to play intros.
+    pub dj_intro: Option<Duration>,
+    /// Share of listeners needed to skip a song, `None` when anyone can.
+    pub vote_skip: Option<f32>,
+    pub skip_vote: Option<SkipVote>,
+    /// Stream quality to ask for, `None` to follow the channel bitrate.
+    pub quality: Option<Quality>,
+    /// Audio filter of every song, shared with the queued sources.
+    pub filter: FilterHandle,
+    /// Songs turned down by `~softmute` with their volume before, `None`
+    /// when the guild is not muted.
+    pub soft_muted: Option<Vec<(TrackHandle, f32)>>,
+    /// The bot waits on a stage to be made a speaker, songs hold until then.
+    pub stage_wait: bool,
+    /// When the queue ran empty.
+    pub idle_since: Option<Instant>,
+    /// When the last listener left the voice channel.
+    pub alone_since: Option<Instant>,
+    /// Call which rejoins when its connection drops.
+    pub watched_call: Option<Weak<Mutex<Call>>>,
+}
+
+pub(crate) type PlaybackLock = Arc<RwLock<HashMap<u64, PlaybackState>>>;
+
+pub(crate) struct GuildPlayback;
+
+impl TypeMapKey for GuildPlayback {
+    type Value = PlaybackLock;
+}
+
+pub(crate) async fn playback_lock(ctx: &Context) -> PlaybackLock {
+    let read = ctx.data.read().await;
+
+    read.get::<GuildPlayback>()
+        .expect("Expected GuildPlayback in TypeMap.")
+        .clone()
+}
+
+/// Whether the channel is the text chat built into a voice channel.
+pub(crate) fn is_voice_chat(ctx: &Context, channel: ChannelId) -> bool {
+    ctx.cache
+        .guild_channel(channel)
+        .map(|x| matches!(x.kind, ChannelType::Voice | ChannelType::Stage))
+        .unwrap_or(false)
+}
+
+/// The guild's announcement channel if it set one, `channel` otherwise.
+async fn announce_channel(ctx: &Context, guild_id: u64, channel: ChannelId) -> ChannelId {
+    settings::get(ctx, guild_id)
+        .await
+        .announce_channel
+        .map(ChannelId)
+        .unwrap_or(channel)
+}
+
+/// Makes `channel` the announcement channel, unless commands already came
+/// from the voice channel's own text chat.
+pub(crate) async fn announce_in(ctx: &Context, guild_id: u64, channel: ChannelId) {
+    let channel = announce_channel(ctx, guild_id, channel).await;
+    let voice_chat = is_voice_chat(ctx, channel);
+    let lock = playback_lock(ctx).await;
+    let mut playback = lock.write().await;
+    let state = playback.entry(guild_id).or_default();
+
+    if voice_chat || !state.voice_chat {
+        state.text_channel = Some(channel);
+        state.voice_chat = voice_chat;
+    }
+}
+
+/// After joining, announcements follow the channel `~join` came from.
+pub(crate) async fn reset_announcements(ctx: &Context, guild_id: u64, channel: ChannelId) {
+    let channel = announce_channel(ctx, guild_id, channel).await;
+    let voice_chat = is_voice_chat(ctx, channel);
+    let lock = playback_lock(ctx).await;
+    let mut playback = lock.write().await;
+    let state = playback.entry(guild_id).or_default();
+
+    state.text_channel = Some(channel);
+    state.voice_chat = voice_chat;
+}
+
+/// Starts a session unless one is already running.
+pub(crate) async fn start_session(ctx: &Context, guild_id: u64) {
+    let lock = playback_lock(ctx).await;
+    let mut playback = lock.write().await;
+
+    playback
+        .entry(guild_id)
+        .or_default()
+        .session
+        .get_or_insert_with(Session::default);
+}
+
+pub(crate) async fn end_session(ctx: &Context, guild_id: u64) -> Option<Session> {
+    let lock = playback_lock(ctx).await;
+    let mut playback = lock.write().await;
+
+    playback.get_mut(&guild_id).and_then(|x| x.session.take())
+}
+
+pub(crate) async fn record_skip(ctx: &Context, guild_id: u64, title: String) {
+    let lock = playback_lock(ctx).await;
+    let mut playback = lock.write().await;
+
+    playback
+        .entry(guild_id)
+        .or_default()
+        .session
+        .get_or_insert_with(Session::default)
+        .record_skip(title);
+}
+
+pub(crate) async fn crossfade(ctx: &Context, guild_id: u64) -> Option<Duration> {
+    let lock = playback_lock(ctx).await;
+    let playback = lock.read().await;
+
+    playback.get(&guild_id).and_then(|state| state.crossfade)
+}
+
+pub(crate) async fn volume_ceiling(ctx: &Context, guild_id: u64) -> Option<f32> {
+    let lock = playback_lock(ctx).await;
+    let playback = lock.read().await;
+
+    playback
+        .get(&guild_id)
+        .and_then(|state| state.volume_ceiling)
+}
+
+/// Where songs of the guild are requested from, if anywhere yet.
+pub(crate) async fn text_channel(ctx: &Context, guild_id: u64) -> Option<ChannelId> {
+    let lock = playback_lock(ctx).await;
+    let playback = lock.read().await;
+
+    playback.get(&guild_id).and_then(|state| state.text_channel)
+}
+
+/// Effects songs of the guild play with.
+pub(crate) async fn effects(ctx: &Context, guild_id: u64) -> Effects {
+    let lock = playback_lock(ctx).await;
+    let playback = lock.read().await;
+
+    playback
+        .get(&guild_id)
+        .map(|state| state.filter.get())
+        .unwrap_or_default()
+}
+
+pub(crate) async fn set_loudnorm(ctx: &Context, guild_id: u64, loudnorm: bool) {
+    let lock = playback_lock(ctx).await;
+    let playback = lock.read().await;
+
+    if let Some(state) = playback.get(&guild_id) {
+        state.filter.set_loudnorm(loudnorm);
+    }
+}
+
+/// What track event handlers need to act on a guild's playback: its voice
+/// call, its playback state and how it wants songs to be shown.
+#[derive(Clone)]
+pub(crate) struct GuildPlayer {
+    pub guild_id: u64,
+    pub call: Arc<Mutex<Call>>,
+    pub playback: PlaybackLock,
+    pub display: DisplayLock,
+    pub history: HistoryLock,
+    pub played: PlayedLock,
+    pub profiles: ProfileLock,
+    pub settings: SettingsLock,
+    pub bandwidth: BandwidthLock,
+    pub http: Arc<Http>,
+    pub cache: Arc<Cache>,
+}
+
+impl GuildPlayer {
+    pub(crate) async fn new(ctx: &Context, guild_id: u64, call: Arc<Mutex<Call>>) -> Self {
+        Self {
+            guild_id,
+            call,
+            playback: playback_lock(ctx).await,
+            display: display::display_lock(ctx).await,
+            history: history::history_lock(ctx).await,
+            played: history::played_lock(ctx).await,
+            profiles: profile::profile_lock(ctx).await,
+            settings: settings::settings_lock(ctx).await,
+            bandwidth: bandwidth::bandwidth_lock(ctx).await,
+            http: ctx.http.clone(),
+            cache: ctx.cache.clone(),
+        }
+    }
+
+    pub(crate) async fn display_options(&self) -> DisplayOptions {
+        let display = self.display.read().await;
+
+        display.get(&self.guild_id).cloned().unwrap_or_default()
+    }
+
+    pub(crate) async fn settings(&self) -> Settings {
+        let settings = self.settings.read().await;
+
+        settings.get(&self.guild_id).cloned().unwrap_or_default()
+    }
+
+    /// Reads a value out of the guild's playback state.
+    pub(crate) async fn state<T>(&self, f: impl FnOnce(&PlaybackState) -> T) -> T {
+        let playback = self.playback.read().await;
+
+        f(playback
+            .get(&self.guild_id)
+            .unwrap_or(&PlaybackState::default()))
+    }
+
+    /// Whether the guild used more traffic this month than its
+    /// `bandwidthcap`.
+    pub(crate) async fn over_bandwidth_cap(&self) -> bool {
+        match self.settings().await.bandwidth_cap {
+            Some(cap) => {
+                bandwidth::usage(&self.bandwidth, self.guild_id)
+                    .await
+                    .total()
+                    >= cap.saturating_mul(bandwidth::GB)
+            }
+            None => false,
+        }
+    }
+
+    /// Quality of the streams to queue: low over the bandwidth cap, else the
+    /// guild's choice, or what the voice channel's bitrate can carry.
+    pub(crate) async fn quality(&self) -> Quality {
+        if self.over_bandwidth_cap().await {
+            return Quality::Low;
+        }
+        if let Some(quality) = self.state(|x| x.quality).await {
+            return quality;
+        }
+        let channel = self.call.lock().await.current_channel();
+
+        channel
+            .and_then(|x| self.cache.guild_channel(x.0))
+            .and_then(|x| x.bitrate)
+            .map(Quality::for_bitrate)
+            .unwrap_or_default()
+    }
+
+    /// Filter to give new sources, so they follow `~filter` changes.
+    pub(crate) async fn filter(&self) -> FilterHandle {
+        let loudnorm = self.settings().await.loudnorm();
+        let mut playback = self.playback.write().await;
+        let filter = playback
+            .entry(self.guild_id)
+            .or_default()
+            .filter
+            .clone()
+            .for_guild(self.guild_id);
+        filter.set_loudnorm(loudnorm);
+
+        filter
+    }
+
+    /// Makes a queued track follow the playback state of the guild. `first`
+    /// when it went into an empty queue, which callers tell under the lock
+    /// they queued it with: another song queued meanwhile must not hide it.
+    pub(crate) async fn attach(&self, track: &TrackHandle, first: bool) -> TrackResult<()> {
+        let recorder = Recorder {
+            player: self.clone(),
+        };
+        let intro_skipper = IntroSkipper {
+            player: self.clone(),
+        };
+        let now_playing = NowPlaying {
+            player: self.clone(),
+        };
+        let soft_muter = SoftMuter {
+            player: self.clone(),
+        };
+        let stage_gate = StageGate {
+            player: self.clone(),
+        };
+        let end_warner = EndWarner {
+            player: self.clone(),
+        };
+        let preload_timer = PreloadTimer {
+            player: self.clone(),
+        };
+        // The first track of an empty queue starts without a `Play` event.
+        if first {
+            recorder.record(track).await;
+            intro_skipper.skip(track).await;
+            now_playing.announce(track).await;
+            soft_muter.mute(track).await?;
+            stage_gate.hold(track).await?;
+            end_warner.warn().await;
+            preload_timer.schedule(track).await?;
+        } else {
+            track.add_event(Event::Track(TrackEvent::Play), recorder)?;
+            track.add_event(Event::Track(TrackEvent::Play), intro_skipper)?;
+            track.add_event(Event::Track(TrackEvent::Play), now_playing)?;
+            track.add_event(Event::Track(TrackEvent::Play), soft_muter)?;
+            track.add_event(Event::Track(TrackEvent::Play), stage_gate)?;
+            track.add_event(Event::Track(TrackEvent::Play), end_warner)?;
+            track.add_event(Event::Track(TrackEvent::Play), preload_timer)?;
+        }
+
+        track.add_event(
+            Event::Track(TrackEvent::Play),
+            Announcer {
+                player: self.clone(),
+            },
+        )?;
+        // Songs queued right after the current one are due soon. In a task
+        // of its own, as it locks the call, which callers may still hold.
+        let prefetcher = Prefetcher {
+            player: self.clone(),
+        };
+        tokio::spawn(async move { prefetcher.prefetch().await });
+        track.add_event(
+            Event::Track(TrackEvent::Play),
+            Prefetcher {
+                player: self.clone(),
+            },
+        )?;
+
+        let looper = Looper {
+            player: self.clone(),
+        };
+        looper.on_enqueue(track).await?;
+        track.add_event(Event::Track(TrackEvent::Play), looper.clone())?;
+        track.add_event(Event::Track(TrackEvent::End), looper)?;
+        track.add_event(
+            Event::Track(TrackEvent::End),
+            EndLogger {
+                player: self.clone(),
+            },
+        )?;
+        track.add_event(
+            Event::Track(TrackEvent::End),
+            ListenCounter {
+                player: self.clone(),
+            },
+        )?;
+        track.add_event(
+            Event::Track(TrackEvent::End),
+            Refiller {
+                player: self.clone(),
+            },
+        )?;
+        track.add_event(
+            Event::Track(TrackEvent::End),
+            CutShortCounter {
+                player: self.clone(),
+            },
+        )?;
+        track.add_event(
+            Event::Track(TrackEvent::End),
+            BandwidthCounter {
+                player: self.clone(),
+            },
+        )?;
+        track.add_event(
+            Event::Track(TrackEvent::End),
+            Continuer {
+                player: self.clone(),
+            },
+        )?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,24 @@
+use std::env;
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+
+use crate::source::SourceProvider;
+
+const DEFAULT_PLAYLIST_MAX: usize = 50;
+
+lazy_static! {
+    /// Most songs added from a single playlist, from `PLAYLIST_MAX` in the environment.
+    static ref PLAYLIST_MAX: usize = env::var("PLAYLIST_MAX")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(DEFAULT_PLAYLIST_MAX);
+}
+
+/// Expands a playlist into the URLs of its songs, at most `PLAYLIST_MAX` of them.
+pub(crate) async fn expand(provider: &dyn SourceProvider, url: &str) -> Result<Vec<String>> {
+    let mut urls = provider.resolve_playlist(url, *PLAYLIST_MAX).await?;
+    urls.truncate(*PLAYLIST_MAX);
+
+    Ok(urls)
+}
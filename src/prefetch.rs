@@ -0,0 +1,142 @@
+//! Asks for the stream URLs of the next songs while one plays, and starts
+//! the next song once most of the current one is played, so the queue
+//! moves on without waiting on the service or ffmpeg in between.
+use std::time::Duration;
+
+use serenity::async_trait;
+use songbird::{
+    tracks::{TrackHandle, TrackResult},
+    Event, EventContext, EventHandler as VoiceEventHandler,
+};
+use tracing::info;
+
+use crate::{ffmpeg::Effects, playback::GuildPlayer, quality::Quality, source, stream_cache};
+
+/// Songs after the current one which are resolved ahead.
+const AHEAD: usize = 2;
+/// Share of a song played before the next one is started.
+const PRELOAD_AT: f32 = 0.8;
+
+pub(crate) struct Prefetcher {
+    pub player: GuildPlayer,
+}
+
+impl Prefetcher {
+    /// Resolves the songs coming up after the current one in the
+    /// background.
+    pub(crate) async fn prefetch(&self) {
+        let tracks = self.player.call.lock().await.queue().current_queue();
+        let quality = self.player.quality().await;
+        let urls = tracks
+            .iter()
+            .skip(1)
+            .take(AHEAD)
+            .filter_map(|x| x.metadata().source_url.clone());
+        for url in urls {
+            tokio::spawn(prefetch(url, quality));
+        }
+    }
+}
+
+async fn prefetch(url: String, quality: Quality) {
+    if !stream_cache::start_pending(&url, quality) {
+        return;
+    }
+    if let Err(e) = source::provider(&url).prefetch(&url, quality).await {
+        info!("Err prefetching {}: {:?}", url, e);
+    }
+    stream_cache::end_pending(&url, quality);
+}
+
+#[async_trait]
+impl VoiceEventHandler for Prefetcher {
+    async fn act(&self, _ctx: &EventContext<'_>) -> Option<Event> {
+        self.prefetch().await;
+
+        // Resuming after a pause fires `Play` again, the next songs are
+        // the same.
+        Some(Event::Cancel)
+    }
+}
+
+/// Playing time of a song of `duration` after which the next one is
+/// started.
+pub(crate) fn preload_at(duration: Duration, effects: &Effects) -> Duration {
+    effects.played(duration).mul_f32(PRELOAD_AT)
+}
+
+/// Sets the `Preloader` of a song as it starts, with the effects it is
+/// played with by then.
+pub(crate) struct PreloadTimer {
+    pub player: GuildPlayer,
+}
+
+impl PreloadTimer {
+    pub(crate) async fn schedule(&self, track: &TrackHandle) -> TrackResult<()> {
+        let duration = match track.metadata().duration {
+            Some(duration) => duration,
+            None => return Ok(()),
+        };
+        let at = preload_at(duration, &self.player.filter().await.get());
+
+        track.add_event(
+            Event::Delayed(at),
+            Preloader {
+                player: self.player.clone(),
+            },
+        )
+    }
+}
+
+#[async_trait]
+impl VoiceEventHandler for PreloadTimer {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if let EventContext::Track(&[(_, track)]) = ctx {
+            if let Err(e) = self.schedule(track).await {
+                info!("Err scheduling the preload: {:?}", e);
+            }
+        }
+
+        // Once, a pause doesn't move the preload.
+        Some(Event::Cancel)
+    }
+}
+
+/// Starts the next song paused, so its first seconds are read ahead by the
+/// time it is due.
+pub(crate) struct Preloader {
+    pub player: GuildPlayer,
+}
+
+#[async_trait]
+impl VoiceEventHandler for Preloader {
+    async fn act(&self, _ctx: &EventContext<'_>) -> Option<Event> {
+        let next = self
+            .player
+            .call
+            .lock()
+            .await
+            .queue()
+            .current_queue()
+            .get(1)
+            .cloned();
+        if let Some(next) = next {
+            if let Err(e) = next.make_playable() {
+                info!("Err preloading the next song: {:?}", e);
+            }
+        }
+
+        None
+    }
+}
+
+#[test]
+fn test_preload_at() {
+    let minutes = |x: u64| Duration::from_secs(x * 60);
+    assert_eq!(preload_at(minutes(5), &Effects::default()), minutes(4));
+    let fast = Effects {
+        speed: 2.0,
+        ..Default::default()
+    };
+    assert_eq!(preload_at(minutes(5), &fast), minutes(2));
+}
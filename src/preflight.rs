@@ -0,0 +1,79 @@
+//! Checks whether the bot can join a voice channel before it tries, so
+//! users learn what to fix instead of getting a bare join error.
+use anyhow::{anyhow, bail, Result};
+use serenity::{
+    cache::Cache,
+    model::{
+        channel::ChannelType,
+        id::{ChannelId, GuildId},
+        Permissions,
+    },
+};
+
+/// Permissions the bot needs to play in a channel of `kind`. Stage
+/// channels are joined as a listener first.
+fn required(kind: ChannelType) -> Permissions {
+    match kind {
+        ChannelType::Stage => Permissions::VIEW_CHANNEL | Permissions::CONNECT,
+        _ => Permissions::VIEW_CHANNEL | Permissions::CONNECT | Permissions::SPEAK,
+    }
+}
+
+/// Names of the permissions in `required` which `granted` lacks.
+fn missing(required: Permissions, granted: Permissions) -> Vec<&'static str> {
+    (required - granted).get_permission_names()
+}
+
+/// A full channel only lets in members who can move others.
+fn is_full(limit: Option<u64>, members: usize, granted: Permissions) -> bool {
+    limit.is_some_and(|x| x > 0 && members as u64 >= x) && !granted.move_members()
+}
+
+/// Fails with the reason the bot can not join `channel`, like a missing
+/// permission or a full channel.
+pub(crate) fn check(cache: &Cache, guild_id: GuildId, channel: ChannelId) -> Result<()> {
+    let bot_id = cache.current_user_id();
+    let channel = cache
+        .guild_channel(channel)
+        .ok_or_else(|| anyhow!("Can not find the voice channel"))?;
+    // Unknown permissions are left for joining to find out.
+    let granted = match channel.permissions_for_user(cache, bot_id) {
+        Ok(granted) => granted,
+        Err(_) => return Ok(()),
+    };
+
+    let missing = missing(required(channel.kind), granted);
+    if !missing.is_empty() {
+        bail!(
+            "Missing the {} permission in {} to play there",
+            missing.join(", "),
+            channel.name
+        );
+    }
+
+    let members = cache
+        .guild_field(guild_id, |x| {
+            x.voice_states
+                .values()
+                .filter(|x| x.channel_id == Some(channel.id) && x.user_id != bot_id)
+                .count()
+        })
+        .unwrap_or_default();
+    if is_full(channel.user_limit, members, granted) {
+        bail!("{} is full", channel.name);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_preflight() {
+    let granted = Permissions::VIEW_CHANNEL | Permissions::CONNECT;
+    assert_eq!(missing(required(ChannelType::Voice), granted), ["Speak"]);
+    assert!(missing(required(ChannelType::Stage), granted).is_empty());
+
+    assert!(is_full(Some(2), 2, granted));
+    assert!(!is_full(Some(2), 2, granted | Permissions::MOVE_MEMBERS));
+    assert!(!is_full(Some(0), 5, granted));
+    assert!(!is_full(None, 5, granted));
+}
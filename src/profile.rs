@@ -0,0 +1,247 @@
+//! Listening streaks and badges of every member, shown by `~profile`.
+//! Guilds which turn `profiles` off in `~settings` are not tracked.
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serenity::{
+    async_trait,
+    cache::Cache,
+    client::Context,
+    model::id::{ChannelId, GuildId},
+    prelude::TypeMapKey,
+};
+use songbird::{Event, EventContext, EventHandler as VoiceEventHandler};
+use tokio::sync::RwLock;
+use tracing::warn;
+
+use crate::{playback::GuildPlayer, settings, store};
+
+const PROFILES: &str = "profiles";
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Profile {
+    pub requests: u64,
+    /// Seconds of songs played while in the voice channel.
+    pub listened: u64,
+    /// Days in a row with a request or a song listened to.
+    pub streak: u32,
+    pub best_streak: u32,
+    /// Day of the last activity, counted from the Unix epoch in UTC.
+    last_day: u64,
+}
+
+impl Profile {
+    fn active_on(&mut self, day: u64) {
+        if self.streak > 0 && self.last_day == day {
+            return;
+        }
+        self.streak = if self.streak > 0 && self.last_day + 1 == day {
+            self.streak + 1
+        } else {
+            1
+        };
+        self.best_streak = self.best_streak.max(self.streak);
+        self.last_day = day;
+    }
+
+    /// The streak as of `day`, a missed day ends it.
+    pub(crate) fn streak_on(&self, day: u64) -> u32 {
+        if self.last_day + 1 >= day {
+            self.streak
+        } else {
+            0
+        }
+    }
+
+    pub(crate) fn listened(&self) -> Duration {
+        Duration::from_secs(self.listened)
+    }
+
+    pub(crate) fn badges(&self) -> Vec<&'static str> {
+        BADGES
+            .iter()
+            .filter(|x| (x.reached)(self))
+            .map(|x| x.name)
+            .collect()
+    }
+}
+
+struct Badge {
+    name: &'static str,
+    reached: fn(&Profile) -> bool,
+}
+
+const BADGES: &[Badge] = &[
+    Badge {
+        name: "🎵 First request",
+        reached: |x| x.requests >= 1,
+    },
+    Badge {
+        name: "💯 100 requests",
+        reached: |x| x.requests >= 100,
+    },
+    Badge {
+        name: "🎧 24 hours listened",
+        reached: |x| x.listened >= DAY_SECS,
+    },
+    Badge {
+        name: "🔥 7 day streak",
+        reached: |x| x.best_streak >= 7,
+    },
+    Badge {
+        name: "🏆 30 day streak",
+        reached: |x| x.best_streak >= 30,
+    },
+];
+
+pub(crate) fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs() / DAY_SECS)
+        .unwrap_or_default()
+}
+
+type Profiles = HashMap<u64, HashMap<u64, Profile>>;
+
+pub(crate) type ProfileLock = Arc<RwLock<Profiles>>;
+
+pub(crate) struct GuildProfiles;
+
+impl TypeMapKey for GuildProfiles {
+    type Value = ProfileLock;
+}
+
+pub(crate) async fn load() -> Result<Profiles> {
+    store::load(PROFILES).await
+}
+
+pub(crate) async fn profile_lock(ctx: &Context) -> ProfileLock {
+    let read = ctx.data.read().await;
+
+    read.get::<GuildProfiles>()
+        .expect("Expected GuildProfiles in TypeMap.")
+        .clone()
+}
+
+/// Changes the profiles of `users` and saves them all.
+async fn update(lock: &ProfileLock, guild_id: u64, users: &[u64], f: impl Fn(&mut Profile)) {
+    let mut profiles = lock.write().await;
+    let guild = profiles.entry(guild_id).or_default();
+    for user in users {
+        f(guild.entry(*user).or_default());
+    }
+
+    if let Err(e) = store::save(PROFILES, &*profiles).await {
+        warn!("Err saving profiles: {:?}", e);
+    }
+}
+
+/// Counts songs a member requested.
+pub(crate) async fn record_requests(ctx: &Context, guild_id: u64, user: u64, n: u64) {
+    if n == 0 || !settings::get(ctx, guild_id).await.profiles() {
+        return;
+    }
+    let day = today();
+
+    update(&profile_lock(ctx).await, guild_id, &[user], |x| {
+        x.requests += n;
+        x.active_on(day);
+    })
+    .await;
+}
+
+pub(crate) async fn get(ctx: &Context, guild_id: u64, user: u64) -> Profile {
+    let lock = profile_lock(ctx).await;
+    let profiles = lock.read().await;
+
+    profiles
+        .get(&guild_id)
+        .and_then(|x| x.get(&user))
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Members in the voice channel, without bots.
+fn listeners(cache: &Cache, guild_id: GuildId, channel: ChannelId) -> Vec<u64> {
+    cache
+        .guild_field(guild_id, |guild| {
+            guild
+                .voice_states
+                .values()
+                .filter(|x| x.channel_id == Some(channel))
+                .filter(|x| match &x.member {
+                    Some(member) => !member.user.bot,
+                    None => !cache.user(x.user_id).is_some_and(|x| x.bot),
+                })
+                .map(|x| x.user_id.0)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Credits the time a song played to everyone listening when it ends.
+pub(crate) struct ListenCounter {
+    pub player: GuildPlayer,
+}
+
+#[async_trait]
+impl VoiceEventHandler for ListenCounter {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let played = match ctx {
+            EventContext::Track(&[(state, _)]) if !state.play_time.is_zero() => state.play_time,
+            _ => return None,
+        };
+        if !self.player.settings().await.profiles() {
+            return None;
+        }
+        let channel = match self.player.call.lock().await.current_channel() {
+            Some(channel) => ChannelId(channel.0),
+            None => return None,
+        };
+        let guild_id = GuildId(self.player.guild_id);
+        let users = listeners(&self.player.cache, guild_id, channel);
+        let day = today();
+
+        update(&self.player.profiles, guild_id.0, &users, |x| {
+            x.listened += played.as_secs();
+            x.active_on(day);
+        })
+        .await;
+
+        None
+    }
+}
+
+#[test]
+fn test_streak() {
+    let mut profile = Profile::default();
+    profile.active_on(100);
+    profile.active_on(100);
+    profile.active_on(101);
+    profile.active_on(102);
+    assert_eq!(profile.streak, 3);
+    assert_eq!(profile.streak_on(103), 3);
+    assert_eq!(profile.streak_on(104), 0);
+
+    profile.active_on(110);
+    assert_eq!(profile.streak, 1);
+    assert_eq!(profile.best_streak, 3);
+}
+
+#[test]
+fn test_badges() {
+    let mut profile = Profile::default();
+    assert!(profile.badges().is_empty());
+
+    profile.requests = 100;
+    profile.listened = DAY_SECS;
+    assert_eq!(profile.badges().len(), 3);
+}
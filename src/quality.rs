@@ -0,0 +1,96 @@
+//! How good a stream to ask the services for. Audio never sounds better
+//! than the voice channel's bitrate, so low bitrate channels get smaller
+//! streams and boosted ones the best there is.
+use std::{fmt, str::FromStr};
+
+use anyhow::anyhow;
+
+/// Channels at or below this many bits per second count as low bitrate.
+const LOW_BITRATE: u64 = 64_000;
+
+/// Channels above this are only possible with server boosts.
+const BOOSTED_BITRATE: u64 = 96_000;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub(crate) enum Quality {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl Quality {
+    pub(crate) fn for_bitrate(bitrate: u64) -> Self {
+        if bitrate <= LOW_BITRATE {
+            Quality::Low
+        } else if bitrate > BOOSTED_BITRATE {
+            Quality::High
+        } else {
+            Quality::Normal
+        }
+    }
+
+    /// Netease `br` values, tried in order until one gives a URL.
+    pub(crate) fn netease_bit_rates(&self) -> &'static [&'static str] {
+        match self {
+            Quality::Low => &["128000", "192000", "320000"],
+            Quality::Normal => &["320000", "192000", "128000"],
+            Quality::High => &["999000", "320000", "192000", "128000"],
+        }
+    }
+
+    /// Rough bits per second of the streams the services hand out, to
+    /// estimate traffic.
+    pub(crate) fn stream_bitrate(&self) -> u64 {
+        match self {
+            Quality::Low => 96_000,
+            Quality::Normal => 192_000,
+            Quality::High => 320_000,
+        }
+    }
+
+    /// yt-dlp/youtube-dl `-f` format selection. Audio-only streams come
+    /// first, Opus ones need no transcoding of the codec Discord uses.
+    pub(crate) fn ytdl_format(&self) -> &'static str {
+        match self {
+            Quality::Low => "bestaudio[abr<=64]/worstaudio/worst",
+            Quality::Normal => {
+                "bestaudio[acodec=opus][abr<=160]/bestaudio[abr<=160]/bestaudio/best"
+            }
+            Quality::High => "bestaudio[acodec=opus]/bestaudio/best",
+        }
+    }
+}
+
+impl FromStr for Quality {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low" => Ok(Quality::Low),
+            "normal" => Ok(Quality::Normal),
+            "high" => Ok(Quality::High),
+            _ => Err(anyhow!("Unknown quality: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Quality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Quality::Low => "low",
+            Quality::Normal => "normal",
+            Quality::High => "high",
+        };
+
+        write!(f, "{}", s)
+    }
+}
+
+#[test]
+fn test_for_bitrate() {
+    assert_eq!(Quality::for_bitrate(8_000), Quality::Low);
+    assert_eq!(Quality::for_bitrate(64_000), Quality::Low);
+    assert_eq!(Quality::for_bitrate(96_000), Quality::Normal);
+    assert_eq!(Quality::for_bitrate(384_000), Quality::High);
+}
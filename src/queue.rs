@@ -0,0 +1,469 @@
+//! Helpers on top of songbird's `TrackQueue`.
+//!
+//! The head of the queue (index 0) is always the track which is playing, so
+//! none of the reordering helpers ever move it.
+use std::collections::{HashSet, VecDeque};
+
+use serde::{Deserialize, Serialize};
+use serenity::{
+    model::{channel::Message, user::User},
+    prelude::TypeMapKey,
+};
+use songbird::tracks::{Queued, TrackHandle, TrackQueue, TrackResult};
+
+/// Marks an entry which must stay where a DJ put it, even if the queue is
+/// reordered afterwards.
+pub(crate) struct Pinned;
+
+impl TypeMapKey for Pinned {
+    type Value = bool;
+}
+
+pub(crate) async fn pin(track: &TrackHandle) {
+    track.typemap().write().await.insert::<Pinned>(true);
+}
+
+pub(crate) async fn is_pinned(track: &TrackHandle) -> bool {
+    track
+        .typemap()
+        .read()
+        .await
+        .get::<Pinned>()
+        .copied()
+        .unwrap_or(false)
+}
+
+/// The pinned entries of `queue`, which stay where they are when others
+/// are moved, removed or cleared.
+pub(crate) async fn pinned(queue: &TrackQueue) -> Vec<TrackHandle> {
+    let mut pinned = vec![];
+    for track in queue.current_queue() {
+        if is_pinned(&track).await {
+            pinned.push(track);
+        }
+    }
+
+    pinned
+}
+
+fn pinned_in(pinned: &[TrackHandle]) -> impl Fn(&Queued) -> bool + '_ {
+    move |x| pinned.iter().any(|p| p.uuid() == x.uuid())
+}
+
+/// Volume set for this entry alone with `~vol INDEX VOL`, which setting
+/// the volume of the whole queue leaves alone.
+pub(crate) struct EntryVolume;
+
+impl TypeMapKey for EntryVolume {
+    type Value = f32;
+}
+
+pub(crate) async fn set_entry_volume(track: &TrackHandle, volume: f32) {
+    track.typemap().write().await.insert::<EntryVolume>(volume);
+}
+
+pub(crate) async fn entry_volume(track: &TrackHandle) -> Option<f32> {
+    track.typemap().read().await.get::<EntryVolume>().copied()
+}
+
+/// Sets `volume` on the entries without a volume of their own, returns how
+/// many that were.
+pub(crate) async fn set_volume(tracks: &[TrackHandle], volume: f32) -> TrackResult<usize> {
+    let mut changed = 0;
+    for track in tracks {
+        if entry_volume(track).await.is_none() {
+            track.set_volume(volume)?;
+            changed += 1;
+        }
+    }
+
+    Ok(changed)
+}
+
+/// The entry at `index` as users count, 1 being the current track.
+pub(crate) fn entry(tracks: &[TrackHandle], index: usize) -> Option<&TrackHandle> {
+    index.checked_sub(1).and_then(|x| tracks.get(x))
+}
+
+/// The user who added an entry to the queue.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Requester {
+    pub id: u64,
+    pub name: String,
+    /// Avatar URL, missing in history saved before it was recorded.
+    #[serde(default)]
+    pub avatar: Option<String>,
+}
+
+impl Requester {
+    /// The bot queueing songs on its own, shown as `name`.
+    pub(crate) fn bot(name: &str) -> Self {
+        Self {
+            id: 0,
+            name: name.to_string(),
+            avatar: None,
+        }
+    }
+
+    /// Songs the bot queued on its own, like Netease FM, have id 0.
+    pub(crate) fn is_bot(&self) -> bool {
+        self.id == 0
+    }
+
+    /// Whether `user` may take this requester's song out of the queue
+    /// without being a DJ.
+    pub(crate) fn may_remove(&self, user: u64) -> bool {
+        self.is_bot() || self.id == user
+    }
+}
+
+impl TypeMapKey for Requester {
+    type Value = Requester;
+}
+
+impl From<&User> for Requester {
+    fn from(user: &User) -> Self {
+        Self {
+            id: user.id.0,
+            name: user.name.clone(),
+            avatar: Some(user.face()),
+        }
+    }
+}
+
+impl From<&Message> for Requester {
+    fn from(msg: &Message) -> Self {
+        Self::from(&msg.author)
+    }
+}
+
+pub(crate) async fn set_requester(track: &TrackHandle, requester: Requester) {
+    track.typemap().write().await.insert::<Requester>(requester);
+}
+
+pub(crate) async fn requester(track: &TrackHandle) -> Option<Requester> {
+    track.typemap().read().await.get::<Requester>().cloned()
+}
+
+/// Moves the entry at `from` to `to`. Both are indexes of waiting entries.
+fn move_entry<T>(q: &mut VecDeque<T>, from: usize, to: usize) -> bool {
+    if from < 1 || to < 1 || from >= q.len() || to >= q.len() {
+        return false;
+    }
+
+    if let Some(entry) = q.remove(from) {
+        q.insert(to, entry);
+    }
+
+    true
+}
+
+/// Swaps the entries at `a` and `b`. Both are indexes of waiting entries.
+fn swap_entries<T>(q: &mut VecDeque<T>, a: usize, b: usize) -> bool {
+    if a < 1 || b < 1 || a >= q.len() || b >= q.len() {
+        return false;
+    }
+
+    q.swap(a, b);
+
+    true
+}
+
+/// Runs `f` on `q`, then puts the entries `is_pinned` back at the indexes
+/// they had. `f` must neither remove them nor change their order.
+fn keep_pinned<T, R>(
+    q: &mut VecDeque<T>,
+    is_pinned: impl Fn(&T) -> bool,
+    f: impl FnOnce(&mut VecDeque<T>) -> R,
+) -> R {
+    let at = (0..q.len())
+        .filter(|i| is_pinned(&q[*i]))
+        .collect::<Vec<_>>();
+    let result = f(q);
+
+    let mut taken = vec![];
+    let mut i = 0;
+    while i < q.len() {
+        if is_pinned(&q[i]) {
+            taken.extend(q.remove(i));
+        } else {
+            i += 1;
+        }
+    }
+    // From the first, so the ones before are in place already.
+    for (entry, at) in taken.into_iter().zip(at) {
+        q.insert(at.min(q.len()), entry);
+    }
+
+    result
+}
+
+/// Moves the last entry to `to`, it stays at the end when the queue is
+/// shorter than that.
+fn place_last<T>(q: &mut VecDeque<T>, to: usize) -> bool {
+    let last = q.len().saturating_sub(1);
+
+    to >= last || move_entry(q, last, to)
+}
+
+/// Moves the entry at `from` right after the current track and the
+/// `pinned` entries following it, so boosted songs play in the order they
+/// were boosted.
+///
+/// Returns `None` if `from` does not point to a waiting entry or a pinned
+/// one.
+pub(crate) fn play_next(
+    queue: &TrackQueue,
+    pinned: &[TrackHandle],
+    from: usize,
+) -> Option<TrackHandle> {
+    let is_pinned = pinned_in(pinned);
+    queue.modify_queue(|q| {
+        let moved = q.get(from).filter(|x| !is_pinned(x))?.handle();
+        keep_pinned(q, &is_pinned, |q| move_entry(q, from, 1)).then_some(moved)
+    })
+}
+
+/// Moves the entry at `from` to `to`, `false` if either isn't a waiting entry
+/// or `from` is pinned. The `pinned` entries stay where they are.
+pub(crate) fn move_to(queue: &TrackQueue, pinned: &[TrackHandle], from: usize, to: usize) -> bool {
+    let is_pinned = pinned_in(pinned);
+    queue.modify_queue(|q| {
+        !q.get(from).is_some_and(&is_pinned)
+            && keep_pinned(q, &is_pinned, |q| move_entry(q, from, to))
+    })
+}
+
+/// Moves the entry just added to `to`, `false` if `to` is the current track.
+/// The `pinned` entries stay where they are.
+pub(crate) fn insert_at(queue: &TrackQueue, pinned: &[TrackHandle], to: usize) -> bool {
+    queue.modify_queue(|q| keep_pinned(q, pinned_in(pinned), |q| place_last(q, to)))
+}
+
+/// Takes `tracks` out of the queue, the current track and the `pinned`
+/// entries stay. Returns the removed ones.
+pub(crate) fn remove(
+    queue: &TrackQueue,
+    pinned: &[TrackHandle],
+    tracks: &[TrackHandle],
+) -> Vec<TrackHandle> {
+    let is_pinned = pinned_in(pinned);
+    queue.modify_queue(|q| {
+        keep_pinned(q, &is_pinned, |q| {
+            let mut removed = vec![];
+            let mut i = 1;
+            while i < q.len() {
+                if !is_pinned(&q[i]) && tracks.iter().any(|x| x.uuid() == q[i].uuid()) {
+                    removed.extend(q.remove(i).map(|x| x.handle()));
+                } else {
+                    i += 1;
+                }
+            }
+
+            removed
+        })
+    })
+}
+
+/// Indexes of entries whose URL came up earlier in `urls`, the first of
+/// each stays. Entries without a URL are never duplicates.
+pub(crate) fn later_duplicates(urls: &[Option<&str>]) -> Vec<usize> {
+    let mut seen = HashSet::new();
+
+    urls.iter()
+        .enumerate()
+        .filter(|(_, x)| x.is_some_and(|x| !seen.insert(x)))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Swaps two entries, `false` if either isn't a waiting entry or is one of
+/// the `pinned` ones.
+pub(crate) fn swap(queue: &TrackQueue, pinned: &[TrackHandle], a: usize, b: usize) -> bool {
+    let is_pinned = pinned_in(pinned);
+    queue.modify_queue(|q| {
+        ![a, b].iter().any(|x| q.get(*x).is_some_and(&is_pinned)) && swap_entries(q, a, b)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+
+    #[test]
+    fn test_move_entry() {
+        let mut q = VecDeque::from(vec![0, 1, 2, 3]);
+
+        assert!(move_entry(&mut q, 3, 1));
+        assert_eq!(q, [0, 3, 1, 2]);
+        assert!(move_entry(&mut q, 1, 3));
+        assert_eq!(q, [0, 1, 2, 3]);
+        assert!(!move_entry(&mut q, 0, 2));
+        assert!(!move_entry(&mut q, 2, 0));
+        assert!(!move_entry(&mut q, 1, 4));
+    }
+
+    #[test]
+    fn test_may_remove() {
+        let requester = |id| Requester {
+            id,
+            name: id.to_string(),
+            avatar: None,
+        };
+
+        assert!(requester(1).may_remove(1));
+        assert!(!requester(1).may_remove(2));
+        assert!(requester(0).may_remove(2));
+    }
+
+    #[test]
+    fn test_place_last() {
+        let mut q = VecDeque::from(vec![0, 1, 2, 3]);
+
+        assert!(place_last(&mut q, 1));
+        assert_eq!(q, [0, 3, 1, 2]);
+        assert!(place_last(&mut q, 9));
+        assert_eq!(q, [0, 3, 1, 2]);
+        assert!(!place_last(&mut q, 0));
+    }
+
+    #[test]
+    fn test_later_duplicates() {
+        let urls = [
+            Some("a"),
+            Some("b"),
+            None,
+            Some("a"),
+            None,
+            Some("b"),
+            Some("a"),
+        ];
+
+        assert_eq!(later_duplicates(&urls), [3, 5, 6]);
+    }
+
+    #[test]
+    fn test_swap_entries() {
+        let mut q = VecDeque::from(vec![0, 1, 2, 3]);
+
+        assert!(swap_entries(&mut q, 1, 3));
+        assert_eq!(q, [0, 3, 2, 1]);
+        assert!(!swap_entries(&mut q, 0, 1));
+        assert!(!swap_entries(&mut q, 1, 4));
+    }
+
+    #[tokio::test]
+    async fn test_reorder_queue() {
+        let driver = mock::queued(&["a", "b", "c", "d"]);
+        let queue = driver.queue();
+
+        assert!(play_next(queue, &[], 3).is_some());
+        assert_eq!(mock::titles(&driver), ["a", "d", "b", "c"]);
+        assert!(move_to(queue, &[], 1, 3));
+        assert_eq!(mock::titles(&driver), ["a", "b", "c", "d"]);
+        assert!(swap(queue, &[], 1, 2));
+        assert_eq!(mock::titles(&driver), ["a", "c", "b", "d"]);
+        assert!(play_next(queue, &[], 0).is_none());
+        assert!(!move_to(queue, &[], 4, 1));
+        assert!(!swap(queue, &[], 0, 1));
+        assert_eq!(mock::titles(&driver), ["a", "c", "b", "d"]);
+    }
+
+    #[test]
+    fn test_keep_pinned() {
+        let mut q = VecDeque::from(vec![0, 1, 2, 3, 4]);
+
+        assert!(keep_pinned(&mut q, |x| *x == 2, |q| move_entry(q, 4, 1)));
+        assert_eq!(q, [0, 4, 2, 1, 3]);
+        keep_pinned(&mut q, |x| *x == 2, |q| q.retain(|x| *x != 4));
+        assert_eq!(q, [0, 1, 2, 3]);
+        keep_pinned(&mut q, |x| *x == 3, |q| q.retain(|x| *x == 0 || *x == 3));
+        assert_eq!(q, [0, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_pinned() {
+        let driver = mock::queued(&["a", "b", "c", "d", "e"]);
+        let queue = driver.queue();
+        let list = queue.current_queue();
+
+        pin(&list[2]).await;
+        let pinned = super::pinned(queue).await;
+        assert_eq!(mock::titles_of(&pinned), ["c"]);
+        assert!(play_next(queue, &pinned, 4).is_some());
+        assert_eq!(mock::titles(&driver), ["a", "e", "c", "b", "d"]);
+        assert!(play_next(queue, &pinned, 2).is_none());
+        assert!(!move_to(queue, &pinned, 2, 4));
+        assert!(!swap(queue, &pinned, 1, 2));
+
+        let removed = remove(queue, &pinned, &list);
+        assert_eq!(mock::titles_of(&removed), ["e", "b", "d"]);
+        assert_eq!(mock::titles(&driver), ["a", "c"]);
+    }
+
+    #[tokio::test]
+    async fn test_insert_at() {
+        let mut driver = mock::queued(&["a", "b", "c"]);
+
+        driver.enqueue_source(mock::track("d", "https://example.com/d"));
+        assert!(insert_at(driver.queue(), &[], 1));
+        assert_eq!(mock::titles(&driver), ["a", "d", "b", "c"]);
+        driver.enqueue_source(mock::track("e", "https://example.com/e"));
+        assert!(!insert_at(driver.queue(), &[], 0));
+        assert_eq!(mock::titles(&driver), ["a", "d", "b", "c", "e"]);
+    }
+
+    #[tokio::test]
+    async fn test_remove() {
+        let driver = mock::queued(&["a", "b", "c", "d"]);
+        let list = driver.queue().current_queue();
+
+        let removed = remove(driver.queue(), &[], &[list[0].clone(), list[2].clone()]);
+        assert_eq!(mock::titles_of(&removed), ["c"]);
+        assert_eq!(mock::titles(&driver), ["a", "b", "d"]);
+    }
+
+    #[tokio::test]
+    async fn test_entry() {
+        let driver = mock::queued(&["a", "b"]);
+        let list = driver.queue().current_queue();
+        let title = |x: Option<&TrackHandle>| x.and_then(|x| x.metadata().title.clone());
+
+        assert_eq!(title(entry(&list, 0)), None);
+        assert_eq!(title(entry(&list, 1)).as_deref(), Some("a"));
+        assert_eq!(title(entry(&list, 2)).as_deref(), Some("b"));
+        assert_eq!(title(entry(&list, 3)), None);
+    }
+
+    #[tokio::test]
+    async fn test_set_volume() {
+        let driver = mock::queued(&["a", "b", "c"]);
+        let list = driver.queue().current_queue();
+
+        set_entry_volume(&list[1], 1.5).await;
+        assert_eq!(set_volume(&list, 0.5).await.unwrap(), 2);
+        assert_eq!(entry_volume(&list[1]).await, Some(1.5));
+        assert_eq!(entry_volume(&list[0]).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_requester() {
+        let driver = mock::queued(&["a", "b"]);
+        let list = driver.queue().current_queue();
+
+        set_requester(&list[1], Requester::bot("FM")).await;
+        assert!(requester(&list[0]).await.is_none());
+        assert!(requester(&list[1]).await.is_some_and(|x| x.is_bot()));
+    }
+
+    #[tokio::test]
+    async fn test_guilds_apart() {
+        let first = mock::queued(&["a", "b", "c"]);
+        let second = mock::queued(&["x", "y"]);
+
+        assert!(swap(first.queue(), &[], 1, 2));
+        assert_eq!(mock::titles(&first), ["a", "c", "b"]);
+        assert_eq!(mock::titles(&second), ["x", "y"]);
+    }
+}
@@ -0,0 +1,79 @@
+//! "Radio DJ" mode: announces every song before the queue starts it.
+use serenity::async_trait;
+use songbird::{
+    tracks::TrackHandle, Event, EventContext, EventHandler as VoiceEventHandler, TrackEvent,
+};
+use tracing::warn;
+
+use crate::{playback::GuildPlayer, queue, track_name, tts};
+
+pub(crate) const DEFAULT_LANGUAGE: &str = "en";
+
+/// Fires when the queue starts the track. Only the first start counts, so a
+/// resume after the announcement doesn't trigger another one.
+pub(crate) struct Announcer {
+    pub player: GuildPlayer,
+}
+
+#[async_trait]
+impl VoiceEventHandler for Announcer {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if let EventContext::Track(&[(_, track)]) = ctx {
+            let language = self.player.state(|x| x.radio_dj.clone()).await;
+
+            if let Some(language) = language {
+                if let Err(e) = self.announce(track, &language).await {
+                    warn!("Err announcing track: {:?}", e);
+                    let _ = track.play();
+                }
+            }
+        }
+
+        Some(Event::Cancel)
+    }
+}
+
+impl Announcer {
+    async fn announce(&self, track: &TrackHandle, language: &str) -> anyhow::Result<()> {
+        let text = announcement(track, self.player.display_options().await.show_requester).await;
+        let voice = tts::speak(&text, language)?;
+
+        track.pause()?;
+        let voice = self.player.call.lock().await.play_source(voice);
+        voice.add_event(
+            Event::Track(TrackEvent::End),
+            ResumeAfterAnnouncement {
+                track: track.clone(),
+            },
+        )?;
+
+        Ok(())
+    }
+}
+
+async fn announcement(track: &TrackHandle, show_requester: bool) -> String {
+    let metadata = track.metadata();
+    let mut s = format!("Next up: {}", track_name(metadata));
+
+    if let Some(artist) = metadata.artist.as_ref().filter(|x| !x.is_empty()) {
+        s.push_str(&format!(" by {}", artist));
+    }
+    if let Some(requester) = queue::requester(track).await.filter(|_| show_requester) {
+        s.push_str(&format!(", requested by {}", requester.name));
+    }
+
+    s
+}
+
+struct ResumeAfterAnnouncement {
+    track: TrackHandle,
+}
+
+#[async_trait]
+impl VoiceEventHandler for ResumeAfterAnnouncement {
+    async fn act(&self, _ctx: &EventContext<'_>) -> Option<Event> {
+        let _ = self.track.play();
+
+        None
+    }
+}
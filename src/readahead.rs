@@ -0,0 +1,130 @@
+//! Reads ffmpeg's output on a thread of its own, a few seconds ahead of
+//! the mixer. A song started before its turn decodes its first seconds
+//! while the one before still plays, so the queue moves on without the
+//! silence of ffmpeg starting up.
+use std::{
+    io::{self, Read, Seek, SeekFrom},
+    mem,
+    sync::{
+        mpsc::{self, Receiver},
+        Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use songbird::input::reader::MediaSource;
+
+use crate::{gateway, supervisor::Guard};
+
+/// Bytes of a second of 48 kHz stereo float audio.
+const BYTES_PER_SECOND: usize = 48000 * 2 * mem::size_of::<f32>();
+const CHUNK_BYTES: usize = BYTES_PER_SECOND / 10;
+/// Audio read ahead at most, unless memory is short.
+const AHEAD: Duration = Duration::from_secs(5);
+
+/// How much audio to read ahead, none with `LOW_MEMORY`.
+pub(crate) fn ahead() -> Duration {
+    if gateway::low_memory() {
+        Duration::ZERO
+    } else {
+        AHEAD
+    }
+}
+
+pub(crate) struct Readahead {
+    /// In a `Mutex` only because sources have to be `Sync`.
+    chunks: Mutex<Receiver<io::Result<Vec<u8>>>>,
+    chunk: Vec<u8>,
+    read: usize,
+    /// Kills the processes `source` reads from, a read waiting on them
+    /// ends with them.
+    _guard: Option<Guard>,
+}
+
+impl Readahead {
+    /// Reads up to `ahead` of `source` ahead until this is dropped, which
+    /// drops `source` too.
+    pub(crate) fn new(
+        mut source: impl Read + Send + 'static,
+        ahead: Duration,
+        guard: Option<Guard>,
+    ) -> Self {
+        let chunks_ahead = (ahead.as_secs_f64() * BYTES_PER_SECOND as f64) as usize / CHUNK_BYTES;
+        let (sender, chunks) = mpsc::sync_channel(chunks_ahead);
+        thread::Builder::new()
+            .name("readahead".to_string())
+            .spawn(move || loop {
+                let mut chunk = vec![0; CHUNK_BYTES];
+                let chunk = match source.read(&mut chunk) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        chunk.truncate(n);
+                        Ok(chunk)
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => Err(e),
+                };
+                let failed = chunk.is_err();
+                if sender.send(chunk).is_err() || failed {
+                    break;
+                }
+            })
+            .expect("Err starting a readahead thread");
+
+        Self {
+            chunks: Mutex::new(chunks),
+            chunk: vec![],
+            read: 0,
+            _guard: guard,
+        }
+    }
+}
+
+impl Read for Readahead {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.read == self.chunk.len() {
+            let chunks = self.chunks.get_mut().unwrap_or_else(|e| e.into_inner());
+            match chunks.recv() {
+                Ok(chunk) => self.chunk = chunk?,
+                // The source ended.
+                Err(_) => return Ok(0),
+            }
+            self.read = 0;
+        }
+        let n = buf.len().min(self.chunk.len() - self.read);
+        buf[..n].copy_from_slice(&self.chunk[self.read..self.read + n]);
+        self.read += n;
+
+        Ok(n)
+    }
+}
+
+impl Seek for Readahead {
+    fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+impl MediaSource for Readahead {
+    fn is_seekable(&self) -> bool {
+        false
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        None
+    }
+}
+
+#[test]
+fn test_readahead() {
+    let audio = (0..CHUNK_BYTES * 3 + 7)
+        .map(|x| x as u8)
+        .collect::<Vec<_>>();
+    for ahead in [AHEAD, Duration::ZERO] {
+        let mut readahead = Readahead::new(io::Cursor::new(audio.clone()), ahead, None);
+        let mut read = vec![];
+        readahead.read_to_end(&mut read).unwrap();
+        assert_eq!(read, audio);
+    }
+}
@@ -0,0 +1,138 @@
+//! Names the playing song by fingerprinting a snippet of it with AudD,
+//! for streams which come without metadata.
+use std::{io::Read, time::Duration};
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use songbird::input::{Codec, Input};
+
+use crate::{
+    credentials::{self, Credential},
+    ffmpeg::FilterHandle,
+    quality::Quality,
+    source,
+};
+
+const AUDD_URL: &str = "https://api.audd.io/";
+const SNIPPET_LENGTH: Duration = Duration::from_secs(10);
+const SAMPLE_RATE: u32 = 48000;
+
+#[derive(Deserialize, Debug)]
+struct AuddResult {
+    status: String,
+    result: Option<Song>,
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct Song {
+    pub artist: String,
+    pub title: String,
+    pub album: Option<String>,
+    pub song_link: Option<String>,
+}
+
+/// `~whatsong` is off until there is an AudD api token.
+pub(crate) fn enabled() -> bool {
+    credentials::get(Credential::AuddApiToken).is_some()
+}
+
+/// Identifies what the source at `url` plays at `position`. `None` when
+/// AudD doesn't know the song.
+pub(crate) async fn recognize(url: String, position: Duration) -> Result<Option<Song>> {
+    let token = match credentials::get(Credential::AuddApiToken) {
+        Some(token) => token,
+        None => bail!("AudD api token is not set!"),
+    };
+
+    // Open our own copy of the source: songbird only hands out the mixed
+    // and encoded output, and this also leaves listeners' voices out.
+    let input: Input = source::open(&url, Quality::Low, FilterHandle::default())
+        .await?
+        .into();
+    let samples = tokio::task::spawn_blocking(move || capture(input, position)).await??;
+    let audio = base64::encode(wav(&samples));
+
+    let result = reqwest::Client::new()
+        .post(AUDD_URL)
+        .timeout(Duration::from_secs(30))
+        .form(&[("api_token", token.as_str()), ("audio", audio.as_str())])
+        .send()
+        .await?
+        .json::<AuddResult>()
+        .await?;
+    if result.status != "success" {
+        bail!("AudD request failed: {}", result.status);
+    }
+
+    Ok(result.result)
+}
+
+/// Reads a snippet as mono samples. Blocks on the decoder.
+fn capture(mut input: Input, position: Duration) -> Result<Vec<f32>> {
+    if !matches!(input.kind, Codec::FloatPcm) {
+        bail!("Can not capture {:?} audio!", input.kind);
+    }
+    if !position.is_zero() && input.is_seekable() {
+        input.seek_time(position);
+    }
+
+    // Interleaved stereo f32.
+    let frame_bytes = 2 * 4;
+    let mut buf = vec![0u8; SNIPPET_LENGTH.as_secs() as usize * SAMPLE_RATE as usize * frame_bytes];
+    let mut filled = 0;
+    while filled < buf.len() {
+        match input.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    buf.truncate(filled - filled % frame_bytes);
+
+    Ok(buf
+        .chunks_exact(frame_bytes)
+        .map(|x| {
+            let left = f32::from_le_bytes([x[0], x[1], x[2], x[3]]);
+            let right = f32::from_le_bytes([x[4], x[5], x[6], x[7]]);
+            (left + right) / 2.0
+        })
+        .collect())
+}
+
+/// Encodes mono samples as 16 bit PCM WAV.
+fn wav(samples: &[f32]) -> Vec<u8> {
+    let data_len = samples.len() as u32 * 2;
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    // PCM, one channel.
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+
+    wav
+}
+
+#[test]
+fn test_wav() {
+    let wav = wav(&[0.0, 1.0, -1.0, 2.0]);
+
+    assert_eq!(wav.len(), 44 + 8);
+    assert_eq!(&wav[..4], b"RIFF");
+    assert_eq!(u32::from_le_bytes([wav[4], wav[5], wav[6], wav[7]]), 36 + 8);
+    assert_eq!(&wav[36..40], b"data");
+    assert_eq!(i16::from_le_bytes([wav[46], wav[47]]), i16::MAX);
+    assert_eq!(i16::from_le_bytes([wav[48], wav[49]]), -i16::MAX);
+    // Clipped.
+    assert_eq!(i16::from_le_bytes([wav[50], wav[51]]), i16::MAX);
+}
@@ -0,0 +1,160 @@
+//! Rejoins the voice channel when Discord drops the connection, e.g. on a
+//! voice server migration, instead of going silent.
+use std::{
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use serenity::{
+    async_trait,
+    client::Context,
+    http::Http,
+    model::id::{ChannelId, GuildId},
+};
+use songbird::{
+    events::context_data::{DisconnectKind, DisconnectReason},
+    Call, CoreEvent, Event, EventContext, EventHandler as VoiceEventHandler, Songbird,
+};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::{
+    check_msg,
+    playback::{self, PlaybackLock},
+};
+
+/// Waits between attempts to rejoin, the last one is the final try.
+const BACKOFF: &[Duration] = &[
+    Duration::from_secs(1),
+    Duration::from_secs(2),
+    Duration::from_secs(4),
+    Duration::from_secs(8),
+    Duration::from_secs(16),
+    Duration::from_secs(32),
+];
+
+/// Watches the guild's call for dropped connections, once per call.
+pub(crate) async fn watch(ctx: &Context, guild_id: u64, call: &Arc<Mutex<Call>>) {
+    let lock = playback::playback_lock(ctx).await;
+    {
+        let mut playback = lock.write().await;
+        let state = playback.entry(guild_id).or_default();
+        if state
+            .watched_call
+            .as_ref()
+            .and_then(Weak::upgrade)
+            .is_some_and(|x| Arc::ptr_eq(&x, call))
+        {
+            return;
+        }
+        state.watched_call = Some(Arc::downgrade(call));
+    }
+
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    call.lock().await.add_global_event(
+        Event::Core(CoreEvent::DriverDisconnect),
+        Reconnector {
+            manager,
+            guild_id: GuildId(guild_id),
+            playback: lock,
+            http: ctx.http.clone(),
+        },
+    );
+}
+
+/// Holds no `GuildPlayer`, the call would keep itself alive through it.
+#[derive(Clone)]
+struct Reconnector {
+    manager: Arc<Songbird>,
+    guild_id: GuildId,
+    playback: PlaybackLock,
+    http: Arc<Http>,
+}
+
+#[async_trait]
+impl VoiceEventHandler for Reconnector {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let data = match ctx {
+            EventContext::DriverDisconnect(data) => data,
+            _ => return None,
+        };
+        // No reason means the bot left or was moved on purpose. Failed
+        // connects come from joining, which reports them itself.
+        match data.reason {
+            None | Some(DisconnectReason::AttemptDiscarded) => return None,
+            Some(_) if data.kind == DisconnectKind::Connect => return None,
+            Some(_) => (),
+        }
+        let channel = data.channel_id?;
+        warn!(
+            "Voice connection of guild {} dropped ({:?}, {:?})",
+            data.guild_id, data.kind, data.reason
+        );
+
+        let reconnector = self.clone();
+        tokio::spawn(async move {
+            reconnector.rejoin(ChannelId(channel.0)).await;
+        });
+
+        None
+    }
+}
+
+impl Reconnector {
+    /// Pauses the playing song, so it carries on where it stopped, and
+    /// rejoins with backoff until it works or the bot was made to leave.
+    async fn rejoin(&self, channel: ChannelId) {
+        let guild_id = self.guild_id;
+        let current = match self.manager.get(guild_id) {
+            Some(call) => call.lock().await.queue().current(),
+            None => return,
+        };
+        if let Some(current) = &current {
+            let _ = current.pause();
+        }
+
+        for (attempt, wait) in BACKOFF.iter().enumerate() {
+            tokio::time::sleep(*wait).await;
+            // Gone after `~leave`, don't drag the bot back.
+            if self.manager.get(guild_id).is_none() {
+                return;
+            }
+
+            let (_, success) = self.manager.join(guild_id, channel).await;
+            match success {
+                Ok(()) => {
+                    info!("Rejoined voice channel of guild {}", guild_id);
+                    if let Some(current) = &current {
+                        let _ = current.play();
+                    }
+
+                    return;
+                }
+                Err(e) => warn!(
+                    "Err rejoining guild {} (attempt {}): {:?}",
+                    guild_id,
+                    attempt + 1,
+                    e
+                ),
+            }
+        }
+
+        let text_channel = {
+            let playback = self.playback.read().await;
+            playback.get(&guild_id.0).and_then(|x| x.text_channel)
+        };
+        if let Some(text_channel) = text_channel {
+            check_msg(
+                text_channel
+                    .say(
+                        &self.http,
+                        "Lost the voice connection and could not get it back, use ~join",
+                    )
+                    .await,
+            );
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! Embeds for replies about songs: what's playing, the queue and what was
+//! just added.
+use std::time::Duration;
+
+use serenity::builder::CreateEmbed;
+use songbird::input::Metadata;
+
+use crate::{
+    display::{self, DisplayOptions},
+    duration_formatter,
+    ffmpeg::Effects,
+    queue::Requester,
+    track_name,
+};
+
+const PROGRESS_BAR_WIDTH: usize = 20;
+
+/// Songs on a page of `~list`.
+pub(crate) const QUEUE_PAGE_SIZE: usize = 10;
+
+/// A bar like `▬▬▬🔘▬▬▬▬▬▬` for how far the song got.
+pub(crate) fn progress_bar(position: Duration, duration: Duration) -> String {
+    let done = if duration.is_zero() {
+        0
+    } else {
+        let ratio = position.as_secs_f64() / duration.as_secs_f64();
+        ((ratio * PROGRESS_BAR_WIDTH as f64) as usize).min(PROGRESS_BAR_WIDTH - 1)
+    };
+
+    format!(
+        "{}🔘{}",
+        "▬".repeat(done),
+        "▬".repeat(PROGRESS_BAR_WIDTH - 1 - done)
+    )
+}
+
+/// Title linking to the source, artist, cover and requester of a song.
+fn song<'a>(
+    e: &'a mut CreateEmbed,
+    metadata: &Metadata,
+    requester: Option<&Requester>,
+    options: &DisplayOptions,
+) -> &'a mut CreateEmbed {
+    e.title(display::show(&track_name(metadata), options));
+    if let Some(url) = metadata.source_url.as_ref().filter(|_| options.show_url) {
+        e.url(url);
+    }
+    if let Some(artist) = &metadata.artist {
+        e.field("Artist", display::show(artist, options), true);
+    }
+    if let Some(thumbnail) = metadata
+        .thumbnail
+        .as_ref()
+        .filter(|_| options.show_thumbnail)
+    {
+        e.thumbnail(thumbnail);
+    }
+    if let Some(requester) = requester.filter(|_| options.show_requester) {
+        e.footer(|f| {
+            f.text(format!("Requested by {}", requester.name));
+            if let Some(avatar) = &requester.avatar {
+                f.icon_url(avatar);
+            }
+            f
+        });
+    }
+
+    e
+}
+
+/// `position` is the played time, the song's duration is scaled by the
+/// speed to match it.
+pub(crate) fn now<'a>(
+    e: &'a mut CreateEmbed,
+    metadata: &Metadata,
+    position: Duration,
+    requester: Option<&Requester>,
+    options: &DisplayOptions,
+    effects: &Effects,
+) -> &'a mut CreateEmbed {
+    e.author(|a| a.name("Now Playing"));
+    song(e, metadata, requester, options);
+
+    match metadata.duration.map(|x| effects.played(x)) {
+        Some(duration) => e.description(format!(
+            "{} {} / {}",
+            progress_bar(position, duration),
+            duration_formatter(&position),
+            duration_formatter(&duration)
+        )),
+        // Radio and other streams without an end.
+        None => e.description(format!("🔴 LIVE {}", duration_formatter(&position))),
+    }
+}
+
+/// A song the queue just started.
+pub(crate) fn started<'a>(
+    e: &'a mut CreateEmbed,
+    metadata: &Metadata,
+    requester: Option<&Requester>,
+    options: &DisplayOptions,
+) -> &'a mut CreateEmbed {
+    e.author(|a| a.name("Now Playing"));
+    song(e, metadata, requester, options);
+
+    if let Some(duration) = &metadata.duration {
+        e.field("Duration", duration_formatter(duration), true);
+    }
+
+    e
+}
+
+/// `fallback` names the service the song was found on instead of the link.
+pub(crate) fn added<'a>(
+    e: &'a mut CreateEmbed,
+    metadata: &Metadata,
+    requester: Option<&Requester>,
+    fallback: Option<&str>,
+    options: &DisplayOptions,
+) -> &'a mut CreateEmbed {
+    e.author(|a| a.name("Added to queue"));
+    song(e, metadata, requester, options);
+
+    if let Some(duration) = &metadata.duration {
+        e.field("Duration", duration_formatter(duration), true);
+    }
+    if let Some(fallback) = fallback {
+        e.description(format!("From {}, the link could not be played", fallback));
+    }
+
+    e
+}
+
+fn queue_line(
+    i: usize,
+    metadata: &Metadata,
+    requester: Option<&Requester>,
+    options: &DisplayOptions,
+    effects: &Effects,
+) -> String {
+    let name = display::show(&track_name(metadata), options);
+    let mut line = match metadata.source_url.as_ref().filter(|_| options.show_url) {
+        Some(url) => format!("{}. [{}]({})", i + 1, name, url),
+        None => format!("{}. {}", i + 1, name),
+    };
+    if let Some(duration) = metadata.duration.map(|x| effects.played(x)) {
+        line.push_str(&format!(" `{}`", duration_formatter(&duration)));
+    }
+    // Mentions in embeds don't notify anyone.
+    match requester.filter(|_| options.show_requester) {
+        Some(requester) if !requester.is_bot() => line.push_str(&format!(" - <@{}>", requester.id)),
+        Some(requester) => line.push_str(&format!(" - {}", requester.name)),
+        None => (),
+    }
+
+    line
+}
+
+/// Number of `~list` pages, an empty queue still has one.
+pub(crate) fn queue_pages(len: usize) -> usize {
+    len.max(1).div_ceil(QUEUE_PAGE_SIZE)
+}
+
+/// One page of the queue as numbered lines, `page` counts from 0.
+pub(crate) fn queue<'a>(
+    e: &'a mut CreateEmbed,
+    entries: &[(Metadata, Option<Requester>)],
+    page: usize,
+    options: &DisplayOptions,
+    effects: &Effects,
+) -> &'a mut CreateEmbed {
+    let description = entries
+        .iter()
+        .enumerate()
+        .skip(page * QUEUE_PAGE_SIZE)
+        .take(QUEUE_PAGE_SIZE)
+        .map(|(i, (metadata, requester))| {
+            queue_line(i, metadata, requester.as_ref(), options, effects)
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let total = entries
+        .iter()
+        .filter_map(|x| x.0.duration)
+        .map(|x| effects.played(x))
+        .sum::<Duration>();
+
+    e.title("Queue").description(description).footer(|f| {
+        f.text(format!(
+            "Page {}/{}, {} songs, {} total",
+            page + 1,
+            queue_pages(entries.len()),
+            entries.len(),
+            duration_formatter(&total)
+        ))
+    })
+}
+
+#[test]
+fn test_progress_bar() {
+    let bar = |position, duration| {
+        progress_bar(Duration::from_secs(position), Duration::from_secs(duration))
+    };
+
+    assert!(bar(0, 100).starts_with('🔘'));
+    assert!(bar(100, 100).ends_with('🔘'));
+    assert_eq!(bar(50, 100).chars().position(|c| c == '🔘'), Some(10));
+    assert_eq!(bar(10, 0).chars().count(), PROGRESS_BAR_WIDTH);
+}
+
+#[test]
+fn test_queue_pages() {
+    assert_eq!(queue_pages(0), 1);
+    assert_eq!(queue_pages(10), 1);
+    assert_eq!(queue_pages(11), 2);
+}
@@ -0,0 +1,259 @@
+//! Sends unexpected errors to whoever runs the bot, besides logging them:
+//! to a Discord channel through the webhook in `ERROR_WEBHOOK` and to
+//! Sentry at `SENTRY_DSN`. Command errors, failed resolves and panics are
+//! reported, errors users are told how to fix are not.
+use std::{
+    backtrace::Backtrace,
+    collections::VecDeque,
+    env, panic,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+use reqwest::Url;
+use serde_json::{json, Value};
+use tracing::warn;
+
+/// Reports sent in `WINDOW` at most, so a failing source can't flood the
+/// channel.
+const REPORTS_MAX: usize = 10;
+const WINDOW: Duration = Duration::from_secs(60);
+/// Discord's limit for a message.
+const MESSAGE_MAX_CHARS: usize = 2000;
+
+lazy_static! {
+    static ref WEBHOOK: Option<String> = env::var("ERROR_WEBHOOK").ok();
+    static ref SENTRY: Option<Dsn> = env::var("SENTRY_DSN").ok().and_then(|x| {
+        let dsn = Dsn::parse(&x);
+        if dsn.is_none() {
+            warn!("SENTRY_DSN is not a Sentry DSN, errors won't be sent to Sentry");
+        }
+        dsn
+    });
+    static ref SENT: Mutex<VecDeque<Instant>> = Mutex::new(VecDeque::new());
+    static ref CLIENT: reqwest::Client = reqwest::Client::new();
+}
+
+/// Where a Sentry project takes events.
+#[derive(Debug, PartialEq, Eq)]
+struct Dsn {
+    key: String,
+    envelope_url: String,
+}
+
+impl Dsn {
+    /// `https://KEY@HOST/PROJECT`, as Sentry shows it.
+    fn parse(dsn: &str) -> Option<Self> {
+        let url = Url::parse(dsn).ok()?;
+        if url.username().is_empty() {
+            return None;
+        }
+        let path = url.path().trim_matches('/');
+        let (prefix, project) = match path.rsplit_once('/') {
+            Some((prefix, project)) => (format!("/{}", prefix), project),
+            None => (String::new(), path),
+        };
+        if project.is_empty() {
+            return None;
+        }
+        let port = url.port().map(|x| format!(":{}", x)).unwrap_or_default();
+
+        Some(Self {
+            key: url.username().to_string(),
+            envelope_url: format!(
+                "{}://{}{}{}/api/{}/envelope/",
+                url.scheme(),
+                url.host_str()?,
+                port,
+                prefix,
+                project
+            ),
+        })
+    }
+}
+
+/// Whether one more report fits in the window, counting it if so.
+fn allow(sent: &mut VecDeque<Instant>, now: Instant) -> bool {
+    while sent
+        .front()
+        .is_some_and(|x| now.duration_since(*x) >= WINDOW)
+    {
+        sent.pop_front();
+    }
+    if sent.len() >= REPORTS_MAX {
+        return false;
+    }
+    sent.push_back(now);
+
+    true
+}
+
+/// The webhook message, cut to fit.
+fn message(kind: &str, guild_id: Option<u64>, detail: &str) -> String {
+    let title = match guild_id {
+        Some(guild_id) => format!("**{} error** in server {}", kind, guild_id),
+        None => format!("**{} error**", kind),
+    };
+    let room = MESSAGE_MAX_CHARS - title.chars().count() - "\n```\n```".len();
+    let detail = if detail.chars().count() > room {
+        let mut cut = detail.chars().take(room - 1).collect::<String>();
+        cut.push('…');
+        cut
+    } else {
+        detail.to_string()
+    };
+
+    format!("{}\n```\n{}```", title, detail)
+}
+
+fn sentry_event(kind: &str, guild_id: Option<u64>, detail: &str) -> Value {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs_f64())
+        .unwrap_or_default();
+    let mut tags = json!({ "kind": kind });
+    if let Some(guild_id) = guild_id {
+        tags["guild_id"] = guild_id.to_string().into();
+    }
+
+    json!({
+        "event_id": hex::encode(rand::random::<[u8; 16]>()),
+        "timestamp": timestamp,
+        "level": if kind == "panic" { "fatal" } else { "error" },
+        "platform": "native",
+        "logger": kind,
+        "release": concat!("bibicord@", env!("CARGO_PKG_VERSION")),
+        "message": { "formatted": detail },
+        "tags": tags,
+    })
+}
+
+/// `event` in an envelope, the one format Sentry takes events in: a line
+/// of headers, one describing the item and the item itself.
+fn envelope(event: &Value) -> String {
+    let payload = event.to_string();
+    let headers = json!({ "event_id": event["event_id"] });
+    let item = json!({ "type": "event", "length": payload.len() });
+
+    format!("{}\n{}\n{}\n", headers, item, payload)
+}
+
+async fn send(kind: &str, guild_id: Option<u64>, detail: &str) -> Result<()> {
+    if let Some(webhook) = WEBHOOK.as_ref() {
+        CLIENT
+            .post(webhook)
+            .json(&json!({
+                "content": message(kind, guild_id, detail),
+                "allowed_mentions": { "parse": [] },
+            }))
+            .send()
+            .await?
+            .error_for_status()?;
+    }
+    if let Some(dsn) = SENTRY.as_ref() {
+        let auth = format!(
+            "Sentry sentry_version=7, sentry_client=bibicord/{}, sentry_key={}",
+            env!("CARGO_PKG_VERSION"),
+            dsn.key
+        );
+        CLIENT
+            .post(&dsn.envelope_url)
+            .header("X-Sentry-Auth", auth)
+            .header("Content-Type", "application/x-sentry-envelope")
+            .body(envelope(&sentry_event(kind, guild_id, detail)))
+            .send()
+            .await?
+            .error_for_status()?;
+    }
+
+    Ok(())
+}
+
+/// Reports an error of `kind`, e.g. `command`, in the background.
+pub(crate) fn error(kind: &'static str, guild_id: Option<u64>, detail: String) {
+    if WEBHOOK.is_none() && SENTRY.is_none() {
+        return;
+    }
+    if !allow(&mut SENT.lock().unwrap(), Instant::now()) {
+        return;
+    }
+    // Panics outside the runtime are only logged.
+    if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+        runtime.spawn(async move {
+            if let Err(e) = send(kind, guild_id, &detail).await {
+                warn!("Err reporting an error: {:?}", e);
+            }
+        });
+    }
+}
+
+/// Reports panics too, after printing them as before.
+pub(crate) fn install_panic_hook() {
+    let print = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        print(info);
+        error(
+            "panic",
+            None,
+            format!("{}\n\n{}", info, Backtrace::force_capture()),
+        );
+    }));
+}
+
+#[test]
+fn test_dsn() {
+    assert_eq!(
+        Dsn::parse("https://abc@o1.ingest.sentry.io/42"),
+        Some(Dsn {
+            key: "abc".to_string(),
+            envelope_url: "https://o1.ingest.sentry.io/api/42/envelope/".to_string()
+        })
+    );
+    assert_eq!(
+        Dsn::parse("http://abc@localhost:9000/sentry/7")
+            .unwrap()
+            .envelope_url,
+        "http://localhost:9000/sentry/api/7/envelope/"
+    );
+    assert_eq!(Dsn::parse("https://o1.ingest.sentry.io/42"), None);
+    assert_eq!(Dsn::parse("not a dsn"), None);
+}
+
+#[test]
+fn test_envelope() {
+    let event = sentry_event("command", Some(1), "boom");
+    let envelope = envelope(&event);
+    let lines = envelope.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3);
+
+    let headers = serde_json::from_str::<Value>(lines[0]).unwrap();
+    assert_eq!(headers["event_id"], event["event_id"]);
+    let item = serde_json::from_str::<Value>(lines[1]).unwrap();
+    assert_eq!(item["type"], "event");
+    assert_eq!(item["length"], lines[2].len());
+    assert_eq!(lines[2], event.to_string());
+}
+
+#[test]
+fn test_allow() {
+    let mut sent = VecDeque::new();
+    let now = Instant::now();
+    for _ in 0..REPORTS_MAX {
+        assert!(allow(&mut sent, now));
+    }
+    assert!(!allow(&mut sent, now));
+    assert!(allow(&mut sent, now + WINDOW));
+}
+
+#[test]
+fn test_message() {
+    assert_eq!(
+        message("command", Some(1), "boom"),
+        "**command error** in server 1\n```\nboom```"
+    );
+    let long = message("resolve", None, &"x".repeat(3000));
+    assert_eq!(long.chars().count(), MESSAGE_MAX_CHARS);
+    assert!(long.ends_with("x…```"));
+}
@@ -0,0 +1,167 @@
+//! Turns a URL into something playable, and when its own service can't
+//! serve it, searches the song's title on the other services.
+use std::time::Instant;
+
+use anyhow::Result;
+use songbird::input::{Input, Metadata};
+use tracing::{info, warn};
+
+use crate::{
+    audio_cache,
+    ffmpeg::FilterHandle,
+    metrics, neteaseapi,
+    quality::Quality,
+    search,
+    source::{self, SourceProvider},
+};
+
+/// Services tried in this order when a URL fails.
+const FALLBACKS: &[Fallback] = &[Fallback::Netease, Fallback::YouTube];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Fallback {
+    Netease,
+    YouTube,
+}
+
+impl Fallback {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Fallback::Netease => "Netease",
+            Fallback::YouTube => "YouTube",
+        }
+    }
+
+    /// Whether a URL of this provider failing leaves nothing to gain from
+    /// searching it again.
+    fn covers(&self, provider: &dyn SourceProvider) -> bool {
+        match self {
+            Fallback::Netease => provider.name() == source::Netease.name(),
+            Fallback::YouTube => provider.name() == source::Ytdl.name(),
+        }
+    }
+
+    async fn search(&self, keywords: &str) -> Result<Option<String>> {
+        let songs = match self {
+            Fallback::Netease => neteaseapi::netease_search(keywords, 1).await?,
+            Fallback::YouTube => search::search_youtube(keywords, 1).await?,
+        };
+
+        Ok(songs.into_iter().next().and_then(|x| x.source_url))
+    }
+}
+
+pub(crate) struct Resolved {
+    pub input: Input,
+    /// Where the song was found instead, `None` when the URL itself worked.
+    pub fallback: Option<Fallback>,
+}
+
+pub(crate) async fn resolve(
+    url: String,
+    quality: Quality,
+    filter: FilterHandle,
+) -> Result<Resolved> {
+    let provider = source::provider(&url);
+    let (e, metadata) = match playable(&url, quality, filter.clone()).await {
+        Ok(input) => {
+            return Ok(Resolved {
+                input,
+                fallback: None,
+            })
+        }
+        Err(failure) => failure,
+    };
+    warn!("{} source failed for {}: {:?}", provider.name(), url, e);
+    metrics::resolve_failed(provider.name());
+
+    let metadata = match metadata {
+        Some(metadata) => Some(metadata),
+        None => provider.metadata(&url).await.ok(),
+    };
+    let keywords = match metadata.as_ref().and_then(keywords) {
+        Some(keywords) => keywords,
+        // Without a title there is nothing to search for.
+        None => return Err(e),
+    };
+
+    for fallback in FALLBACKS.iter().filter(|x| !x.covers(provider)) {
+        info!("Trying {} for {:?}", fallback.name(), keywords);
+        let found = match fallback.search(&keywords).await {
+            Ok(Some(found)) => found,
+            Ok(None) => {
+                info!("{} found nothing for {:?}", fallback.name(), keywords);
+                continue;
+            }
+            Err(e) => {
+                warn!("{} search failed: {:?}", fallback.name(), e);
+                continue;
+            }
+        };
+        match playable(&found, quality, filter.clone()).await {
+            Ok(input) => {
+                info!("Playing {} from {} instead", found, fallback.name());
+
+                return Ok(Resolved {
+                    input,
+                    fallback: Some(*fallback),
+                });
+            }
+            Err((e, _)) => {
+                warn!("{} source failed for {}: {:?}", fallback.name(), found, e);
+                metrics::resolve_failed(source::provider(&found).name());
+            }
+        }
+    }
+
+    Err(e.context(format!("No source could play {}", url)))
+}
+
+/// Opens the source lazily, but makes sure it can stream. On failure
+/// returns what could be learnt about the song.
+async fn playable(
+    url: &str,
+    quality: Quality,
+    filter: FilterHandle,
+) -> Result<Input, (anyhow::Error, Option<Metadata>)> {
+    if let Some(saved) = audio_cache::open(url, true, filter.clone()).await {
+        return Ok(saved.into());
+    }
+    let provider = source::provider(url);
+    let started = Instant::now();
+    let input: Input = provider
+        .resolve(url, true, quality, filter)
+        .await
+        .map_err(|e| (e, None))?
+        .into();
+    metrics::resolved(provider.name(), started.elapsed());
+
+    // Netease hands out metadata even for songs it then won't stream.
+    if let Err(e) = provider.check_playable(url).await {
+        return Err((e, Some((*input.metadata).clone())));
+    }
+
+    Ok(input)
+}
+
+fn keywords(metadata: &Metadata) -> Option<String> {
+    let title = metadata.title.as_ref()?;
+
+    Some(match &metadata.artist {
+        Some(artist) if !artist.is_empty() => format!("{} {}", title, artist),
+        _ => title.to_owned(),
+    })
+}
+
+#[test]
+fn test_keywords() {
+    let metadata = Metadata {
+        title: Some("Lemon".to_string()),
+        artist: Some("米津玄師".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(keywords(&metadata), Some("Lemon 米津玄師".to_string()));
+    assert_eq!(keywords(&Metadata::default()), None);
+    assert!(Fallback::YouTube.covers(&source::Ytdl));
+    assert!(!Fallback::YouTube.covers(&source::Netease));
+}
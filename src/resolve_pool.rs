@@ -0,0 +1,137 @@
+//! Bounds how many songs are resolved at once, each one running yt-dlp,
+//! ffmpeg or a request to a service: `RESOLVE_GUILD_WORKERS` (3 by default)
+//! per guild and `RESOLVE_WORKERS` (8 by default) in all, so a long
+//! playlist or a spammed `~play` can't starve the bot.
+use std::{
+    collections::HashMap,
+    env,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::{
+    ffmpeg::FilterHandle,
+    quality::Quality,
+    resolve::{self, Resolved},
+};
+
+const DEFAULT_WORKERS: usize = 8;
+const DEFAULT_GUILD_WORKERS: usize = 3;
+/// Time between two edits of the progress message, Discord rate limits
+/// them.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+
+fn workers(name: &str, default: usize) -> usize {
+    env::var(name)
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .filter(|x| *x > 0)
+        .unwrap_or(default)
+}
+
+lazy_static! {
+    pub(crate) static ref GUILD_WORKERS: usize =
+        workers("RESOLVE_GUILD_WORKERS", DEFAULT_GUILD_WORKERS);
+    static ref WORKERS: Arc<Semaphore> =
+        Arc::new(Semaphore::new(workers("RESOLVE_WORKERS", DEFAULT_WORKERS)));
+    static ref GUILDS: Mutex<HashMap<u64, Arc<Semaphore>>> = Mutex::new(HashMap::new());
+}
+
+/// Held while a song is resolved.
+struct Permits {
+    _guild: OwnedSemaphorePermit,
+    _global: OwnedSemaphorePermit,
+}
+
+async fn acquire(guild_id: u64) -> Permits {
+    let guild = GUILDS
+        .lock()
+        .unwrap()
+        .entry(guild_id)
+        .or_insert_with(|| Arc::new(Semaphore::new(*GUILD_WORKERS)))
+        .clone();
+    // The semaphores are never closed.
+    let guild = guild.acquire_owned().await.expect("semaphore closed");
+    let global = WORKERS
+        .clone()
+        .acquire_owned()
+        .await
+        .expect("semaphore closed");
+
+    Permits {
+        _guild: guild,
+        _global: global,
+    }
+}
+
+/// `resolve::resolve` once a worker of the guild is free.
+pub(crate) async fn resolve(
+    guild_id: u64,
+    url: String,
+    quality: Quality,
+    filter: FilterHandle,
+) -> Result<Resolved> {
+    let _permits = acquire(guild_id).await;
+
+    resolve::resolve(url, quality, filter).await
+}
+
+/// Counts resolved songs of a playlist, telling when to show them.
+pub(crate) struct Progress {
+    total: usize,
+    done: usize,
+    shown: Instant,
+}
+
+impl Progress {
+    pub(crate) fn new(total: usize) -> Self {
+        Self {
+            total,
+            done: 0,
+            shown: Instant::now(),
+        }
+    }
+
+    /// Counts one more song, returning the message to show if one is due.
+    pub(crate) fn step(&mut self, now: Instant) -> Option<String> {
+        self.done += 1;
+        if self.done == self.total || now.duration_since(self.shown) < PROGRESS_INTERVAL {
+            return None;
+        }
+        self.shown = now;
+
+        Some(format!("Resolving {}/{} songs...", self.done, self.total))
+    }
+}
+
+#[test]
+fn test_progress() {
+    let mut progress = Progress::new(3);
+    let start = progress.shown;
+    assert_eq!(progress.step(start), None);
+    assert_eq!(
+        progress.step(start + PROGRESS_INTERVAL).as_deref(),
+        Some("Resolving 2/3 songs...")
+    );
+    // The last one is told by the summary.
+    assert_eq!(progress.step(start + PROGRESS_INTERVAL * 2), None);
+}
+
+#[tokio::test]
+async fn test_acquire() {
+    let held = futures_util::future::join_all((0..*GUILD_WORKERS).map(|_| acquire(1))).await;
+    let more = tokio::time::timeout(Duration::from_millis(50), acquire(1)).await;
+    assert!(more.is_err());
+    // Other guilds still get a worker.
+    assert!(tokio::time::timeout(Duration::from_millis(50), acquire(2))
+        .await
+        .is_ok());
+    drop(held);
+    assert!(tokio::time::timeout(Duration::from_millis(50), acquire(1))
+        .await
+        .is_ok());
+}
@@ -0,0 +1,601 @@
+//! Saves the queue of every guild to disk and restores it after a restart.
+//! Users can also suspend a queue to their profile and resume it in
+//! another guild, or save it under a name for the whole guild to load.
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Result};
+use lazy_static::lazy_static;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use serenity::{
+    client::Context,
+    model::id::{ChannelId, GuildId, UserId},
+};
+use songbird::{input::Input, tracks::TrackHandle, Call};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::{
+    check_msg, dj, limiter,
+    looping::LoopMode,
+    playback::{self, GuildPlayer},
+    preflight,
+    queue::{self, Requester},
+    queue_full, reconnect, settings, source, store, SongVolume,
+};
+
+const QUEUES: &str = "queues";
+const SUSPENDED: &str = "suspended";
+const PLAYLISTS: &str = "playlists";
+const PLAYLIST_NAME_MAX: usize = 32;
+pub(crate) const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+lazy_static! {
+    /// Saved queues of guilds whose shard is not ready yet, `None` until
+    /// the first shard is. Saving keeps them, so a shard starting late
+    /// still finds its queues.
+    static ref UNRESTORED: Mutex<Option<HashMap<u64, SavedQueue>>> = Mutex::new(None);
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct SavedQueue {
+    voice_channel: u64,
+    text_channel: Option<u64>,
+    loop_mode: LoopMode,
+    /// How far the first entry had been played.
+    position: Duration,
+    entries: Vec<SavedEntry>,
+}
+
+/// A queue a user took with them, keyed by their id in `SUSPENDED`.
+#[derive(Serialize, Deserialize)]
+struct SuspendedQueue {
+    loop_mode: LoopMode,
+    position: Duration,
+    entries: Vec<SavedEntry>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct SavedEntry {
+    url: String,
+    volume: f32,
+    /// `volume` was set for this entry alone.
+    #[serde(default)]
+    entry_volume: bool,
+    requester: Option<Requester>,
+}
+
+/// How far the first track had been played, and the tracks which can be
+/// opened again.
+async fn save_tracks(tracks: &[TrackHandle]) -> (Duration, Vec<SavedEntry>) {
+    let mut position = Duration::default();
+    let mut entries = vec![];
+    for (i, track) in tracks.iter().enumerate() {
+        let url = match &track.metadata().source_url {
+            Some(url) => url.to_owned(),
+            None => continue,
+        };
+        let info = match track.get_info().await {
+            Ok(info) => info,
+            Err(_) => continue,
+        };
+        if i == 0 {
+            position = info.position;
+        }
+
+        entries.push(SavedEntry {
+            url,
+            volume: info.volume,
+            entry_volume: queue::entry_volume(track).await.is_some(),
+            requester: queue::requester(track).await,
+        });
+    }
+
+    (position, entries)
+}
+
+async fn snapshot(ctx: &Context) -> HashMap<u64, SavedQueue> {
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    let guilds = {
+        let lock = playback::playback_lock(ctx).await;
+        let playback = lock.read().await;
+        playback
+            .iter()
+            .map(|(id, state)| (*id, state.loop_mode, state.text_channel))
+            .collect::<Vec<_>>()
+    };
+
+    let mut queues = HashMap::new();
+    for (guild_id, loop_mode, text_channel) in guilds {
+        let (voice_channel, tracks) = match manager.get(guild_id) {
+            Some(handler_lock) => {
+                let handler = handler_lock.lock().await;
+                (handler.current_channel(), handler.queue().current_queue())
+            }
+            None => continue,
+        };
+        let voice_channel = match voice_channel {
+            Some(channel) if !tracks.is_empty() => channel,
+            _ => continue,
+        };
+
+        let (position, entries) = save_tracks(&tracks).await;
+        queues.insert(
+            guild_id,
+            SavedQueue {
+                voice_channel: voice_channel.0,
+                text_channel: text_channel.map(|x| x.0),
+                loop_mode,
+                position,
+                entries,
+            },
+        );
+    }
+
+    queues
+}
+
+/// Writes the queues of all guilds to disk.
+pub(crate) async fn save(ctx: &Context) -> Result<()> {
+    let unrestored = UNRESTORED.lock().await;
+    // Before any shard is ready the last run's queues are all still on disk.
+    let unrestored = match unrestored.as_ref() {
+        Some(unrestored) => unrestored,
+        None => return Ok(()),
+    };
+    let mut queues = snapshot(ctx).await;
+    for (guild_id, saved) in unrestored {
+        queues.entry(*guild_id).or_insert_with(|| saved.clone());
+    }
+
+    store::save(QUEUES, &queues).await
+}
+
+/// Leaves voice channels Discord still shows the bot in although this run
+/// never joined them, left over from a crash. Runs after `restore`, so
+/// guilds with a saved queue are rejoined instead.
+pub(crate) async fn disconnect_stale(ctx: &Context, guilds: &[GuildId]) {
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    let bot_id = ctx.cache.current_user_id();
+
+    for guild_id in guilds {
+        let connected = ctx
+            .cache
+            .guild_field(guild_id, |x| {
+                x.voice_states
+                    .get(&bot_id)
+                    .and_then(|x| x.channel_id)
+                    .is_some()
+            })
+            .unwrap_or(false);
+        if !connected || manager.get(*guild_id).is_some() {
+            continue;
+        }
+
+        // A fresh call isn't connected, leaving it tells Discord the bot
+        // is in no channel.
+        manager.get_or_insert(*guild_id);
+        match manager.remove(*guild_id).await {
+            Ok(()) => info!("Left stale voice channel in guild {}", guild_id),
+            Err(e) => warn!("Err leaving stale voice channel in {}: {:?}", guild_id, e),
+        }
+    }
+}
+
+/// Drops the saved queues no shard restored, of guilds the bot is no
+/// longer in.
+pub(crate) async fn forget_unrestored() {
+    if let Some(unrestored) = UNRESTORED.lock().await.as_mut() {
+        for guild_id in unrestored.keys() {
+            info!(
+                "Dropped the saved queue of guild {}, not in it anymore",
+                guild_id
+            );
+        }
+        unrestored.clear();
+    }
+}
+
+/// Rejoins the voice channels of `guilds` and enqueues the songs the last
+/// run saved for them.
+pub(crate) async fn restore(ctx: &Context, guilds: &[GuildId]) -> Result<()> {
+    let queues = {
+        let mut unrestored = UNRESTORED.lock().await;
+        if unrestored.is_none() {
+            *unrestored = Some(store::load::<HashMap<u64, SavedQueue>>(QUEUES).await?);
+        }
+        let unrestored = unrestored.get_or_insert_with(HashMap::new);
+
+        guilds
+            .iter()
+            .filter_map(|x| Some((x.0, unrestored.get(&x.0)?.clone())))
+            .collect::<Vec<_>>()
+    };
+
+    for (guild_id, saved) in queues {
+        if let Err(e) = restore_guild(ctx, guild_id, saved).await {
+            warn!("Err restoring queue of guild {}: {:?}", guild_id, e);
+        }
+        // Saved until now, in case the queue is saved while it is restored.
+        if let Some(unrestored) = UNRESTORED.lock().await.as_mut() {
+            unrestored.remove(&guild_id);
+        }
+    }
+
+    Ok(())
+}
+
+async fn restore_guild(ctx: &Context, guild_id: u64, saved: SavedQueue) -> Result<()> {
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    preflight::check(
+        &ctx.cache,
+        GuildId(guild_id),
+        ChannelId(saved.voice_channel),
+    )?;
+    let (handler_lock, success) = manager
+        .join(GuildId(guild_id), ChannelId(saved.voice_channel))
+        .await;
+    success.map_err(|e| anyhow!("Can not join voice channel: {:?}", e))?;
+
+    playback::start_session(ctx, guild_id).await;
+    reconnect::watch(ctx, guild_id, &handler_lock).await;
+    let text_channel = saved.text_channel.map(ChannelId);
+    {
+        let lock = playback::playback_lock(ctx).await;
+        let mut playback = lock.write().await;
+        let state = playback.entry(guild_id).or_default();
+        state.loop_mode = saved.loop_mode;
+        state.text_channel = text_channel;
+    }
+
+    if let (Some(channel), Some(first)) = (text_channel, saved.entries.first()) {
+        let lock = {
+            let read = ctx.data.read().await;
+            read.get::<SongVolume>()
+                .expect("Expected SongVolume in TypeMap.")
+                .clone()
+        };
+        lock.write().await.insert(channel.0, first.volume);
+    }
+
+    let restored =
+        enqueue_saved(ctx, guild_id, handler_lock, saved.entries, saved.position).await?;
+
+    if let Some(channel) = text_channel {
+        check_msg(
+            channel
+                .say(
+                    &ctx.http,
+                    format!("Restored {} songs after restart", restored),
+                )
+                .await,
+        );
+    }
+
+    Ok(())
+}
+
+/// Adds saved entries to the end of the queue. The first one continues
+/// from `position` if it starts playing right away. Like any request, they
+/// stop once the queue is full, and songs over `maxduration` are left out
+/// unless a DJ requested them.
+async fn enqueue_saved(
+    ctx: &Context,
+    guild_id: u64,
+    handler_lock: Arc<Mutex<Call>>,
+    entries: Vec<SavedEntry>,
+    position: Duration,
+) -> Result<usize> {
+    let ceiling = playback::volume_ceiling(ctx, guild_id).await;
+    let player = GuildPlayer::new(ctx, guild_id, handler_lock.clone()).await;
+    let quality = player.quality().await;
+    let filter = player.filter().await;
+    let settings = settings::get(ctx, guild_id).await;
+    let continues = handler_lock.lock().await.queue().is_empty();
+    let mut restored = 0;
+    for (i, entry) in entries.into_iter().enumerate() {
+        if queue_full(&handler_lock, &settings).await {
+            break;
+        }
+        // Sources stay lazy, nothing is fetched before a song comes up.
+        let input: Input = match source::restartable(&entry.url, quality, filter.clone()).await {
+            Ok(source) => source.into(),
+            Err(e) => {
+                warn!("Err restoring {}: {:?}", entry.url, e);
+                continue;
+            }
+        };
+        if settings.too_long(input.metadata.duration) {
+            let by_dj = match &entry.requester {
+                Some(requester) => {
+                    dj::is_member_dj(ctx, GuildId(guild_id), UserId(requester.id)).await
+                }
+                None => false,
+            };
+            if !by_dj {
+                continue;
+            }
+        }
+        let (track, first) = {
+            let mut handler = handler_lock.lock().await;
+            let first = handler.queue().is_empty();
+            (handler.enqueue_source(input), first)
+        };
+        let volume = limiter::cap(entry.volume, ceiling);
+        track.set_volume(volume)?;
+        if entry.entry_volume {
+            queue::set_entry_volume(&track, volume).await;
+        }
+        if let Some(requester) = entry.requester {
+            queue::set_requester(&track, requester).await;
+        }
+        player.attach(&track, first).await?;
+        if i == 0 && continues && !position.is_zero() {
+            track.seek_time(position)?;
+        }
+        restored += 1;
+    }
+
+    Ok(restored)
+}
+
+/// A queue put away while the bot hibernates, with its processes ended.
+pub(crate) struct Stowed {
+    position: Duration,
+    entries: Vec<SavedEntry>,
+}
+
+/// Drops the songs of the call and empties its queue without ending them,
+/// so none of the handlers run when a song ends, like FM refilling the
+/// queue or the listen counters.
+fn put_away(handler: &mut Call) {
+    // Stopping the driver drops the songs with their events, which ends
+    // their processes.
+    handler.stop();
+    handler.queue().modify_queue(|q| q.clear());
+}
+
+/// Saves the guild's queue and puts it away, which ends the processes of
+/// the playing song.
+pub(crate) async fn stow(handler_lock: &Arc<Mutex<Call>>) -> Option<Stowed> {
+    let tracks = handler_lock.lock().await.queue().current_queue();
+    let (position, entries) = save_tracks(&tracks).await;
+    if entries.is_empty() {
+        return None;
+    }
+    put_away(&mut *handler_lock.lock().await);
+
+    Some(Stowed { position, entries })
+}
+
+/// Queues a stowed queue again, its first song paused where it was.
+pub(crate) async fn unstow(
+    ctx: &Context,
+    guild_id: u64,
+    handler_lock: Arc<Mutex<Call>>,
+    stowed: Stowed,
+) -> Result<usize> {
+    let restored = enqueue_saved(
+        ctx,
+        guild_id,
+        handler_lock.clone(),
+        stowed.entries,
+        stowed.position,
+    )
+    .await?;
+    if let Some(current) = handler_lock.lock().await.queue().current() {
+        current.pause()?;
+    }
+
+    Ok(restored)
+}
+
+/// Adds songs from `urls` to the end of the queue, requested by
+/// `requester` at `volume`.
+pub(crate) async fn enqueue_urls(
+    ctx: &Context,
+    guild_id: u64,
+    handler_lock: Arc<Mutex<Call>>,
+    urls: Vec<String>,
+    volume: f32,
+    requester: Requester,
+) -> Result<usize> {
+    let entries = urls
+        .into_iter()
+        .map(|url| SavedEntry {
+            url,
+            volume,
+            entry_volume: false,
+            requester: Some(requester.clone()),
+        })
+        .collect();
+
+    enqueue_saved(ctx, guild_id, handler_lock, entries, Duration::ZERO).await
+}
+
+/// Saves the guild's queue to the user's profile and stops it. Returns the
+/// number of saved songs, a queue suspended before is replaced.
+pub(crate) async fn suspend(ctx: &Context, guild_id: u64, user: u64) -> Result<usize> {
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    let handler_lock = manager
+        .get(guild_id)
+        .ok_or_else(|| anyhow!("Not in a voice channel"))?;
+    let tracks = handler_lock.lock().await.queue().current_queue();
+
+    let (position, entries) = save_tracks(&tracks).await;
+    let saved = entries.len();
+    if saved == 0 {
+        return Ok(0);
+    }
+    let loop_mode = {
+        let lock = playback::playback_lock(ctx).await;
+        let playback = lock.read().await;
+        playback
+            .get(&guild_id)
+            .map(|x| x.loop_mode)
+            .unwrap_or_default()
+    };
+
+    let queue = SuspendedQueue {
+        loop_mode,
+        position,
+        entries,
+    };
+    store::update(SUSPENDED, |suspended: &mut HashMap<u64, SuspendedQueue>| {
+        suspended.insert(user, queue)
+    })
+    .await?;
+    put_away(&mut *handler_lock.lock().await);
+
+    Ok(saved)
+}
+
+/// Queues what the user suspended, in whatever guild they are now. `None`
+/// when they have nothing suspended. The saved queue is only forgotten once
+/// its songs are back, so a failed resume can be tried again.
+pub(crate) async fn resume(
+    ctx: &Context,
+    guild_id: u64,
+    user: u64,
+    handler_lock: Arc<Mutex<Call>>,
+) -> Result<Option<usize>> {
+    let mut suspended = store::load::<HashMap<u64, SuspendedQueue>>(SUSPENDED).await?;
+    let saved = match suspended.remove(&user) {
+        Some(saved) => saved,
+        None => return Ok(None),
+    };
+
+    if handler_lock.lock().await.queue().is_empty() {
+        let lock = playback::playback_lock(ctx).await;
+        let mut playback = lock.write().await;
+        playback.entry(guild_id).or_default().loop_mode = saved.loop_mode;
+    }
+    let restored =
+        enqueue_saved(ctx, guild_id, handler_lock, saved.entries, saved.position).await?;
+    store::update(SUSPENDED, |suspended: &mut HashMap<u64, SuspendedQueue>| {
+        suspended.remove(&user)
+    })
+    .await?;
+
+    Ok(Some(restored))
+}
+
+/// Saved playlists of every guild, by name.
+type Playlists = HashMap<u64, BTreeMap<String, Vec<SavedEntry>>>;
+
+/// Names are matched case-insensitively.
+fn playlist_name(name: &str) -> Result<String> {
+    let name = name.trim().to_lowercase();
+    if name.is_empty() || name.chars().count() > PLAYLIST_NAME_MAX {
+        bail!("Playlist name must be 1 to {} letters", PLAYLIST_NAME_MAX);
+    }
+
+    Ok(name)
+}
+
+/// Saves the guild's queue under `name`. A playlist of that name is only
+/// replaced when `replace`, as deleting one needs a DJ. Returns the number
+/// of saved songs.
+pub(crate) async fn save_playlist(
+    guild_id: u64,
+    name: &str,
+    handler_lock: Arc<Mutex<Call>>,
+    replace: bool,
+) -> Result<usize> {
+    let name = playlist_name(name)?;
+    let tracks = handler_lock.lock().await.queue().current_queue();
+    let (_, entries) = save_tracks(&tracks).await;
+    let saved = entries.len();
+    if saved == 0 {
+        return Ok(0);
+    }
+
+    let replaced = store::update(PLAYLISTS, |playlists: &mut Playlists| {
+        let playlists = playlists.entry(guild_id).or_default();
+        if playlists.contains_key(&name) && !replace {
+            return false;
+        }
+        playlists.insert(name.clone(), entries);
+
+        true
+    })
+    .await?;
+    if !replaced {
+        bail!("Only DJs can replace playlist {}", name);
+    }
+
+    Ok(saved)
+}
+
+/// Adds the playlist saved as `name` to the end of the queue, in random
+/// order when `shuffled`. Returns how many of its songs were added, `None`
+/// when the guild has no such playlist.
+pub(crate) async fn load_playlist(
+    ctx: &Context,
+    guild_id: u64,
+    name: &str,
+    handler_lock: Arc<Mutex<Call>>,
+    shuffled: bool,
+) -> Result<Option<(usize, usize)>> {
+    let name = playlist_name(name)?;
+    let mut playlists = store::load::<Playlists>(PLAYLISTS).await?;
+    let mut entries = match playlists.get_mut(&guild_id).and_then(|x| x.remove(&name)) {
+        Some(entries) => entries,
+        None => return Ok(None),
+    };
+    if shuffled {
+        entries.shuffle(&mut rand::thread_rng());
+    }
+
+    let total = entries.len();
+    let loaded = enqueue_saved(ctx, guild_id, handler_lock, entries, Duration::ZERO).await?;
+
+    Ok(Some((loaded, total)))
+}
+
+/// Names of the guild's saved playlists with how many songs they have.
+pub(crate) async fn playlists(guild_id: u64) -> Result<Vec<(String, usize)>> {
+    let mut playlists = store::load::<Playlists>(PLAYLISTS).await?;
+
+    Ok(playlists
+        .remove(&guild_id)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(name, entries)| (name, entries.len()))
+        .collect())
+}
+
+/// Whether there was a playlist `name` to delete.
+pub(crate) async fn delete_playlist(guild_id: u64, name: &str) -> Result<bool> {
+    let name = playlist_name(name)?;
+    store::update(PLAYLISTS, |playlists: &mut Playlists| {
+        playlists
+            .get_mut(&guild_id)
+            .and_then(|x| x.remove(&name))
+            .is_some()
+    })
+    .await
+}
+
+#[test]
+fn test_playlist_name() {
+    assert_eq!(playlist_name(" Friday Night ").unwrap(), "friday night");
+    assert!(playlist_name("").is_err());
+    assert!(playlist_name(&"a".repeat(PLAYLIST_NAME_MAX + 1)).is_err());
+}
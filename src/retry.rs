@@ -0,0 +1,132 @@
+//! "Retry" buttons on songs which failed to load for a passing reason, like
+//! a timeout or a server error of the service.
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use serenity::{
+    builder::CreateComponents, client::Context, model::application::component::ButtonStyle,
+    prelude::TypeMapKey,
+};
+use tokio::sync::RwLock;
+
+use crate::queue::Requester;
+
+/// Prefix of the custom id of retry buttons, followed by the retry id.
+pub(crate) const RETRY_BUTTON: &str = "retry:";
+
+/// Buttons older than this stop working.
+const RETRY_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A request which can be run again, custom ids are too short for URLs.
+/// It runs as first asked, whoever clicks the button.
+pub(crate) struct Retry {
+    pub url: String,
+    pub shuffled: bool,
+    pub requester: Requester,
+    /// Queue index the songs were to go to, the end when `None`.
+    pub position: Option<usize>,
+    /// How far into the song it was to start, for bookmarks.
+    pub start: Option<Duration>,
+    created: Instant,
+}
+
+pub(crate) struct PendingRetries;
+
+impl TypeMapKey for PendingRetries {
+    type Value = Arc<RwLock<HashMap<u64, Retry>>>;
+}
+
+async fn retries_lock(ctx: &Context) -> Arc<RwLock<HashMap<u64, Retry>>> {
+    let read = ctx.data.read().await;
+
+    read.get::<PendingRetries>()
+        .expect("Expected PendingRetries in TypeMap.")
+        .clone()
+}
+
+/// Whether trying again later may work: timeouts, dropped connections and
+/// server errors anywhere in the chain of causes.
+pub(crate) fn is_transient(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            return e.is_timeout()
+                || e.is_connect()
+                || e.status().is_some_and(|x| x.is_server_error());
+        }
+        if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+            return matches!(
+                e.kind(),
+                ErrorKind::TimedOut | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted
+            );
+        }
+
+        false
+    })
+}
+
+/// Keeps a request to be run again, returns the id for the button.
+pub(crate) async fn remember(
+    ctx: &Context,
+    url: String,
+    shuffled: bool,
+    requester: Requester,
+    position: Option<usize>,
+    start: Option<Duration>,
+) -> u64 {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let lock = retries_lock(ctx).await;
+    let mut retries = lock.write().await;
+    retries.retain(|_, x| x.created.elapsed() < RETRY_TIMEOUT);
+    retries.insert(
+        id,
+        Retry {
+            url,
+            shuffled,
+            requester,
+            position,
+            start,
+            created: Instant::now(),
+        },
+    );
+
+    id
+}
+
+/// Takes the request back out, each button works once.
+pub(crate) async fn take(ctx: &Context, id: &str) -> Option<Retry> {
+    let id = id.parse::<u64>().ok()?;
+    let lock = retries_lock(ctx).await;
+    let mut retries = lock.write().await;
+
+    retries
+        .remove(&id)
+        .filter(|x| x.created.elapsed() < RETRY_TIMEOUT)
+}
+
+pub(crate) fn button(c: &mut CreateComponents, id: u64) -> &mut CreateComponents {
+    c.create_action_row(|r| {
+        r.create_button(|b| {
+            b.custom_id(format!("{}{}", RETRY_BUTTON, id))
+                .label("Retry")
+                .style(ButtonStyle::Primary)
+        })
+    })
+}
+
+#[test]
+fn test_is_transient() {
+    let timeout = std::io::Error::new(ErrorKind::TimedOut, "timed out");
+    assert!(is_transient(
+        &anyhow::Error::new(timeout).context("No source could play")
+    ));
+    assert!(!is_transient(&anyhow::anyhow!("Can not get song url!")));
+}
@@ -0,0 +1,115 @@
+//! `~scrape`: collects the songs shared in a channel, so a song sharing
+//! channel can be played as a playlist.
+use std::time::Duration;
+
+use anyhow::Result;
+use serenity::{
+    client::Context,
+    model::id::{ChannelId, MessageId},
+};
+
+use crate::source;
+
+/// Most messages scanned by one `~scrape`.
+pub(crate) const SCRAPE_MAX: u64 = 500;
+/// Messages fetched by one request, the most Discord returns.
+const PAGE_SIZE: u64 = 100;
+/// Pause between message requests, to stay clear of the rate limit.
+const PAGE_DELAY: Duration = Duration::from_secs(1);
+
+/// Links that are songs of the sites youtube-dl plays. Other providers
+/// match their own links.
+const YTDL_SONGS: &[&str] = &[
+    "youtube.com/watch",
+    "youtu.be/",
+    "music.youtube.com/watch",
+    "bandcamp.com/track/",
+];
+
+/// Whether `url` is a single song the bot can play, playlists and other
+/// links are left out.
+fn is_song(url: &str) -> bool {
+    let provider = source::provider(url);
+    if provider.is_playlist(url) {
+        return false;
+    }
+
+    provider.name() != "youtube-dl" || YTDL_SONGS.iter().any(|x| url.contains(x))
+}
+
+/// Song links in a message, in the order they were written.
+fn song_urls(content: &str) -> Vec<String> {
+    content
+        .split_whitespace()
+        // Links in <> have no embed.
+        .map(|x| {
+            x.trim_start_matches('<')
+                .trim_end_matches(['>', ')', ',', '.'])
+        })
+        .filter(|x| x.starts_with("https://") || x.starts_with("http://"))
+        .filter(|x| is_song(x))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Song links of the last `count` messages in `channel` up to `from`, or
+/// the latest ones. The oldest come first and a song shared again is only
+/// kept once.
+pub(crate) async fn scrape(
+    ctx: &Context,
+    channel: ChannelId,
+    from: Option<MessageId>,
+    count: u64,
+) -> Result<Vec<String>> {
+    let mut messages = vec![];
+    let mut before = None;
+    if let Some(from) = from {
+        messages.push(channel.message(&ctx.http, from).await?);
+        before = Some(from);
+    }
+
+    while (messages.len() as u64) < count {
+        if before.is_some() {
+            tokio::time::sleep(PAGE_DELAY).await;
+        }
+        let limit = PAGE_SIZE.min(count - messages.len() as u64);
+        let page = channel
+            .messages(&ctx.http, |x| match before {
+                Some(before) => x.before(before).limit(limit),
+                None => x.limit(limit),
+            })
+            .await?;
+        let last = match page.last() {
+            Some(last) => last.id,
+            None => break,
+        };
+        let done = (page.len() as u64) < limit;
+        messages.extend(page);
+        if done {
+            break;
+        }
+        before = Some(last);
+    }
+
+    let mut urls: Vec<String> = vec![];
+    for message in messages.iter().rev() {
+        for url in song_urls(&message.content) {
+            if !urls.contains(&url) {
+                urls.push(url);
+            }
+        }
+    }
+
+    Ok(urls)
+}
+
+#[test]
+fn test_song_urls() {
+    assert_eq!(
+        song_urls(
+            "listen <https://youtu.be/abc>, and https://music.163.com/song?id=1 \
+             https://example.com/cat.png https://www.youtube.com/playlist?list=x"
+        ),
+        ["https://youtu.be/abc", "https://music.163.com/song?id=1"]
+    );
+}
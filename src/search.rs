@@ -0,0 +1,62 @@
+use anyhow::Result;
+use songbird::input::Metadata;
+use tracing::warn;
+
+use crate::{neteaseapi, ytdl};
+
+/// Searches Netease first and falls back to YouTube when Netease finds
+/// nothing or is unreachable.
+///
+/// Every result carries the URL to play in `source_url`.
+pub(crate) async fn search(keywords: &str, limit: usize) -> Result<Vec<Metadata>> {
+    match neteaseapi::netease_search(keywords, limit).await {
+        Ok(songs) if !songs.is_empty() => return Ok(songs),
+        Ok(_) => {}
+        Err(e) => warn!("Netease search failed: {:?}", e),
+    }
+
+    search_youtube(keywords, limit).await
+}
+
+pub(crate) async fn search_youtube(keywords: &str, limit: usize) -> Result<Vec<Metadata>> {
+    ytdl::flat_playlist(&format!("ytsearch{}:{}", limit, keywords)).await
+}
+
+/// Whether the title or artist of `song` has every keyword, ignoring case.
+fn matches(song: &Metadata, keywords: &str) -> bool {
+    let text = format!(
+        "{} {}",
+        song.title.as_deref().unwrap_or_default(),
+        song.artist.as_deref().unwrap_or_default()
+    )
+    .to_lowercase();
+
+    keywords
+        .to_lowercase()
+        .split_whitespace()
+        .all(|x| text.contains(x))
+}
+
+/// Songs of the Netease cloud disk matching `keywords`, with their
+/// 1-based position in it.
+pub(crate) async fn search_cloud(keywords: &str, max: usize) -> Result<Vec<(usize, Metadata)>> {
+    Ok(neteaseapi::netease_cloud(max)
+        .await?
+        .into_iter()
+        .enumerate()
+        .filter(|(_, x)| matches(x, keywords))
+        .map(|(i, x)| (i + 1, x))
+        .collect())
+}
+
+#[test]
+fn test_matches() {
+    let song = Metadata {
+        title: Some("Lemon".to_string()),
+        artist: Some("米津玄師".to_string()),
+        ..Default::default()
+    };
+
+    assert!(matches(&song, "lemon 米津"));
+    assert!(!matches(&song, "lemon aimer"));
+}
@@ -0,0 +1,113 @@
+//! Keeps the audio of the playing song in memory, so seeking back in it
+//! doesn't fetch the song again, which is slow on poor connections.
+//! Off unless `SEEK_CACHE=memory`: decoded audio takes about 23 MB a
+//! minute, so songs longer than `SEEK_CACHE_MAX_MINUTES` (10 by default) are
+//! still fetched again, and `LOW_MEMORY=1` turns it off.
+use std::{convert::TryFrom, env, time::Duration};
+
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use songbird::input::{
+    cached::Memory,
+    error::Result,
+    restartable::{Restart, Restartable},
+    Codec, Container, Input, Metadata,
+};
+
+use crate::{
+    ffmpeg::{Effects, FilterHandle},
+    gateway,
+};
+
+const DEFAULT_MAX_MINUTES: u64 = 10;
+
+lazy_static! {
+    static ref ENABLED: bool = env::var("SEEK_CACHE")
+        .map(|x| x == "memory")
+        .unwrap_or(false);
+    static ref MAX_DURATION: Duration = Duration::from_secs(
+        env::var("SEEK_CACHE_MAX_MINUTES")
+            .ok()
+            .and_then(|x| x.parse().ok())
+            .unwrap_or(DEFAULT_MAX_MINUTES)
+            * 60
+    );
+}
+
+/// Whether a song of `duration` is kept, songs of unknown length may be
+/// endless streams.
+fn fits(duration: Option<Duration>, max: Duration) -> bool {
+    duration.is_some_and(|x| x <= max)
+}
+
+/// Restarts from memory when it can, from `inner` when it can't.
+struct Cached<R> {
+    inner: R,
+    filter: FilterHandle,
+    /// The audio so far, with the effects it was made with.
+    cache: Option<(Memory, Effects)>,
+}
+
+#[async_trait]
+impl<R: Restart + Send> Restart for Cached<R> {
+    async fn call_restart(&mut self, time: Option<Duration>) -> Result<Input> {
+        let effects = self.filter.get();
+        match &self.cache {
+            // Changed effects need a new ffmpeg.
+            Some((memory, cached_with)) if *cached_with == effects => {
+                let mut input = Input::try_from(memory.new_handle())?;
+                if let Some(time) = time {
+                    input.seek_time(time);
+                }
+
+                return Ok(input);
+            }
+            _ => self.cache = None,
+        }
+
+        let input = self.inner.call_restart(time).await?;
+        // Only a cache from the start can be seeked in.
+        let from_start = time.unwrap_or_default().is_zero();
+        if !from_start || !fits(input.metadata.duration, *MAX_DURATION) {
+            return Ok(input);
+        }
+        let memory = Memory::new(input)?;
+        let input = Input::try_from(memory.new_handle())?;
+        self.cache = Some((memory, effects));
+
+        Ok(input)
+    }
+
+    async fn lazy_init(&mut self) -> Result<(Option<Metadata>, Codec, Container)> {
+        self.inner.lazy_init().await
+    }
+}
+
+/// Like `Restartable::new`, with the seek cache when it is turned on.
+/// `filter` has to be the one `restarter` reads its effects from.
+pub(crate) async fn restartable<R: Restart + Send + 'static>(
+    restarter: R,
+    filter: FilterHandle,
+    lazy: bool,
+) -> Result<Restartable> {
+    if *ENABLED && !gateway::low_memory() {
+        let cached = Cached {
+            inner: restarter,
+            filter,
+            cache: None,
+        };
+        Restartable::new(cached, lazy).await
+    } else {
+        Restartable::new(restarter, lazy).await
+    }
+}
+
+#[test]
+fn test_fits() {
+    let max = Duration::from_secs(10 * 60);
+
+    assert!(fits(Some(Duration::from_secs(3 * 60)), max));
+    assert!(fits(Some(max), max));
+    assert!(!fits(Some(Duration::from_secs(60 * 60)), max));
+    assert!(!fits(None, max));
+}
@@ -0,0 +1,142 @@
+//! `~select`: acting on several entries of the last `~list` a user saw.
+//!
+//! Positions refer to the queue as it was listed, so entries which moved
+//! since are still found, and ones which left the queue are skipped.
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Result};
+use serenity::{client::Context, model::id::GuildId, prelude::TypeMapKey};
+use songbird::tracks::{TrackHandle, TrackQueue};
+use tokio::sync::RwLock;
+
+/// How long a listing can be selected from.
+const SELECTION_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SelectAction {
+    Remove,
+    Boost,
+    Pin,
+}
+
+impl FromStr for SelectAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "remove" => Ok(SelectAction::Remove),
+            "boost" => Ok(SelectAction::Boost),
+            "pin" => Ok(SelectAction::Pin),
+            _ => Err(anyhow!("Unknown action: {}, one of remove, boost, pin", s)),
+        }
+    }
+}
+
+/// The queue as a user last saw it, index 0 is position 1.
+pub(crate) struct Selection {
+    tracks: Vec<TrackHandle>,
+    listed: Instant,
+}
+
+pub(crate) struct Selections;
+
+impl TypeMapKey for Selections {
+    type Value = Arc<RwLock<HashMap<(u64, u64), Selection>>>;
+}
+
+async fn selections_lock(ctx: &Context) -> Arc<RwLock<HashMap<(u64, u64), Selection>>> {
+    let read = ctx.data.read().await;
+
+    read.get::<Selections>()
+        .expect("Expected Selections in TypeMap.")
+        .clone()
+}
+
+/// Remembers the queue which was just listed to `user`.
+pub(crate) async fn remember(ctx: &Context, guild_id: GuildId, user: u64) {
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    let tracks = match manager.get(guild_id) {
+        Some(handler_lock) => handler_lock.lock().await.queue().current_queue(),
+        None => return,
+    };
+
+    let lock = selections_lock(ctx).await;
+    let mut selections = lock.write().await;
+    selections.retain(|_, x| x.listed.elapsed() < SELECTION_TIMEOUT);
+    selections.insert(
+        (guild_id.0, user),
+        Selection {
+            tracks,
+            listed: Instant::now(),
+        },
+    );
+}
+
+/// Forgets every listed queue, they would keep their tracks around.
+pub(crate) async fn forget_all(ctx: &Context) {
+    selections_lock(ctx).await.write().await.clear();
+}
+
+/// 1-based positions out of `len` listed ones to 0-based indexes, in the
+/// order given and without repeats. The playing song can't be selected.
+fn pick(len: usize, positions: &[usize]) -> Result<Vec<usize>> {
+    let mut picked = vec![];
+    for &position in positions {
+        if position < 2 || position > len {
+            bail!("Position must 2 to {}", len);
+        }
+        if !picked.contains(&(position - 1)) {
+            picked.push(position - 1);
+        }
+    }
+
+    Ok(picked)
+}
+
+/// The selected entries which are still queued, with where they are now.
+pub(crate) async fn resolve(
+    ctx: &Context,
+    guild_id: GuildId,
+    user: u64,
+    positions: &[usize],
+    queue: &TrackQueue,
+) -> Result<Vec<TrackHandle>> {
+    let lock = selections_lock(ctx).await;
+    let selections = lock.read().await;
+    let selection = selections
+        .get(&(guild_id.0, user))
+        .filter(|x| x.listed.elapsed() < SELECTION_TIMEOUT)
+        .ok_or_else(|| anyhow!("Use ~list first, then select positions from it"))?;
+
+    let current = queue.current_queue();
+    Ok(pick(selection.tracks.len(), positions)?
+        .into_iter()
+        .map(|i| &selection.tracks[i])
+        .filter_map(|x| current.iter().find(|y| y.uuid() == x.uuid()))
+        .cloned()
+        .collect())
+}
+
+/// Index of the entry in the queue now.
+pub(crate) fn position(queue: &TrackQueue, track: &TrackHandle) -> Option<usize> {
+    queue
+        .current_queue()
+        .iter()
+        .position(|x| x.uuid() == track.uuid())
+}
+
+#[test]
+fn test_pick() {
+    assert_eq!(pick(5, &[3, 5, 3]).unwrap(), vec![2, 4]);
+    assert!(pick(5, &[1]).is_err());
+    assert!(pick(5, &[6]).is_err());
+    assert_eq!(pick(5, &[]).unwrap(), Vec::<usize>::new());
+}
@@ -0,0 +1,167 @@
+//! `bibicord --selftest`: a smoke test of the whole audio path for self
+//! hosters and CI. The bot joins `SELFTEST_CHANNEL`, queues two tones made
+//! by ffmpeg, changes the volume, skips and leaves again. It exits with 0
+//! when all of that worked and 1 when anything didn't.
+use std::{env, time::Duration};
+
+use anyhow::{anyhow, bail, ensure, Context as _, Result};
+use serenity::{
+    async_trait,
+    client::{Client, Context, EventHandler},
+    model::{
+        gateway::GatewayIntents,
+        id::{ChannelId, GuildId},
+    },
+};
+use songbird::{
+    input::Metadata,
+    tracks::{PlayMode, TrackHandle},
+    SerenityInit,
+};
+use tracing::info;
+
+use crate::{
+    control,
+    ffmpeg::{self, Effects, Pipeline},
+    playback::GuildPlayer,
+    queue::Requester,
+};
+
+/// Longest the whole test may take.
+const TIMEOUT: Duration = Duration::from_secs(60);
+/// Longest a step may wait for playback to get somewhere.
+const STEP_TIMEOUT: Duration = Duration::from_secs(15);
+const POLL: Duration = Duration::from_millis(250);
+/// Length of the test tones.
+const TONE_SECS: u64 = 10;
+
+struct SelfTest {
+    channel: ChannelId,
+}
+
+#[async_trait]
+impl EventHandler for SelfTest {
+    async fn cache_ready(&self, ctx: Context, _guilds: Vec<GuildId>) {
+        let code = match run(&ctx, self.channel).await {
+            Ok(()) => {
+                info!("Self test passed");
+                0
+            }
+            Err(e) => {
+                eprintln!("Self test failed: {:?}", e);
+                1
+            }
+        };
+        std::process::exit(code);
+    }
+}
+
+/// A sine tone of `frequency`, ffmpeg makes it so no file has to ship.
+fn tone(frequency: u32) -> Result<songbird::input::Input> {
+    let source = format!("sine=frequency={}:duration={}", frequency, TONE_SECS);
+    let child = Pipeline::new(&source, Effects::default())
+        .input_args(&["-f", "lavfi"])
+        .spawn()
+        .context("Starting ffmpeg")?;
+    let metadata = Metadata {
+        title: Some(format!("{} Hz", frequency)),
+        duration: Some(Duration::from_secs(TONE_SECS)),
+        ..Default::default()
+    };
+
+    Ok(ffmpeg::input(vec![child], metadata, None)?)
+}
+
+/// Waits until `track` played for a second.
+async fn wait_playing(track: &TrackHandle) -> Result<()> {
+    tokio::time::timeout(STEP_TIMEOUT, async {
+        loop {
+            let info = track.get_info().await?;
+            if info.playing == PlayMode::Play && info.position >= Duration::from_secs(1) {
+                return Ok(());
+            }
+            tokio::time::sleep(POLL).await;
+        }
+    })
+    .await
+    .map_err(|_| anyhow!("{:?} did not start playing", track.metadata().title))?
+}
+
+async fn run(ctx: &Context, channel: ChannelId) -> Result<()> {
+    let guild_id = ctx
+        .cache
+        .guild_channel(channel)
+        .map(|x| x.guild_id)
+        .ok_or_else(|| anyhow!("Can not see voice channel {}", channel))?;
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+
+    let (handler_lock, joined) = manager.join(guild_id, channel).await;
+    joined.context("Joining the voice channel")?;
+    info!("Joined {}", channel);
+
+    // The tones go through the same hooks as songs queued by a command.
+    let player = GuildPlayer::new(ctx, guild_id.0, handler_lock.clone()).await;
+    let requester = Requester {
+        id: ctx.cache.current_user_id().0,
+        name: "self test".to_string(),
+        avatar: None,
+    };
+    let first = crate::add_track(&player, tone(440)?, None, 1.0, requester.clone()).await?;
+    let second = crate::add_track(&player, tone(660)?, None, 1.0, requester).await?;
+    let queued = handler_lock.lock().await.queue().len();
+    ensure!(queued == 2, "Queued {} tones instead of 2", queued);
+    wait_playing(&first).await?;
+    info!("Playing the first tone");
+
+    control::set_volume(ctx, guild_id, None, 0.5).await?;
+    let volume = first.get_info().await?.volume;
+    ensure!(
+        (volume - 0.5).abs() < f32::EPSILON,
+        "Volume is {} after setting 0.5",
+        volume
+    );
+    info!("Volume changed");
+
+    ensure!(control::skip(ctx, guild_id).await, "Nothing to skip");
+    wait_playing(&second).await?;
+    let current = handler_lock.lock().await.queue().current();
+    if current.map(|x| x.uuid()) != Some(second.uuid()) {
+        bail!("The second tone is not the current one after skipping");
+    }
+    info!("Skipped to the second tone");
+
+    handler_lock.lock().await.queue().stop();
+    manager.remove(guild_id).await.context("Leaving")?;
+    info!("Left {}", channel);
+
+    Ok(())
+}
+
+/// Runs the self test with the bot's token and exits.
+pub(crate) async fn main(token: &str) -> ! {
+    let channel = match env::var("SELFTEST_CHANNEL").map(|x| x.parse::<u64>()) {
+        Ok(Ok(channel)) => ChannelId(channel),
+        _ => {
+            eprintln!("SELFTEST_CHANNEL must be the id of a voice channel for the self test");
+            std::process::exit(1);
+        }
+    };
+    let intents = GatewayIntents::GUILDS | GatewayIntents::GUILD_VOICE_STATES;
+    let mut client = Client::builder(token, intents)
+        .event_handler(SelfTest { channel })
+        .register_songbird()
+        .await
+        .expect("Err creating client");
+    crate::insert_data(&mut *client.data.write().await).await;
+
+    // The handler exits once done, getting here means it never was.
+    match tokio::time::timeout(TIMEOUT, client.start()).await {
+        Ok(Ok(())) => eprintln!("Self test failed: the client stopped"),
+        Ok(Err(e)) => eprintln!("Self test failed: {:?}", e),
+        Err(_) => eprintln!("Self test failed: timed out after {:?}", TIMEOUT),
+    }
+    std::process::exit(1);
+}
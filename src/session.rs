@@ -0,0 +1,378 @@
+//! What happened between joining and leaving a voice channel, summed up
+//! when the bot leaves.
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+use serenity::{
+    async_trait,
+    http::Http,
+    model::id::{ChannelId, MessageId},
+};
+use songbird::{tracks::TrackHandle, Event, EventContext, EventHandler as VoiceEventHandler};
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use tracing::{info, warn};
+
+use crate::{
+    audio_cache, check_msg, duration_formatter, history, metrics, playback::GuildPlayer, queue,
+    track_name,
+};
+
+const LOG_THREAD_NAME: &str = "Listening session";
+/// Longest message Discord takes.
+const MESSAGE_MAX: usize = 2000;
+
+lazy_static! {
+    static ref POSTING: std::sync::Mutex<HashMap<u64, Arc<Mutex<()>>>> =
+        std::sync::Mutex::new(HashMap::new());
+}
+
+/// Held while a guild's log thread or tracklist is posted, so a session
+/// gets one of each. Not the playback lock, which every guild needs.
+async fn posting(guild_id: u64) -> OwnedMutexGuard<()> {
+    let lock = POSTING.lock().unwrap().entry(guild_id).or_default().clone();
+
+    lock.lock_owned().await
+}
+
+/// Where the session's log thread or tracklist is.
+enum Target<T> {
+    Existing(T),
+    /// Not posted yet, it goes in this channel.
+    Post(ChannelId),
+}
+
+pub(crate) struct Session {
+    pub started: Instant,
+    /// Requester of every played track.
+    played: Vec<Option<String>>,
+    skipped: HashMap<String, u32>,
+    /// Thread the session is logged to, when the guild keeps a log.
+    pub log_thread: Option<ChannelId>,
+    /// Unix timestamp and name of every played track.
+    tracks: Vec<(u64, String)>,
+    /// Pinned message listing `tracks`, when the guild keeps one.
+    pub tracklist: Option<(ChannelId, MessageId)>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            played: vec![],
+            skipped: HashMap::new(),
+            log_thread: None,
+            tracks: vec![],
+            tracklist: None,
+        }
+    }
+}
+
+impl Session {
+    pub(crate) fn record_play(&mut self, requester: Option<String>) {
+        self.played.push(requester);
+    }
+
+    pub(crate) fn record_skip(&mut self, title: String) {
+        *self.skipped.entry(title).or_insert(0) += 1;
+    }
+
+    pub(crate) fn played(&self) -> usize {
+        self.played.len()
+    }
+
+    /// Who requested the most played songs, with their count.
+    pub(crate) fn top_requester(&self) -> Option<(&str, u32)> {
+        let mut counts = HashMap::new();
+        for name in self.played.iter().filter_map(|x| x.as_deref()) {
+            *counts.entry(name).or_insert(0) += 1;
+        }
+
+        max_count(counts)
+    }
+
+    pub(crate) fn most_skipped(&self) -> Option<(&str, u32)> {
+        max_count(self.skipped.iter().map(|(title, n)| (title.as_str(), *n)))
+    }
+}
+
+/// Ties go to the name which sorts first so the summary is stable.
+fn max_count<'a>(counts: impl IntoIterator<Item = (&'a str, u32)>) -> Option<(&'a str, u32)> {
+    counts
+        .into_iter()
+        .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(a.0)))
+}
+
+/// Adds a track to the session and the guild's plays once it starts
+/// playing.
+pub(crate) struct Recorder {
+    pub player: GuildPlayer,
+}
+
+impl Recorder {
+    pub(crate) async fn record(&self, track: &TrackHandle) {
+        let requester = queue::requester(track).await.map(|x| x.name);
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or_default();
+        {
+            let mut playback = self.player.playback.write().await;
+            let session = playback
+                .entry(self.player.guild_id)
+                .or_default()
+                .session
+                .get_or_insert_with(Session::default);
+            session.record_play(requester);
+            session.tracks.push((started, track_name(track.metadata())));
+        }
+
+        history::record_play(&self.player.played, self.player.guild_id, track).await;
+        audio_cache::played(track.metadata()).await;
+        metrics::track_played();
+        info!(
+            guild_id = self.player.guild_id,
+            url = track.metadata().source_url.as_deref().unwrap_or_default(),
+            "Playing {}",
+            track_name(track.metadata())
+        );
+        log(&self.player, format!("▶ {}", track_name(track.metadata()))).await;
+        update_tracklist(&self.player).await;
+    }
+}
+
+#[async_trait]
+impl VoiceEventHandler for Recorder {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if let EventContext::Track(&[(_, track)]) = ctx {
+            self.record(track).await;
+        }
+
+        // Resuming after a pause fires `Play` again, count each track once.
+        Some(Event::Cancel)
+    }
+}
+
+/// Logs tracks which actually played once they end or are skipped.
+pub(crate) struct EndLogger {
+    pub player: GuildPlayer,
+}
+
+#[async_trait]
+impl VoiceEventHandler for EndLogger {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if let EventContext::Track(&[(state, track)]) = ctx {
+            if !state.play_time.is_zero() {
+                let text = format!(
+                    "⏹ {} ({})",
+                    track_name(track.metadata()),
+                    duration_formatter(&state.play_time)
+                );
+                log(&self.player, text).await;
+            }
+        }
+
+        None
+    }
+}
+
+/// Posts to the session's log thread, which is started under the
+/// announcement channel on first use. Nothing happens unless the guild
+/// turned the log on.
+pub(crate) async fn log(player: &GuildPlayer, text: String) {
+    let mut target = log_target(player).await;
+    if let Some(Target::Post(_)) = target {
+        let _posting = posting(player.guild_id).await;
+        // Unless it was started meanwhile.
+        target = log_target(player).await;
+        if let Some(Target::Post(channel)) = target {
+            target = match start_log(&player.http, channel).await {
+                Ok(thread) => {
+                    let mut playback = player.playback.write().await;
+                    let state = playback.entry(player.guild_id).or_default();
+                    let session = state.session.get_or_insert_with(Session::default);
+                    Some(Target::Existing(*session.log_thread.insert(thread)))
+                }
+                Err(e) => {
+                    warn!("Err starting session log: {:?}", e);
+                    return;
+                }
+            };
+        }
+    }
+
+    if let Some(Target::Existing(thread)) = target {
+        check_msg(thread.say(&player.http, text).await);
+    }
+}
+
+/// The session's log thread, or the channel to start it under when there
+/// is none yet. `None` unless the guild keeps a log.
+async fn log_target(player: &GuildPlayer) -> Option<Target<ChannelId>> {
+    let playback = player.playback.read().await;
+    let state = playback.get(&player.guild_id)?;
+    let channel = state.text_channel.filter(|_| state.session_log)?;
+
+    Some(match state.session.as_ref().and_then(|x| x.log_thread) {
+        Some(thread) => Target::Existing(thread),
+        None => Target::Post(channel),
+    })
+}
+
+async fn start_log(http: &Http, channel: ChannelId) -> Result<ChannelId> {
+    let anchor = channel.say(http, "Session log").await?;
+    let thread = channel
+        .create_public_thread(http, anchor.id, |t| {
+            t.name(LOG_THREAD_NAME).auto_archive_duration(1440)
+        })
+        .await?;
+
+    Ok(thread.id)
+}
+
+/// Closes the log of a finished session and unpins its tracklist.
+pub(crate) async fn archive_log(http: &Http, session: &Session) {
+    if let Some(thread) = session.log_thread {
+        if let Err(e) = thread.edit_thread(http, |t| t.archived(true)).await {
+            warn!("Err archiving session log: {:?}", e);
+        }
+    }
+    if let Some((channel, message)) = session.tracklist {
+        if let Err(e) = channel.unpin(http, message).await {
+            warn!("Err unpinning tracklist: {:?}", e);
+        }
+    }
+}
+
+/// The tracklist as it fits in a message, the oldest tracks are left out
+/// of long sessions.
+fn render_tracklist(tracks: &[(u64, String)], max_len: usize) -> String {
+    let header = "🎶 Played this session:\n";
+    // Room for the line saying how many were left out.
+    let mut len = header.len() + 32;
+    let mut lines = vec![];
+    for (started, name) in tracks.iter().rev() {
+        let line = format!("<t:{}:t> {}\n", started, name);
+        if len + line.len() > max_len {
+            break;
+        }
+        len += line.len();
+        lines.push(line);
+    }
+
+    let mut s = header.to_string();
+    if lines.len() < tracks.len() {
+        s.push_str(&format!(
+            "...{} earlier songs\n",
+            tracks.len() - lines.len()
+        ));
+    }
+    s.extend(lines.into_iter().rev());
+
+    s
+}
+
+/// Posts and pins the session's tracklist in the announcement channel, or
+/// edits it with the latest track. Nothing happens unless the guild turned
+/// the tracklist on.
+pub(crate) async fn update_tracklist(player: &GuildPlayer) {
+    let mut target = tracklist_target(player).await;
+    if let Some((Target::Post(_), _)) = target {
+        let _posting = posting(player.guild_id).await;
+        // Unless it was posted meanwhile.
+        target = tracklist_target(player).await;
+        if let Some((Target::Post(channel), content)) = target {
+            match start_tracklist(&player.http, channel, content).await {
+                Ok(message) => {
+                    let mut playback = player.playback.write().await;
+                    let state = playback.entry(player.guild_id).or_default();
+                    state.session.get_or_insert_with(Session::default).tracklist = Some(message);
+                }
+                Err(e) => warn!("Err posting tracklist: {:?}", e),
+            }
+
+            return;
+        }
+    }
+
+    let ((channel, message), content) = match target {
+        Some((Target::Existing(message), content)) => (message, content),
+        _ => return,
+    };
+    if let Err(e) = channel
+        .edit_message(&player.http, message, |m| m.content(content))
+        .await
+    {
+        warn!("Err updating tracklist: {:?}", e);
+    }
+}
+
+/// The session's tracklist message, or the channel to post it in when
+/// there is none yet, with what it should say. `None` unless the guild
+/// keeps a tracklist.
+async fn tracklist_target(
+    player: &GuildPlayer,
+) -> Option<(Target<(ChannelId, MessageId)>, String)> {
+    let playback = player.playback.read().await;
+    let state = playback.get(&player.guild_id)?;
+    let channel = state.text_channel.filter(|_| state.tracklist)?;
+    let session = state.session.as_ref();
+    let tracks = session.map(|x| x.tracks.as_slice()).unwrap_or_default();
+
+    let target = match session.and_then(|x| x.tracklist) {
+        Some(message) => Target::Existing(message),
+        None => Target::Post(channel),
+    };
+
+    Some((target, render_tracklist(tracks, MESSAGE_MAX)))
+}
+
+async fn start_tracklist(
+    http: &Http,
+    channel: ChannelId,
+    content: String,
+) -> Result<(ChannelId, MessageId)> {
+    let message = channel.say(http, content).await?;
+    // Still worth editing without the Manage Messages permission.
+    if let Err(e) = message.pin(http).await {
+        warn!("Err pinning tracklist: {:?}", e);
+    }
+
+    Ok((channel, message.id))
+}
+
+#[test]
+fn test_session_summary() {
+    let mut session = Session::default();
+    session.record_play(Some("bob".to_string()));
+    session.record_play(Some("alice".to_string()));
+    session.record_play(Some("bob".to_string()));
+    session.record_play(None);
+    session.record_skip("b".to_string());
+    session.record_skip("c".to_string());
+    session.record_skip("b".to_string());
+
+    assert_eq!(session.played(), 4);
+    assert_eq!(session.top_requester(), Some(("bob", 2)));
+    assert_eq!(session.most_skipped(), Some(("b", 2)));
+    assert_eq!(Session::default().top_requester(), None);
+}
+
+#[test]
+fn test_render_tracklist() {
+    let tracks = (0..100)
+        .map(|i| (i, format!("song {}", i)))
+        .collect::<Vec<_>>();
+
+    let s = render_tracklist(&tracks[..2], MESSAGE_MAX);
+    assert!(s.ends_with("<t:0:t> song 0\n<t:1:t> song 1\n"));
+
+    let s = render_tracklist(&tracks, 500);
+    assert!(s.len() <= 500);
+    assert!(s.contains("earlier songs"));
+    assert!(s.ends_with("<t:99:t> song 99\n"));
+}
@@ -0,0 +1,201 @@
+//! Queues as files, so a setlist can be shared between servers: `~export`
+//! uploads the queue as JSON or M3U, `~import` queues such a file again,
+//! attached or from a paste site.
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use songbird::input::Metadata;
+
+use crate::track_name;
+
+/// Longest file `~import` reads.
+pub(crate) const MAX_BYTES: u64 = 1_000_000;
+
+#[derive(Serialize, Deserialize)]
+struct Setlist {
+    entries: Vec<SetlistEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SetlistEntry {
+    url: String,
+    title: String,
+    /// Seconds, missing for streams.
+    #[serde(default)]
+    duration: Option<u64>,
+}
+
+impl SetlistEntry {
+    fn from_metadata(metadata: &Metadata) -> Option<Self> {
+        Some(Self {
+            url: metadata.source_url.clone()?,
+            title: track_name(metadata),
+            duration: metadata.duration.map(|x| x.as_secs()),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Format {
+    Json,
+    M3u,
+}
+
+impl Format {
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::M3u => "m3u",
+        }
+    }
+}
+
+/// The songs of a queue in `format`, songs without a URL are left out.
+pub(crate) fn export(songs: &[Metadata], format: Format) -> Result<String> {
+    let entries = songs
+        .iter()
+        .filter_map(SetlistEntry::from_metadata)
+        .collect::<Vec<_>>();
+
+    match format {
+        Format::Json => Ok(serde_json::to_string_pretty(&Setlist { entries })?),
+        Format::M3u => {
+            let mut s = "#EXTM3U\n".to_string();
+            for entry in entries {
+                let duration = entry.duration.map_or(-1, |x| x as i64);
+                s.push_str(&format!(
+                    "#EXTINF:{},{}\n{}\n",
+                    duration,
+                    entry.title.replace('\n', " "),
+                    entry.url
+                ));
+            }
+
+            Ok(s)
+        }
+    }
+}
+
+/// URLs of the songs in an exported file, either format. Only web links
+/// are taken, files from another bot's disk would not play here anyway.
+pub(crate) fn import(text: &str) -> Result<Vec<String>> {
+    let text = text.trim_start_matches('\u{feff}').trim();
+    let urls = if text.starts_with('{') {
+        serde_json::from_str::<Setlist>(text)?
+            .entries
+            .into_iter()
+            .map(|x| x.url)
+            .collect()
+    } else {
+        text.lines()
+            .map(str::trim)
+            .filter(|x| !x.is_empty() && !x.starts_with('#'))
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    };
+    let urls = urls
+        .into_iter()
+        .filter(|x| x.starts_with("http://") || x.starts_with("https://"))
+        .collect::<Vec<_>>();
+    if urls.is_empty() {
+        bail!("No songs in the setlist");
+    }
+
+    Ok(urls)
+}
+
+/// Where a paste site serves a paste as plain text.
+pub(crate) fn raw_url(url: &str) -> String {
+    let parsed = match Url::parse(url) {
+        Ok(parsed) => parsed,
+        Err(_) => return url.to_string(),
+    };
+    let id = parsed.path().trim_matches('/');
+    match parsed.host_str() {
+        Some("pastebin.com") if !id.is_empty() && !id.starts_with("raw/") => {
+            format!("https://pastebin.com/raw/{}", id)
+        }
+        Some("hastebin.com") if !id.is_empty() && !id.starts_with("raw/") => {
+            format!("https://hastebin.com/raw/{}", id)
+        }
+        _ => url.to_string(),
+    }
+}
+
+/// The setlist at `url`, read from its raw form on paste sites.
+pub(crate) async fn fetch(url: &str) -> Result<String> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()?;
+    let mut response = client.get(raw_url(url)).send().await?.error_for_status()?;
+    if response.content_length().is_some_and(|x| x > MAX_BYTES) {
+        bail!("Setlist is larger than {} bytes", MAX_BYTES);
+    }
+    // Servers may leave the length out, so stop reading once past it.
+    let mut bytes = vec![];
+    while let Some(chunk) = response.chunk().await? {
+        bytes.extend_from_slice(&chunk);
+        if bytes.len() as u64 > MAX_BYTES {
+            bail!("Setlist is larger than {} bytes", MAX_BYTES);
+        }
+    }
+
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[test]
+fn test_round_trip() {
+    let songs = [
+        Metadata {
+            title: Some("A".to_string()),
+            source_url: Some("https://music.163.com/#/song?id=1".to_string()),
+            duration: Some(Duration::from_secs(200)),
+            ..Default::default()
+        },
+        Metadata {
+            title: Some("No link".to_string()),
+            ..Default::default()
+        },
+        Metadata {
+            title: Some("Radio".to_string()),
+            source_url: Some("http://radio.example.com:8000/;".to_string()),
+            ..Default::default()
+        },
+    ];
+    let urls = [
+        "https://music.163.com/#/song?id=1",
+        "http://radio.example.com:8000/;",
+    ];
+
+    let m3u = export(&songs, Format::M3u).unwrap();
+    assert!(m3u.starts_with("#EXTM3U\n#EXTINF:200,A\nhttps://"));
+    assert!(m3u.contains("#EXTINF:-1,Radio\n"));
+    assert_eq!(import(&m3u).unwrap(), urls);
+    assert_eq!(
+        import(&export(&songs, Format::Json).unwrap()).unwrap(),
+        urls
+    );
+    assert_eq!(
+        import("file:///etc/a.mp3\nhttps://b/c.mp3\n").unwrap(),
+        ["https://b/c.mp3"]
+    );
+    assert!(import("#EXTM3U\n").is_err());
+}
+
+#[test]
+fn test_raw_url() {
+    assert_eq!(
+        raw_url("https://pastebin.com/AbCd1234"),
+        "https://pastebin.com/raw/AbCd1234"
+    );
+    assert_eq!(
+        raw_url("https://pastebin.com/raw/AbCd1234"),
+        "https://pastebin.com/raw/AbCd1234"
+    );
+    assert_eq!(
+        raw_url("https://example.com/a.m3u"),
+        "https://example.com/a.m3u"
+    );
+}
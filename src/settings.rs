@@ -0,0 +1,381 @@
+//! Per-guild options set with `~settings`, saved across restarts.
+use std::{collections::HashMap, env, fmt::Write, sync::Arc, time::Duration};
+
+use anyhow::{anyhow, bail, Result};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use serenity::{
+    client::Context,
+    model::{
+        channel::Message,
+        id::{ChannelId, GuildId, RoleId},
+    },
+    prelude::TypeMapKey,
+};
+use tokio::sync::RwLock;
+
+use crate::store;
+
+const SETTINGS: &str = "settings";
+
+lazy_static! {
+    /// Prefix of guilds which did not pick one.
+    static ref DEFAULT_PREFIX: String = env::var("PREFIX")
+        .ok()
+        .filter(|x| !x.is_empty() && !x.contains(char::is_whitespace))
+        .unwrap_or_else(|| "~".to_string());
+}
+
+/// `PREFIX`, or `~`.
+pub(crate) fn default_prefix() -> &'static str {
+    &DEFAULT_PREFIX
+}
+
+/// Idle time after which the bot leaves, unless the guild set another.
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Longest prefix a guild may set.
+const PREFIX_MAX: usize = 5;
+/// Largest bandwidth cap in GB, a petabyte.
+const BANDWIDTH_CAP_MAX: u64 = 1_000_000;
+
+/// Names of the settings, as given to `~settings set`.
+pub(crate) const KEYS: &[&str] = &[
+    "prefix",
+    "volume",
+    "maxqueue",
+    "maxduration",
+    "djrole",
+    "announce",
+    "idletimeout",
+    "profiles",
+    "loudnorm",
+    "endwarning",
+    "bandwidthcap",
+];
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Settings {
+    pub prefix: Option<String>,
+    /// Volume of channels nobody set one for, 1.0 is 100.
+    pub default_volume: Option<f32>,
+    /// Most songs the queue may hold.
+    pub max_queue: Option<usize>,
+    /// Seconds the songs members other than DJs queue may last.
+    pub max_duration: Option<u64>,
+    /// Role which makes members DJs instead of the role named "DJ".
+    pub dj_role: Option<u64>,
+    /// Announcements go here instead of where songs are requested from.
+    pub announce_channel: Option<u64>,
+    /// Seconds the bot stays idle in a voice channel before it leaves, 0
+    /// to stay.
+    pub idle_timeout: Option<u64>,
+    /// Whether streaks and badges are tracked, on unless turned off.
+    pub profiles: Option<bool>,
+    /// Whether songs are normalized to the same loudness, off by default.
+    pub loudnorm: Option<bool>,
+    /// Whether the last song of the queue is pointed out, on by default.
+    pub end_warning: Option<bool>,
+    /// GB of traffic a month after which streams are low quality.
+    pub bandwidth_cap: Option<u64>,
+}
+
+impl Settings {
+    pub(crate) fn prefix(&self) -> &str {
+        self.prefix.as_deref().unwrap_or(default_prefix())
+    }
+
+    pub(crate) fn default_volume(&self) -> f32 {
+        self.default_volume.unwrap_or(1.0)
+    }
+
+    pub(crate) fn max_duration(&self) -> Option<Duration> {
+        self.max_duration.map(Duration::from_secs)
+    }
+
+    /// Whether a song of `duration` is over `maxduration`. Songs of unknown
+    /// length, like live streams, are not.
+    pub(crate) fn too_long(&self, duration: Option<Duration>) -> bool {
+        match (self.max_duration(), duration) {
+            (Some(max), Some(duration)) => duration > max,
+            _ => false,
+        }
+    }
+
+    /// `None` when the bot stays in idle voice channels.
+    pub(crate) fn idle_timeout(&self) -> Option<Duration> {
+        match self.idle_timeout {
+            Some(0) => None,
+            Some(timeout) => Some(Duration::from_secs(timeout)),
+            None => Some(DEFAULT_IDLE_TIMEOUT),
+        }
+    }
+
+    pub(crate) fn profiles(&self) -> bool {
+        self.profiles.unwrap_or(true)
+    }
+
+    pub(crate) fn loudnorm(&self) -> bool {
+        self.loudnorm.unwrap_or(false)
+    }
+
+    pub(crate) fn end_warning(&self) -> bool {
+        self.end_warning.unwrap_or(true)
+    }
+
+    /// Changes the setting named `key`, `None` resets it to the default.
+    pub(crate) fn set(&mut self, key: &str, value: Option<&str>) -> Result<()> {
+        match key {
+            "prefix" => {
+                self.prefix = match value {
+                    Some(x) if x.is_empty() || x.chars().count() > PREFIX_MAX => {
+                        bail!("Prefix must be 1 ~ {} characters", PREFIX_MAX)
+                    }
+                    Some(x) if x.chars().any(char::is_whitespace) => {
+                        bail!("Prefix can not contain spaces")
+                    }
+                    x => x.map(str::to_string),
+                }
+            }
+            "volume" => {
+                self.default_volume = match value.map(str::parse::<f32>) {
+                    Some(Ok(x)) if (0.0..=200.0).contains(&x) => Some(x / 100.0),
+                    Some(_) => bail!("Volume must in 0 ~ 200"),
+                    None => None,
+                }
+            }
+            "maxqueue" => {
+                self.max_queue = match value.map(str::parse::<usize>) {
+                    Some(Ok(x)) if x > 0 => Some(x),
+                    Some(_) => bail!("Max queue must be a positive number"),
+                    None => None,
+                }
+            }
+            "maxduration" => {
+                self.max_duration = match value {
+                    Some("off") => None,
+                    Some(x) => match x.parse::<u64>() {
+                        Ok(x) if x > 0 => Some(x * 60),
+                        _ => bail!("Max duration must be a positive number of minutes or off"),
+                    },
+                    None => None,
+                }
+            }
+            "djrole" => self.dj_role = value.map(parse_role).transpose()?,
+            "announce" => self.announce_channel = value.map(parse_channel).transpose()?,
+            "idletimeout" => {
+                self.idle_timeout = match value {
+                    Some("off") => Some(0),
+                    Some(x) => match x.parse::<u64>() {
+                        Ok(x) if x > 0 => Some(x * 60),
+                        _ => bail!("Idle timeout must be a positive number of minutes or off"),
+                    },
+                    None => None,
+                }
+            }
+            "profiles" => {
+                self.profiles = match value {
+                    Some("on") => Some(true),
+                    Some("off") => Some(false),
+                    Some(_) => bail!("Profiles must be on or off"),
+                    None => None,
+                }
+            }
+            "loudnorm" => {
+                self.loudnorm = match value {
+                    Some("on") => Some(true),
+                    Some("off") => Some(false),
+                    Some(_) => bail!("Loudnorm must be on or off"),
+                    None => None,
+                }
+            }
+            "endwarning" => {
+                self.end_warning = match value {
+                    Some("on") => Some(true),
+                    Some("off") => Some(false),
+                    Some(_) => bail!("End warning must be on or off"),
+                    None => None,
+                }
+            }
+            "bandwidthcap" => {
+                self.bandwidth_cap = match value {
+                    Some("off") => None,
+                    Some(x) => match x.parse::<u64>() {
+                        Ok(x) if x > 0 && x <= BANDWIDTH_CAP_MAX => Some(x),
+                        _ => bail!(
+                            "Bandwidth cap must be a number of GB from 1 to {} or off",
+                            BANDWIDTH_CAP_MAX
+                        ),
+                    },
+                    None => None,
+                }
+            }
+            _ => bail!("Unknown setting {}, one of: {}", key, KEYS.join(", ")),
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn describe(&self) -> String {
+        let mut s = String::new();
+        let _ = writeln!(s, "prefix: {}", self.prefix());
+        let _ = writeln!(s, "volume: {:.0}", self.default_volume() * 100.0);
+        let _ = match self.max_queue {
+            Some(max) => writeln!(s, "maxqueue: {}", max),
+            None => writeln!(s, "maxqueue: unlimited"),
+        };
+        let _ = match self.max_duration() {
+            Some(max) => writeln!(s, "maxduration: {} min", max.as_secs() / 60),
+            None => writeln!(s, "maxduration: unlimited"),
+        };
+        let _ = match self.dj_role {
+            Some(role) => writeln!(s, "djrole: <@&{}>", role),
+            None => writeln!(s, "djrole: roles named DJ"),
+        };
+        let _ = match self.announce_channel {
+            Some(channel) => writeln!(s, "announce: <#{}>", channel),
+            None => writeln!(s, "announce: where songs are requested"),
+        };
+        let _ = match self.idle_timeout() {
+            Some(timeout) => writeln!(s, "idletimeout: {} min", timeout.as_secs() / 60),
+            None => writeln!(s, "idletimeout: off"),
+        };
+        let _ = writeln!(
+            s,
+            "profiles: {}",
+            if self.profiles() { "on" } else { "off" }
+        );
+        let _ = writeln!(
+            s,
+            "loudnorm: {}",
+            if self.loudnorm() { "on" } else { "off" }
+        );
+        let _ = writeln!(
+            s,
+            "endwarning: {}",
+            if self.end_warning() { "on" } else { "off" }
+        );
+        let _ = match self.bandwidth_cap {
+            Some(cap) => write!(s, "bandwidthcap: {} GB a month", cap),
+            None => write!(s, "bandwidthcap: off"),
+        };
+
+        s
+    }
+}
+
+/// Takes a role mention or id.
+fn parse_role(s: &str) -> Result<u64> {
+    s.parse::<RoleId>()
+        .map(|x| x.0)
+        .map_err(|_| anyhow!("Must be a role mention or id"))
+}
+
+/// Takes a channel mention or id.
+fn parse_channel(s: &str) -> Result<u64> {
+    s.parse::<ChannelId>()
+        .map(|x| x.0)
+        .map_err(|_| anyhow!("Must be a channel mention or id"))
+}
+
+pub(crate) type SettingsLock = Arc<RwLock<HashMap<u64, Settings>>>;
+
+pub(crate) struct GuildSettings;
+
+impl TypeMapKey for GuildSettings {
+    type Value = SettingsLock;
+}
+
+pub(crate) async fn load() -> Result<HashMap<u64, Settings>> {
+    store::load(SETTINGS).await
+}
+
+pub(crate) async fn settings_lock(ctx: &Context) -> SettingsLock {
+    let read = ctx.data.read().await;
+
+    read.get::<GuildSettings>()
+        .expect("Expected GuildSettings in TypeMap.")
+        .clone()
+}
+
+pub(crate) async fn get(ctx: &Context, guild_id: u64) -> Settings {
+    let lock = settings_lock(ctx).await;
+    let settings = lock.read().await;
+
+    settings.get(&guild_id).cloned().unwrap_or_default()
+}
+
+/// Changes one setting of the guild and saves them all.
+pub(crate) async fn set(
+    ctx: &Context,
+    guild_id: u64,
+    key: &str,
+    value: Option<&str>,
+) -> Result<Settings> {
+    let lock = settings_lock(ctx).await;
+    let mut settings = lock.write().await;
+    let mut changed = settings.get(&guild_id).cloned().unwrap_or_default();
+    changed.set(key, value)?;
+    settings.insert(guild_id, changed.clone());
+    store::save(SETTINGS, &*settings).await?;
+
+    Ok(changed)
+}
+
+/// Prefix of the guild the message came from, the default one in DMs.
+pub(crate) async fn prefix(ctx: &Context, msg: &Message) -> String {
+    match msg.guild_id {
+        Some(GuildId(guild_id)) => get(ctx, guild_id).await.prefix().to_string(),
+        None => default_prefix().to_string(),
+    }
+}
+
+#[test]
+fn test_set() {
+    let mut settings = Settings::default();
+
+    settings.set("prefix", Some("!")).unwrap();
+    assert_eq!(settings.prefix(), "!");
+    assert!(settings.set("prefix", Some("a b")).is_err());
+    settings.set("prefix", None).unwrap();
+    assert_eq!(settings.prefix(), default_prefix());
+
+    settings.set("volume", Some("50")).unwrap();
+    assert_eq!(settings.default_volume(), 0.5);
+    assert!(settings.set("volume", Some("300")).is_err());
+
+    assert!(!settings.too_long(Some(Duration::from_secs(10 * 60 * 60))));
+    settings.set("maxduration", Some("15")).unwrap();
+    assert!(settings.too_long(Some(Duration::from_secs(10 * 60 * 60))));
+    assert!(!settings.too_long(Some(Duration::from_secs(15 * 60))));
+    assert!(!settings.too_long(None));
+    assert!(settings.set("maxduration", Some("0")).is_err());
+
+    settings.set("djrole", Some("<@&1234>")).unwrap();
+    assert_eq!(settings.dj_role, Some(1234));
+    settings.set("announce", Some("<#5678>")).unwrap();
+    assert_eq!(settings.announce_channel, Some(5678));
+    settings.set("idletimeout", Some("5")).unwrap();
+    assert_eq!(settings.idle_timeout(), Some(Duration::from_secs(300)));
+    settings.set("idletimeout", Some("off")).unwrap();
+    assert_eq!(settings.idle_timeout(), None);
+    settings.set("idletimeout", None).unwrap();
+    assert_eq!(settings.idle_timeout(), Some(DEFAULT_IDLE_TIMEOUT));
+
+    assert!(settings.profiles());
+    settings.set("profiles", Some("off")).unwrap();
+    assert!(!settings.profiles());
+    assert!(settings.set("profiles", Some("maybe")).is_err());
+    settings.set("loudnorm", Some("on")).unwrap();
+    assert!(settings.loudnorm());
+
+    settings.set("bandwidthcap", Some("50")).unwrap();
+    assert_eq!(settings.bandwidth_cap, Some(50));
+    assert!(settings.set("bandwidthcap", Some("0")).is_err());
+    assert!(settings
+        .set("bandwidthcap", Some(&u64::MAX.to_string()))
+        .is_err());
+
+    assert!(settings.set("color", Some("red")).is_err());
+}
@@ -0,0 +1,189 @@
+//! Runs the bot on as many gateway shards as Discord recommends, or on
+//! `SHARD_COUNT`, so it can be in more than 2500 guilds. Every shard gets
+//! its guilds' saved queues back when its own cache is ready.
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
+
+use lazy_static::lazy_static;
+use serenity::{
+    client::{bridge::gateway::ShardManager, Client, Context},
+    gateway::ConnectionStage,
+    model::id::GuildId,
+};
+use tokio::sync::Mutex;
+
+use crate::shutdown::ShardManagerContainer;
+
+/// `~shardinfo` lists every shard up to this many.
+const LISTED_MAX: usize = 20;
+
+lazy_static! {
+    /// Shards whose cache got ready in this run.
+    static ref READY: StdMutex<HashSet<u64>> = StdMutex::new(HashSet::new());
+}
+
+/// Starts the shards and runs until they all stopped.
+pub(crate) async fn start(client: &mut Client) -> serenity::Result<()> {
+    match env::var("SHARD_COUNT").ok().and_then(|x| x.parse().ok()) {
+        Some(count) => client.start_shards(count).await,
+        None => client.start_autosharded().await,
+    }
+}
+
+/// Shard which carries the events of `guild_id` when there are `count`.
+pub(crate) fn shard_of(guild_id: GuildId, count: u64) -> u64 {
+    (guild_id.0 >> 22) % count.max(1)
+}
+
+/// Marks the cache of `shard` ready, `false` if it already was before,
+/// like after a reconnect.
+pub(crate) fn first_ready(shard: u64) -> bool {
+    READY.lock().unwrap().insert(shard)
+}
+
+/// Whether every one of `count` shards got ready once.
+pub(crate) fn all_ready(count: u64) -> bool {
+    READY.lock().unwrap().len() as u64 >= count
+}
+
+pub(crate) async fn manager(ctx: &Context) -> Arc<Mutex<ShardManager>> {
+    ctx.data
+        .read()
+        .await
+        .get::<ShardManagerContainer>()
+        .expect("Expected ShardManagerContainer in TypeMap.")
+        .clone()
+}
+
+pub(crate) struct ShardStatus {
+    pub id: u64,
+    pub stage: ConnectionStage,
+    /// Time the gateway took to answer the last heartbeat.
+    pub latency: Option<Duration>,
+}
+
+/// Shards of this process, by id.
+pub(crate) async fn statuses(ctx: &Context) -> Vec<ShardStatus> {
+    let manager = manager(ctx).await;
+    let manager = manager.lock().await;
+    let runners = manager.runners.lock().await;
+    let mut statuses = runners
+        .iter()
+        .map(|(id, runner)| ShardStatus {
+            id: id.0,
+            stage: runner.stage,
+            latency: runner.latency,
+        })
+        .collect::<Vec<_>>();
+    statuses.sort_by_key(|x| x.id);
+
+    statuses
+}
+
+fn describe_one(status: &ShardStatus, guilds: &HashMap<u64, usize>, this: u64) -> String {
+    let latency = status.latency.map_or("no heartbeat yet".to_string(), |x| {
+        format!("{} ms", x.as_millis())
+    });
+
+    format!(
+        "Shard {}: {}, {}, {} servers{}",
+        status.id,
+        status.stage,
+        latency,
+        guilds.get(&status.id).copied().unwrap_or_default(),
+        if status.id == this {
+            " (this server)"
+        } else {
+            ""
+        }
+    )
+}
+
+/// What `~shardinfo` shows: the shards with their state, latency and
+/// number of servers. With many shards only the one of `this` server.
+pub(crate) fn describe(
+    statuses: &[ShardStatus],
+    guilds: &HashMap<u64, usize>,
+    this: u64,
+) -> String {
+    if statuses.len() <= LISTED_MAX {
+        return statuses
+            .iter()
+            .map(|x| describe_one(x, guilds, this))
+            .collect::<Vec<_>>()
+            .join("\n");
+    }
+    let connected = statuses
+        .iter()
+        .filter(|x| x.stage == ConnectionStage::Connected)
+        .count();
+    let mut s = statuses
+        .iter()
+        .find(|x| x.id == this)
+        .map(|x| describe_one(x, guilds, this) + "\n")
+        .unwrap_or_default();
+    s.push_str(&format!(
+        "{} of {} shards connected",
+        connected,
+        statuses.len()
+    ));
+
+    s
+}
+
+/// Servers in the cache per shard.
+pub(crate) fn guild_counts(guilds: &[GuildId], count: u64) -> HashMap<u64, usize> {
+    let mut counts = HashMap::new();
+    for guild_id in guilds {
+        *counts.entry(shard_of(*guild_id, count)).or_default() += 1;
+    }
+
+    counts
+}
+
+#[test]
+fn test_shard_of() {
+    assert_eq!(shard_of(GuildId(197038439483310086), 2), 0);
+    assert_eq!(shard_of(GuildId(3 << 22), 2), 1);
+    assert_eq!(shard_of(GuildId(3 << 22), 0), 0);
+}
+
+#[test]
+fn test_ready() {
+    assert!(first_ready(1000));
+    assert!(!first_ready(1000));
+}
+
+#[test]
+fn test_describe() {
+    let status = |id, stage| ShardStatus {
+        id,
+        stage,
+        latency: Some(Duration::from_millis(42)),
+    };
+    let guilds = guild_counts(&[GuildId(0), GuildId(1 << 22), GuildId(3 << 22)], 2);
+
+    assert_eq!(
+        describe(
+            &[
+                status(0, ConnectionStage::Connected),
+                status(1, ConnectionStage::Resuming)
+            ],
+            &guilds,
+            1
+        ),
+        "Shard 0: connected, 42 ms, 1 servers\nShard 1: resuming, 42 ms, 2 servers (this server)"
+    );
+
+    let many = (0..30)
+        .map(|x| status(x, ConnectionStage::Connected))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        describe(&many, &guilds, 1),
+        "Shard 1: connected, 42 ms, 2 servers (this server)\n30 of 30 shards connected"
+    );
+}
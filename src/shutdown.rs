@@ -0,0 +1,96 @@
+//! Stops cleanly on Ctrl-C or SIGTERM instead of dying mid-song: the queues
+//! are saved for the next run, listeners are told about the restart and the
+//! voice channels are left.
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use serenity::{client::bridge::gateway::ShardManager, client::Context, prelude::TypeMapKey};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::{check_msg, hibernate, playback, resume, shard};
+
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+pub(crate) struct ShardManagerContainer;
+
+impl TypeMapKey for ShardManagerContainer {
+    type Value = Arc<Mutex<ShardManager>>;
+}
+
+/// Once stopping, the queues are being emptied and must not be saved.
+pub(crate) fn is_stopping() -> bool {
+    STOPPING.load(Ordering::SeqCst)
+}
+
+#[cfg(unix)]
+async fn signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("Err listening for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => (),
+        _ = terminate.recv() => (),
+    }
+}
+
+#[cfg(not(unix))]
+async fn signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Waits for a signal to stop, then shuts the bot down.
+pub(crate) async fn run(ctx: Context) {
+    signal().await;
+    info!("Shutting down");
+
+    // Queues put away by hibernating are saved too.
+    hibernate::wake(&ctx).await;
+    // Saved before the songs are stopped, the next run picks them up.
+    if let Err(e) = resume::save(&ctx).await {
+        warn!("Err saving queues: {:?}", e);
+    }
+    STOPPING.store(true, Ordering::SeqCst);
+
+    let manager = songbird::get(&ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    let guilds = {
+        let lock = playback::playback_lock(&ctx).await;
+        let playback = lock.read().await;
+        playback
+            .iter()
+            .map(|(id, state)| (*id, state.text_channel))
+            .collect::<Vec<_>>()
+    };
+    for (guild_id, text_channel) in guilds {
+        let handler_lock = match manager.get(guild_id) {
+            Some(handler_lock) => handler_lock,
+            None => continue,
+        };
+        let playing = {
+            let handler = handler_lock.lock().await;
+            let playing = !handler.queue().is_empty();
+            handler.queue().stop();
+            playing
+        };
+        if let (true, Some(text_channel)) = (playing, text_channel) {
+            check_msg(
+                text_channel
+                    .say(
+                        &ctx.http,
+                        "Restarting, the queue carries on when the bot is back",
+                    )
+                    .await,
+            );
+        }
+        if let Err(e) = manager.remove(guild_id).await {
+            warn!("Err leaving voice channel in {}: {:?}", guild_id, e);
+        }
+    }
+
+    shard::manager(&ctx).await.lock().await.shutdown_all().await;
+}
@@ -0,0 +1,183 @@
+//! Slash commands for the basics, so the player can be driven without
+//! message content. `bibicord register-commands` registers them, they go
+//! through `control` like the dashboard.
+use anyhow::Result;
+use serenity::{
+    builder::CreateApplicationCommands,
+    client::Context,
+    http::Http,
+    model::{
+        application::{
+            command::{Command, CommandOptionType},
+            interaction::{
+                application_command::{ApplicationCommandInteraction, CommandDataOption},
+                InteractionResponseType,
+            },
+        },
+        id::GuildId,
+    },
+};
+use tracing::warn;
+
+use crate::{
+    control::{self, Snapshot},
+    dj,
+};
+
+/// Entries `/queue` shows.
+const QUEUE_SHOWN: usize = 10;
+
+fn define(commands: &mut CreateApplicationCommands) -> &mut CreateApplicationCommands {
+    commands
+        .create_application_command(|c| c.name("skip").description("Skip the current song"))
+        .create_application_command(|c| c.name("pause").description("Pause the current song"))
+        .create_application_command(|c| c.name("resume").description("Resume playing"))
+        .create_application_command(|c| {
+            c.name("volume")
+                .description("Set the volume")
+                .create_option(|o| {
+                    o.name("percent")
+                        .description("0 to 200")
+                        .kind(CommandOptionType::Integer)
+                        .min_int_value(0)
+                        .max_int_value(200)
+                        .required(true)
+                })
+        })
+        .create_application_command(|c| c.name("queue").description("Show the queue"))
+}
+
+/// Registers the commands in `guild`, which shows them at once, or
+/// globally. `clear` removes them instead. Returns how many there are now.
+pub(crate) async fn register(http: &Http, guild: Option<GuildId>, clear: bool) -> Result<usize> {
+    let application = http.get_current_application_info().await?;
+    http.set_application_id(application.id.0);
+
+    let commands = match (guild, clear) {
+        (Some(guild_id), false) => guild_id.set_application_commands(http, define).await?,
+        (Some(guild_id), true) => guild_id.set_application_commands(http, |c| c).await?,
+        (None, false) => Command::set_global_application_commands(http, define).await?,
+        (None, true) => Command::set_global_application_commands(http, |c| c).await?,
+    };
+
+    Ok(commands.len())
+}
+
+fn percent(options: &[CommandDataOption]) -> Option<f32> {
+    let option = options.iter().find(|x| x.name == "percent")?;
+    let percent = option.value.as_ref()?.as_i64()?;
+
+    (0..=200).contains(&percent).then_some(percent as f32)
+}
+
+/// What `/queue` answers.
+fn describe_queue(snapshot: &Snapshot) -> String {
+    if snapshot.entries.is_empty() {
+        return "Queue is empty!".to_string();
+    }
+    let mut lines = snapshot
+        .entries
+        .iter()
+        .take(QUEUE_SHOWN)
+        .enumerate()
+        .map(|(i, x)| format!("{}. {}", i + 1, x.title))
+        .collect::<Vec<_>>();
+    if snapshot.entries.len() > QUEUE_SHOWN {
+        lines.push(format!("and {} more", snapshot.entries.len() - QUEUE_SHOWN));
+    }
+
+    lines.join("\n")
+}
+
+async fn run(
+    ctx: &Context,
+    guild_id: GuildId,
+    command: &ApplicationCommandInteraction,
+) -> Result<String> {
+    let done = |done: bool, s: &str| {
+        if done {
+            s.to_string()
+        } else {
+            "Nothing is playing".to_string()
+        }
+    };
+    let reply = match command.data.name.as_str() {
+        "skip" => {
+            if crate::vote_skip_on(ctx, guild_id.0).await
+                && !dj::is_member_dj(ctx, guild_id, command.user.id).await
+            {
+                "Vote skip is on, vote with the skip command".to_string()
+            } else {
+                done(control::skip(ctx, guild_id).await, "Skipped")
+            }
+        }
+        "pause" => done(control::pause(ctx, guild_id, true).await?, "Paused"),
+        "resume" => done(control::pause(ctx, guild_id, false).await?, "Resumed"),
+        "volume" => match percent(&command.data.options) {
+            Some(percent) => {
+                let requested = percent / 100.0;
+                match control::set_volume(ctx, guild_id, Some(command.channel_id), requested)
+                    .await?
+                {
+                    Some(volume) => {
+                        let mut s = format!("Volume set to {:.0}", (volume * 100.0).round());
+                        if requested > volume {
+                            s.push_str(" (volume ceiling)");
+                        }
+                        s
+                    }
+                    None => "Not in a voice channel".to_string(),
+                }
+            }
+            None => "Volume must be from 0 to 200".to_string(),
+        },
+        "queue" => match control::snapshot(ctx, guild_id).await {
+            Some(snapshot) => describe_queue(&snapshot),
+            None => "Not in a voice channel".to_string(),
+        },
+        other => format!("Unknown command {}", other),
+    };
+
+    Ok(reply)
+}
+
+/// Answers a slash command.
+pub(crate) async fn handle(ctx: &Context, command: &ApplicationCommandInteraction) {
+    let reply = match command.guild_id {
+        Some(guild_id) => run(ctx, guild_id, command).await.unwrap_or_else(|e| {
+            warn!("Err running /{}: {:?}", command.data.name, e);
+            "Something went wrong".to_string()
+        }),
+        None => "Only works in servers".to_string(),
+    };
+
+    if let Err(e) = command
+        .create_interaction_response(&ctx.http, |r| {
+            r.kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|d| d.content(reply))
+        })
+        .await
+    {
+        warn!("Err answering /{}: {:?}", command.data.name, e);
+    }
+}
+
+#[test]
+fn test_describe_queue() {
+    let entry = |title: &str| control::Entry {
+        title: title.to_string(),
+        url: None,
+        duration: None,
+        requester: None,
+    };
+    let snapshot = |n: usize| Snapshot {
+        position: None,
+        paused: false,
+        volume: None,
+        entries: (0..n).map(|x| entry(&x.to_string())).collect(),
+    };
+
+    assert_eq!(describe_queue(&snapshot(0)), "Queue is empty!");
+    assert_eq!(describe_queue(&snapshot(2)), "1. 0\n2. 1");
+    assert!(describe_queue(&snapshot(12)).ends_with("10. 9\nand 2 more"));
+}
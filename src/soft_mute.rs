@@ -0,0 +1,184 @@
+//! `~softmute`: turns the songs down to almost nothing while they keep
+//! playing, so a listening party stays in sync during an announcement.
+use serenity::async_trait;
+use songbird::{
+    tracks::{TrackHandle, TrackResult},
+    Event, EventContext, EventHandler as VoiceEventHandler,
+};
+
+use crate::{
+    playback::{GuildPlayer, PlaybackLock},
+    queue,
+};
+
+/// Volume of muted songs, not silent so everyone can tell it still plays.
+const SOFT_MUTE_VOLUME: f32 = 0.02;
+
+pub(crate) struct SoftMuter {
+    pub player: GuildPlayer,
+}
+
+impl SoftMuter {
+    /// Turns `track` down if the guild is muted, keeping its volume for
+    /// `unmute`.
+    pub(crate) async fn mute(&self, track: &TrackHandle) -> TrackResult<()> {
+        // Most guilds are never muted, spare them the round trip to the mixer.
+        if self.player.state(|x| x.soft_muted.is_none()).await {
+            return Ok(());
+        }
+        let volume = track.get_info().await?.volume;
+        {
+            let mut playback = self.player.playback.write().await;
+            let muted = match playback
+                .get_mut(&self.player.guild_id)
+                .and_then(|x| x.soft_muted.as_mut())
+            {
+                Some(muted) => muted,
+                None => return Ok(()),
+            };
+            if muted.iter().any(|(x, _)| x.uuid() == track.uuid()) {
+                return Ok(());
+            }
+            muted.push((track.clone(), volume));
+        }
+
+        track.set_volume(SOFT_MUTE_VOLUME)
+    }
+}
+
+#[async_trait]
+impl VoiceEventHandler for SoftMuter {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if let EventContext::Track(&[(_, track)]) = ctx {
+            let _ = self.mute(track).await;
+        }
+
+        None
+    }
+}
+
+/// Mutes the guild, the playing song first and every later one as it
+/// starts. False when it already was.
+pub(crate) async fn mute(player: &GuildPlayer) -> TrackResult<bool> {
+    {
+        let mut playback = player.playback.write().await;
+        let state = playback.entry(player.guild_id).or_default();
+        if state.soft_muted.is_some() {
+            return Ok(false);
+        }
+        state.soft_muted = Some(vec![]);
+    }
+
+    let current = player.call.lock().await.queue().current();
+    if let Some(current) = current {
+        SoftMuter {
+            player: player.clone(),
+        }
+        .mute(&current)
+        .await?;
+    }
+
+    Ok(true)
+}
+
+/// Gives the songs muted so far their volume back. False when the guild
+/// was not muted.
+pub(crate) async fn unmute(player: &GuildPlayer) -> TrackResult<bool> {
+    let muted = {
+        let mut playback = player.playback.write().await;
+        match playback
+            .get_mut(&player.guild_id)
+            .and_then(|x| x.soft_muted.take())
+        {
+            Some(muted) => muted,
+            None => return Ok(false),
+        }
+    };
+
+    // Songs which ended meanwhile can't be changed anymore.
+    for (track, volume) in muted {
+        let _ = track.set_volume(volume);
+    }
+
+    Ok(true)
+}
+
+/// `queue::set_volume` which keeps a mute: songs muted so far get `volume`
+/// back on unmute instead of now.
+pub(crate) async fn set_volume(
+    playback: &PlaybackLock,
+    guild_id: u64,
+    tracks: &[TrackHandle],
+    volume: f32,
+) -> TrackResult<usize> {
+    let mut live = vec![];
+    let mut saved = 0;
+    {
+        let mut playback = playback.write().await;
+        match playback
+            .get_mut(&guild_id)
+            .and_then(|x| x.soft_muted.as_mut())
+        {
+            Some(muted) => {
+                for track in tracks {
+                    match muted.iter_mut().find(|(x, _)| x.uuid() == track.uuid()) {
+                        Some((_, before)) => {
+                            if queue::entry_volume(track).await.is_none() {
+                                *before = volume;
+                                saved += 1;
+                            }
+                        }
+                        None => live.push(track.clone()),
+                    }
+                }
+            }
+            None => live = tracks.to_vec(),
+        }
+    }
+
+    Ok(saved + queue::set_volume(&live, volume).await?)
+}
+
+/// Volume `track` had before it was muted, for songs queued again from it.
+pub(crate) async fn volume_before(player: &GuildPlayer, track: &TrackHandle) -> Option<f32> {
+    player
+        .state(|x| {
+            x.soft_muted
+                .as_ref()?
+                .iter()
+                .find(|(x, _)| x.uuid() == track.uuid())
+                .map(|(_, volume)| *volume)
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mock, playback::PlaybackState};
+
+    #[tokio::test]
+    async fn test_set_volume() {
+        let driver = mock::queued(&["a", "b", "c"]);
+        let list = driver.queue().current_queue();
+        let playback = PlaybackLock::default();
+        playback.write().await.insert(
+            1,
+            PlaybackState {
+                soft_muted: Some(vec![(list[0].clone(), 1.0), (list[1].clone(), 1.0)]),
+                ..Default::default()
+            },
+        );
+
+        queue::set_entry_volume(&list[1], 1.5).await;
+        assert_eq!(set_volume(&playback, 1, &list, 0.5).await.unwrap(), 2);
+        let saved = playback.read().await[&1]
+            .soft_muted
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|(_, volume)| *volume)
+            .collect::<Vec<_>>();
+        assert_eq!(saved, [0.5, 1.0]);
+    }
+}
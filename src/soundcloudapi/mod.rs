@@ -0,0 +1,23 @@
+use songbird::input::Restartable;
+
+use self::soundcloud::{_forget_client_id, _soundcloud_playlist, _soundcloud_restartable};
+
+mod soundcloud;
+use crate::ffmpeg::FilterHandle;
+use anyhow::Result;
+
+pub(crate) async fn soundcloud_restartable(
+    url: &str,
+    lazy: bool,
+    filter: FilterHandle,
+) -> Result<Restartable> {
+    _soundcloud_restartable(url, lazy, filter).await
+}
+
+pub(crate) async fn soundcloud_playlist(url: &str) -> Result<Vec<String>> {
+    _soundcloud_playlist(url).await
+}
+
+pub(crate) async fn forget_client_id() {
+    _forget_client_id().await
+}
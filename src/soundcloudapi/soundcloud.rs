@@ -0,0 +1,342 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use reqwest::Client;
+use serde::{de::DeserializeOwned, Deserialize};
+use songbird::input::{restartable::Restart, Codec, Container, Input, Metadata, Restartable};
+use tokio::sync::Mutex;
+use tracing::{debug, info};
+
+use crate::{
+    credentials::{self, Credential},
+    ffmpeg::{self, FilterHandle, Pipeline},
+    seek_cache,
+};
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum Resolved {
+    Track(Track),
+    Playlist(Playlist),
+}
+
+#[derive(Deserialize, Debug)]
+struct Track {
+    id: u64,
+    /// Only the id is there for tracks deep into a playlist.
+    title: Option<String>,
+    permalink_url: Option<String>,
+    artwork_url: Option<String>,
+    /// In milliseconds.
+    duration: Option<u64>,
+    user: Option<TrackUser>,
+    media: Option<TrackMedia>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TrackUser {
+    username: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TrackMedia {
+    #[serde(default)]
+    transcodings: Vec<Transcoding>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Transcoding {
+    url: String,
+    format: TranscodingFormat,
+}
+
+#[derive(Deserialize, Debug)]
+struct TranscodingFormat {
+    protocol: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Playlist {
+    #[serde(default)]
+    tracks: Vec<Track>,
+}
+
+#[derive(Deserialize, Debug)]
+struct StreamUrl {
+    url: String,
+}
+
+const USER_AGENT: &str = "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0 Safari/537.36";
+const BASE_URL: &str = "https://api-v2.soundcloud.com";
+/// The `/tracks` endpoint takes at most this many ids at once.
+const TRACKS_CHUNK: usize = 50;
+
+lazy_static! {
+    /// Scraped client id, reused until the api refuses it.
+    static ref CLIENT_ID: Mutex<Option<String>> = Mutex::new(None);
+}
+
+impl From<&Track> for Metadata {
+    fn from(track: &Track) -> Self {
+        Self {
+            track: None,
+            artist: track.user.as_ref().and_then(|x| x.username.clone()),
+            date: None,
+            channels: Some(2),
+            channel: None,
+            start_time: None,
+            duration: track.duration.map(Duration::from_millis),
+            sample_rate: Some(48000),
+            source_url: track.permalink_url.to_owned(),
+            title: track.title.to_owned(),
+            thumbnail: track.artwork_url.to_owned(),
+        }
+    }
+}
+
+struct SoundCloudClient {
+    client: Client,
+    client_id: String,
+}
+
+/// Drops the scraped client id, the next request scrapes it again.
+pub(crate) async fn _forget_client_id() {
+    *CLIENT_ID.lock().await = None;
+}
+
+impl SoundCloudClient {
+    async fn new() -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .user_agent(USER_AGENT)
+            .timeout(Duration::from_secs(10))
+            .build()?;
+        let mut cached = CLIENT_ID.lock().await;
+        let configured = credentials::get(Credential::SoundCloudClientId);
+        let client_id = match configured.as_ref().or(cached.as_ref()) {
+            Some(client_id) => client_id.to_owned(),
+            None => {
+                let client_id = scrape_client_id(&client).await?;
+                *cached = Some(client_id.clone());
+
+                client_id
+            }
+        };
+
+        Ok(Self { client, client_id })
+    }
+
+    async fn get<T: DeserializeOwned>(&self, url: &str, query: &[(&str, &str)]) -> Result<T> {
+        let response = self
+            .client
+            .get(url)
+            .query(&[("client_id", self.client_id.as_str())])
+            .query(query)
+            .send()
+            .await?;
+        if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+            // Scrape a fresh one next time.
+            *CLIENT_ID.lock().await = None;
+            bail!("SoundCloud client id expired!");
+        }
+
+        Ok(response.error_for_status()?.json::<T>().await?)
+    }
+
+    async fn resolve(&self, url: &str) -> Result<Resolved> {
+        self.get(&format!("{}/resolve", BASE_URL), &[("url", url)])
+            .await
+    }
+
+    async fn tracks(&self, ids: &[u64]) -> Result<Vec<Track>> {
+        let ids = ids
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+
+        self.get(&format!("{}/tracks", BASE_URL), &[("ids", &ids)])
+            .await
+    }
+}
+
+/// The web player keeps its client id in one of its script bundles.
+async fn scrape_client_id(client: &Client) -> Result<String> {
+    let page = client
+        .get("https://soundcloud.com")
+        .send()
+        .await?
+        .text()
+        .await?;
+    let scripts = script_urls(&page);
+    // The id is usually in one of the last bundles.
+    for script in scripts.iter().rev() {
+        let script = client.get(script).send().await?.text().await?;
+        if let Some(client_id) = find_client_id(&script) {
+            debug!("soundcloud client id {}", client_id);
+
+            return Ok(client_id);
+        }
+    }
+
+    bail!("Can not find SoundCloud client id!")
+}
+
+fn script_urls(page: &str) -> Vec<String> {
+    page.split("<script crossorigin src=\"")
+        .skip(1)
+        .filter_map(|x| x.split('"').next())
+        .filter(|x| x.starts_with("https://"))
+        .map(|x| x.to_string())
+        .collect()
+}
+
+fn find_client_id(script: &str) -> Option<String> {
+    let id = script.split("client_id:\"").nth(1)?.split('"').next()?;
+
+    (!id.is_empty() && id.chars().all(|x| x.is_ascii_alphanumeric())).then(|| id.to_string())
+}
+
+async fn get_track(client: &SoundCloudClient, url: &str) -> Result<Track> {
+    match client.resolve(url).await? {
+        Resolved::Track(track) => Ok(track),
+        Resolved::Playlist(_) => bail!("Url is a SoundCloud set, not a track!"),
+    }
+}
+
+/// Progressive MP3 seeks better than HLS, use it when there is one.
+async fn get_stream_url(client: &SoundCloudClient, track: &Track) -> Result<String> {
+    let transcodings = track
+        .media
+        .as_ref()
+        .map(|x| &x.transcodings[..])
+        .unwrap_or_default();
+    let transcoding = transcodings
+        .iter()
+        .find(|x| x.format.protocol == "progressive")
+        .or_else(|| transcodings.first())
+        .ok_or_else(|| anyhow!("SoundCloud track {} is not streamable!", track.id))?;
+    let stream = client.get::<StreamUrl>(&transcoding.url, &[]).await?;
+
+    Ok(stream.url)
+}
+
+struct SoundCloudRestarter {
+    url: String,
+    filter: FilterHandle,
+}
+
+#[async_trait]
+impl Restart for SoundCloudRestarter {
+    async fn call_restart(
+        &mut self,
+        time: Option<Duration>,
+    ) -> songbird::input::error::Result<Input> {
+        Ok(_soundcloud(&self.url, time, &self.filter)
+            .await
+            .map_err(std::io::Error::other)?)
+    }
+
+    async fn lazy_init(
+        &mut self,
+    ) -> songbird::input::error::Result<(Option<Metadata>, Codec, Container)> {
+        let client = SoundCloudClient::new()
+            .await
+            .map_err(std::io::Error::other)?;
+        let track = get_track(&client, &self.url)
+            .await
+            .map_err(std::io::Error::other)?;
+
+        Ok((
+            Some(Metadata::from(&track)),
+            Codec::FloatPcm,
+            Container::Raw,
+        ))
+    }
+}
+
+pub(crate) async fn _soundcloud_restartable(
+    url: &str,
+    lazy: bool,
+    filter: FilterHandle,
+) -> Result<Restartable> {
+    let restarter = SoundCloudRestarter {
+        url: url.to_string(),
+        filter: filter.clone(),
+    };
+
+    Ok(seek_cache::restartable(restarter, filter, lazy).await?)
+}
+
+pub(crate) async fn _soundcloud_playlist(url: &str) -> Result<Vec<String>> {
+    let client = SoundCloudClient::new().await?;
+    let tracks = match client.resolve(url).await? {
+        Resolved::Playlist(playlist) => playlist.tracks,
+        Resolved::Track(_) => bail!("Url is a SoundCloud track, not a set!"),
+    };
+
+    let ids = tracks.iter().map(|x| x.id).collect::<Vec<_>>();
+    let mut urls = vec![];
+    for chunk in ids.chunks(TRACKS_CHUNK) {
+        let mut details = client.tracks(chunk).await?;
+        // The api doesn't keep the order of the ids.
+        details.sort_by_key(|x| chunk.iter().position(|id| *id == x.id));
+        urls.extend(details.into_iter().filter_map(|x| x.permalink_url));
+    }
+
+    Ok(urls)
+}
+
+async fn _soundcloud(uri: &str, time: Option<Duration>, filter: &FilterHandle) -> Result<Input> {
+    let client = SoundCloudClient::new().await?;
+    let track = get_track(&client, uri).await?;
+    let url = get_stream_url(&client, &track).await?;
+    let metadata = Metadata::from(&track);
+    let ffmpeg_command = Pipeline::new(&url, filter.get()).seek(time).spawn()?;
+    info!("soundcloud track metadata {:?}", metadata);
+
+    Ok(ffmpeg::input(
+        vec![ffmpeg_command],
+        metadata,
+        filter.guild_id(),
+    )?)
+}
+
+#[test]
+fn test_find_client_id() {
+    let page = r#"<script crossorigin src="https://a-v2.sndcdn.com/assets/0-a.js"></script>
+<script crossorigin src="https://a-v2.sndcdn.com/assets/50-b.js"></script>"#;
+    assert_eq!(
+        script_urls(page),
+        vec![
+            "https://a-v2.sndcdn.com/assets/0-a.js",
+            "https://a-v2.sndcdn.com/assets/50-b.js"
+        ]
+    );
+
+    let script = r#"n={client_id:"aBc123XyZ",env:"production"}"#;
+    assert_eq!(find_client_id(script), Some("aBc123XyZ".to_string()));
+    assert_eq!(find_client_id("nothing here"), None);
+}
+
+#[test]
+fn test_resolved_kind() {
+    let resolved = serde_json::from_str::<Resolved>(
+        r#"{"kind":"playlist","tracks":[
+            {"id":1,"title":"a","permalink_url":"https://soundcloud.com/u/a",
+             "duration":1000,"user":{"username":"u"}},
+            {"id":2}]}"#,
+    )
+    .unwrap();
+    let tracks = match resolved {
+        Resolved::Playlist(playlist) => playlist.tracks,
+        Resolved::Track(_) => panic!("Expected a playlist"),
+    };
+
+    assert_eq!(tracks.len(), 2);
+    let metadata = Metadata::from(&tracks[0]);
+    assert_eq!(metadata.artist, Some("u".to_string()));
+    assert_eq!(metadata.duration, Some(Duration::from_secs(1)));
+    assert_eq!(tracks[1].title, None);
+}
@@ -0,0 +1,371 @@
+//! Services songs can be played from. Supporting another one means
+//! implementing `SourceProvider` and listing it in `PROVIDERS`.
+use std::time::Instant;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use songbird::input::{Input, Metadata, Restartable};
+
+use crate::{
+    audio_cache, bilibiliapi, direct, ffmpeg::FilterHandle, local, metrics, neteaseapi,
+    quality::Quality, soundcloudapi, spotify, ytdl,
+};
+
+#[async_trait]
+pub(crate) trait SourceProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn matches_url(&self, url: &str) -> bool;
+
+    /// Opens a single song. Lazy sources don't fetch audio until played.
+    /// Services with one stream per song ignore `quality`. `filter` is read
+    /// again on every (re)start of the song.
+    async fn resolve(
+        &self,
+        url: &str,
+        lazy: bool,
+        quality: Quality,
+        filter: FilterHandle,
+    ) -> Result<Restartable>;
+
+    fn is_playlist(&self, _url: &str) -> bool {
+        false
+    }
+
+    /// URLs of the songs in a playlist. `limit` is a hint for services
+    /// where every song costs a request, callers still cut the list.
+    async fn resolve_playlist(&self, url: &str, _limit: usize) -> Result<Vec<String>> {
+        Err(anyhow!("{} is not a playlist", url))
+    }
+
+    /// URLs of songs like the one at `url`, to keep playing after it.
+    async fn related(&self, url: &str, _limit: usize) -> Result<Vec<String>> {
+        Err(anyhow!("{} has no related songs for {}", self.name(), url))
+    }
+
+    async fn metadata(&self, url: &str) -> Result<Metadata> {
+        let input: Input = self
+            .resolve(url, true, Quality::default(), FilterHandle::default())
+            .await?
+            .into();
+
+        Ok(*input.metadata)
+    }
+
+    /// Fails when the service knows the song but won't stream it.
+    async fn check_playable(&self, _url: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Asks for the song's stream URL before it comes up, for services
+    /// which are slow to give one.
+    async fn prefetch(&self, _url: &str, _quality: Quality) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub(crate) struct Netease;
+
+#[async_trait]
+impl SourceProvider for Netease {
+    fn name(&self) -> &'static str {
+        "Netease"
+    }
+
+    fn matches_url(&self, url: &str) -> bool {
+        url.contains("music.163.com")
+    }
+
+    async fn resolve(
+        &self,
+        url: &str,
+        lazy: bool,
+        quality: Quality,
+        filter: FilterHandle,
+    ) -> Result<Restartable> {
+        neteaseapi::netease_restartable(url, lazy, quality, filter).await
+    }
+
+    fn is_playlist(&self, url: &str) -> bool {
+        url.contains("playlist")
+    }
+
+    async fn resolve_playlist(&self, url: &str, _limit: usize) -> Result<Vec<String>> {
+        neteaseapi::netease_playlist(url).await
+    }
+
+    async fn check_playable(&self, url: &str) -> Result<()> {
+        neteaseapi::netease_stream_url(url, Quality::default())
+            .await
+            .map(|_| ())
+    }
+
+    async fn prefetch(&self, url: &str, quality: Quality) -> Result<()> {
+        neteaseapi::netease_stream_url(url, quality)
+            .await
+            .map(|_| ())
+    }
+
+    async fn related(&self, url: &str, limit: usize) -> Result<Vec<String>> {
+        neteaseapi::netease_related(url, limit).await
+    }
+}
+
+pub(crate) struct Bilibili;
+
+#[async_trait]
+impl SourceProvider for Bilibili {
+    fn name(&self) -> &'static str {
+        "Bilibili"
+    }
+
+    fn matches_url(&self, url: &str) -> bool {
+        url.contains("bilibili.com/video") || url.contains("b23.tv")
+    }
+
+    async fn resolve(
+        &self,
+        url: &str,
+        lazy: bool,
+        _quality: Quality,
+        filter: FilterHandle,
+    ) -> Result<Restartable> {
+        bilibiliapi::bilibili_restartable(url, lazy, filter).await
+    }
+}
+
+pub(crate) struct SoundCloud;
+
+#[async_trait]
+impl SourceProvider for SoundCloud {
+    fn name(&self) -> &'static str {
+        "SoundCloud"
+    }
+
+    fn matches_url(&self, url: &str) -> bool {
+        url.contains("soundcloud.com")
+    }
+
+    async fn resolve(
+        &self,
+        url: &str,
+        lazy: bool,
+        _quality: Quality,
+        filter: FilterHandle,
+    ) -> Result<Restartable> {
+        soundcloudapi::soundcloud_restartable(url, lazy, filter).await
+    }
+
+    fn is_playlist(&self, url: &str) -> bool {
+        url.contains("/sets/")
+    }
+
+    async fn resolve_playlist(&self, url: &str, _limit: usize) -> Result<Vec<String>> {
+        soundcloudapi::soundcloud_playlist(url).await
+    }
+}
+
+pub(crate) struct Spotify;
+
+#[async_trait]
+impl SourceProvider for Spotify {
+    fn name(&self) -> &'static str {
+        "Spotify"
+    }
+
+    fn matches_url(&self, url: &str) -> bool {
+        spotify::is_spotify(url)
+    }
+
+    async fn resolve(
+        &self,
+        url: &str,
+        lazy: bool,
+        quality: Quality,
+        filter: FilterHandle,
+    ) -> Result<Restartable> {
+        let url = spotify::track_url(url).await?;
+
+        provider(&url).resolve(&url, lazy, quality, filter).await
+    }
+
+    fn is_playlist(&self, url: &str) -> bool {
+        spotify::is_collection(url)
+    }
+
+    async fn resolve_playlist(&self, url: &str, limit: usize) -> Result<Vec<String>> {
+        // Every track costs a search, don't look up more than will be kept.
+        spotify::collection_urls(url, limit).await
+    }
+}
+
+/// Attachments and `LOCAL_MUSIC_DIR`, as `file://` URLs.
+pub(crate) struct Local;
+
+#[async_trait]
+impl SourceProvider for Local {
+    fn name(&self) -> &'static str {
+        "Local"
+    }
+
+    fn matches_url(&self, url: &str) -> bool {
+        local::is_local(url)
+    }
+
+    async fn resolve(
+        &self,
+        url: &str,
+        lazy: bool,
+        _quality: Quality,
+        filter: FilterHandle,
+    ) -> Result<Restartable> {
+        local::restartable(url, lazy, filter).await
+    }
+}
+
+/// Audio files and radio streams, played without the downloader.
+pub(crate) struct Direct;
+
+#[async_trait]
+impl SourceProvider for Direct {
+    fn name(&self) -> &'static str {
+        "Direct"
+    }
+
+    fn matches_url(&self, url: &str) -> bool {
+        direct::is_direct(url)
+    }
+
+    async fn resolve(
+        &self,
+        url: &str,
+        lazy: bool,
+        _quality: Quality,
+        filter: FilterHandle,
+    ) -> Result<Restartable> {
+        direct::restartable(url, lazy, filter).await
+    }
+}
+
+/// Everything else the downloader (yt-dlp or youtube-dl) can extract.
+pub(crate) struct Ytdl;
+
+#[async_trait]
+impl SourceProvider for Ytdl {
+    fn name(&self) -> &'static str {
+        "youtube-dl"
+    }
+
+    fn matches_url(&self, _url: &str) -> bool {
+        true
+    }
+
+    async fn resolve(
+        &self,
+        url: &str,
+        lazy: bool,
+        quality: Quality,
+        filter: FilterHandle,
+    ) -> Result<Restartable> {
+        ytdl::ytdl_restartable(url, lazy, quality, filter).await
+    }
+
+    fn is_playlist(&self, url: &str) -> bool {
+        url.contains("youtube.com/playlist")
+    }
+
+    async fn resolve_playlist(&self, url: &str, _limit: usize) -> Result<Vec<String>> {
+        Ok(ytdl::flat_playlist(url)
+            .await?
+            .into_iter()
+            .filter_map(|x| x.source_url)
+            .collect())
+    }
+
+    async fn related(&self, url: &str, limit: usize) -> Result<Vec<String>> {
+        ytdl::related(url, limit).await
+    }
+
+    async fn prefetch(&self, url: &str, quality: Quality) -> Result<()> {
+        ytdl::prefetch(url, quality).await
+    }
+}
+
+/// Tried in order, the first match wins. `Ytdl` matches anything and has
+/// to stay last.
+static PROVIDERS: &[&dyn SourceProvider] = &[
+    &Netease,
+    &Bilibili,
+    &SoundCloud,
+    &Spotify,
+    &Local,
+    &Direct,
+    &Ytdl,
+];
+
+pub(crate) fn provider(url: &str) -> &'static dyn SourceProvider {
+    PROVIDERS
+        .iter()
+        .find(|x| x.matches_url(url))
+        .copied()
+        .unwrap_or(&Ytdl)
+}
+
+/// Opens the song at `url` with the provider for it, or from the audio
+/// cache. Sources are lazy, so queued songs cost nothing before they come
+/// up.
+pub(crate) async fn restartable(
+    url: &str,
+    quality: Quality,
+    filter: FilterHandle,
+) -> Result<Restartable> {
+    open_source(url, true, quality, filter).await
+}
+
+/// Opens the song at `url` right away, for reading it outside of songbird:
+/// a lazy source only starts in the mixer and reads as silence until then.
+pub(crate) async fn open(url: &str, quality: Quality, filter: FilterHandle) -> Result<Restartable> {
+    open_source(url, false, quality, filter).await
+}
+
+async fn open_source(
+    url: &str,
+    lazy: bool,
+    quality: Quality,
+    filter: FilterHandle,
+) -> Result<Restartable> {
+    if let Some(saved) = audio_cache::open(url, lazy, filter.clone()).await {
+        return Ok(saved);
+    }
+    let provider = provider(url);
+    let started = Instant::now();
+    let restartable = provider.resolve(url, lazy, quality, filter).await?;
+    metrics::resolved(provider.name(), started.elapsed());
+
+    Ok(restartable)
+}
+
+#[test]
+fn test_provider() {
+    assert_eq!(
+        provider("https://music.163.com/#/song?id=26209670").name(),
+        "Netease"
+    );
+    assert_eq!(provider("https://b23.tv/abcdef").name(), "Bilibili");
+    assert_eq!(
+        provider("https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC").name(),
+        "Spotify"
+    );
+    assert_eq!(
+        provider("https://www.youtube.com/watch?v=x").name(),
+        "youtube-dl"
+    );
+    assert_eq!(
+        provider("http://radio.example.com:8000/live.mp3").name(),
+        "Direct"
+    );
+    assert_eq!(provider("file:///music/a.flac").name(), "Local");
+    assert!(
+        provider("https://soundcloud.com/u/sets/s").is_playlist("https://soundcloud.com/u/sets/s")
+    );
+}
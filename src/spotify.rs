@@ -0,0 +1,278 @@
+//! Spotify doesn't let bots stream, so its links are read for titles and
+//! artists which are then searched on the services we can play.
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Result};
+use lazy_static::lazy_static;
+use reqwest::{Client, Url};
+use serde::{de::DeserializeOwned, Deserialize};
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+use crate::{
+    credentials::{self, Credential},
+    search,
+};
+
+const TOKEN_URL: &str = "https://accounts.spotify.com/api/token";
+const BASE_URL: &str = "https://api.spotify.com/v1";
+/// Largest page the playlist endpoint hands out.
+const PAGE_LIMIT: usize = 100;
+
+lazy_static! {
+    /// Token, its expiry and the credentials generation it was made with.
+    static ref TOKEN: Mutex<Option<(String, Instant, u64)>> = Mutex::new(None);
+}
+
+#[derive(Deserialize, Debug)]
+struct TokenResult {
+    access_token: String,
+    /// In seconds.
+    expires_in: u64,
+}
+
+#[derive(Deserialize, Debug)]
+struct Track {
+    name: String,
+    #[serde(default)]
+    artists: Vec<Artist>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Artist {
+    name: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Page<T> {
+    items: Vec<T>,
+    next: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PlaylistItem {
+    /// `null` for tracks which were removed from Spotify.
+    track: Option<Track>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum LinkType {
+    Track,
+    Album,
+    Playlist,
+}
+
+impl Track {
+    fn query(&self) -> String {
+        match self.artists.first() {
+            Some(artist) => format!("{} {}", self.name, artist.name),
+            None => self.name.to_owned(),
+        }
+    }
+}
+
+pub(crate) fn is_spotify(url: &str) -> bool {
+    url.contains("open.spotify.com")
+}
+
+/// Whether the link is an album or playlist rather than a single track.
+pub(crate) fn is_collection(url: &str) -> bool {
+    matches!(
+        parse_link(url),
+        Ok((LinkType::Album, _)) | Ok((LinkType::Playlist, _))
+    )
+}
+
+/// `https://open.spotify.com/intl-ja/track/<id>?si=...`
+fn parse_link(url: &str) -> Result<(LinkType, String)> {
+    let url = Url::parse(url)?;
+    let mut segments = url
+        .path_segments()
+        .ok_or_else(|| anyhow!("Url is not right!"))?
+        .skip_while(|x| x.starts_with("intl-"));
+    let t = match segments.next() {
+        Some("track") => LinkType::Track,
+        Some("album") => LinkType::Album,
+        Some("playlist") => LinkType::Playlist,
+        _ => bail!("Unsupported Spotify link!"),
+    };
+    let id = segments
+        .next()
+        .filter(|x| !x.is_empty())
+        .ok_or_else(|| anyhow!("Url is not right!"))?;
+
+    Ok((t, id.to_string()))
+}
+
+struct SpotifyClient {
+    client: Client,
+    token: String,
+}
+
+impl SpotifyClient {
+    async fn new() -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()?;
+        let token = token(&client).await?;
+
+        Ok(Self { client, token })
+    }
+
+    async fn get<T: DeserializeOwned>(&self, url: &str) -> Result<T> {
+        Ok(self
+            .client
+            .get(url)
+            .bearer_auth(&self.token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<T>()
+            .await?)
+    }
+
+    /// Follows `next` until `limit` items are collected.
+    async fn pages<T: DeserializeOwned>(&self, url: String, limit: usize) -> Result<Vec<T>> {
+        let mut items = vec![];
+        let mut next = Some(url);
+        while let Some(url) = next.filter(|_| items.len() < limit) {
+            let page = self.get::<Page<T>>(&url).await?;
+            items.extend(page.items);
+            next = page.next;
+        }
+        items.truncate(limit);
+
+        Ok(items)
+    }
+}
+
+/// Drops the token, the next request gets a new one.
+pub(crate) async fn forget_token() {
+    *TOKEN.lock().await = None;
+}
+
+/// Client credentials flow, the token is reused until it expires or the
+/// credentials change.
+async fn token(client: &Client) -> Result<String> {
+    let generation = credentials::generation();
+    let mut cached = TOKEN.lock().await;
+    if let Some((token, expires, made_with)) = &*cached {
+        if Instant::now() < *expires && *made_with == generation {
+            return Ok(token.to_owned());
+        }
+    }
+
+    let (id, secret) = match (
+        credentials::get(Credential::SpotifyClientId),
+        credentials::get(Credential::SpotifyClientSecret),
+    ) {
+        (Some(id), Some(secret)) => (id, secret),
+        _ => bail!("Spotify links need SPOTIFY_CLIENT_ID and SPOTIFY_CLIENT_SECRET"),
+    };
+    let result = client
+        .post(TOKEN_URL)
+        .basic_auth(id, Some(secret))
+        .form(&[("grant_type", "client_credentials")])
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<TokenResult>()
+        .await?;
+    // Leave a minute of headroom for requests in flight.
+    let expires = Instant::now() + Duration::from_secs(result.expires_in.saturating_sub(60));
+    *cached = Some((result.access_token.clone(), expires, generation));
+
+    Ok(result.access_token)
+}
+
+/// Search queries for the tracks behind a link, at most `limit` of them.
+async fn queries(url: &str, limit: usize) -> Result<Vec<String>> {
+    let (t, id) = parse_link(url)?;
+    let client = SpotifyClient::new().await?;
+    let tracks = match t {
+        LinkType::Track => vec![
+            client
+                .get::<Track>(&format!("{}/tracks/{}", BASE_URL, id))
+                .await?,
+        ],
+        LinkType::Album => {
+            let url = format!("{}/albums/{}/tracks?limit=50", BASE_URL, id);
+            client.pages::<Track>(url, limit).await?
+        }
+        LinkType::Playlist => {
+            let url = format!(
+                "{}/playlists/{}/tracks?limit={}&fields=items(track(name,artists(name))),next",
+                BASE_URL, id, PAGE_LIMIT
+            );
+            client
+                .pages::<PlaylistItem>(url, limit)
+                .await?
+                .into_iter()
+                .filter_map(|x| x.track)
+                .collect()
+        }
+    };
+    let queries = tracks.iter().map(Track::query).collect::<Vec<_>>();
+    debug!("spotify queries {:?}", queries);
+
+    Ok(queries)
+}
+
+async fn find(query: &str) -> Result<String> {
+    search::search(query, 1)
+        .await?
+        .into_iter()
+        .next()
+        .and_then(|x| x.source_url)
+        .ok_or_else(|| anyhow!("Nothing found for {}", query))
+}
+
+/// The playable URL of a Spotify track link.
+pub(crate) async fn track_url(url: &str) -> Result<String> {
+    let query = queries(url, 1)
+        .await?
+        .pop()
+        .ok_or_else(|| anyhow!("Can not get Spotify track!"))?;
+
+    find(&query).await
+}
+
+/// Playable URLs of an album or playlist link, skipping tracks which
+/// could not be found.
+pub(crate) async fn collection_urls(url: &str, limit: usize) -> Result<Vec<String>> {
+    let mut urls = vec![];
+    for query in queries(url, limit).await? {
+        match find(&query).await {
+            Ok(url) => urls.push(url),
+            Err(e) => warn!("Err translating Spotify track: {:?}", e),
+        }
+    }
+
+    Ok(urls)
+}
+
+#[test]
+fn test_parse_link() {
+    assert_eq!(
+        parse_link("https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC?si=abc").unwrap(),
+        (LinkType::Track, "4uLU6hMCjMI75M1A2tKUQC".to_string())
+    );
+    assert_eq!(
+        parse_link("https://open.spotify.com/intl-ja/playlist/37i9dQZF1DXcBWIGoYBM5M").unwrap(),
+        (LinkType::Playlist, "37i9dQZF1DXcBWIGoYBM5M".to_string())
+    );
+    assert!(is_collection(
+        "https://open.spotify.com/album/1DFixLWuPkv3KT3TnV35m3"
+    ));
+    assert!(parse_link("https://open.spotify.com/artist/0OdUWJ0sBjDrqHygGUXeCF").is_err());
+}
+
+#[test]
+fn test_track_query() {
+    let track = serde_json::from_str::<Track>(
+        r#"{"name":"Never Gonna Give You Up","artists":[{"name":"Rick Astley"},{"name":"x"}]}"#,
+    )
+    .unwrap();
+
+    assert_eq!(track.query(), "Never Gonna Give You Up Rick Astley");
+}
@@ -0,0 +1,200 @@
+//! Stage channels: the bot joins them as a listener, so it raises its hand
+//! and holds the songs until a stage moderator makes it a speaker.
+use anyhow::{bail, Result};
+use serenity::{
+    async_trait,
+    client::Context,
+    model::{
+        channel::{ChannelType, GuildChannel},
+        id::{GuildId, UserId},
+        voice::VoiceState,
+        Permissions,
+    },
+};
+use songbird::{
+    tracks::{TrackHandle, TrackResult},
+    Event, EventContext, EventHandler as VoiceEventHandler,
+};
+use tracing::warn;
+
+use crate::{
+    check_msg,
+    playback::{self, GuildPlayer},
+};
+
+/// What a voice state update of the bot means for its playback.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Change {
+    /// Suppressed on a stage, hold the songs.
+    Wait,
+    /// Made a speaker, play the songs.
+    Speak,
+    /// Left the stage while waiting.
+    Leave,
+}
+
+fn change(waiting: bool, on_stage: bool, suppressed: bool) -> Option<Change> {
+    match (waiting, on_stage, suppressed) {
+        (false, true, true) => Some(Change::Wait),
+        (true, true, false) => Some(Change::Speak),
+        (true, false, _) => Some(Change::Leave),
+        _ => None,
+    }
+}
+
+/// Stage moderators listening in `channel`, they can accept the request.
+fn moderators(ctx: &Context, channel: &GuildChannel) -> Vec<UserId> {
+    let bot_id = ctx.cache.current_user_id();
+    let users = ctx
+        .cache
+        .guild_field(channel.guild_id, |x| {
+            x.voice_states
+                .values()
+                .filter(|x| x.channel_id == Some(channel.id) && x.user_id != bot_id)
+                .map(|x| x.user_id)
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    users
+        .into_iter()
+        .filter(|x| {
+            channel
+                .permissions_for_user(&ctx.cache, *x)
+                .is_ok_and(|x| x.mute_members())
+        })
+        .collect()
+}
+
+/// Follows the bot's own voice state on stages: asks to speak while it is
+/// suppressed, and plays once it is allowed to.
+pub(crate) async fn voice_state_changed(ctx: &Context, guild_id: GuildId, state: &VoiceState) {
+    if state.user_id != ctx.cache.current_user_id() {
+        return;
+    }
+    let channel = state
+        .channel_id
+        .and_then(|x| ctx.cache.guild_channel(x))
+        .filter(|x| x.kind == ChannelType::Stage);
+
+    let lock = playback::playback_lock(ctx).await;
+    let (change, text_channel) = {
+        let mut playback = lock.write().await;
+        let playback = playback.entry(guild_id.0).or_default();
+        let change = match change(playback.stage_wait, channel.is_some(), state.suppress) {
+            Some(change) => change,
+            None => return,
+        };
+        playback.stage_wait = change == Change::Wait;
+
+        (change, playback.text_channel)
+    };
+
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    let current = match manager.get(guild_id) {
+        Some(handler_lock) => handler_lock.lock().await.queue().current(),
+        None => None,
+    };
+    let s = match (change, channel) {
+        (Change::Wait, Some(channel)) => {
+            if let Some(current) = current {
+                let _ = current.pause();
+            }
+            match ask_to_speak(ctx, &channel).await {
+                Ok(true) => return,
+                Ok(false) => {
+                    let mentions = moderators(ctx, &channel)
+                        .iter()
+                        .map(|x| format!("<@{}>", x))
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    format!(
+                        "Asked to speak in {}, songs wait until a stage moderator accepts {}",
+                        channel.name, mentions
+                    )
+                }
+                Err(why) => why.to_string(),
+            }
+        }
+        (Change::Speak, _) => {
+            if let Some(current) = current {
+                let _ = current.play();
+            }
+            "Speaking on the stage now, playing".to_string()
+        }
+        _ => return,
+    };
+
+    if let Some(text_channel) = text_channel {
+        check_msg(text_channel.say(&ctx.http, s.trim_end()).await);
+    }
+}
+
+/// Becomes a speaker right away if the bot may, raises its hand otherwise.
+/// True when it needs nobody to accept.
+async fn ask_to_speak(ctx: &Context, channel: &GuildChannel) -> Result<bool> {
+    let granted = channel
+        .permissions_for_user(&ctx.cache, ctx.cache.current_user_id())
+        .unwrap_or_else(|_| Permissions::empty());
+    if granted.mute_members() {
+        channel
+            .edit_own_voice_state(&ctx.http, |x| x.suppress(false))
+            .await?;
+
+        return Ok(true);
+    }
+    if !granted.request_to_speak() {
+        bail!(
+            "Missing the Request to Speak permission in {}, songs wait until a stage moderator invites the bot to speak",
+            channel.name
+        );
+    }
+
+    if let Err(e) = channel
+        .edit_own_voice_state(&ctx.http, |x| x.request_to_speak(true))
+        .await
+    {
+        warn!("Err requesting to speak in {}: {:?}", channel.id, e);
+    }
+
+    Ok(false)
+}
+
+/// Holds tracks which start while the bot waits to speak on a stage.
+pub(crate) struct StageGate {
+    pub player: GuildPlayer,
+}
+
+impl StageGate {
+    pub(crate) async fn hold(&self, track: &TrackHandle) -> TrackResult<()> {
+        if self.player.state(|x| x.stage_wait).await {
+            track.pause()?;
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl VoiceEventHandler for StageGate {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if let EventContext::Track(&[(_, track)]) = ctx {
+            let _ = self.hold(track).await;
+        }
+
+        None
+    }
+}
+
+#[test]
+fn test_change() {
+    assert_eq!(change(false, true, true), Some(Change::Wait));
+    assert_eq!(change(true, true, true), None);
+    assert_eq!(change(true, true, false), Some(Change::Speak));
+    assert_eq!(change(true, false, false), Some(Change::Leave));
+    assert_eq!(change(false, false, true), None);
+    assert_eq!(change(false, true, false), None);
+}
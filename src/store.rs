@@ -0,0 +1,62 @@
+//! Small JSON file store for state which has to survive restarts.
+//!
+//! Every value lives in its own `<name>.json` under `DATA_DIR` (`data` by
+//! default).
+use std::{env, path::PathBuf};
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::Mutex;
+
+lazy_static! {
+    static ref DATA_DIR: PathBuf = env::var("DATA_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("data"));
+    /// Held while a value is updated.
+    static ref UPDATING: Mutex<()> = Mutex::new(());
+}
+
+fn path(name: &str) -> PathBuf {
+    DATA_DIR.join(format!("{}.json", name))
+}
+
+/// A directory under `DATA_DIR` for files which aren't JSON values.
+pub(crate) fn dir(name: &str) -> PathBuf {
+    DATA_DIR.join(name)
+}
+
+/// Loads a value, or its default if it was never saved.
+pub(crate) async fn load<T: DeserializeOwned + Default>(name: &str) -> Result<T> {
+    match tokio::fs::read(path(name)).await {
+        Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Saves a value. The file is replaced at once so a crash while writing
+/// can't leave half of it behind.
+pub(crate) async fn save<T: Serialize>(name: &str, value: &T) -> Result<()> {
+    tokio::fs::create_dir_all(&*DATA_DIR).await?;
+    let path = path(name);
+    let tmp = path.with_extension("json.tmp");
+    tokio::fs::write(&tmp, serde_json::to_vec_pretty(value)?).await?;
+    tokio::fs::rename(tmp, path).await?;
+
+    Ok(())
+}
+
+/// Loads a value, changes it with `f` and saves it. Updates run one at a
+/// time, so none is lost to another one made meanwhile.
+pub(crate) async fn update<T, R>(name: &str, f: impl FnOnce(&mut T) -> R) -> Result<R>
+where
+    T: Serialize + DeserializeOwned + Default,
+{
+    let _updating = UPDATING.lock().await;
+    let mut value = load::<T>(name).await?;
+    let result = f(&mut value);
+    save(name, &value).await?;
+
+    Ok(result)
+}
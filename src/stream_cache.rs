@@ -0,0 +1,139 @@
+//! Stream URLs the services handed out, kept until shortly before they
+//! expire so a song starts without asking for its URL again. The prefetch
+//! fills it for the songs coming up next.
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use lazy_static::lazy_static;
+use songbird::input::Metadata;
+
+use crate::quality::Quality;
+
+/// URLs kept at most, the one to expire first goes when it is full.
+const ENTRIES_MAX: usize = 500;
+/// Taken off every expiry, ffmpeg still has to open the URL in time.
+const MARGIN: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug)]
+pub(crate) struct Stream {
+    pub url: String,
+    /// ffmpeg options for opening `url`, like headers.
+    pub input_args: Vec<String>,
+    pub metadata: Metadata,
+}
+
+struct Entry {
+    stream: Stream,
+    expires: Instant,
+}
+
+/// Streams by the song's own URL and the quality they were asked in.
+#[derive(Default)]
+struct Cache(HashMap<(String, Quality), Entry>);
+
+impl Cache {
+    fn get(&mut self, url: &str, quality: Quality, now: Instant) -> Option<Stream> {
+        let key = (url.to_string(), quality);
+        match self.0.get(&key) {
+            Some(entry) if entry.expires > now => Some(entry.stream.clone()),
+            Some(_) => {
+                self.0.remove(&key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert(&mut self, url: &str, quality: Quality, stream: Stream, ttl: Duration, now: Instant) {
+        let ttl = match ttl.checked_sub(MARGIN) {
+            Some(ttl) if !ttl.is_zero() => ttl,
+            _ => return,
+        };
+        self.0.retain(|_, x| x.expires > now);
+        if self.0.len() >= ENTRIES_MAX {
+            let first = self
+                .0
+                .iter()
+                .min_by_key(|(_, x)| x.expires)
+                .map(|(key, _)| key.clone());
+            if let Some(first) = first {
+                self.0.remove(&first);
+            }
+        }
+        let entry = Entry {
+            stream,
+            expires: now + ttl,
+        };
+        self.0.insert((url.to_string(), quality), entry);
+    }
+}
+
+lazy_static! {
+    static ref CACHE: Mutex<Cache> = Mutex::new(Cache::default());
+    /// Songs being resolved ahead right now.
+    static ref PENDING: Mutex<HashSet<(String, Quality)>> = Mutex::new(HashSet::new());
+}
+
+/// The stream of the song at `url`, if one was resolved and still works.
+pub(crate) fn get(url: &str, quality: Quality) -> Option<Stream> {
+    CACHE.lock().unwrap().get(url, quality, Instant::now())
+}
+
+/// Keeps the stream of the song at `url`, which works for `ttl`.
+pub(crate) fn insert(url: &str, quality: Quality, stream: Stream, ttl: Duration) {
+    CACHE
+        .lock()
+        .unwrap()
+        .insert(url, quality, stream, ttl, Instant::now());
+}
+
+/// Marks the song as being resolved ahead, `false` if it already is.
+pub(crate) fn start_pending(url: &str, quality: Quality) -> bool {
+    PENDING.lock().unwrap().insert((url.to_string(), quality))
+}
+
+pub(crate) fn end_pending(url: &str, quality: Quality) {
+    PENDING.lock().unwrap().remove(&(url.to_string(), quality));
+}
+
+#[test]
+fn test_cache() {
+    let stream = |url: &str| Stream {
+        url: url.to_string(),
+        input_args: vec![],
+        metadata: Metadata::default(),
+    };
+    let now = Instant::now();
+    let minutes = |x| Duration::from_secs(x * 60);
+    let mut cache = Cache::default();
+
+    cache.insert("a", Quality::High, stream("a.mp3"), minutes(20), now);
+    assert_eq!(
+        cache.get("a", Quality::High, now).map(|x| x.url).as_deref(),
+        Some("a.mp3")
+    );
+    assert!(cache.get("a", Quality::Normal, now).is_none());
+    // Expired a minute early.
+    assert!(cache.get("a", Quality::High, now + minutes(19)).is_none());
+
+    // Too short to be of use.
+    cache.insert("b", Quality::High, stream("b.mp3"), MARGIN, now);
+    assert!(cache.get("b", Quality::High, now).is_none());
+
+    for i in 0..ENTRIES_MAX + 1 {
+        let url = i.to_string();
+        cache.insert(
+            &url,
+            Quality::High,
+            stream(&url),
+            minutes(10 + i as u64),
+            now,
+        );
+    }
+    assert_eq!(cache.0.len(), ENTRIES_MAX);
+    assert!(cache.get("0", Quality::High, now).is_none());
+    assert!(cache.get("1", Quality::High, now).is_some());
+}
@@ -0,0 +1,196 @@
+//! Keeps track of the ffmpeg and yt-dlp processes behind the songs, by
+//! guild. They are killed when their song is dropped, and all of a guild's
+//! when its queue is cleared or the bot leaves, so none keep running on
+//! their own. At most `MAX_PROCESSES` (200 by default) run at once, and the
+//! ones which exited are reaped.
+use std::{
+    collections::HashMap,
+    env, io,
+    process::{Child, ChildStdout},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use lazy_static::lazy_static;
+use tracing::{info, warn};
+
+use crate::hibernate;
+
+const DEFAULT_MAX_PROCESSES: usize = 200;
+/// How often exited processes are reaped.
+const REAP_TICK: Duration = Duration::from_secs(30);
+
+lazy_static! {
+    static ref MAX_PROCESSES: usize = env::var("MAX_PROCESSES")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(DEFAULT_MAX_PROCESSES);
+    static ref SUPERVISED: Mutex<Supervised> = Mutex::new(Supervised::default());
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// The processes of one song.
+struct Group {
+    guild_id: Option<u64>,
+    children: Vec<Child>,
+}
+
+#[derive(Default)]
+struct Supervised(HashMap<u64, Group>);
+
+impl Supervised {
+    fn running(&self) -> usize {
+        self.0.values().map(|x| x.children.len()).sum()
+    }
+
+    /// Waits for the processes which exited, dropping them.
+    fn reap(&mut self) -> usize {
+        let mut reaped = 0;
+        for group in self.0.values_mut() {
+            let before = group.children.len();
+            group
+                .children
+                .retain_mut(|x| !matches!(x.try_wait(), Ok(Some(_)) | Err(_)));
+            reaped += before - group.children.len();
+        }
+        self.0.retain(|_, x| !x.children.is_empty());
+
+        reaped
+    }
+
+    fn take_guild(&mut self, guild_id: u64) -> Vec<Child> {
+        let ids = self
+            .0
+            .iter()
+            .filter(|(_, x)| x.guild_id == Some(guild_id))
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+
+        ids.into_iter()
+            .filter_map(|id| self.0.remove(&id))
+            .flat_map(|x| x.children)
+            .collect()
+    }
+}
+
+/// Kills the song's processes when dropped.
+pub(crate) struct Guard(u64);
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let group = SUPERVISED.lock().unwrap().0.remove(&self.0);
+        if let Some(group) = group {
+            kill(group.children);
+        }
+    }
+}
+
+/// Kills and reaps `children`, on a blocking thread when in the runtime,
+/// as songs are dropped on the mixer's.
+fn kill(children: Vec<Child>) {
+    if children.is_empty() {
+        return;
+    }
+    let reap = move || {
+        for mut child in children {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    };
+    match tokio::runtime::Handle::try_current() {
+        Ok(runtime) => drop(runtime.spawn_blocking(reap)),
+        Err(_) => reap(),
+    }
+}
+
+/// Watches the processes of a song of `guild_id`, the last of `children`
+/// making the audio. Fails, killing them, when too many run already.
+pub(crate) fn supervise(
+    mut children: Vec<Child>,
+    guild_id: Option<u64>,
+) -> io::Result<(ChildStdout, Guard)> {
+    let stdout = children.last_mut().and_then(|x| x.stdout.take());
+    let mut supervised = SUPERVISED.lock().unwrap();
+    supervised.reap();
+    let stdout = match stdout {
+        Some(stdout) if supervised.running() + children.len() <= *MAX_PROCESSES => stdout,
+        Some(_) => {
+            drop(supervised);
+            kill(children);
+            warn!("Refused a song, {} processes run already", *MAX_PROCESSES);
+            return Err(io::Error::other("Too many songs are being played"));
+        }
+        None => {
+            drop(supervised);
+            kill(children);
+            return Err(io::Error::other("ffmpeg has no output to read"));
+        }
+    };
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    supervised.0.insert(id, Group { guild_id, children });
+
+    Ok((stdout, Guard(id)))
+}
+
+/// Kills the processes of all songs of `guild_id`, when its queue is gone.
+pub(crate) fn kill_guild(guild_id: u64) {
+    let children = SUPERVISED.lock().unwrap().take_guild(guild_id);
+    if !children.is_empty() {
+        info!("Killing {} processes of guild {}", children.len(), guild_id);
+    }
+    kill(children);
+}
+
+/// Reaps exited processes now and then, e.g. yt-dlp once it downloaded the
+/// song while ffmpeg still plays it.
+pub(crate) async fn run() {
+    loop {
+        tokio::time::sleep(hibernate::tick(REAP_TICK)).await;
+        SUPERVISED.lock().unwrap().reap();
+    }
+}
+
+#[test]
+fn test_supervised() {
+    use std::process::{Command, Stdio};
+
+    let spawn = |args: &[&str]| {
+        Command::new(args[0])
+            .args(&args[1..])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap()
+    };
+    let mut supervised = Supervised::default();
+    supervised.0.insert(
+        1,
+        Group {
+            guild_id: Some(1),
+            children: vec![spawn(&["true"]), spawn(&["true"])],
+        },
+    );
+    supervised.0.insert(
+        2,
+        Group {
+            guild_id: Some(2),
+            children: vec![spawn(&["sleep", "10"])],
+        },
+    );
+    assert_eq!(supervised.running(), 3);
+
+    let mut taken = supervised.take_guild(2);
+    assert_eq!(taken.len(), 1);
+    assert_eq!(supervised.running(), 2);
+    taken[0].kill().unwrap();
+    taken[0].wait().unwrap();
+
+    for child in &mut supervised.0.get_mut(&1).unwrap().children {
+        child.wait().unwrap();
+    }
+    assert_eq!(supervised.reap(), 2);
+    assert!(supervised.0.is_empty());
+}
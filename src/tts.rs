@@ -0,0 +1,54 @@
+//! Text to speech through Google Translate's TTS endpoint.
+use std::process::{Command, Stdio};
+
+use anyhow::Result;
+use reqwest::Url;
+use songbird::input::{children_to_reader, Codec, Container, Input};
+
+use crate::ffmpeg;
+
+const TTS_URL: &str = "https://translate.google.com/translate_tts";
+/// The endpoint refuses longer texts.
+const TTS_MAX_CHARS: usize = 200;
+
+/// Creates an input speaking `text` in `lang` (e.g. `en`, `zh-CN`, `ja`).
+pub(crate) fn speak(text: &str, lang: &str) -> Result<Input> {
+    let text = text.chars().take(TTS_MAX_CHARS).collect::<String>();
+    let url = Url::parse_with_params(
+        TTS_URL,
+        &[
+            ("ie", "UTF-8"),
+            ("client", "tw-ob"),
+            ("tl", lang),
+            ("q", text.as_str()),
+        ],
+    )?;
+    let from_pipe_args = vec![
+        "-i",
+        url.as_str(),
+        "-acodec",
+        "pcm_f32le",
+        "-ac",
+        "2",
+        "-ar",
+        "48000",
+        "-f",
+        "f32le",
+        "-",
+    ];
+
+    let ffmpeg_command = Command::new(ffmpeg::ffmpeg())
+        .args(from_pipe_args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    Ok(Input::new(
+        true,
+        children_to_reader::<f32>(vec![ffmpeg_command]),
+        Codec::FloatPcm,
+        Container::Raw,
+        None,
+    ))
+}
@@ -0,0 +1,128 @@
+//! Vote skipping: when it's on, listeners who aren't DJs vote to skip the
+//! playing song instead of skipping it outright.
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+use serenity::{client::Context, model::id::GuildId};
+use songbird::tracks::TrackHandle;
+
+use crate::playback;
+
+/// Custom id of the button listeners vote with.
+pub(crate) const VOTE_BUTTON: &str = "voteskip";
+
+/// Share of listeners a vote needs when it's turned on without one.
+pub(crate) const DEFAULT_FRACTION: f32 = 0.5;
+
+/// A vote nobody joined for this long starts over.
+const VOTE_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub(crate) struct SkipVote {
+    track: TrackHandle,
+    voters: HashSet<u64>,
+    started: Instant,
+}
+
+pub(crate) enum Vote {
+    /// Vote skipping is off, or nothing is playing to vote on.
+    Off,
+    /// Only users in the bot's voice channel may vote.
+    NotListening,
+    Pending {
+        votes: usize,
+        required: usize,
+    },
+    Passed,
+}
+
+fn required_votes(listeners: usize, fraction: f32) -> usize {
+    ((listeners as f32 * fraction).ceil() as usize).max(1)
+}
+
+/// Users other than bots in the voice channel.
+fn listeners(ctx: &Context, guild_id: GuildId, channel: u64) -> HashSet<u64> {
+    ctx.cache
+        .guild_field(guild_id, |guild| {
+            guild
+                .voice_states
+                .values()
+                .filter(|x| x.channel_id.map(|x| x.0) == Some(channel))
+                .filter(|x| {
+                    let bot = match &x.member {
+                        Some(member) => member.user.bot,
+                        None => ctx.cache.user(x.user_id).is_some_and(|x| x.bot),
+                    };
+                    !bot
+                })
+                .map(|x| x.user_id.0)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Counts `user`'s vote to skip the playing song.
+pub(crate) async fn vote(ctx: &Context, guild_id: GuildId, user: u64) -> Vote {
+    let manager = songbird::get(ctx)
+        .await
+        .expect("Songbird Voice client placed in at initialisation.")
+        .clone();
+    let (channel, current) = match manager.get(guild_id) {
+        Some(handler_lock) => {
+            let handler = handler_lock.lock().await;
+            (handler.current_channel(), handler.queue().current())
+        }
+        None => return Vote::Off,
+    };
+    let (channel, current) = match (channel, current) {
+        (Some(channel), Some(current)) => (channel, current),
+        _ => return Vote::Off,
+    };
+
+    let listeners = listeners(ctx, guild_id, channel.0);
+    if !listeners.contains(&user) {
+        return Vote::NotListening;
+    }
+
+    let lock = playback::playback_lock(ctx).await;
+    let mut playback = lock.write().await;
+    let state = playback.entry(guild_id.0).or_default();
+    let fraction = match state.vote_skip {
+        Some(fraction) => fraction,
+        None => return Vote::Off,
+    };
+
+    let vote = match &mut state.skip_vote {
+        Some(vote)
+            if vote.track.uuid() == current.uuid() && vote.started.elapsed() < VOTE_TIMEOUT =>
+        {
+            vote
+        }
+        vote => vote.insert(SkipVote {
+            track: current,
+            voters: HashSet::new(),
+            started: Instant::now(),
+        }),
+    };
+    vote.voters.insert(user);
+
+    // Voters who left the channel don't count any more.
+    let votes = vote.voters.intersection(&listeners).count();
+    let required = required_votes(listeners.len(), fraction);
+    if votes >= required {
+        state.skip_vote = None;
+        Vote::Passed
+    } else {
+        Vote::Pending { votes, required }
+    }
+}
+
+#[test]
+fn test_required_votes() {
+    assert_eq!(required_votes(1, 0.5), 1);
+    assert_eq!(required_votes(3, 0.5), 2);
+    assert_eq!(required_votes(4, 0.5), 2);
+    assert_eq!(required_votes(4, 1.0), 4);
+    assert_eq!(required_votes(0, 0.5), 1);
+}
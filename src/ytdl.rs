@@ -0,0 +1,376 @@
+use std::{
+    io::{BufRead, BufReader},
+    process::Stdio,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::Value;
+use songbird::input::{
+    error::Error as InputError, restartable::Restart, Codec, Container, Input, Metadata,
+    Restartable,
+};
+
+use crate::{
+    downloader,
+    ffmpeg::{self, FilterHandle, Pipeline},
+    metrics,
+    quality::Quality,
+    seek_cache,
+    stream_cache::{self, Stream},
+};
+
+/// How long stream URLs work when they don't say, YouTube's do.
+const DEFAULT_URL_TTL: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Deserialize, Debug)]
+struct FlatPlaylist {
+    #[serde(default)]
+    entries: Vec<FlatPlaylistEntry>,
+}
+
+#[derive(Deserialize, Debug)]
+struct FlatPlaylistEntry {
+    id: Option<String>,
+    url: Option<String>,
+    ie_key: Option<String>,
+    title: Option<String>,
+    duration: Option<f64>,
+}
+
+impl FlatPlaylistEntry {
+    /// youtube-dl only gives the video id as `url` for YouTube entries,
+    /// while yt-dlp gives a full URL.
+    fn source_url(&self) -> Option<String> {
+        match &self.url {
+            Some(url) if url.starts_with("http") => Some(url.to_owned()),
+            _ if self.ie_key.as_deref() == Some("Youtube") => self
+                .id
+                .as_ref()
+                .map(|id| format!("https://www.youtube.com/watch?v={}", id)),
+            _ => None,
+        }
+    }
+
+    fn into_metadata(self) -> Option<Metadata> {
+        let source_url = self.source_url()?;
+
+        Some(Metadata {
+            title: self.title,
+            duration: self.duration.map(Duration::from_secs_f64),
+            source_url: Some(source_url),
+            ..Default::default()
+        })
+    }
+}
+
+fn parse_flat_playlist(json: &[u8]) -> Result<Vec<Metadata>> {
+    let playlist = serde_json::from_slice::<FlatPlaylist>(json)?;
+
+    Ok(playlist
+        .entries
+        .into_iter()
+        .filter_map(FlatPlaylistEntry::into_metadata)
+        .collect())
+}
+
+/// Lists the entries of a playlist (or of a `ytsearchN:` query) without
+/// resolving every entry.
+pub(crate) async fn flat_playlist(url: &str) -> Result<Vec<Metadata>> {
+    let output = downloader::command()
+        .args(["--flat-playlist", "-J", url])
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{} failed: {}",
+            downloader::name(),
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    parse_flat_playlist(&output.stdout)
+}
+
+/// YouTube's mix of songs like the video at `url`, a playlist which starts
+/// with the video itself.
+fn mix_url(url: &str) -> Option<String> {
+    if !url.contains("youtube.com/watch") && !url.contains("youtu.be/") {
+        return None;
+    }
+    let id = match url.split_once("v=") {
+        Some((_, rest)) => rest,
+        None => url.rsplit_once('/')?.1,
+    };
+    let id = id.split(['&', '?', '#']).next().filter(|x| !x.is_empty())?;
+
+    Some(format!(
+        "https://www.youtube.com/watch?v={}&list=RD{}",
+        id, id
+    ))
+}
+
+/// Videos of the YouTube mix of the video at `url`.
+pub(crate) async fn related(url: &str, limit: usize) -> Result<Vec<String>> {
+    let mix = mix_url(url).ok_or_else(|| anyhow!("No YouTube mix for {}", url))?;
+
+    Ok(flat_playlist(&mix)
+        .await?
+        .into_iter()
+        .filter_map(|x| x.source_url)
+        .filter(|x| mix_url(x) != Some(mix.clone()))
+        .take(limit)
+        .collect())
+}
+
+/// Like songbird's `Restartable::ytdl`, but the audio goes through the
+/// limiter like every other source.
+struct YtdlRestarter {
+    url: String,
+    quality: Quality,
+    filter: FilterHandle,
+}
+
+#[async_trait]
+impl Restart for YtdlRestarter {
+    async fn call_restart(
+        &mut self,
+        time: Option<Duration>,
+    ) -> songbird::input::error::Result<Input> {
+        let url = self.url.clone();
+        let time = time.unwrap_or_default();
+        let quality = self.quality;
+        let filter = self.filter.clone();
+        if let Some(stream) = stream_cache::get(&url, quality) {
+            let input_args = stream
+                .input_args
+                .iter()
+                .map(String::as_str)
+                .collect::<Vec<_>>();
+            let ffmpeg = Pipeline::new(&stream.url, filter.get())
+                .input_args(&input_args)
+                .seek(Some(time))
+                .spawn()?;
+
+            return Ok(ffmpeg::input(
+                vec![ffmpeg],
+                stream.metadata,
+                filter.guild_id(),
+            )?);
+        }
+
+        tokio::task::spawn_blocking(move || ytdl_input(&url, time, quality, &filter))
+            .await
+            .map_err(|_| InputError::Metadata)?
+    }
+
+    async fn lazy_init(
+        &mut self,
+    ) -> songbird::input::error::Result<(Option<Metadata>, Codec, Container)> {
+        let value = extract(&self.url, self.quality).await?;
+        if let Some((stream, ttl)) = stream(&value) {
+            stream_cache::insert(&self.url, self.quality, stream, ttl);
+        }
+
+        Ok((
+            Some(Metadata::from_ytdl_output(value)),
+            Codec::FloatPcm,
+            Container::Raw,
+        ))
+    }
+}
+
+/// The song's JSON from the downloader, without the audio.
+async fn extract(url: &str, quality: Quality) -> songbird::input::error::Result<Value> {
+    let output = downloader::command()
+        .arg("-j")
+        .args(downloader::audio_args(quality))
+        .arg(url)
+        .stdin(Stdio::null())
+        .output()
+        .await?;
+
+    serde_json::from_slice(&output.stdout).map_err(|error| InputError::Json {
+        error,
+        parsed_text: String::from_utf8_lossy(&output.stdout).into_owned(),
+    })
+}
+
+/// Time left until the `expire` parameter of a stream URL, as YouTube's
+/// have.
+fn expiry(url: &str, now: SystemTime) -> Option<Duration> {
+    let url = Url::parse(url).ok()?;
+    let expire = url
+        .query_pairs()
+        .find(|(k, _)| k == "expire")?
+        .1
+        .parse()
+        .ok()?;
+
+    (UNIX_EPOCH + Duration::from_secs(expire))
+        .duration_since(now)
+        .ok()
+}
+
+/// The stream in the downloader's JSON, when ffmpeg can open it by itself,
+/// and how long it works.
+fn stream(value: &Value) -> Option<(Stream, Duration)> {
+    let url = value["url"].as_str()?;
+    if !matches!(value["protocol"].as_str(), Some("http" | "https")) {
+        return None;
+    }
+    let headers = value["http_headers"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter_map(|(k, v)| Some(format!("{}: {}\r\n", k, v.as_str()?)))
+        .collect::<String>();
+    let input_args = if headers.is_empty() {
+        vec![]
+    } else {
+        vec!["-headers".to_string(), headers]
+    };
+    let stream = Stream {
+        url: url.to_string(),
+        input_args,
+        metadata: Metadata::from_ytdl_output(value.clone()),
+    };
+    let ttl = expiry(url, SystemTime::now()).unwrap_or(DEFAULT_URL_TTL);
+
+    Some((stream, ttl))
+}
+
+/// Resolves the stream of the song at `url` ahead, unless it is known.
+pub(crate) async fn prefetch(url: &str, quality: Quality) -> Result<()> {
+    if stream_cache::get(url, quality).is_some() {
+        return Ok(());
+    }
+    let value = extract(url, quality).await?;
+    if let Some((stream, ttl)) = stream(&value) {
+        stream_cache::insert(url, quality, stream, ttl);
+    }
+
+    Ok(())
+}
+
+/// Pipes the downloader into ffmpeg. It prints the song's JSON on stderr
+/// before the audio starts, which gives the metadata.
+fn ytdl_input(
+    url: &str,
+    time: Duration,
+    quality: Quality,
+    filter: &FilterHandle,
+) -> songbird::input::error::Result<Input> {
+    let mut youtube_dl = downloader::std_command()
+        .args(["--print-json", "-R", "infinite"])
+        .args(downloader::audio_args(quality))
+        .args([url, "-o", "-"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    let mut stderr = BufReader::new(youtube_dl.stderr.take().ok_or(InputError::Stdout)?);
+    let mut line = vec![];
+    stderr.read_until(b'\n', &mut line)?;
+    let value = serde_json::from_slice(&line).map_err(|error| InputError::Json {
+        error,
+        parsed_text: String::from_utf8_lossy(&line).into_owned(),
+    })?;
+    youtube_dl.stderr = Some(stderr.into_inner());
+
+    let ffmpeg = Pipeline::new("-", filter.get())
+        .seek(Some(time))
+        .command()
+        .stdin(youtube_dl.stdout.take().ok_or(InputError::Stdout)?)
+        .stderr(Stdio::null())
+        .spawn()
+        .inspect_err(|_| metrics::ffmpeg_spawn_failed())?;
+
+    Ok(ffmpeg::input(
+        vec![youtube_dl, ffmpeg],
+        Metadata::from_ytdl_output(value),
+        filter.guild_id(),
+    )?)
+}
+
+pub(crate) async fn ytdl_restartable(
+    url: &str,
+    lazy: bool,
+    quality: Quality,
+    filter: FilterHandle,
+) -> Result<Restartable> {
+    let restarter = YtdlRestarter {
+        url: url.to_string(),
+        quality,
+        filter: filter.clone(),
+    };
+
+    Ok(seek_cache::restartable(restarter, filter, lazy).await?)
+}
+
+#[test]
+fn test_parse_flat_playlist() {
+    let json = br#"{
+        "_type": "playlist",
+        "title": "test",
+        "entries": [
+            {"_type": "url", "ie_key": "Youtube", "id": "dQw4w9WgXcQ", "url": "dQw4w9WgXcQ", "title": "a", "duration": 212.0},
+            {"_type": "url", "ie_key": "Youtube", "id": "x", "url": "https://www.youtube.com/watch?v=9bZkp7q19f0"},
+            {"_type": "url", "ie_key": "Generic", "id": "y", "url": "y"}
+        ]
+    }"#;
+    let entries = parse_flat_playlist(json).unwrap();
+    let urls = entries
+        .iter()
+        .map(|x| x.source_url.as_deref().unwrap())
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        urls,
+        vec![
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+            "https://www.youtube.com/watch?v=9bZkp7q19f0"
+        ]
+    );
+    assert_eq!(entries[0].title.as_deref(), Some("a"));
+    assert_eq!(entries[0].duration, Some(Duration::from_secs(212)));
+}
+
+#[test]
+fn test_mix_url() {
+    let mix = Some("https://www.youtube.com/watch?v=abc&list=RDabc".to_string());
+
+    assert_eq!(mix_url("https://www.youtube.com/watch?v=abc&t=10"), mix);
+    assert_eq!(mix_url("https://youtu.be/abc?t=10"), mix);
+    assert_eq!(mix_url("https://soundcloud.com/u/s"), None);
+}
+
+#[test]
+fn test_stream() {
+    let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let url = "https://rr1.googlevideo.com/videoplayback?expire=1700003600&itag=251";
+    assert_eq!(expiry(url, now), Some(Duration::from_secs(3600)));
+    assert_eq!(expiry("https://example.com/a.mp3", now), None);
+
+    let value = serde_json::json!({
+        "title": "A",
+        "url": url,
+        "protocol": "https",
+        "http_headers": { "User-Agent": "Mozilla/5.0" },
+    });
+    let (found, _) = stream(&value).unwrap();
+    assert_eq!(found.url, url);
+    assert_eq!(
+        found.input_args,
+        ["-headers", "User-Agent: Mozilla/5.0\r\n"]
+    );
+    assert_eq!(found.metadata.title.as_deref(), Some("A"));
+
+    let hls = serde_json::json!({ "url": url, "protocol": "m3u8_native" });
+    assert!(stream(&hls).is_none());
+}